@@ -1,5 +1,13 @@
 // Necessary because of this issue: https://github.com/rust-lang/cargo/issues/9641
 fn main() -> anyhow::Result<()> {
+    // Nothing to propagate when building for the host, e.g. to run the unit tests
+    if std::env::var("CARGO_CFG_TARGET_OS").as_deref() != Ok("espidf") {
+        // `esp-idf` options checked by the modules which are also built for the host
+        println!("cargo:rustc-check-cfg=cfg(esp_idf_bt_ble_50_features_supported)");
+        println!("cargo:rustc-check-cfg=cfg(esp_idf_version_major, values(\"4\", \"5\"))");
+        return Ok(());
+    }
+
     embuild::build::CfgArgs::output_propagated("ESP_IDF")?;
     embuild::build::LinkArgs::output_propagated("ESP_IDF")
 }
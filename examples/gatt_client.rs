@@ -0,0 +1,81 @@
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use esp_idf_ble::{AddressType, BdAddr, EspBle, GattClientEvent};
+use esp_idf_svc::nvs::{EspDefaultNvs, EspDefaultNvsPartition};
+use esp_idf_sys::*;
+
+use log::*;
+
+// Address of the peripheral to connect to, e.g. a board running the gatt_server example
+const PEER_ADDRESS: BdAddr = BdAddr([0x24, 0x0a, 0xc4, 0x00, 0x00, 0x01]);
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+fn main() {
+    esp_idf_sys::link_patches();
+
+    // Bind the log crate to the ESP Logging facilities
+    esp_idf_svc::log::EspLogger::initialize_default();
+
+    #[allow(unused)]
+    let default_nvs =
+        Arc::new(Mutex::new(EspDefaultNvs::new(EspDefaultNvsPartition::take().unwrap(), "ble", true).unwrap()));
+
+    let mut ble = EspBle::new("ESP32".into(), 500, default_nvs).unwrap();
+
+    let gattc_if = ble
+        .register_gatt_client_application_blocking(1, TIMEOUT)
+        .expect("Unable to register client");
+    info!("Client registered with gattc_if: {gattc_if}");
+
+    ble.register_client_disconnect_handler(gattc_if, |_, disconnect| {
+        if let GattClientEvent::Disconnect(disconnect) = disconnect {
+            info!("Disconnected with reason: {:?}", disconnect.reason);
+        }
+    });
+
+    let conn_id = ble
+        .open_blocking(gattc_if, PEER_ADDRESS, AddressType::Public, TIMEOUT)
+        .expect("Unable to open connection");
+    info!("Connection opened with conn_id: {conn_id}");
+
+    ble.search_services_blocking(gattc_if, conn_id, None, TIMEOUT)
+        .expect("Unable to search services");
+    info!("Service discovery complete");
+
+    for svc in ble
+        .get_services(gattc_if, conn_id)
+        .expect("Unable to get services")
+    {
+        info!("Service: {svc:?}");
+
+        for charac in ble
+            .get_characteristics(gattc_if, conn_id, &svc)
+            .expect("Unable to get characteristics")
+        {
+            info!("  Characteristic: {charac:?}");
+
+            for desc in ble
+                .get_descriptors(gattc_if, conn_id, &charac)
+                .expect("Unable to get descriptors")
+            {
+                info!("    Descriptor: {desc:?}");
+            }
+
+            if charac.properties & ESP_GATT_CHAR_PROP_BIT_READ as u8 != 0 {
+                ble.read_characteristic(gattc_if, conn_id, charac.handle, |_, read| {
+                    if let GattClientEvent::ReadCharacteristic(read) = read {
+                        info!("Read {:?} from {}: {:?}", read.status, read.handle, read.value);
+                    }
+                })
+                .expect("Unable to read characteristic");
+            }
+        }
+    }
+
+    loop {
+        thread::sleep(Duration::from_millis(500));
+    }
+}
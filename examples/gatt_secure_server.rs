@@ -1,11 +1,12 @@
 use std::sync::mpsc::sync_channel;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
 
 use esp_idf_ble::{
-    AdvertiseData, AttributeValue, AutoResponse, BtUuid, EspBle, GattCharacteristic,
-    GattDescriptor, GattService, GattServiceEvent, SecurityConfig, AuthenticationRequest, IOCapabilities, KeyMask,
+    AddAttributeEvent, AdvertiseData, AdvertisingParameters, AttributeValue, AutoResponse, BtUuid,
+    CreateServiceEvent, EspBle, GattCharacteristic, GattDescriptor, GattService, GattServiceEvent,
+    SecurityConfig, ServiceEvent, AuthenticationRequest, IOCapabilities, KeyMask,
 };
 use esp_idf_hal::delay;
 // use esp_idf_hal::prelude::*;
@@ -27,13 +28,13 @@ fn main() {
     let netif_stack = Arc::new(EspNetif::new(NetifStack::Eth).expect("Unable to init Netif Stack"));
 
     #[allow(unused)]
-    let default_nvs = Arc::new(EspDefaultNvs::new( EspDefaultNvsPartition::take().unwrap(), "ble", true).unwrap());
+    let default_nvs = Arc::new(Mutex::new(EspDefaultNvs::new( EspDefaultNvsPartition::take().unwrap(), "ble", true).unwrap()));
 
     let mut delay = delay::Ets {};
 
     delay.delay_us(100_u32);
 
-    let mut ble = EspBle::new("ESP32".into(), default_nvs).unwrap();
+    let mut ble = EspBle::new("ESP32".into(), 500, default_nvs).unwrap();
 
     let security_config = SecurityConfig {
         auth_req_mode: AuthenticationRequest::SecureMitmBonding,
@@ -41,8 +42,8 @@ fn main() {
         max_key_size: Some(16),
         only_accept_specified_auth: false,
         enable_oob: false,
-        responder_key: Some(KeyMask::IdentityResolvingKey | KeyMask::EncryptionKey),
-        initiator_key: Some(KeyMask::IdentityResolvingKey | KeyMask::EncryptionKey),
+        responder_key: Some(KeyMask::IDENTITY_RESOLVING_KEY | KeyMask::ENCRYPTION_KEY),
+        initiator_key: Some(KeyMask::IDENTITY_RESOLVING_KEY | KeyMask::ENCRYPTION_KEY),
         static_passkey: Some(123456),
         ..Default::default()
     };
@@ -69,7 +70,7 @@ fn main() {
 
     ble.register_connect_handler(gatts_if, move |_gatts_if, connect| {
         if let GattServiceEvent::Connect(connect) = connect {
-            info!("Connection from {:?}", connect.remote_bda);
+            info!("Connection from {}", connect.bda);
         }
     });
 
@@ -77,9 +78,9 @@ fn main() {
 
     ble.create_service(gatts_if, svc, move |gatts_if, create| {
 
-        if let GattServiceEvent::Create(esp_ble_gatts_cb_param_t_gatts_create_evt_param { status, service_handle, .. }) = create {
+        if let GattServiceEvent::Create(CreateServiceEvent { status, service_handle, .. }) = create {
             info!(
-                "Service created with {{ \tgatts_if: {gatts_if}\tstatus: {status}\n\thandle: {service_handle}\n}}"
+                "Service created with {{ \tgatts_if: {gatts_if}\tstatus: {status:?}\n\thandle: {service_handle}\n}}"
             );
             s.send(service_handle).expect("Unable to send value");
         }
@@ -89,11 +90,7 @@ fn main() {
     let svc_handle = r.recv().expect("Unable to receive value");
 
     ble.start_service(svc_handle, |_, start| {
-        if let GattServiceEvent::StartComplete(esp_ble_gatts_cb_param_t_gatts_start_evt_param {
-            service_handle,
-            ..
-        }) = start
-        {
+        if let GattServiceEvent::StartComplete(ServiceEvent { service_handle, .. }) = start {
             info!("Service started for handle: {service_handle}");
         }
     })
@@ -113,9 +110,9 @@ fn main() {
     let (s, r) = sync_channel(1);
 
     ble.add_characteristic(svc_handle, charac, move |_, add_char| {
-        if let GattServiceEvent::AddCharacteristicComplete(
-            esp_ble_gatts_cb_param_t_gatts_add_char_evt_param { attr_handle, .. },
-        ) = add_char
+        if let GattServiceEvent::AddCharacteristicComplete(AddAttributeEvent {
+            attr_handle, ..
+        }) = add_char
         {
             info!("Attr added with handle: {attr_handle}");
             s.send(attr_handle).expect("Unable to send value");
@@ -135,9 +132,9 @@ fn main() {
         ESP_GATT_PERM_READ as _,
     );
     ble.add_descriptor(svc_handle, cdesc, |_, add_desc| {
-        if let GattServiceEvent::AddDescriptorComplete(
-            esp_ble_gatts_cb_param_t_gatts_add_char_descr_evt_param { attr_handle, .. },
-        ) = add_desc
+        if let GattServiceEvent::AddDescriptorComplete(AddAttributeEvent {
+            attr_handle, ..
+        }) = add_desc
         {
             info!("Descriptor added with handle: {attr_handle}");
         }
@@ -162,23 +159,21 @@ fn main() {
 
     ble.register_write_handler(char_attr_handle, move |gatts_if, write| {
         if let GattServiceEvent::Write(write) = write {
-            if write.is_prep {
-                warn!("Unsupported write");
-            } else {
-                let value = unsafe { std::slice::from_raw_parts(write.value, write.len as usize) };
-                info!("Write event received for {char_attr_handle} with: {value:?}");
-
-                if write.need_rsp {
-                    esp_idf_ble::send(
-                        gatts_if,
-                        char_attr_handle,
-                        write.conn_id,
-                        write.trans_id,
-                        esp_gatt_status_t_ESP_GATT_OK,
-                        &[],
-                    )
-                    .expect("Unable to send response");
-                }
+            info!(
+                "Write event received for {char_attr_handle} with: {:?}",
+                write.value
+            );
+
+            if write.need_rsp {
+                esp_idf_ble::send(
+                    gatts_if,
+                    char_attr_handle,
+                    write.conn_id,
+                    write.trans_id,
+                    esp_gatt_status_t_ESP_GATT_OK,
+                    &[],
+                )
+                .expect("Unable to send response");
             }
         }
     });
@@ -218,7 +213,7 @@ fn main() {
     })
     .expect("Failed to configure advertising data");
 
-    ble.start_advertise(|_| {
+    ble.start_advertise(AdvertisingParameters::default(), |_| {
         info!("advertising started");
     })
     .expect("Failed to start advertising");
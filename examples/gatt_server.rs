@@ -1,12 +1,11 @@
 use std::error::Error;
-use std::sync::mpsc::sync_channel;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
 
 use esp_idf_ble::{
-    AdvertiseData, AttributeValue, AutoResponse, BtUuid, EspBle, GattCharacteristic,
-    GattDescriptor, GattService, GattServiceEvent, ServiceUuid,
+    AdvertiseData, AdvertisingParameters, AttributeValue, AutoResponse, BtUuid, EspBle,
+    GattCharacteristic, GattDescriptor, GattService, GattServiceEvent, ServiceUuid,
 };
 use esp_idf_hal::delay;
 // use esp_idf_hal::prelude::*;
@@ -28,30 +27,23 @@ fn main() {
     let netif_stack = Arc::new(EspNetif::new(NetifStack::Eth).expect("Unable to init Netif Stack"));
 
     #[allow(unused)]
-    let default_nvs = Arc::new(EspDefaultNvs::new( EspDefaultNvsPartition::take().unwrap(), "ble", true).unwrap());
+    let default_nvs = Arc::new(Mutex::new(EspDefaultNvs::new( EspDefaultNvsPartition::take().unwrap(), "ble", true).unwrap()));
 
     let mut delay = delay::Ets {};
 
     delay.delay_us(100_u32);
 
-    let ble = EspBle::new("ESP32".into(), default_nvs);
+    let ble = EspBle::new("ESP32".into(), 500, default_nvs);
 
     let mut ble = match ble {
         Ok(ble) => ble,
         Err(e) => {error!("{}: {:?}", e.to_string(), e.source()); panic!()},
     };
 
-    let (s, r) = sync_channel(1);
-
-    ble.register_gatt_service_application(1, move |gatts_if, reg| {
-        if let GattServiceEvent::Register(reg) = reg {
-            info!("Service registered with {:?}", reg);
-            s.send(gatts_if).expect("Unable to send result");
-        } else {
-            warn!("What are you doing here??");
-        }
-    })
-    .expect("Unable to register service");
+    let gatts_if = ble
+        .register_gatt_service_application_blocking(1, Duration::from_secs(1))
+        .expect("Unable to register service");
+    info!("Service registered with gatts_if: {}", gatts_if);
 
     let svc_uuid = BtUuid::Uuid16(ServiceUuid::Battery as u16);
 
@@ -59,35 +51,20 @@ fn main() {
 
     info!("GattService to be created: {:?}", svc);
 
-    let gatts_if = r.recv().expect("Unable to receive value");
-
-    let (s, r) = sync_channel(1);
-
     ble.register_connect_handler(gatts_if, |_gatts_if, connect| {
         if let GattServiceEvent::Connect(connect) = connect {
             info!("Connect event: {:?}", connect);
         }
     });
 
-    ble.create_service(gatts_if, svc, move |gatts_if, create| {
-        if let GattServiceEvent::Create(create) = create {
-            info!(
-                "Service created with {{ \tgatts_if: {}\tstatus: {}\n\thandle: {}\n}}",
-                gatts_if, create.status, create.service_handle
-            );
-            s.send(create.service_handle).expect("Unable to send value");
-        }
-    })
-    .expect("Unable to create service");
-
-    let svc_handle = r.recv().expect("Unable to receive value");
+    let svc_handle = ble
+        .create_service_blocking(gatts_if, svc, Duration::from_secs(1))
+        .expect("Unable to create service");
+    info!("Service created with handle: {}", svc_handle);
 
-    ble.start_service(svc_handle, |_, start| {
-        if let GattServiceEvent::StartComplete(start) = start {
-            info!("Service started for handle: {}", start.service_handle);
-        }
-    })
-    .expect("Unable to start ble service");
+    ble.start_service_blocking(svc_handle, Duration::from_secs(1))
+        .expect("Unable to start ble service");
+    info!("Service started for handle: {}", svc_handle);
 
     let attr_value: AttributeValue<12> = AttributeValue::new_with_value(&[
         0x48, 0x65, 0x6C, 0x6C, 0x6F, 0x20, 0x57, 0x6F, 0x72, 0x6C, 0x64,
@@ -100,17 +77,10 @@ fn main() {
         AutoResponse::ByApp,
     );
 
-    let (s, r) = sync_channel(1);
-
-    ble.add_characteristic(svc_handle, charac, move |_, add_char| {
-        if let GattServiceEvent::AddCharacteristicComplete(add_char) = add_char {
-            info!("Attr added with handle: {}", add_char.attr_handle);
-            s.send(add_char.attr_handle).expect("Unable to send value");
-        }
-    })
-    .expect("Unable to add characteristic");
-
-    let char_attr_handle = r.recv().expect("Unable to recv attr_handle");
+    let char_attr_handle = ble
+        .add_characteristic_blocking(svc_handle, charac, Duration::from_secs(1))
+        .expect("Unable to add characteristic");
+    info!("Attr added with handle: {}", char_attr_handle);
 
     let data = ble
         .read_attribute_value(char_attr_handle)
@@ -121,12 +91,10 @@ fn main() {
         BtUuid::Uuid16(ESP_GATT_UUID_CHAR_CLIENT_CONFIG as u16),
         ESP_GATT_PERM_READ as _,
     );
-    ble.add_descriptor(svc_handle, cdesc, |_, add_desc| {
-        if let GattServiceEvent::AddDescriptorComplete(add_desc) = add_desc {
-            info!("Descriptor added with handle: {}", add_desc.attr_handle);
-        }
-    })
-    .expect("Unable to add characteristic");
+    let desc_handle = ble
+        .add_descriptor_blocking(svc_handle, cdesc, Duration::from_secs(1))
+        .expect("Unable to add descriptor");
+    info!("Descriptor added with handle: {}", desc_handle);
 
     ble.register_read_handler(char_attr_handle, move |gatts_if, read| {
         let val = [0x48, 0x65, 0x6c, 0x6c, 0x6f];
@@ -146,30 +114,32 @@ fn main() {
 
     ble.register_write_handler(char_attr_handle, move |gatts_if, write| {
         if let GattServiceEvent::Write(write) = write {
-            if write.is_prep {
-                warn!("Unsupported write");
-            } else {
-                let value = unsafe { std::slice::from_raw_parts(write.value, write.len as usize) };
-                info!(
-                    "Write event received for {} with: {:?}",
-                    char_attr_handle, value
-                );
-
-                if write.need_rsp {
-                    esp_idf_ble::send(
-                        gatts_if,
-                        char_attr_handle,
-                        write.conn_id,
-                        write.trans_id,
-                        esp_gatt_status_t_ESP_GATT_OK,
-                        &[],
-                    )
-                    .expect("Unable to send response");
-                }
+            info!(
+                "Write event received for {} with: {:?}",
+                char_attr_handle, write.value
+            );
+
+            if write.need_rsp {
+                esp_idf_ble::send(
+                    gatts_if,
+                    char_attr_handle,
+                    write.conn_id,
+                    write.trans_id,
+                    esp_gatt_status_t_ESP_GATT_OK,
+                    &[],
+                )
+                .expect("Unable to send response");
             }
         }
     });
 
+    ble.register_subscription_handler(|subscription| {
+        info!(
+            "Connection {} subscribed to {}: {:?}",
+            subscription.conn_id, subscription.handle, subscription.configuration
+        );
+    });
+
     let adv_data = AdvertiseData {
         include_name: true,
         include_txpower: false,
@@ -203,7 +173,7 @@ fn main() {
     })
     .expect("Failed to configure advertising data");
 
-    ble.start_advertise(|_| {
+    ble.start_advertise(AdvertisingParameters::default(), |_| {
         info!("advertising started");
     })
     .expect("Failed to start advertising");
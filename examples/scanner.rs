@@ -0,0 +1,36 @@
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use esp_idf_ble::{EspBle, ScanParameters};
+use esp_idf_svc::nvs::{EspDefaultNvs, EspDefaultNvsPartition};
+
+use log::*;
+
+fn main() {
+    esp_idf_sys::link_patches();
+
+    // Bind the log crate to the ESP Logging facilities
+    esp_idf_svc::log::EspLogger::initialize_default();
+
+    #[allow(unused)]
+    let default_nvs =
+        Arc::new(Mutex::new(EspDefaultNvs::new(EspDefaultNvsPartition::take().unwrap(), "ble", true).unwrap()));
+
+    let ble = EspBle::new("ESP32".into(), 500, default_nvs).unwrap();
+
+    ble.start_scan(ScanParameters::default(), 30, |result| {
+        info!(
+            "Found {} ({:?}) rssi: {} name: {:?}",
+            result.bda,
+            result.event_type,
+            result.rssi,
+            result.name()
+        );
+    })
+    .expect("Unable to start scanning");
+
+    loop {
+        thread::sleep(Duration::from_millis(500));
+    }
+}
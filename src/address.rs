@@ -0,0 +1,91 @@
+#[cfg(target_os = "espidf")]
+use esp_idf_sys::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AddressType {
+    Public,
+    Random,
+    RpaPublic,
+    RpaRandom,
+}
+
+#[cfg(target_os = "espidf")]
+impl From<esp_ble_addr_type_t> for AddressType {
+    #[allow(non_upper_case_globals)]
+    fn from(addr_type: esp_ble_addr_type_t) -> Self {
+        match addr_type {
+            esp_ble_addr_type_t_BLE_ADDR_TYPE_RANDOM => AddressType::Random,
+            esp_ble_addr_type_t_BLE_ADDR_TYPE_RPA_PUBLIC => AddressType::RpaPublic,
+            esp_ble_addr_type_t_BLE_ADDR_TYPE_RPA_RANDOM => AddressType::RpaRandom,
+            _ => AddressType::Public,
+        }
+    }
+}
+
+#[cfg(target_os = "espidf")]
+impl From<AddressType> for esp_ble_addr_type_t {
+    fn from(addr_type: AddressType) -> Self {
+        match addr_type {
+            AddressType::Public => esp_ble_addr_type_t_BLE_ADDR_TYPE_PUBLIC,
+            AddressType::Random => esp_ble_addr_type_t_BLE_ADDR_TYPE_RANDOM,
+            AddressType::RpaPublic => esp_ble_addr_type_t_BLE_ADDR_TYPE_RPA_PUBLIC,
+            AddressType::RpaRandom => esp_ble_addr_type_t_BLE_ADDR_TYPE_RPA_RANDOM,
+        }
+    }
+}
+
+/// A Bluetooth device address, in the byte order used by Bluedroid which is
+/// also the order it is displayed in.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct BdAddr(pub [u8; 6]);
+
+impl BdAddr {
+    pub fn new(addr: [u8; 6]) -> Self {
+        Self(addr)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 6] {
+        &self.0
+    }
+}
+
+impl From<[u8; 6]> for BdAddr {
+    fn from(addr: [u8; 6]) -> Self {
+        Self(addr)
+    }
+}
+
+impl From<BdAddr> for [u8; 6] {
+    fn from(addr: BdAddr) -> Self {
+        addr.0
+    }
+}
+
+impl std::fmt::Display for BdAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(
+            f,
+            "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            a, b, c, d, e, g
+        )
+    }
+}
+
+impl std::fmt::Debug for BdAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "BdAddr({})", self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_bd_addr() {
+        let addr = BdAddr::new([0x24, 0x0a, 0xc4, 0x00, 0x1b, 0xfe]);
+        assert_eq!(addr.to_string(), "24:0a:c4:00:1b:fe");
+        assert_eq!(format!("{:?}", addr), "BdAddr(24:0a:c4:00:1b:fe)");
+    }
+}
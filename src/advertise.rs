@@ -1,9 +1,11 @@
+use crate::AddressType;
 use crate::BtUuid;
+#[cfg(target_os = "espidf")]
 use esp_idf_sys::*;
 
 #[allow(clippy::upper_case_acronyms)]
 #[repr(u16)]
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum AppearanceCategory {
     Unknown = 0x00,
     Phone,
@@ -58,39 +60,225 @@ pub enum AppearanceCategory {
     OutdoorSportsActivity = 0x51,
 }
 
+impl AppearanceCategory {
+    const ALL: [AppearanceCategory; 51] = [
+        AppearanceCategory::Unknown,
+        AppearanceCategory::Phone,
+        AppearanceCategory::Computer,
+        AppearanceCategory::Watch,
+        AppearanceCategory::Clock,
+        AppearanceCategory::Display,
+        AppearanceCategory::RemoteControl,
+        AppearanceCategory::EyeGlass,
+        AppearanceCategory::Tag,
+        AppearanceCategory::Keyring,
+        AppearanceCategory::MediaPlayer,
+        AppearanceCategory::BarcodeScanner,
+        AppearanceCategory::Thermometer,
+        AppearanceCategory::HeartRateSensor,
+        AppearanceCategory::BloodPressure,
+        AppearanceCategory::HumanInterfaceDevice,
+        AppearanceCategory::GlucoseMeter,
+        AppearanceCategory::RunningWalkingSensor,
+        AppearanceCategory::Cycling,
+        AppearanceCategory::ControlDevice,
+        AppearanceCategory::NetworkDevice,
+        AppearanceCategory::Sensor,
+        AppearanceCategory::LightFixtures,
+        AppearanceCategory::Fan,
+        AppearanceCategory::HVAC,
+        AppearanceCategory::AirConditionning,
+        AppearanceCategory::Humidifier,
+        AppearanceCategory::Heating,
+        AppearanceCategory::AccessControl,
+        AppearanceCategory::MotorizedDevice,
+        AppearanceCategory::PowerDevice,
+        AppearanceCategory::LightSource,
+        AppearanceCategory::WindowCovering,
+        AppearanceCategory::AudioSink,
+        AppearanceCategory::AudioSource,
+        AppearanceCategory::MotorizedVehicle,
+        AppearanceCategory::DomesticAppliance,
+        AppearanceCategory::WearableAudioDevice,
+        AppearanceCategory::Aircraft,
+        AppearanceCategory::AVEquipment,
+        AppearanceCategory::DisplayEquipment,
+        AppearanceCategory::HearingAid,
+        AppearanceCategory::Gaming,
+        AppearanceCategory::Signage,
+        AppearanceCategory::PulseOximeter,
+        AppearanceCategory::WeightScale,
+        AppearanceCategory::PersonalMobilityDevice,
+        AppearanceCategory::ContinuousGlucoseMonitor,
+        AppearanceCategory::InsulinPump,
+        AppearanceCategory::MedicationDelivery,
+        AppearanceCategory::OutdoorSportsActivity,
+    ];
+
+    /// Find the category of a 16-bit appearance value, the sub-category bits are ignored.
+    pub fn from_appearance(appearance: u16) -> Option<Self> {
+        let category = appearance >> 6;
+        Self::ALL.into_iter().find(|cat| *cat as u16 == category)
+    }
+}
+
 impl From<AppearanceCategory> for i32 {
     fn from(cat: AppearanceCategory) -> Self {
         ((cat as u16) << 6) as _
     }
 }
 
+/// Maximum size of a legacy advertising or scan response payload
+pub const ADV_DATA_MAX_LEN: usize = 31;
+
+/// Maximum size of an encoded AD structure, its length is stored in one byte
+pub const AD_STRUCTURE_MAX_LEN: usize = 256;
+
+#[derive(Clone, Debug)]
 pub struct RawAdvertiseData {
     data: Vec<AdvertiseType>,
+    raw: Vec<u8>,
     pub(crate) set_scan_rsp: bool,
 }
 
 impl RawAdvertiseData {
+    /// Build the payload without checking its size, see `RawAdvertiseDataBuilder`
+    /// for a checked alternative.
     pub fn new(data: Vec<AdvertiseType>, set_scan_rsp: bool) -> Self {
-        RawAdvertiseData { data, set_scan_rsp }
-    }
-
-    pub fn as_raw_data(&self) -> (*mut u8, u32) {
-        let mut v: Vec<u8> = self
-            .data
+        let raw = data
             .iter()
             .flat_map(|v: &AdvertiseType| {
                 let v: Vec<u8> = v.into();
                 v
             })
             .collect();
-        v.shrink_to(31);
-        let v = v.as_mut_slice();
-        log::info!("Adv data({}): {{ {:?} }}", v.len(), &v);
-        (v.as_mut_ptr(), v.len() as u32)
+        RawAdvertiseData {
+            data,
+            raw,
+            set_scan_rsp,
+        }
+    }
+
+    pub fn builder() -> RawAdvertiseDataBuilder {
+        RawAdvertiseDataBuilder::default()
+    }
+
+    pub fn data(&self) -> &[AdvertiseType] {
+        &self.data
+    }
+
+    /// Encoded length of the payload.
+    pub fn len(&self) -> usize {
+        self.raw.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.raw.is_empty()
+    }
+
+    /// The encoded AD structures, as they are sent over the air.
+    pub fn to_vec(&self) -> Vec<u8> {
+        self.raw.clone()
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AdvertiseDataError {
+    /// `field` needs `len` bytes while only `available` bytes are left in the payload
+    TooLong {
+        field: AdvertiseType,
+        len: usize,
+        available: usize,
+    },
+    /// `field` is encoded on `len` bytes, more than `AD_STRUCTURE_MAX_LEN`
+    FieldTooLong { field: AdvertiseType, len: usize },
+}
+
+impl std::fmt::Display for AdvertiseDataError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AdvertiseDataError::TooLong {
+                field,
+                len,
+                available,
+            } => write!(
+                f,
+                "{:?} needs {} bytes but only {} are available",
+                field, len, available
+            ),
+            AdvertiseDataError::FieldTooLong { field, len } => write!(
+                f,
+                "{:?} needs {} bytes but an AD structure is at most {} bytes long",
+                field, len, AD_STRUCTURE_MAX_LEN
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AdvertiseDataError {}
+
+/// Build advertising payloads which fit in the legacy advertising PDU.
+///
+/// When `overflow_to_scan_response` is enabled, the fields which do not fit in
+/// the advertising data are put in the scan response instead.
+#[derive(Default)]
+pub struct RawAdvertiseDataBuilder {
+    data: Vec<AdvertiseType>,
+    overflow_to_scan_rsp: bool,
+}
+
+impl RawAdvertiseDataBuilder {
+    pub fn field(mut self, field: AdvertiseType) -> Self {
+        self.data.push(field);
+        self
+    }
+
+    pub fn overflow_to_scan_response(mut self, enable: bool) -> Self {
+        self.overflow_to_scan_rsp = enable;
+        self
+    }
+
+    /// Returns the advertising data and, if any field overflowed into it, the scan response.
+    pub fn build(self) -> Result<(RawAdvertiseData, Option<RawAdvertiseData>), AdvertiseDataError> {
+        let mut adv = vec![];
+        let mut adv_len = 0;
+        let mut scan_rsp = vec![];
+        let mut scan_rsp_len = 0;
+
+        for field in self.data {
+            let len = field.encoded_len();
+            if adv_len + len <= ADV_DATA_MAX_LEN {
+                adv_len += len;
+                adv.push(field);
+            } else if self.overflow_to_scan_rsp && scan_rsp_len + len <= ADV_DATA_MAX_LEN {
+                scan_rsp_len += len;
+                scan_rsp.push(field);
+            } else {
+                let available = if self.overflow_to_scan_rsp {
+                    ADV_DATA_MAX_LEN - scan_rsp_len
+                } else {
+                    ADV_DATA_MAX_LEN - adv_len
+                };
+                return Err(AdvertiseDataError::TooLong {
+                    field,
+                    len,
+                    available,
+                });
+            }
+        }
+
+        Ok((
+            RawAdvertiseData::new(adv, false),
+            if scan_rsp.is_empty() {
+                None
+            } else {
+                Some(RawAdvertiseData::new(scan_rsp, true))
+            },
+        ))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum AdvertiseType {
     Flags(u8),
     ServicePartial16(Vec<u16>),
@@ -102,78 +290,584 @@ pub enum AdvertiseType {
     IntervalRange(u16, u16),
     DeviceNameShort(String),
     DeviceNameComplete(String),
+    /// Appearance category, an appearance with a sub-category is parsed as `Other`
     Appearance(AppearanceCategory),
-    TxPower(u8),
+    /// TX power level in dBm
+    TxPower(i8),
+    /// Company identifier followed by the manufacturer specific data
+    ManufacturerData(u16, Vec<u8>),
+    ServiceData16(u16, Vec<u8>),
+    ServiceData32(u32, Vec<u8>),
+    ServiceData128([u8; 16], Vec<u8>),
+    /// URI whose scheme is encoded with its code point from the Bluetooth
+    /// assigned numbers, e.g. `"\u{17}//example.com"` for `https://example.com`
+    Uri(String),
+    LeRole(LeRole),
+    /// Public addresses, in the same byte order as `esp_bd_addr_t`
+    PublicTargetAddress(Vec<[u8; 6]>),
+    /// Advertising interval in 0.625ms units
+    AdvertisingInterval(u16),
+    /// Any AD type which is not decoded, with its raw data
+    Other(u8, Vec<u8>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LeRole {
+    PeripheralOnly,
+    CentralOnly,
+    PeripheralPreferred,
+    CentralPreferred,
+}
+
+impl From<LeRole> for u8 {
+    fn from(role: LeRole) -> Self {
+        match role {
+            LeRole::PeripheralOnly => 0x00,
+            LeRole::CentralOnly => 0x01,
+            LeRole::PeripheralPreferred => 0x02,
+            LeRole::CentralPreferred => 0x03,
+        }
+    }
+}
+
+impl LeRole {
+    pub fn from_u8(role: u8) -> Option<Self> {
+        match role {
+            0x00 => Some(LeRole::PeripheralOnly),
+            0x01 => Some(LeRole::CentralOnly),
+            0x02 => Some(LeRole::PeripheralPreferred),
+            0x03 => Some(LeRole::CentralPreferred),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AdParseError {
+    /// The length field at `offset` announces more bytes than available
+    Truncated { offset: usize, len: usize },
+    /// The data of `ad_type` does not have a valid length for this type
+    InvalidLength { ad_type: u8, len: usize },
+    /// The device name is not valid UTF-8
+    InvalidName,
+    /// The URI is not valid UTF-8
+    InvalidUri,
+}
+
+impl std::fmt::Display for AdParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AdParseError::Truncated { offset, len } => write!(
+                f,
+                "AD structure at offset {} announces {} bytes past the end of the data",
+                offset, len
+            ),
+            AdParseError::InvalidLength { ad_type, len } => {
+                write!(f, "Invalid length {} for AD type {:#04x}", len, ad_type)
+            }
+            AdParseError::InvalidName => write!(f, "Device name is not valid UTF-8"),
+            AdParseError::InvalidUri => write!(f, "URI is not valid UTF-8"),
+        }
+    }
+}
+
+impl std::error::Error for AdParseError {}
+
+impl AdvertiseType {
+    /// Length of the encoded AD structure, including its length and type bytes.
+    pub fn encoded_len(&self) -> usize {
+        2 + match self {
+            AdvertiseType::Flags(_) => 1,
+            AdvertiseType::ServicePartial16(svc) | AdvertiseType::ServiceComplete16(svc) => {
+                svc.len() * 2
+            }
+            AdvertiseType::ServicePartial32(svc) | AdvertiseType::ServiceComplete32(svc) => {
+                svc.len() * 4
+            }
+            AdvertiseType::ServicePartial128(svc) | AdvertiseType::ServiceComplete128(svc) => {
+                svc.len() * 16
+            }
+            AdvertiseType::IntervalRange(_, _) => 4,
+            AdvertiseType::DeviceNameShort(name) | AdvertiseType::DeviceNameComplete(name) => {
+                name.len()
+            }
+            AdvertiseType::Appearance(_) => 2,
+            AdvertiseType::TxPower(_) => 1,
+            AdvertiseType::ManufacturerData(_, data) | AdvertiseType::ServiceData16(_, data) => {
+                2 + data.len()
+            }
+            AdvertiseType::ServiceData32(_, data) => 4 + data.len(),
+            AdvertiseType::ServiceData128(_, data) => 16 + data.len(),
+            AdvertiseType::Uri(uri) => uri.len(),
+            AdvertiseType::LeRole(_) => 1,
+            AdvertiseType::PublicTargetAddress(addrs) => addrs.len() * 6,
+            AdvertiseType::AdvertisingInterval(_) => 2,
+            AdvertiseType::Other(_, data) => data.len(),
+        }
+    }
+
+    /// Encode `fields` into a payload of at most `max_len` bytes, e.g.
+    /// `EXT_ADV_DATA_MAX_LEN` for extended advertising.
+    pub fn encode_all(
+        fields: &[AdvertiseType],
+        max_len: usize,
+    ) -> Result<Vec<u8>, AdvertiseDataError> {
+        let mut data = vec![];
+        for field in fields {
+            let len = field.encoded_len();
+            if len > AD_STRUCTURE_MAX_LEN {
+                return Err(AdvertiseDataError::FieldTooLong {
+                    field: field.clone(),
+                    len,
+                });
+            }
+            if data.len() + len > max_len {
+                return Err(AdvertiseDataError::TooLong {
+                    field: field.clone(),
+                    len,
+                    available: max_len - data.len(),
+                });
+            }
+            data.append(&mut field.into());
+        }
+        Ok(data)
+    }
+
+    /// Decode a single AD structure from its type and data (without the length and type bytes).
+    pub fn parse(ad_type: u8, data: &[u8]) -> Result<AdvertiseType, AdParseError> {
+        let invalid_length = || AdParseError::InvalidLength {
+            ad_type,
+            len: data.len(),
+        };
+        let name = || String::from_utf8(data.to_vec()).map_err(|_| AdParseError::InvalidName);
+
+        Ok(match ad_type {
+            0x01 => {
+                if data.len() != 1 {
+                    return Err(invalid_length());
+                }
+                AdvertiseType::Flags(data[0])
+            }
+            0x02 | 0x03 => {
+                let chunks = data.chunks_exact(2);
+                if !chunks.remainder().is_empty() {
+                    return Err(invalid_length());
+                }
+                let uuids = chunks
+                    .map(|uuid| u16::from_le_bytes([uuid[0], uuid[1]]))
+                    .collect();
+                if ad_type == 0x02 {
+                    AdvertiseType::ServicePartial16(uuids)
+                } else {
+                    AdvertiseType::ServiceComplete16(uuids)
+                }
+            }
+            0x04 | 0x05 => {
+                let chunks = data.chunks_exact(4);
+                if !chunks.remainder().is_empty() {
+                    return Err(invalid_length());
+                }
+                let uuids = chunks
+                    .map(|uuid| u32::from_le_bytes([uuid[0], uuid[1], uuid[2], uuid[3]]))
+                    .collect();
+                if ad_type == 0x04 {
+                    AdvertiseType::ServicePartial32(uuids)
+                } else {
+                    AdvertiseType::ServiceComplete32(uuids)
+                }
+            }
+            0x06 | 0x07 => {
+                let chunks = data.chunks_exact(16);
+                if !chunks.remainder().is_empty() {
+                    return Err(invalid_length());
+                }
+                let uuids = chunks
+                    .map(|uuid| {
+                        let mut v = [0; 16];
+                        v.copy_from_slice(uuid);
+                        v
+                    })
+                    .collect();
+                if ad_type == 0x06 {
+                    AdvertiseType::ServicePartial128(uuids)
+                } else {
+                    AdvertiseType::ServiceComplete128(uuids)
+                }
+            }
+            0x08 => AdvertiseType::DeviceNameShort(name()?),
+            0x09 => AdvertiseType::DeviceNameComplete(name()?),
+            0x0a => {
+                if data.len() != 1 {
+                    return Err(invalid_length());
+                }
+                AdvertiseType::TxPower(data[0] as i8)
+            }
+            0x12 => {
+                if data.len() != 4 {
+                    return Err(invalid_length());
+                }
+                AdvertiseType::IntervalRange(
+                    u16::from_le_bytes([data[0], data[1]]),
+                    u16::from_le_bytes([data[2], data[3]]),
+                )
+            }
+            0x19 => {
+                if data.len() != 2 {
+                    return Err(invalid_length());
+                }
+                // `Appearance` only holds a category, an appearance with a
+                // sub-category is kept raw to be encoded back as is.
+                let appearance = u16::from_le_bytes([data[0], data[1]]);
+                match AppearanceCategory::from_appearance(appearance) {
+                    Some(cat) if appearance & 0x3f == 0 => AdvertiseType::Appearance(cat),
+                    _ => AdvertiseType::Other(ad_type, data.to_vec()),
+                }
+            }
+            0x16 => {
+                if data.len() < 2 {
+                    return Err(invalid_length());
+                }
+                AdvertiseType::ServiceData16(
+                    u16::from_le_bytes([data[0], data[1]]),
+                    data[2..].to_vec(),
+                )
+            }
+            0x17 => {
+                let chunks = data.chunks_exact(6);
+                if !chunks.remainder().is_empty() {
+                    return Err(invalid_length());
+                }
+                let addrs = chunks
+                    .map(|addr| {
+                        let mut v = [0; 6];
+                        v.copy_from_slice(addr);
+                        v.reverse();
+                        v
+                    })
+                    .collect();
+                AdvertiseType::PublicTargetAddress(addrs)
+            }
+            0x1a => {
+                if data.len() != 2 {
+                    return Err(invalid_length());
+                }
+                AdvertiseType::AdvertisingInterval(u16::from_le_bytes([data[0], data[1]]))
+            }
+            0x1c => {
+                if data.len() != 1 {
+                    return Err(invalid_length());
+                }
+                match LeRole::from_u8(data[0]) {
+                    Some(role) => AdvertiseType::LeRole(role),
+                    None => AdvertiseType::Other(ad_type, data.to_vec()),
+                }
+            }
+            0x20 => {
+                if data.len() < 4 {
+                    return Err(invalid_length());
+                }
+                AdvertiseType::ServiceData32(
+                    u32::from_le_bytes([data[0], data[1], data[2], data[3]]),
+                    data[4..].to_vec(),
+                )
+            }
+            0x21 => {
+                if data.len() < 16 {
+                    return Err(invalid_length());
+                }
+                let mut uuid = [0; 16];
+                uuid.copy_from_slice(&data[..16]);
+                AdvertiseType::ServiceData128(uuid, data[16..].to_vec())
+            }
+            0x24 => AdvertiseType::Uri(
+                String::from_utf8(data.to_vec()).map_err(|_| AdParseError::InvalidUri)?,
+            ),
+            0xff => {
+                if data.len() < 2 {
+                    return Err(invalid_length());
+                }
+                AdvertiseType::ManufacturerData(
+                    u16::from_le_bytes([data[0], data[1]]),
+                    data[2..].to_vec(),
+                )
+            }
+            _ => AdvertiseType::Other(ad_type, data.to_vec()),
+        })
+    }
+
+    /// Decode a whole advertising or scan response payload.
+    ///
+    /// Parsing stops at the first zero length field, which marks the
+    /// end of the significant part of the payload.
+    pub fn parse_all(data: &[u8]) -> Result<Vec<AdvertiseType>, AdParseError> {
+        let mut result = vec![];
+        let mut offset = 0;
+
+        while offset < data.len() {
+            let len = data[offset] as usize;
+            if len == 0 {
+                break;
+            }
+            if offset + 1 + len > data.len() {
+                return Err(AdParseError::Truncated { offset, len });
+            }
+            let ad_type = data[offset + 1];
+            result.push(AdvertiseType::parse(
+                ad_type,
+                &data[offset + 2..offset + 1 + len],
+            )?);
+            offset += 1 + len;
+        }
+
+        Ok(result)
+    }
 }
 
 impl From<&AdvertiseType> for Vec<u8> {
     fn from(adv: &AdvertiseType) -> Self {
-        match adv {
-            AdvertiseType::Flags(flag) => vec![0x02, 0x01, *flag],
+        let (ad_type, mut payload): (u8, Vec<u8>) = match adv {
+            AdvertiseType::Flags(flag) => (0x01, vec![*flag]),
             AdvertiseType::ServicePartial16(svc) => {
-                let l = svc.len() * 2 + 1;
-                let mut v = vec![l as u8, 0x02];
-                v.append(&mut svc.iter().flat_map(|svc| svc.to_be_bytes()).collect());
-                v
+                (0x02, svc.iter().flat_map(|svc| svc.to_le_bytes()).collect())
             }
             AdvertiseType::ServiceComplete16(svc) => {
-                let l = svc.len() * 2 + 1;
-                let mut v = vec![l as u8, 0x03];
-                v.append(&mut svc.iter().flat_map(|svc| svc.to_be_bytes()).collect());
-                v
+                (0x03, svc.iter().flat_map(|svc| svc.to_le_bytes()).collect())
             }
             AdvertiseType::ServicePartial32(svc) => {
-                let l = svc.len() * 4 + 1;
-                let mut v = vec![l as u8, 0x04];
-                v.append(&mut svc.iter().flat_map(|svc| svc.to_be_bytes()).collect());
-                v
+                (0x04, svc.iter().flat_map(|svc| svc.to_le_bytes()).collect())
             }
             AdvertiseType::ServiceComplete32(svc) => {
-                let l = svc.len() * 4 + 1;
-                let mut v = vec![l as u8, 0x05];
-                v.append(&mut svc.iter().flat_map(|svc| svc.to_be_bytes()).collect());
-                v
-            }
-            AdvertiseType::ServicePartial128(svc) => {
-                let l = svc.len() * 16 + 1;
-                let mut v = vec![l as u8, 0x06];
-                v.append(&mut svc.iter().flat_map(|svc| *svc).collect());
-                v
-            }
-            AdvertiseType::ServiceComplete128(svc) => {
-                let l = svc.len() * 16 + 1;
-                let mut v = vec![l as u8, 0x07];
-                v.append(&mut svc.iter().flat_map(|svc| *svc).collect());
-                v
+                (0x05, svc.iter().flat_map(|svc| svc.to_le_bytes()).collect())
             }
+            AdvertiseType::ServicePartial128(svc) => (0x06, svc.concat()),
+            AdvertiseType::ServiceComplete128(svc) => (0x07, svc.concat()),
+            AdvertiseType::DeviceNameShort(name) => (0x08, name.as_bytes().to_vec()),
+            AdvertiseType::DeviceNameComplete(name) => (0x09, name.as_bytes().to_vec()),
+            AdvertiseType::TxPower(pow) => (0x0a, vec![*pow as u8]),
             AdvertiseType::IntervalRange(min, max) => {
-                let mut v = vec![0x06, 0x12];
-                v.append(&mut min.to_be_bytes().to_vec());
-                v.append(&mut max.to_be_bytes().to_vec());
-                v.append(&mut vec![0x00]);
-                v
+                (0x12, [min.to_le_bytes(), max.to_le_bytes()].concat())
             }
-            AdvertiseType::DeviceNameShort(name) => {
-                let mut v = vec![(name.len() + 1) as u8, 0x08];
-                v.append(&mut name.as_bytes().to_vec());
-                v
-            }
-            AdvertiseType::DeviceNameComplete(name) => {
-                let mut v = vec![(name.len() + 1) as u8, 0x09];
-                v.append(&mut name.as_bytes().to_vec());
-                v
+            AdvertiseType::ServiceData16(uuid, data) => {
+                (0x16, [&uuid.to_le_bytes()[..], data].concat())
             }
+            AdvertiseType::PublicTargetAddress(addrs) => (
+                0x17,
+                addrs
+                    .iter()
+                    .flat_map(|addr| addr.iter().rev().copied())
+                    .collect(),
+            ),
             AdvertiseType::Appearance(cat) => {
                 let cat: i32 = (*cat).into();
-                vec![0x02, 0x19, cat as u8]
+                (0x19, (cat as u16).to_le_bytes().to_vec())
             }
-            AdvertiseType::TxPower(_pow) => {
-                vec![0x03, 0x0a, 0x09]
+            AdvertiseType::AdvertisingInterval(interval) => (0x1a, interval.to_le_bytes().to_vec()),
+            AdvertiseType::LeRole(role) => (0x1c, vec![(*role).into()]),
+            AdvertiseType::ServiceData32(uuid, data) => {
+                (0x20, [&uuid.to_le_bytes()[..], data].concat())
             }
+            AdvertiseType::ServiceData128(uuid, data) => (0x21, [&uuid[..], data].concat()),
+            AdvertiseType::Uri(uri) => (0x24, uri.as_bytes().to_vec()),
+            AdvertiseType::ManufacturerData(company_id, data) => {
+                (0xff, [&company_id.to_le_bytes()[..], data].concat())
+            }
+            AdvertiseType::Other(ad_type, data) => (*ad_type, data.clone()),
+        };
+
+        let mut v = vec![(payload.len() + 1) as u8, ad_type];
+        v.append(&mut payload);
+        v
+    }
+}
+
+/// Advertising channel 37
+pub const ADV_CHANNEL_37: u8 = 0x01;
+/// Advertising channel 38
+pub const ADV_CHANNEL_38: u8 = 0x02;
+/// Advertising channel 39
+pub const ADV_CHANNEL_39: u8 = 0x04;
+pub const ADV_CHANNEL_ALL: u8 = ADV_CHANNEL_37 | ADV_CHANNEL_38 | ADV_CHANNEL_39;
+
+/// Bounds of the advertising interval, in 0.625ms units
+pub const ADV_INTERVAL_MIN: u16 = 0x0020;
+pub const ADV_INTERVAL_MAX: u16 = 0x4000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AdvertisingType {
+    ConnectableUndirected,
+    /// Directed to `peer_addr`, the interval is ignored
+    ConnectableDirectedHighDuty,
+    Scannable,
+    NonConnectable,
+    /// Directed to `peer_addr`
+    ConnectableDirectedLowDuty,
+}
+
+impl AdvertisingType {
+    pub fn is_directed(&self) -> bool {
+        matches!(
+            self,
+            AdvertisingType::ConnectableDirectedHighDuty
+                | AdvertisingType::ConnectableDirectedLowDuty
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AdvertisingFilterPolicy {
+    AllowAll,
+    /// Only answer scan requests from devices in the white list
+    ScanWhitelist,
+    /// Only accept connection requests from devices in the white list
+    ConnectWhitelist,
+    ScanAndConnectWhitelist,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AdvertisingParameters {
+    /// Minimum advertising interval in 0.625ms units (0x0020 - 0x4000)
+    pub interval_min: u16,
+    /// Maximum advertising interval in 0.625ms units, must not be lower than `interval_min`
+    pub interval_max: u16,
+    pub adv_type: AdvertisingType,
+    pub own_addr_type: AddressType,
+    /// Peer to advertise to, required by the directed advertising types
+    pub peer_addr: Option<([u8; 6], AddressType)>,
+    /// Combination of the `ADV_CHANNEL_*` bits
+    pub channel_map: u8,
+    pub filter_policy: AdvertisingFilterPolicy,
+}
+
+impl Default for AdvertisingParameters {
+    fn default() -> Self {
+        Self {
+            interval_min: 0x20,
+            interval_max: 0x40,
+            adv_type: AdvertisingType::ConnectableUndirected,
+            own_addr_type: AddressType::Public,
+            peer_addr: None,
+            channel_map: ADV_CHANNEL_ALL,
+            filter_policy: AdvertisingFilterPolicy::AllowAll,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AdvertisingParametersError {
+    /// The interval is outside of `ADV_INTERVAL_MIN..=ADV_INTERVAL_MAX`
+    IntervalOutOfRange(u16),
+    /// `interval_min` is greater than `interval_max`
+    InvalidIntervalRange { min: u16, max: u16 },
+    /// Directed advertising without a peer address
+    MissingPeerAddress,
+    /// The channel map is empty or has bits other than `ADV_CHANNEL_ALL`
+    InvalidChannelMap(u8),
+}
+
+impl std::fmt::Display for AdvertisingParametersError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AdvertisingParametersError::IntervalOutOfRange(interval) => write!(
+                f,
+                "Advertising interval {:#06x} is not in {:#06x}..={:#06x}",
+                interval, ADV_INTERVAL_MIN, ADV_INTERVAL_MAX
+            ),
+            AdvertisingParametersError::InvalidIntervalRange { min, max } => write!(
+                f,
+                "Minimum advertising interval {:#06x} is greater than the maximum {:#06x}",
+                min, max
+            ),
+            AdvertisingParametersError::MissingPeerAddress => {
+                write!(f, "Directed advertising requires a peer address")
+            }
+            AdvertisingParametersError::InvalidChannelMap(map) => {
+                write!(f, "Invalid advertising channel map {:#04x}", map)
+            }
+        }
+    }
+}
+
+impl std::error::Error for AdvertisingParametersError {}
+
+impl AdvertisingParameters {
+    pub fn validate(&self) -> Result<(), AdvertisingParametersError> {
+        // High duty cycle directed advertising does not use the interval
+        if self.adv_type != AdvertisingType::ConnectableDirectedHighDuty {
+            for interval in [self.interval_min, self.interval_max] {
+                if !(ADV_INTERVAL_MIN..=ADV_INTERVAL_MAX).contains(&interval) {
+                    return Err(AdvertisingParametersError::IntervalOutOfRange(interval));
+                }
+            }
+            if self.interval_min > self.interval_max {
+                return Err(AdvertisingParametersError::InvalidIntervalRange {
+                    min: self.interval_min,
+                    max: self.interval_max,
+                });
+            }
+        }
+
+        if self.adv_type.is_directed() && self.peer_addr.is_none() {
+            return Err(AdvertisingParametersError::MissingPeerAddress);
+        }
+
+        if self.channel_map == 0 || self.channel_map & !ADV_CHANNEL_ALL != 0 {
+            return Err(AdvertisingParametersError::InvalidChannelMap(
+                self.channel_map,
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "espidf")]
+impl From<AdvertisingFilterPolicy> for esp_ble_adv_filter_t {
+    fn from(policy: AdvertisingFilterPolicy) -> Self {
+        match policy {
+            AdvertisingFilterPolicy::AllowAll => {
+                esp_ble_adv_filter_t_ADV_FILTER_ALLOW_SCAN_ANY_CON_ANY
+            }
+            AdvertisingFilterPolicy::ScanWhitelist => {
+                esp_ble_adv_filter_t_ADV_FILTER_ALLOW_SCAN_WLST_CON_ANY
+            }
+            AdvertisingFilterPolicy::ConnectWhitelist => {
+                esp_ble_adv_filter_t_ADV_FILTER_ALLOW_SCAN_ANY_CON_WLST
+            }
+            AdvertisingFilterPolicy::ScanAndConnectWhitelist => {
+                esp_ble_adv_filter_t_ADV_FILTER_ALLOW_SCAN_WLST_CON_WLST
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "espidf")]
+impl From<AdvertisingParameters> for esp_ble_adv_params_t {
+    fn from(params: AdvertisingParameters) -> Self {
+        let (peer_addr, peer_addr_type) = params.peer_addr.unwrap_or(([0; 6], AddressType::Public));
+        Self {
+            adv_int_min: params.interval_min,
+            adv_int_max: params.interval_max,
+            adv_type: match params.adv_type {
+                AdvertisingType::ConnectableUndirected => esp_ble_adv_type_t_ADV_TYPE_IND,
+                AdvertisingType::ConnectableDirectedHighDuty => {
+                    esp_ble_adv_type_t_ADV_TYPE_DIRECT_IND_HIGH
+                }
+                AdvertisingType::Scannable => esp_ble_adv_type_t_ADV_TYPE_SCAN_IND,
+                AdvertisingType::NonConnectable => esp_ble_adv_type_t_ADV_TYPE_NONCONN_IND,
+                AdvertisingType::ConnectableDirectedLowDuty => {
+                    esp_ble_adv_type_t_ADV_TYPE_DIRECT_IND_LOW
+                }
+            },
+            own_addr_type: params.own_addr_type.into(),
+            peer_addr,
+            peer_addr_type: peer_addr_type.into(),
+            channel_map: params.channel_map as _,
+            adv_filter_policy: params.filter_policy.into(),
         }
     }
 }
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct AdvertiseData {
     pub set_scan_rsp: bool,
     pub include_name: bool,
@@ -199,8 +893,480 @@ impl Default for AdvertiseData {
             service: None,
             service_uuid: None,
             appearance: AppearanceCategory::Unknown,
-            flag: ESP_BLE_ADV_FLAG_NON_LIMIT_DISC as _,
+            // ESP_BLE_ADV_FLAG_NON_LIMIT_DISC
+            flag: 0,
         }
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(data: Vec<AdvertiseType>) {
+        let raw = RawAdvertiseData::new(data.clone(), false);
+        assert_eq!(AdvertiseType::parse_all(&raw.to_vec()), Ok(data));
+    }
+
+    #[test]
+    fn round_trip_flags_and_names() {
+        round_trip(vec![
+            AdvertiseType::Flags(0x06),
+            AdvertiseType::DeviceNameComplete("ESP32".into()),
+        ]);
+        round_trip(vec![AdvertiseType::DeviceNameShort("ESP".into())]);
+    }
+
+    #[test]
+    fn round_trip_service_128() {
+        let uuid = [
+            0xfb, 0x34, 0x9b, 0x5f, 0x80, 0x00, 0x00, 0x80, 0x00, 0x10, 0x00, 0x00, 0xFF, 0x00,
+            0x00, 0x00,
+        ];
+        round_trip(vec![AdvertiseType::ServiceComplete128(vec![uuid])]);
+        round_trip(vec![AdvertiseType::ServicePartial128(vec![uuid, uuid])]);
+    }
+
+    #[test]
+    fn round_trip_other() {
+        round_trip(vec![
+            AdvertiseType::Flags(0x06),
+            AdvertiseType::Other(0x30, vec![0x4c, 0x00, 0x02, 0x15]),
+            AdvertiseType::Other(0x2a, vec![]),
+        ]);
+    }
+
+    #[test]
+    fn parse_little_endian_fields() {
+        let data = [
+            0x05, 0x03, 0x0f, 0x18, 0x0a, 0x18, // Battery, Device Information
+            0x05, 0x05, 0x78, 0x56, 0x34, 0x12, // 0x12345678
+            0x05, 0x12, 0x06, 0x00, 0x10, 0x00, // 7.5ms - 20ms
+            0x03, 0x19, 0xc0, 0x00, // Generic watch
+            0x02, 0x0a, 0xf4, // -12 dBm
+        ];
+        assert_eq!(
+            AdvertiseType::parse_all(&data),
+            Ok(vec![
+                AdvertiseType::ServiceComplete16(vec![0x180f, 0x180a]),
+                AdvertiseType::ServiceComplete32(vec![0x12345678]),
+                AdvertiseType::IntervalRange(0x0006, 0x0010),
+                AdvertiseType::Appearance(AppearanceCategory::Watch),
+                AdvertiseType::TxPower(-12),
+            ])
+        );
+    }
+
+    #[test]
+    fn parse_unknown_appearance_as_other() {
+        assert_eq!(
+            AdvertiseType::parse_all(&[0x03, 0x19, 0x00, 0x3f]),
+            Ok(vec![AdvertiseType::Other(0x19, vec![0x00, 0x3f])])
+        );
+    }
+
+    #[test]
+    fn round_trip_appearance_with_sub_category() {
+        // Generic watch + sub-category 1 (sports watch)
+        let data = [0x03, 0x19, 0xc1, 0x00];
+        let fields = AdvertiseType::parse_all(&data).unwrap();
+        assert_eq!(fields, vec![AdvertiseType::Other(0x19, vec![0xc1, 0x00])]);
+        assert_eq!(
+            AdvertiseType::encode_all(&fields, ADV_DATA_MAX_LEN),
+            Ok(data.to_vec())
+        );
+    }
+
+    #[test]
+    fn parse_stops_at_zero_length() {
+        assert_eq!(
+            AdvertiseType::parse_all(&[0x02, 0x01, 0x06, 0x00, 0x00, 0x00]),
+            Ok(vec![AdvertiseType::Flags(0x06)])
+        );
+        assert_eq!(AdvertiseType::parse_all(&[]), Ok(vec![]));
+    }
+
+    #[test]
+    fn parse_rejects_truncated_length() {
+        assert_eq!(
+            AdvertiseType::parse_all(&[0x02, 0x01, 0x06, 0x05, 0x09, 0x45, 0x53]),
+            Err(AdParseError::Truncated { offset: 3, len: 5 })
+        );
+        assert_eq!(
+            AdvertiseType::parse_all(&[0x02]),
+            Err(AdParseError::Truncated { offset: 0, len: 2 })
+        );
+    }
+
+    #[test]
+    fn parse_rejects_invalid_field_length() {
+        assert_eq!(
+            AdvertiseType::parse_all(&[0x04, 0x03, 0x0f, 0x18, 0x0a]),
+            Err(AdParseError::InvalidLength {
+                ad_type: 0x03,
+                len: 3
+            })
+        );
+        assert_eq!(
+            AdvertiseType::parse_all(&[0x01, 0x01]),
+            Err(AdParseError::InvalidLength {
+                ad_type: 0x01,
+                len: 0
+            })
+        );
+        assert_eq!(
+            AdvertiseType::parse_all(&[0x03, 0x01, 0x06, 0x00]),
+            Err(AdParseError::InvalidLength {
+                ad_type: 0x01,
+                len: 2
+            })
+        );
+        assert_eq!(
+            AdvertiseType::parse_all(&[0x03, 0x09, 0xff, 0xfe]),
+            Err(AdParseError::InvalidName)
+        );
+    }
+
+    fn all_variants() -> Vec<AdvertiseType> {
+        vec![
+            AdvertiseType::Flags(0x06),
+            AdvertiseType::ServicePartial16(vec![0x180f, 0x180a]),
+            AdvertiseType::ServiceComplete16(vec![0x180f]),
+            AdvertiseType::ServicePartial32(vec![0x0000180f]),
+            AdvertiseType::ServiceComplete32(vec![0x0000180f, 0x0000180a]),
+            AdvertiseType::ServicePartial128(vec![[0; 16]]),
+            AdvertiseType::ServiceComplete128(vec![[0; 16]]),
+            AdvertiseType::IntervalRange(0x20, 0x40),
+            AdvertiseType::DeviceNameShort("ESP".into()),
+            AdvertiseType::DeviceNameComplete("ESP32".into()),
+            AdvertiseType::Appearance(AppearanceCategory::Watch),
+            AdvertiseType::TxPower(9),
+            AdvertiseType::ManufacturerData(0x02e5, vec![0x01, 0x02]),
+            AdvertiseType::ServiceData16(0x180f, vec![0x64]),
+            AdvertiseType::ServiceData32(0x0000180f, vec![0x64]),
+            AdvertiseType::ServiceData128([0; 16], vec![0x64]),
+            AdvertiseType::Uri("\u{17}//example.com".into()),
+            AdvertiseType::LeRole(LeRole::PeripheralPreferred),
+            AdvertiseType::PublicTargetAddress(vec![[0x24, 0x0a, 0xc4, 0x00, 0x00, 0x01]]),
+            AdvertiseType::AdvertisingInterval(0x0800),
+            AdvertiseType::Other(0x30, vec![0x01, 0x02, 0x03]),
+        ]
+    }
+
+    #[test]
+    fn encoded_len_matches_encoding() {
+        for field in all_variants() {
+            let encoded: Vec<u8> = (&field).into();
+            assert_eq!(field.encoded_len(), encoded.len(), "{:?}", field);
+        }
+    }
+
+    #[test]
+    fn builder_fits_in_advertising_data() {
+        let (adv, scan_rsp) = RawAdvertiseData::builder()
+            .field(AdvertiseType::Flags(0x06))
+            .field(AdvertiseType::DeviceNameComplete("ESP32".into()))
+            .build()
+            .unwrap();
+        assert_eq!(adv.len(), 10);
+        assert!(!adv.set_scan_rsp);
+        assert!(scan_rsp.is_none());
+    }
+
+    #[test]
+    fn builder_accepts_exactly_31_bytes() {
+        let (adv, _) = RawAdvertiseData::builder()
+            .field(AdvertiseType::Other(0xff, vec![0; ADV_DATA_MAX_LEN - 2]))
+            .build()
+            .unwrap();
+        assert_eq!(adv.len(), ADV_DATA_MAX_LEN);
+    }
+
+    #[test]
+    fn builder_rejects_overflowing_field() {
+        let name = AdvertiseType::DeviceNameComplete("A rather long device name..".into());
+        let err = RawAdvertiseData::builder()
+            .field(AdvertiseType::Flags(0x06))
+            .field(name.clone())
+            .build()
+            .unwrap_err();
+        assert_eq!(
+            err,
+            AdvertiseDataError::TooLong {
+                field: name,
+                len: 29,
+                available: 28,
+            }
+        );
+    }
+
+    #[test]
+    fn builder_overflows_to_scan_response() {
+        let uuid = AdvertiseType::ServiceComplete128(vec![[0; 16]]);
+        let name = AdvertiseType::DeviceNameComplete("ESP32 peripheral".into());
+        let (adv, scan_rsp) = RawAdvertiseData::builder()
+            .field(AdvertiseType::Flags(0x06))
+            .field(uuid.clone())
+            .field(name.clone())
+            .overflow_to_scan_response(true)
+            .build()
+            .unwrap();
+        assert_eq!(adv.data(), &[AdvertiseType::Flags(0x06), uuid]);
+        let scan_rsp = scan_rsp.unwrap();
+        assert!(scan_rsp.set_scan_rsp);
+        assert_eq!(scan_rsp.data(), &[name]);
+    }
+
+    #[test]
+    fn builder_rejects_field_larger_than_scan_response() {
+        let err = RawAdvertiseData::builder()
+            .field(AdvertiseType::Other(0xff, vec![0; 20]))
+            .field(AdvertiseType::Other(0xff, vec![0; 20]))
+            .field(AdvertiseType::Other(0xff, vec![0; 20]))
+            .overflow_to_scan_response(true)
+            .build()
+            .unwrap_err();
+        assert_eq!(
+            err,
+            AdvertiseDataError::TooLong {
+                field: AdvertiseType::Other(0xff, vec![0; 20]),
+                len: 22,
+                available: 9,
+            }
+        );
+    }
+
+    fn encode(field: AdvertiseType) -> Vec<u8> {
+        (&field).into()
+    }
+
+    #[test]
+    fn encode_golden_bytes() {
+        assert_eq!(encode(AdvertiseType::Flags(0x06)), [0x02, 0x01, 0x06]);
+        assert_eq!(
+            encode(AdvertiseType::ServiceComplete16(vec![0x180f, 0x180a])),
+            [0x05, 0x03, 0x0f, 0x18, 0x0a, 0x18]
+        );
+        assert_eq!(
+            encode(AdvertiseType::ServicePartial16(vec![0x180f])),
+            [0x03, 0x02, 0x0f, 0x18]
+        );
+        assert_eq!(
+            encode(AdvertiseType::ServiceComplete32(vec![0x12345678])),
+            [0x05, 0x05, 0x78, 0x56, 0x34, 0x12]
+        );
+        assert_eq!(
+            encode(AdvertiseType::ServicePartial32(vec![0x12345678])),
+            [0x05, 0x04, 0x78, 0x56, 0x34, 0x12]
+        );
+        assert_eq!(
+            encode(AdvertiseType::IntervalRange(0x0006, 0x0010)),
+            [0x05, 0x12, 0x06, 0x00, 0x10, 0x00]
+        );
+        assert_eq!(
+            encode(AdvertiseType::DeviceNameComplete("ESP".into())),
+            [0x04, 0x09, b'E', b'S', b'P']
+        );
+        assert_eq!(
+            encode(AdvertiseType::Appearance(AppearanceCategory::Watch)),
+            [0x03, 0x19, 0xc0, 0x00]
+        );
+        assert_eq!(
+            encode(AdvertiseType::Appearance(
+                AppearanceCategory::HumanInterfaceDevice
+            )),
+            [0x03, 0x19, 0xc0, 0x03]
+        );
+        assert_eq!(encode(AdvertiseType::TxPower(-12)), [0x02, 0x0a, 0xf4]);
+        assert_eq!(encode(AdvertiseType::TxPower(4)), [0x02, 0x0a, 0x04]);
+    }
+
+    #[test]
+    fn encode_new_ad_types_golden_bytes() {
+        assert_eq!(
+            encode(AdvertiseType::ManufacturerData(0x02e5, vec![0xaa, 0xbb])),
+            [0x05, 0xff, 0xe5, 0x02, 0xaa, 0xbb]
+        );
+        assert_eq!(
+            encode(AdvertiseType::ServiceData16(0x180f, vec![0x64])),
+            [0x04, 0x16, 0x0f, 0x18, 0x64]
+        );
+        assert_eq!(
+            encode(AdvertiseType::ServiceData32(0x12345678, vec![0x64])),
+            [0x06, 0x20, 0x78, 0x56, 0x34, 0x12, 0x64]
+        );
+        let uuid = [
+            0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d,
+            0x0e, 0x0f,
+        ];
+        assert_eq!(
+            encode(AdvertiseType::ServiceData128(uuid, vec![0x64])),
+            [&[0x12, 0x21][..], &uuid, &[0x64]].concat()
+        );
+        assert_eq!(
+            encode(AdvertiseType::Uri("\u{17}//a.io".into())),
+            [0x08, 0x24, 0x17, b'/', b'/', b'a', b'.', b'i', b'o']
+        );
+        assert_eq!(
+            encode(AdvertiseType::LeRole(LeRole::CentralPreferred)),
+            [0x02, 0x1c, 0x03]
+        );
+        assert_eq!(
+            encode(AdvertiseType::PublicTargetAddress(vec![[
+                0x24, 0x0a, 0xc4, 0x00, 0x00, 0x01
+            ]])),
+            [0x07, 0x17, 0x01, 0x00, 0x00, 0xc4, 0x0a, 0x24]
+        );
+        assert_eq!(
+            encode(AdvertiseType::AdvertisingInterval(0x0800)),
+            [0x03, 0x1a, 0x00, 0x08]
+        );
+    }
+
+    #[test]
+    fn round_trip_new_ad_types() {
+        round_trip(vec![
+            AdvertiseType::ManufacturerData(0x004c, vec![0x02, 0x15]),
+            AdvertiseType::ServiceData16(0xfeaa, vec![0x10, 0x00]),
+        ]);
+        round_trip(vec![
+            AdvertiseType::ServiceData32(0x0000feaa, vec![]),
+            AdvertiseType::LeRole(LeRole::PeripheralOnly),
+            AdvertiseType::AdvertisingInterval(0x00a0),
+        ]);
+        round_trip(vec![AdvertiseType::ServiceData128([0xaa; 16], vec![0x01])]);
+        round_trip(vec![
+            AdvertiseType::Uri("\u{16}//example.com".into()),
+            AdvertiseType::PublicTargetAddress(vec![[1, 2, 3, 4, 5, 6], [6, 5, 4, 3, 2, 1]]),
+        ]);
+    }
+
+    #[test]
+    fn parse_rejects_short_service_data() {
+        assert_eq!(
+            AdvertiseType::parse_all(&[0x02, 0x16, 0x0f]),
+            Err(AdParseError::InvalidLength {
+                ad_type: 0x16,
+                len: 1
+            })
+        );
+        assert_eq!(
+            AdvertiseType::parse_all(&[0x02, 0xff, 0x4c]),
+            Err(AdParseError::InvalidLength {
+                ad_type: 0xff,
+                len: 1
+            })
+        );
+    }
+
+    #[test]
+    fn default_advertising_parameters_are_valid() {
+        assert_eq!(AdvertisingParameters::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn advertising_interval_is_validated() {
+        let params = AdvertisingParameters {
+            interval_min: 0x10,
+            ..Default::default()
+        };
+        assert_eq!(
+            params.validate(),
+            Err(AdvertisingParametersError::IntervalOutOfRange(0x10))
+        );
+
+        let params = AdvertisingParameters {
+            interval_max: 0x4001,
+            ..Default::default()
+        };
+        assert_eq!(
+            params.validate(),
+            Err(AdvertisingParametersError::IntervalOutOfRange(0x4001))
+        );
+
+        let params = AdvertisingParameters {
+            interval_min: 0x100,
+            interval_max: 0x80,
+            ..Default::default()
+        };
+        assert_eq!(
+            params.validate(),
+            Err(AdvertisingParametersError::InvalidIntervalRange {
+                min: 0x100,
+                max: 0x80
+            })
+        );
+    }
+
+    #[test]
+    fn directed_advertising_requires_peer() {
+        let params = AdvertisingParameters {
+            adv_type: AdvertisingType::ConnectableDirectedLowDuty,
+            ..Default::default()
+        };
+        assert_eq!(
+            params.validate(),
+            Err(AdvertisingParametersError::MissingPeerAddress)
+        );
+
+        let params = AdvertisingParameters {
+            adv_type: AdvertisingType::ConnectableDirectedHighDuty,
+            interval_min: 0,
+            interval_max: 0,
+            peer_addr: Some(([0x24, 0x0a, 0xc4, 0x00, 0x00, 0x01], AddressType::Public)),
+            ..Default::default()
+        };
+        assert_eq!(params.validate(), Ok(()));
+    }
+
+    #[test]
+    fn advertising_channel_map_is_validated() {
+        for channel_map in [0x00, 0x08] {
+            let params = AdvertisingParameters {
+                channel_map,
+                ..Default::default()
+            };
+            assert_eq!(
+                params.validate(),
+                Err(AdvertisingParametersError::InvalidChannelMap(channel_map))
+            );
+        }
+
+        let params = AdvertisingParameters {
+            channel_map: ADV_CHANNEL_37 | ADV_CHANNEL_39,
+            adv_type: AdvertisingType::NonConnectable,
+            ..Default::default()
+        };
+        assert_eq!(params.validate(), Ok(()));
+    }
+
+    #[test]
+    fn encode_all_checks_payload_and_field_sizes() {
+        let name = AdvertiseType::DeviceNameComplete("ESP32".into());
+        assert_eq!(
+            AdvertiseType::encode_all(&[AdvertiseType::Flags(0x06), name.clone()], 31),
+            Ok(vec![
+                0x02, 0x01, 0x06, 0x06, 0x09, b'E', b'S', b'P', b'3', b'2'
+            ])
+        );
+        assert_eq!(
+            AdvertiseType::encode_all(&[AdvertiseType::Flags(0x06), name.clone()], 9),
+            Err(AdvertiseDataError::TooLong {
+                field: name,
+                len: 7,
+                available: 6
+            })
+        );
+
+        let big = [AdvertiseType::ManufacturerData(0x02e5, vec![0; 252])];
+        assert_eq!(AdvertiseType::encode_all(&big, 1650).unwrap().len(), 256);
+        let too_big = AdvertiseType::ManufacturerData(0x02e5, vec![0; 253]);
+        assert_eq!(
+            AdvertiseType::encode_all(std::slice::from_ref(&too_big), 1650),
+            Err(AdvertiseDataError::FieldTooLong {
+                field: too_big,
+                len: 257
+            })
+        );
+    }
+}
@@ -0,0 +1,288 @@
+use crate::error::EspError;
+use crate::{
+    AddressType, AdvertiseData, AdvertisingParameters, Attribute, AutoResponse, BdAddr,
+    BleEncryption, BondedDevice, BtUuid, ExtendedAdvertisingDuration,
+    ExtendedAdvertisingParameters, GattService, GattStatus, OobTemporaryKey,
+    PeriodicAdvertisingParameters, RemoteCharacteristic, RemoteDescriptor, RemoteService,
+    ScOobData, ScanParameters, WriteType,
+};
+
+/// Security Manager parameter set by `EspBle::configure_security`, mirrors
+/// `esp_ble_sm_param_t`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SecurityParam {
+    AuthenticationRequestMode,
+    IoCapabilities,
+    InitiatorKey,
+    ResponderKey,
+    MaxKeySize,
+    MinKeySize,
+    /// The passkey as a little endian `u32`
+    StaticPasskey,
+    ClearStaticPasskey,
+    OnlyAcceptSpecifiedAuthentication,
+    OobSupport,
+}
+
+/// Connection parameters requested for a peer, in the units of the
+/// Bluetooth specification, see `esp_ble_conn_update_params_t`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConnectionUpdate {
+    pub bda: BdAddr,
+    /// Minimum connection interval in 1.25ms units
+    pub min_int: u16,
+    /// Maximum connection interval in 1.25ms units
+    pub max_int: u16,
+    pub latency: u16,
+    /// Supervision timeout in 10ms units
+    pub timeout: u16,
+}
+
+/// Response to a read or a write request. A prepared write is answered with
+/// the `offset` and the value it carries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GattResponse<'a> {
+    pub status: GattStatus,
+    pub handle: u16,
+    pub offset: u16,
+    pub value: &'a [u8],
+}
+
+/// The calls `EspBle` makes to the Bluetooth stack.
+///
+/// `BluedroidBackend` forwards them to the ESP-IDF Bluedroid API and feeds the
+/// events it reports to the `EspBle` callbacks. Other implementations, like
+/// `SimulatedBackend`, can be given to `EspBle::with_backend` to run the
+/// application logic without the radio.
+///
+/// The calls of a feature the stack is built without, like the BLE 5.0
+/// advertising sets, fail with `ESP_ERR_NOT_SUPPORTED`.
+pub trait BleBackend: Send + Sync {
+    /// Bring up the stack and start reporting its events.
+    fn init(&self) -> Result<(), EspError>;
+
+    /// Shut the stack down, no event is reported afterwards.
+    fn deinit(&self) -> Result<(), EspError>;
+
+    fn set_local_mtu(&self, mtu: u16) -> Result<(), EspError>;
+
+    fn set_device_name(&self, name: &str) -> Result<(), EspError>;
+
+    fn config_adv_data(&self, data: &AdvertiseData) -> Result<(), EspError>;
+
+    fn config_adv_data_raw(&self, data: &[u8]) -> Result<(), EspError>;
+
+    fn config_scan_rsp_data_raw(&self, data: &[u8]) -> Result<(), EspError>;
+
+    fn start_advertising(&self, params: &AdvertisingParameters) -> Result<(), EspError>;
+
+    fn stop_advertising(&self) -> Result<(), EspError>;
+
+    fn set_scan_params(&self, params: &ScanParameters) -> Result<(), EspError>;
+
+    fn start_scanning(&self, duration: u32) -> Result<(), EspError>;
+
+    fn stop_scanning(&self) -> Result<(), EspError>;
+
+    fn update_conn_params(&self, params: &ConnectionUpdate) -> Result<(), EspError>;
+
+    fn set_security_param(&self, param: SecurityParam, value: &[u8]) -> Result<(), EspError>;
+
+    fn security_rsp(&self, bda: BdAddr, accept: bool) -> Result<(), EspError>;
+
+    fn confirm_reply(&self, bda: BdAddr, accept: bool) -> Result<(), EspError>;
+
+    fn passkey_reply(&self, bda: BdAddr, accept: bool, passkey: u32) -> Result<(), EspError>;
+
+    /// Answer the `GapEvent::OOBRequest` of `bda` with the temporary key.
+    fn oob_req_reply(&self, bda: BdAddr, tk: &OobTemporaryKey) -> Result<(), EspError>;
+
+    /// Answer the `GapEvent::SecureConnectionsOOBRequest` of `bda` with its data.
+    fn sc_oob_req_reply(&self, bda: BdAddr, data: &ScOobData) -> Result<(), EspError>;
+
+    /// Create the local Secure Connections OOB data, completed by
+    /// `GapEvent::LocalOOBDataCreated`.
+    fn create_sc_oob_data(&self) -> Result<(), EspError>;
+
+    fn set_encryption(&self, bda: BdAddr, encryption: BleEncryption) -> Result<(), EspError>;
+
+    /// Drop the link with `bda`.
+    fn disconnect(&self, bda: BdAddr) -> Result<(), EspError>;
+
+    /// The peers this device is bonded with.
+    fn bonded_devices(&self) -> Result<Vec<BondedDevice>, EspError>;
+
+    /// Remove the bond with `bda`, completed by `GapEvent::RemoveDeviceBondComplete`.
+    fn remove_bond_device(&self, bda: BdAddr) -> Result<(), EspError>;
+
+    fn gatts_app_register(&self, app_id: u16) -> Result<(), EspError>;
+
+    /// `service.handle` is the number of handles to reserve for the service.
+    fn gatts_create_service(&self, gatts_if: u8, service: &GattService) -> Result<(), EspError>;
+
+    fn gatts_start_service(&self, service_handle: u16) -> Result<(), EspError>;
+
+    /// Create a service from its attributes, the first one being the service
+    /// declaration.
+    fn gatts_create_attr_tab(
+        &self,
+        gatts_if: u8,
+        attributes: &[Attribute],
+        service_instance_id: u8,
+    ) -> Result<(), EspError>;
+
+    fn gatts_add_char(
+        &self,
+        service_handle: u16,
+        uuid: &BtUuid,
+        permissions: u16,
+        property: u8,
+        value: &[u8],
+        auto_rsp: AutoResponse,
+    ) -> Result<(), EspError>;
+
+    fn gatts_add_char_descr(
+        &self,
+        service_handle: u16,
+        uuid: &BtUuid,
+        permissions: u16,
+    ) -> Result<(), EspError>;
+
+    /// Send a notification, or an indication when `need_confirm` is set.
+    fn gatts_send_indicate(
+        &self,
+        gatts_if: u8,
+        conn_id: u16,
+        attr_handle: u16,
+        value: &[u8],
+        need_confirm: bool,
+    ) -> Result<(), EspError>;
+
+    fn gatts_get_attr_value(&self, attr_handle: u16) -> Result<Vec<u8>, EspError>;
+
+    fn gatts_send_response(
+        &self,
+        gatts_if: u8,
+        conn_id: u16,
+        trans_id: u32,
+        response: &GattResponse,
+    ) -> Result<(), EspError>;
+
+    fn gattc_app_register(&self, app_id: u16) -> Result<(), EspError>;
+
+    /// Connect to the server `bda`, completed by `GattClientEvent::Open`.
+    fn gattc_open(&self, gattc_if: u8, bda: BdAddr, addr_type: AddressType)
+        -> Result<(), EspError>;
+
+    fn gattc_close(&self, gattc_if: u8, conn_id: u16) -> Result<(), EspError>;
+
+    /// Discover the services of the server, only the ones with the `filter`
+    /// UUID when given.
+    fn gattc_search_service(
+        &self,
+        gattc_if: u8,
+        conn_id: u16,
+        filter: Option<&BtUuid>,
+    ) -> Result<(), EspError>;
+
+    /// The services found by the last search.
+    fn gattc_get_services(
+        &self,
+        gattc_if: u8,
+        conn_id: u16,
+    ) -> Result<Vec<RemoteService>, EspError>;
+
+    /// The characteristics declared between `start_handle` and `end_handle`.
+    fn gattc_get_characteristics(
+        &self,
+        gattc_if: u8,
+        conn_id: u16,
+        start_handle: u16,
+        end_handle: u16,
+    ) -> Result<Vec<RemoteCharacteristic>, EspError>;
+
+    /// The descriptors of the characteristic at `char_handle`.
+    fn gattc_get_descriptors(
+        &self,
+        gattc_if: u8,
+        conn_id: u16,
+        char_handle: u16,
+    ) -> Result<Vec<RemoteDescriptor>, EspError>;
+
+    fn gattc_read_char(&self, gattc_if: u8, conn_id: u16, handle: u16) -> Result<(), EspError>;
+
+    fn gattc_write_char(
+        &self,
+        gattc_if: u8,
+        conn_id: u16,
+        handle: u16,
+        value: &[u8],
+        write_type: WriteType,
+    ) -> Result<(), EspError>;
+
+    fn gattc_read_char_descr(
+        &self,
+        gattc_if: u8,
+        conn_id: u16,
+        handle: u16,
+    ) -> Result<(), EspError>;
+
+    fn gattc_write_char_descr(
+        &self,
+        gattc_if: u8,
+        conn_id: u16,
+        handle: u16,
+        value: &[u8],
+        write_type: WriteType,
+    ) -> Result<(), EspError>;
+
+    /// Report the notifications and indications `bda` sends for `handle`.
+    fn gattc_register_for_notify(
+        &self,
+        gattc_if: u8,
+        bda: BdAddr,
+        handle: u16,
+    ) -> Result<(), EspError>;
+
+    fn gattc_unregister_for_notify(
+        &self,
+        gattc_if: u8,
+        bda: BdAddr,
+        handle: u16,
+    ) -> Result<(), EspError>;
+
+    /// Configure the advertising set `instance`, which is created if needed.
+    fn ext_adv_set_params(
+        &self,
+        instance: u8,
+        params: &ExtendedAdvertisingParameters,
+    ) -> Result<(), EspError>;
+
+    fn ext_adv_set_rand_addr(&self, instance: u8, addr: BdAddr) -> Result<(), EspError>;
+
+    fn config_ext_adv_data_raw(&self, instance: u8, data: &[u8]) -> Result<(), EspError>;
+
+    fn config_ext_scan_rsp_data_raw(&self, instance: u8, data: &[u8]) -> Result<(), EspError>;
+
+    /// Start advertising the given set instances.
+    fn ext_adv_start(&self, sets: &[(u8, ExtendedAdvertisingDuration)]) -> Result<(), EspError>;
+
+    fn ext_adv_stop(&self, instances: &[u8]) -> Result<(), EspError>;
+
+    fn ext_adv_set_remove(&self, instance: u8) -> Result<(), EspError>;
+
+    /// Remove all the advertising sets.
+    fn ext_adv_set_clear(&self) -> Result<(), EspError>;
+
+    fn periodic_adv_set_params(
+        &self,
+        instance: u8,
+        params: &PeriodicAdvertisingParameters,
+    ) -> Result<(), EspError>;
+
+    fn config_periodic_adv_data_raw(&self, instance: u8, data: &[u8]) -> Result<(), EspError>;
+
+    fn periodic_adv_start(&self, instance: u8) -> Result<(), EspError>;
+
+    fn periodic_adv_stop(&self, instance: u8) -> Result<(), EspError>;
+}
@@ -0,0 +1,449 @@
+use crate::{AdvertiseType, RawAdvertiseData};
+
+const APPLE_COMPANY_ID: u16 = 0x004c;
+const IBEACON_TYPE: u8 = 0x02;
+const IBEACON_LEN: u8 = 0x15;
+
+const EDDYSTONE_UUID: u16 = 0xfeaa;
+const EDDYSTONE_UID: u8 = 0x00;
+const EDDYSTONE_URL: u8 = 0x10;
+const EDDYSTONE_TLM: u8 = 0x20;
+const EDDYSTONE_EID: u8 = 0x30;
+
+/// Maximum size of the encoded URL in an Eddystone-URL frame
+const EDDYSTONE_URL_MAX_LEN: usize = 17;
+
+/// LE General Discoverable, BR/EDR not supported
+const BEACON_FLAGS: u8 = 0x06;
+
+const URL_SCHEMES: [&str; 4] = ["http://www.", "https://www.", "http://", "https://"];
+
+const URL_EXPANSIONS: [&str; 14] = [
+    ".com/", ".org/", ".edu/", ".net/", ".info/", ".biz/", ".gov/", ".com", ".org", ".edu", ".net",
+    ".info", ".biz", ".gov",
+];
+
+/// Apple iBeacon advertisement, multi-byte fields are sent big-endian.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IBeacon {
+    /// Proximity UUID, in the order it is usually displayed
+    pub uuid: [u8; 16],
+    pub major: u16,
+    pub minor: u16,
+    /// RSSI measured at 1m, in dBm
+    pub measured_power: i8,
+}
+
+impl IBeacon {
+    /// Decode the iBeacon payload of a manufacturer specific data field.
+    pub fn from_manufacturer_data(company_id: u16, data: &[u8]) -> Option<Self> {
+        if company_id != APPLE_COMPANY_ID
+            || data.len() != 2 + IBEACON_LEN as usize
+            || data[0] != IBEACON_TYPE
+            || data[1] != IBEACON_LEN
+        {
+            return None;
+        }
+
+        let mut uuid = [0; 16];
+        uuid.copy_from_slice(&data[2..18]);
+        Some(Self {
+            uuid,
+            major: u16::from_be_bytes([data[18], data[19]]),
+            minor: u16::from_be_bytes([data[20], data[21]]),
+            measured_power: data[22] as i8,
+        })
+    }
+
+    /// Find an iBeacon in decoded advertising data.
+    pub fn parse(fields: &[AdvertiseType]) -> Option<Self> {
+        fields.iter().find_map(|field| match field {
+            AdvertiseType::ManufacturerData(company_id, data) => {
+                Self::from_manufacturer_data(*company_id, data)
+            }
+            _ => None,
+        })
+    }
+}
+
+impl From<&IBeacon> for RawAdvertiseData {
+    fn from(beacon: &IBeacon) -> Self {
+        let mut data = vec![IBEACON_TYPE, IBEACON_LEN];
+        data.extend_from_slice(&beacon.uuid);
+        data.extend_from_slice(&beacon.major.to_be_bytes());
+        data.extend_from_slice(&beacon.minor.to_be_bytes());
+        data.push(beacon.measured_power as u8);
+
+        RawAdvertiseData::new(
+            vec![
+                AdvertiseType::Flags(BEACON_FLAGS),
+                AdvertiseType::ManufacturerData(APPLE_COMPANY_ID, data),
+            ],
+            false,
+        )
+    }
+}
+
+/// Google Eddystone frames, multi-byte fields are sent big-endian.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Eddystone {
+    Uid {
+        /// TX power at 0m, in dBm
+        tx_power: i8,
+        namespace: [u8; 10],
+        instance: [u8; 6],
+    },
+    Url {
+        /// TX power at 0m, in dBm
+        tx_power: i8,
+        url: String,
+    },
+    /// Unencrypted telemetry
+    Tlm {
+        /// Battery voltage in mV, 0 if not supported
+        battery_voltage: u16,
+        /// Temperature in signed 8.8 fixed point °C, `i16::MIN` if not supported
+        temperature: i16,
+        adv_count: u32,
+        /// Time since power-on or reboot, in 0.1s units
+        sec_count: u32,
+    },
+    Eid {
+        /// TX power at 0m, in dBm
+        tx_power: i8,
+        eid: [u8; 8],
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EddystoneError {
+    /// The URL does not start with one of the schemes Eddystone-URL can encode
+    UnsupportedScheme,
+    /// The URL contains a character which cannot be sent in an Eddystone-URL frame
+    InvalidCharacter(char),
+    /// The encoded URL is `len` bytes long, at most 17 bytes are allowed
+    UrlTooLong { len: usize },
+}
+
+impl std::fmt::Display for EddystoneError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EddystoneError::UnsupportedScheme => write!(f, "Unsupported URL scheme"),
+            EddystoneError::InvalidCharacter(c) => write!(f, "Invalid URL character {:?}", c),
+            EddystoneError::UrlTooLong { len } => write!(
+                f,
+                "Encoded URL is {} bytes long, at most {} are allowed",
+                len, EDDYSTONE_URL_MAX_LEN
+            ),
+        }
+    }
+}
+
+impl std::error::Error for EddystoneError {}
+
+impl Eddystone {
+    /// Encode the frame, as sent in the service data of the Eddystone service.
+    pub fn to_frame(&self) -> Result<Vec<u8>, EddystoneError> {
+        Ok(match self {
+            Eddystone::Uid {
+                tx_power,
+                namespace,
+                instance,
+            } => {
+                let mut frame = vec![EDDYSTONE_UID, *tx_power as u8];
+                frame.extend_from_slice(namespace);
+                frame.extend_from_slice(instance);
+                // Reserved for future use
+                frame.extend_from_slice(&[0x00, 0x00]);
+                frame
+            }
+            Eddystone::Url { tx_power, url } => {
+                let mut frame = vec![EDDYSTONE_URL, *tx_power as u8];
+                frame.append(&mut encode_url(url)?);
+                frame
+            }
+            Eddystone::Tlm {
+                battery_voltage,
+                temperature,
+                adv_count,
+                sec_count,
+            } => {
+                // Version 0, unencrypted
+                let mut frame = vec![EDDYSTONE_TLM, 0x00];
+                frame.extend_from_slice(&battery_voltage.to_be_bytes());
+                frame.extend_from_slice(&temperature.to_be_bytes());
+                frame.extend_from_slice(&adv_count.to_be_bytes());
+                frame.extend_from_slice(&sec_count.to_be_bytes());
+                frame
+            }
+            Eddystone::Eid { tx_power, eid } => {
+                let mut frame = vec![EDDYSTONE_EID, *tx_power as u8];
+                frame.extend_from_slice(eid);
+                frame
+            }
+        })
+    }
+
+    /// Build the advertising data broadcasting this frame.
+    pub fn to_advertise_data(&self) -> Result<RawAdvertiseData, EddystoneError> {
+        Ok(RawAdvertiseData::new(
+            vec![
+                AdvertiseType::Flags(BEACON_FLAGS),
+                AdvertiseType::ServiceComplete16(vec![EDDYSTONE_UUID]),
+                AdvertiseType::ServiceData16(EDDYSTONE_UUID, self.to_frame()?),
+            ],
+            false,
+        ))
+    }
+
+    /// Decode a frame from the service data of the Eddystone service.
+    pub fn from_frame(frame: &[u8]) -> Option<Self> {
+        let (&frame_type, data) = frame.split_first()?;
+        match frame_type {
+            // The reserved bytes are optional
+            EDDYSTONE_UID if data.len() == 17 || data.len() == 19 => {
+                let mut namespace = [0; 10];
+                namespace.copy_from_slice(&data[1..11]);
+                let mut instance = [0; 6];
+                instance.copy_from_slice(&data[11..17]);
+                Some(Eddystone::Uid {
+                    tx_power: data[0] as i8,
+                    namespace,
+                    instance,
+                })
+            }
+            EDDYSTONE_URL if data.len() >= 2 => Some(Eddystone::Url {
+                tx_power: data[0] as i8,
+                url: decode_url(&data[1..])?,
+            }),
+            EDDYSTONE_TLM if data.len() == 13 && data[0] == 0x00 => Some(Eddystone::Tlm {
+                battery_voltage: u16::from_be_bytes([data[1], data[2]]),
+                temperature: i16::from_be_bytes([data[3], data[4]]),
+                adv_count: u32::from_be_bytes([data[5], data[6], data[7], data[8]]),
+                sec_count: u32::from_be_bytes([data[9], data[10], data[11], data[12]]),
+            }),
+            EDDYSTONE_EID if data.len() == 9 => {
+                let mut eid = [0; 8];
+                eid.copy_from_slice(&data[1..]);
+                Some(Eddystone::Eid {
+                    tx_power: data[0] as i8,
+                    eid,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Find an Eddystone frame in decoded advertising data.
+    pub fn parse(fields: &[AdvertiseType]) -> Option<Self> {
+        fields.iter().find_map(|field| match field {
+            AdvertiseType::ServiceData16(EDDYSTONE_UUID, frame) => Self::from_frame(frame),
+            _ => None,
+        })
+    }
+}
+
+fn encode_url(url: &str) -> Result<Vec<u8>, EddystoneError> {
+    let (scheme, mut rest) = URL_SCHEMES
+        .iter()
+        .enumerate()
+        .find_map(|(code, scheme)| url.strip_prefix(scheme).map(|rest| (code as u8, rest)))
+        .ok_or(EddystoneError::UnsupportedScheme)?;
+
+    let mut encoded = vec![scheme];
+    while let Some(c) = rest.chars().next() {
+        if let Some((code, expansion)) = URL_EXPANSIONS
+            .iter()
+            .enumerate()
+            .find(|(_, expansion)| rest.starts_with(*expansion))
+        {
+            encoded.push(code as u8);
+            rest = &rest[expansion.len()..];
+        } else if ('\u{21}'..='\u{7e}').contains(&c) {
+            encoded.push(c as u8);
+            rest = &rest[1..];
+        } else {
+            return Err(EddystoneError::InvalidCharacter(c));
+        }
+    }
+
+    // The scheme prefix is not part of the limit
+    if encoded.len() - 1 > EDDYSTONE_URL_MAX_LEN {
+        return Err(EddystoneError::UrlTooLong {
+            len: encoded.len() - 1,
+        });
+    }
+
+    Ok(encoded)
+}
+
+fn decode_url(encoded: &[u8]) -> Option<String> {
+    let (&scheme, rest) = encoded.split_first()?;
+    let mut url = URL_SCHEMES.get(scheme as usize)?.to_string();
+    for &b in rest {
+        match URL_EXPANSIONS.get(b as usize) {
+            Some(expansion) => url.push_str(expansion),
+            None if (0x21..=0x7e).contains(&b) => url.push(b as char),
+            None => return None,
+        }
+    }
+    Some(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UUID: [u8; 16] = [
+        0xe2, 0xc5, 0x6d, 0xb5, 0xdf, 0xfb, 0x48, 0xd2, 0xb0, 0x60, 0xd0, 0xf5, 0xa7, 0x10, 0x96,
+        0xe0,
+    ];
+
+    #[test]
+    fn ibeacon_frame_layout() {
+        let beacon = IBeacon {
+            uuid: UUID,
+            major: 0x0001,
+            minor: 0x0203,
+            measured_power: -59,
+        };
+        let raw: RawAdvertiseData = (&beacon).into();
+        let mut expected = vec![
+            0x02, 0x01, 0x06, // Flags
+            0x1a, 0xff, 0x4c, 0x00, 0x02, 0x15, // Apple, iBeacon
+        ];
+        expected.extend_from_slice(&UUID);
+        expected.extend_from_slice(&[0x00, 0x01, 0x02, 0x03, 0xc5]);
+        assert_eq!(raw.to_vec(), expected);
+        assert_eq!(raw.len(), 30);
+
+        let fields = AdvertiseType::parse_all(&expected).unwrap();
+        assert_eq!(IBeacon::parse(&fields), Some(beacon));
+    }
+
+    #[test]
+    fn ibeacon_ignores_other_manufacturers() {
+        let mut data = vec![0x02, 0x15];
+        data.extend_from_slice(&[0; 21]);
+        assert_eq!(IBeacon::from_manufacturer_data(0x0059, &data), None);
+        assert!(IBeacon::from_manufacturer_data(APPLE_COMPANY_ID, &data).is_some());
+        assert_eq!(
+            IBeacon::from_manufacturer_data(APPLE_COMPANY_ID, &data[..22]),
+            None
+        );
+    }
+
+    #[test]
+    fn eddystone_uid_frame_layout() {
+        let uid = Eddystone::Uid {
+            tx_power: -20,
+            namespace: [0x8b, 0x0c, 0xa7, 0x50, 0xe7, 0xa7, 0x4e, 0x14, 0xbd, 0x99],
+            instance: [0x01, 0x02, 0x03, 0x04, 0x05, 0x06],
+        };
+        let raw = uid.to_advertise_data().unwrap();
+        assert_eq!(
+            raw.to_vec(),
+            [
+                0x02, 0x01, 0x06, // Flags
+                0x03, 0x03, 0xaa, 0xfe, // Eddystone service
+                0x17, 0x16, 0xaa, 0xfe, // Eddystone service data
+                0x00, 0xec, // UID, tx power
+                0x8b, 0x0c, 0xa7, 0x50, 0xe7, 0xa7, 0x4e, 0x14, 0xbd, 0x99, // namespace
+                0x01, 0x02, 0x03, 0x04, 0x05, 0x06, // instance
+                0x00, 0x00, // RFU
+            ]
+        );
+        assert_eq!(
+            Eddystone::parse(&AdvertiseType::parse_all(&raw.to_vec()).unwrap()),
+            Some(uid)
+        );
+    }
+
+    #[test]
+    fn eddystone_url_compression() {
+        let url = Eddystone::Url {
+            tx_power: -20,
+            url: "https://www.example.com/beacon".into(),
+        };
+        assert_eq!(
+            url.to_frame().unwrap(),
+            [
+                0x10, 0xec, 0x01, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x00, b'b', b'e', b'a',
+                b'c', b'o', b'n'
+            ]
+        );
+        assert_eq!(Eddystone::from_frame(&url.to_frame().unwrap()), Some(url));
+
+        assert_eq!(
+            encode_url("http://goo.gl/S6zT6P").unwrap(),
+            [0x02, b'g', b'o', b'o', b'.', b'g', b'l', b'/', b'S', b'6', b'z', b'T', b'6', b'P']
+        );
+        assert_eq!(
+            encode_url("https://example.org").unwrap(),
+            [0x03, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x08]
+        );
+    }
+
+    #[test]
+    fn eddystone_url_errors() {
+        assert_eq!(
+            encode_url("ftp://example.com"),
+            Err(EddystoneError::UnsupportedScheme)
+        );
+        assert_eq!(
+            encode_url("https://exa mple.com"),
+            Err(EddystoneError::InvalidCharacter(' '))
+        );
+        assert_eq!(
+            encode_url("https://a-very-long-domain.com"),
+            Err(EddystoneError::UrlTooLong { len: 19 })
+        );
+        assert_eq!(
+            encode_url("https://abcdefghijklmnop.com").unwrap().len(),
+            18
+        );
+        assert_eq!(
+            encode_url("https://abcdefghijklmnopq.com"),
+            Err(EddystoneError::UrlTooLong { len: 18 })
+        );
+    }
+
+    #[test]
+    fn eddystone_tlm_frame_layout() {
+        let tlm = Eddystone::Tlm {
+            battery_voltage: 3000,
+            temperature: 0x1780,
+            adv_count: 0x00010203,
+            sec_count: 0x0a0b0c0d,
+        };
+        let frame = [
+            0x20, 0x00, // TLM, version
+            0x0b, 0xb8, // 3000mV
+            0x17, 0x80, // 23.5°C
+            0x00, 0x01, 0x02, 0x03, // adv count
+            0x0a, 0x0b, 0x0c, 0x0d, // sec count
+        ];
+        assert_eq!(tlm.to_frame().unwrap(), frame);
+        assert_eq!(Eddystone::from_frame(&frame), Some(tlm));
+    }
+
+    #[test]
+    fn eddystone_eid_frame_layout() {
+        let eid = Eddystone::Eid {
+            tx_power: 0,
+            eid: [1, 2, 3, 4, 5, 6, 7, 8],
+        };
+        let frame = [0x30, 0x00, 1, 2, 3, 4, 5, 6, 7, 8];
+        assert_eq!(eid.to_frame().unwrap(), frame);
+        assert_eq!(Eddystone::from_frame(&frame), Some(eid));
+    }
+
+    #[test]
+    fn eddystone_rejects_malformed_frames() {
+        assert_eq!(Eddystone::from_frame(&[]), None);
+        assert_eq!(Eddystone::from_frame(&[0x00, 0xec, 0x01]), None);
+        assert_eq!(Eddystone::from_frame(&[0x10, 0xec, 0x04, b'a']), None);
+        assert_eq!(Eddystone::from_frame(&[0x20, 0x01]), None);
+        assert_eq!(Eddystone::from_frame(&[0x40, 0x00]), None);
+    }
+}
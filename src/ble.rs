@@ -0,0 +1,3828 @@
+use std::sync::{Arc, Mutex};
+
+use ::log::*;
+
+#[cfg(target_os = "espidf")]
+use esp_idf_svc::nvs::EspDefaultNvs;
+
+use crate::advertise::{self, RawAdvertiseData, ADV_DATA_MAX_LEN};
+use crate::backend::{BleBackend, GattResponse, SecurityParam};
+#[cfg(target_os = "espidf")]
+use crate::bluedroid::{BluedroidBackend, NvsSubscriptionStore};
+use crate::connection::{ConnectionChange, ConnectionManager};
+use crate::dispatch::{Dispatched, Dispatcher};
+use crate::error::{bt_result, EspError};
+use crate::extended_advertise::ExtendedAdvertisingSets;
+use crate::long_attribute::{read_response, LongAttributeState};
+use crate::notification::{NotificationState, ATT_DEFAULT_MTU, ATT_MAX_MTU};
+use crate::{
+    validate_extended_data, AddressType, AdvertisingParameters, ArgumentError, AttributeTable,
+    AttributeTableEvent, BdAddr, BleEncryption, BleError, BondedDevice, BtStatus, BtUuid,
+    ConnectEvent, Connection, ConnectionParameters, ConnectionParametersPolicy, DisconnectReason,
+    ExecWriteEvent, ExtendedAdvertisingDuration, ExtendedAdvertisingParameters,
+    ExtendedAdvertisingSet, GapEvent, GattCharacteristic, GattClientEvent, GattDescriptor,
+    GattService, GattServiceEvent, GattStatus, NotifyError, PairingDelegate, PairingFailure,
+    PeriodicAdvertisingParameters, RemoteCharacteristic, RemoteDescriptor, RemoteService,
+    ScanParameters, ScanResult, ScanSearchEvent, SecurityConfig, ServiceHandles, StateError,
+    SubscriptionEvent, WriteEvent, WriteType, MAX_PASSKEY,
+};
+#[cfg(target_os = "espidf")]
+use crate::{RawGapEvent, RawGattClientEvent, RawGattServiceEvent};
+
+/// Backend of the running `EspBle`, `None` until one is created.
+static BACKEND: Mutex<Option<Arc<dyn BleBackend>>> = Mutex::new(None);
+
+static EXT_ADV_SETS: Mutex<ExtendedAdvertisingSets> = Mutex::new(ExtendedAdvertisingSets::new());
+
+/// Release `set` once it is removed or after its configuration failed.
+pub(crate) fn release_extended_advertising_set(set: ExtendedAdvertisingSet) {
+    if let Ok(mut sets) = EXT_ADV_SETS.lock() {
+        sets.release(set);
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum GapCallbacks {
+    RawAdvertisingDataset,
+    RawScanResponseDataset,
+    AdvertisingDataset,
+    ScanResponseDataset,
+    AdvertisingStart,
+    AdvertisingStop,
+    ScanParameterDataset,
+    ScanStart,
+    ScanResult,
+    ScanStop,
+    UpdateConnectionParams(BdAddr),
+    RemoveBond(BdAddr),
+    PasskeyNotify,
+    KeyEvent,
+    AuthComplete,
+    NumericComparisonRequest,
+    PasskeyRequest,
+    OOBRequest,
+    SecureConnectionsOOBRequest,
+    LocalOOBData,
+    SecurityRequest,
+    ExtendedAdvertisingRandomAddress,
+    ExtendedAdvertisingParams,
+    ExtendedAdvertisingDataset,
+    ExtendedScanResponseDataset,
+    ExtendedAdvertisingStart,
+    ExtendedAdvertisingStop,
+    ExtendedAdvertisingRemove,
+    ExtendedAdvertisingClear,
+    AdvertisingTerminated,
+    PeriodicAdvertisingParams,
+    PeriodicAdvertisingDataset,
+    PeriodicAdvertisingStart,
+    PeriodicAdvertisingStop,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum GattCallbacks {
+    Register(u16),              // app_id
+    Create(u8),                 // gatts_if
+    Start(u16),                 // svc_handle
+    AddCharacteristic(u16),     // svc_handle
+    AddCharacteristicDesc(u16), // svc_handle
+    Read(u16),                  // attr_handle
+    Write(u16),                 // attr_handle
+    Connect(u8),                // gatts_if
+    Confirm(u16, u16),          // conn_id, attr_handle
+    Mtu(u8),                    // gatts_if
+    CreateAttributeTable(u8),   // gatts_if
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum GattClientCallbacks {
+    Register(u16),                 // app_id
+    Open(u8, BdAddr),              // gattc_if, remote_bda
+    SearchComplete(u16),           // conn_id
+    ReadCharacteristic(u16, u16),  // conn_id, handle
+    WriteCharacteristic(u16, u16), // conn_id, handle
+    ReadDescriptor(u16, u16),      // conn_id, handle
+    WriteDescriptor(u16, u16),     // conn_id, handle
+    Connect(u8),                   // gattc_if
+    Disconnect(u8),                // gattc_if
+    Notify(BdAddr, u16),           // remote_bda, handle
+}
+
+lazy_static! {
+    static ref GAP_CALLBACKS: Dispatcher<GapCallbacks, GapEvent> = Dispatcher::new();
+    static ref GATT_CALLBACKS: Dispatcher<GattCallbacks, (u8, GattServiceEvent)> =
+        Dispatcher::new();
+    static ref NOTIFICATIONS: Mutex<NotificationState> = Mutex::new(NotificationState::default());
+    static ref SUBSCRIPTION_CALLBACKS: Dispatcher<(), SubscriptionEvent> = Dispatcher::new();
+    static ref LONG_ATTRIBUTES: Mutex<LongAttributeState> =
+        Mutex::new(LongAttributeState::default());
+    static ref CONNECTIONS: Mutex<ConnectionManager> = Mutex::new(ConnectionManager::default());
+    static ref PAIRING_DELEGATE: Mutex<Option<Arc<dyn PairingDelegate>>> = Mutex::new(None);
+    static ref CONNECTED_CALLBACKS: Dispatcher<(), Connection> = Dispatcher::new();
+    static ref DISCONNECTED_CALLBACKS: Dispatcher<(), (Connection, DisconnectReason)> =
+        Dispatcher::new();
+    static ref GATTC_CALLBACKS: Dispatcher<GattClientCallbacks, (u8, GattClientEvent)> =
+        Dispatcher::new();
+}
+
+#[cfg(target_os = "espidf")]
+lazy_static! {
+    static ref GAP_RAW_CALLBACKS: Dispatcher<(), RawGapEvent> = Dispatcher::new();
+    static ref GATT_RAW_CALLBACKS: Dispatcher<(), (u8, RawGattServiceEvent)> = Dispatcher::new();
+    static ref GATTC_RAW_CALLBACKS: Dispatcher<(), (u8, RawGattClientEvent)> = Dispatcher::new();
+}
+
+fn insert_gatt_cb_kept(
+    cb_key: GattCallbacks,
+    cb: impl Fn(u8, GattServiceEvent) + Send + Sync + 'static,
+) {
+    GATT_CALLBACKS.insert_kept(cb_key, move |(gatts_if, event)| cb(gatts_if, event));
+}
+
+fn insert_gatt_cb_queued(
+    cb_key: GattCallbacks,
+    cb: impl Fn(u8, GattServiceEvent) + Send + 'static,
+) -> usize {
+    GATT_CALLBACKS.push_queued(cb_key, move |(gatts_if, event)| cb(gatts_if, event))
+}
+
+/// Give up waiting for the completion of a GATT server operation, e.g. after
+/// a timeout.
+pub(crate) fn remove_gatt_completion(cb_key: GattCallbacks) {
+    GATT_CALLBACKS.remove_one_time(cb_key);
+}
+
+/// Same as `remove_gatt_completion` for the queued callback `id`, the
+/// completion of its operation is still awaited to keep the queue in order.
+pub(crate) fn abandon_gatt_queued(cb_key: GattCallbacks, id: usize) {
+    GATT_CALLBACKS.abandon_queued(cb_key, id);
+}
+
+fn insert_gatt_cb_onetime(
+    cb_key: GattCallbacks,
+    cb: impl Fn(u8, GattServiceEvent) + Send + 'static,
+) {
+    GATT_CALLBACKS.insert_one_time(cb_key, move |(gatts_if, event)| cb(gatts_if, event));
+}
+
+/// Result of the call starting a GATT server operation, its one-time callback
+/// is removed when the call fails.
+fn gatt_call(cb_key: GattCallbacks, result: Result<(), EspError>) -> Result<(), BleError> {
+    if result.is_err() {
+        remove_gatt_completion(cb_key);
+    }
+    Ok(result?)
+}
+
+fn insert_gattc_cb_kept(
+    cb_key: GattClientCallbacks,
+    cb: impl Fn(u8, GattClientEvent) + Send + Sync + 'static,
+) {
+    GATTC_CALLBACKS.insert_kept(cb_key, move |(gattc_if, event)| cb(gattc_if, event));
+}
+
+fn insert_gattc_cb_onetime(
+    cb_key: GattClientCallbacks,
+    cb: impl Fn(u8, GattClientEvent) + Send + 'static,
+) {
+    GATTC_CALLBACKS.insert_one_time(cb_key, move |(gattc_if, event)| cb(gattc_if, event));
+}
+
+/// Result of the call starting a GATT client operation, its one-time callback
+/// is removed when the call fails.
+fn gattc_call(cb_key: GattClientCallbacks, result: Result<(), EspError>) -> Result<(), BleError> {
+    if result.is_err() {
+        remove_gattc_completion(cb_key);
+    }
+    Ok(result?)
+}
+
+/// Give up waiting for the completion of a GATT client operation, e.g. after
+/// a timeout.
+pub(crate) fn remove_gattc_completion(cb_key: GattClientCallbacks) {
+    GATTC_CALLBACKS.remove_one_time(cb_key);
+}
+
+fn insert_gap_cb(cb_key: GapCallbacks, cb: impl Fn(GapEvent) + Send + Sync + 'static) {
+    GAP_CALLBACKS.insert_kept(cb_key, cb);
+}
+
+/// Register `cb` for the completion of a GAP operation. The completion events
+/// do not tell which call they complete, so only one operation of each kind
+/// can be pending.
+fn insert_gap_completion(
+    cb_key: GapCallbacks,
+    cb: impl Fn(GapEvent) + Send + 'static,
+) -> Result<(), BleError> {
+    if GAP_CALLBACKS.try_insert_one_time(cb_key, cb) {
+        Ok(())
+    } else {
+        warn!("A {:?} operation is already pending", cb_key);
+        Err(BleError::InvalidState(StateError::OperationPending))
+    }
+}
+
+/// Result of the call starting a GAP operation, its completion callback is
+/// removed when the call fails.
+fn gap_call(cb_key: GapCallbacks, result: Result<(), EspError>) -> Result<(), BleError> {
+    if result.is_err() {
+        remove_gap_completion(cb_key);
+    }
+    Ok(result?)
+}
+
+/// Give up waiting for the completion of a GAP operation, e.g. after a
+/// timeout, for another one of the same kind to be started.
+pub(crate) fn remove_gap_completion(cb_key: GapCallbacks) {
+    GAP_CALLBACKS.remove_one_time(cb_key);
+}
+
+fn warn_dropped(cb_key: Option<impl std::fmt::Debug>) {
+    match cb_key {
+        Some(cb_key) => warn!("No callback registered for {:?}", cb_key),
+        None => warn!("Unhandled event, no catch-all handler registered"),
+    }
+}
+
+type ServiceCallback = Arc<Mutex<dyn Fn(Result<ServiceHandles, GattStatus>) + Send>>;
+
+fn report_service(cb: &ServiceCallback, result: Result<ServiceHandles, GattStatus>) {
+    if let Ok(cb) = cb.lock() {
+        cb(result);
+    }
+}
+
+/// Second step of `EspBle::create_attribute_table`: track the CCCDs of the
+/// table and start the service.
+fn attribute_table_created(
+    gatts_if: u8,
+    table: &AttributeTable,
+    created: AttributeTableEvent,
+    cb: ServiceCallback,
+) {
+    if !created.status.is_ok() {
+        warn!(
+            "Unable to create the service {:?}: {:?}",
+            table.uuid(),
+            created.status
+        );
+        report_service(&cb, Err(created.status));
+        return;
+    }
+
+    let handles = match table.handles(&created.handles) {
+        Some(handles) => handles,
+        None => {
+            warn!(
+                "Service {:?} created with {} handles for {} attributes",
+                table.uuid(),
+                created.handles.len(),
+                table.attributes().len()
+            );
+            report_service(&cb, Err(GattStatus::Unlikely));
+            return;
+        }
+    };
+
+    if let Ok(mut notifications) = NOTIFICATIONS.lock() {
+        for characteristic in &handles.characteristics {
+            if let Some(cccd) = characteristic.cccd() {
+                notifications.add_cccd(gatts_if, characteristic.value_handle, cccd);
+            }
+        }
+    }
+
+    let service_handle = handles.service_handle;
+    let started = cb.clone();
+    insert_gatt_cb_onetime(GattCallbacks::Start(service_handle), move |_, event| {
+        if let GattServiceEvent::StartComplete(start) = event {
+            let result = if start.status.is_ok() {
+                Ok(handles.clone())
+            } else {
+                Err(start.status)
+            };
+            report_service(&started, result);
+        }
+    });
+
+    let result =
+        current_backend().and_then(|backend| Ok(backend.gatts_start_service(service_handle)?));
+    if let Err(err) = result {
+        warn!("Unable to start the service {:?}: {}", table.uuid(), err);
+        GATT_CALLBACKS.remove_one_time(GattCallbacks::Start(service_handle));
+        report_service(&cb, Err(GattStatus::Unlikely));
+    }
+}
+
+fn is_bonded(bda: BdAddr) -> bool {
+    current_backend()
+        .and_then(|backend| Ok(backend.bonded_devices()?))
+        .map(|bonds| bonds.iter().any(|bond| bond.bda == bda))
+        .unwrap_or(false)
+}
+
+fn save_subscriptions(bda: BdAddr) {
+    if let Ok(notifications) = NOTIFICATIONS.lock() {
+        notifications.save(bda);
+    }
+}
+
+/// Stack of the threads answering the pairing requests with the delegate
+const PAIRING_STACK_SIZE: usize = 4096;
+
+/// Pairing delegate until one is set: accepts the security requests, but no
+/// user can confirm a numeric comparison or enter a passkey, so the pairings
+/// with MITM protection are rejected. Passkeys to display are only logged.
+struct RejectMitm;
+
+impl PairingDelegate for RejectMitm {
+    fn on_passkey_display(&self, bda: BdAddr, passkey: u32) {
+        info!("Passkey for {}: {:06}", bda, passkey);
+    }
+
+    fn on_numeric_comparison(&self, bda: BdAddr, _passkey: u32) -> bool {
+        warn!("No pairing delegate to confirm the passkey of {}", bda);
+        false
+    }
+
+    fn on_pairing_complete(&self, bda: BdAddr, result: Result<(), PairingFailure>) {
+        info!("Pairing with {}: {:?}", bda, result);
+    }
+}
+
+fn pairing_delegate() -> Arc<dyn PairingDelegate> {
+    PAIRING_DELEGATE
+        .lock()
+        .ok()
+        .and_then(|delegate| delegate.clone())
+        .unwrap_or_else(|| Arc::new(RejectMitm))
+}
+
+/// Answer a pairing request from a thread of its own, the delegate may wait
+/// for the user.
+fn answer_pairing_request(answer: impl FnOnce(&dyn PairingDelegate) + Send + 'static) {
+    let delegate = pairing_delegate();
+    if let Err(err) = std::thread::Builder::new()
+        .name("ble-pairing".into())
+        .stack_size(PAIRING_STACK_SIZE)
+        .spawn(move || answer(delegate.as_ref()))
+    {
+        warn!("Unable to answer the pairing request: {}", err);
+    }
+}
+
+/// The OOB requests can't be rejected, the pairing is stopped by dropping the
+/// link instead.
+fn reject_oob_request(backend: &Arc<dyn BleBackend>, bda: BdAddr) -> Result<(), EspError> {
+    warn!("No OOB data for {}, disconnecting", bda);
+    backend.disconnect(bda)
+}
+
+fn insert_pairing_callbacks(backend: &Arc<dyn BleBackend>) {
+    let security_backend = backend.clone();
+    insert_gap_cb(GapCallbacks::SecurityRequest, move |sec_req| {
+        if let GapEvent::SecurityRequest(bda) = sec_req {
+            info!("SecurityRequest from {}", bda);
+            let backend = security_backend.clone();
+            answer_pairing_request(move |delegate| {
+                let accept = delegate.on_security_request(bda);
+                if let Err(err) = backend.security_rsp(bda, accept) {
+                    warn!("Unable to answer the security request: {}", err);
+                }
+            });
+        }
+    });
+
+    let passkey_backend = backend.clone();
+    insert_gap_cb(GapCallbacks::PasskeyRequest, move |request| {
+        if let GapEvent::PasskeyRequest(bda) = request {
+            let backend = passkey_backend.clone();
+            answer_pairing_request(move |delegate| {
+                let passkey = delegate.on_passkey_request(bda);
+                if let Some(passkey) = passkey.filter(|passkey| *passkey > MAX_PASSKEY) {
+                    warn!("Passkey {} has more than 6 digits", passkey);
+                }
+                let passkey = passkey.filter(|passkey| *passkey <= MAX_PASSKEY);
+                let result =
+                    backend.passkey_reply(bda, passkey.is_some(), passkey.unwrap_or_default());
+                if let Err(err) = result {
+                    warn!("Unable to answer the passkey request: {}", err);
+                }
+            });
+        }
+    });
+
+    let oob_backend = backend.clone();
+    insert_gap_cb(GapCallbacks::OOBRequest, move |request| {
+        if let GapEvent::OOBRequest(bda) = request {
+            let backend = oob_backend.clone();
+            answer_pairing_request(move |delegate| {
+                let result = match delegate.on_oob_request(bda) {
+                    Some(tk) => backend.oob_req_reply(bda, &tk),
+                    None => reject_oob_request(&backend, bda),
+                };
+                if let Err(err) = result {
+                    warn!("Unable to answer the OOB request: {}", err);
+                }
+            });
+        }
+    });
+
+    let sc_oob_backend = backend.clone();
+    insert_gap_cb(GapCallbacks::SecureConnectionsOOBRequest, move |request| {
+        if let GapEvent::SecureConnectionsOOBRequest(bda) = request {
+            let backend = sc_oob_backend.clone();
+            answer_pairing_request(move |delegate| {
+                let result = match delegate.on_sc_oob_request(bda) {
+                    Some(data) => backend.sc_oob_req_reply(bda, &data),
+                    None => reject_oob_request(&backend, bda),
+                };
+                if let Err(err) = result {
+                    warn!("Unable to answer the OOB request: {}", err);
+                }
+            });
+        }
+    });
+
+    let comparison_backend = backend.clone();
+    insert_gap_cb(GapCallbacks::NumericComparisonRequest, move |request| {
+        if let GapEvent::NumericComparisonRequest(request) = request {
+            let backend = comparison_backend.clone();
+            answer_pairing_request(move |delegate| {
+                let accept = delegate.on_numeric_comparison(request.bda, request.passkey);
+                if let Err(err) = backend.confirm_reply(request.bda, accept) {
+                    warn!("Unable to complete numeric comparison request: {}", err);
+                }
+            });
+        }
+    });
+
+    insert_gap_cb(GapCallbacks::PasskeyNotify, |notify| {
+        if let GapEvent::PasskeyNotification(notify) = notify {
+            pairing_delegate().on_passkey_display(notify.bda, notify.passkey);
+        }
+    });
+    insert_gap_cb(GapCallbacks::AuthComplete, |auth| {
+        if let GapEvent::AuthenticationComplete(auth) = auth {
+            let result = if auth.success {
+                Ok(())
+            } else {
+                Err(PairingFailure {
+                    reason: auth.fail_reason,
+                })
+            };
+            pairing_delegate().on_pairing_complete(auth.bda, result);
+        }
+    });
+}
+
+/// MTU of the connection `conn_id`, `ATT_DEFAULT_MTU` when it is unknown.
+fn connection_mtu(conn_id: u16) -> u16 {
+    CONNECTIONS
+        .lock()
+        .ok()
+        .and_then(|connections| connections.get(conn_id))
+        .map_or(ATT_DEFAULT_MTU, |connection| connection.mtu)
+}
+
+/// Check that `value` can be sent to `conn_id` for `attr_handle`, and return
+/// the interface to send it with.
+fn check_notification(
+    notifications: &NotificationState,
+    conn_id: u16,
+    attr_handle: u16,
+    value: &[u8],
+    mtu: u16,
+    indicate: bool,
+) -> Result<u8, BleError> {
+    notifications
+        .check(conn_id, attr_handle, value.len(), mtu, indicate)
+        .map_err(|err| {
+            warn!("Unable to send the value of {}: {}", attr_handle, err);
+            match err {
+                NotifyError::UnknownCharacteristic(handle) => {
+                    ArgumentError::UnknownCharacteristic(handle).into()
+                }
+                NotifyError::ValueTooLong { len, max } => {
+                    ArgumentError::PayloadTooLong { len, max }.into()
+                }
+                NotifyError::NotSubscribed { conn_id, handle } => {
+                    StateError::NotSubscribed { conn_id, handle }.into()
+                }
+                NotifyError::IndicationInProgress(conn_id) => {
+                    StateError::IndicationPending(conn_id).into()
+                }
+            }
+        })
+}
+
+fn current_backend() -> Result<Arc<dyn BleBackend>, BleError> {
+    BACKEND
+        .lock()
+        .ok()
+        .and_then(|backend| backend.clone())
+        .ok_or(BleError::InvalidState(StateError::NotRunning))
+}
+
+#[cfg(target_os = "espidf")]
+pub(crate) fn dispatch_raw_gap_event(event: RawGapEvent) {
+    GAP_RAW_CALLBACKS.dispatch(None, event);
+}
+
+/// Call the callback registered for a GAP event reported by the backend.
+#[cfg_attr(
+    not(any(target_os = "espidf", feature = "simulated", test)),
+    allow(dead_code)
+)]
+pub(crate) fn dispatch_gap_event(event: GapEvent) {
+    debug!("Called gap event handler with event {{ {:#?} }}", &event);
+
+    if let Ok(mut connections) = CONNECTIONS.lock() {
+        connections.handle_gap_event(&event);
+    }
+
+    match &event {
+        // The peer may have subscribed before bonding
+        GapEvent::AuthenticationComplete(auth) if auth.success && is_bonded(auth.bda) => {
+            save_subscriptions(auth.bda);
+        }
+        GapEvent::RemoveDeviceBondComplete(bond) if bond.status.is_success() => {
+            if let Ok(notifications) = NOTIFICATIONS.lock() {
+                notifications.forget(bond.bda);
+            }
+        }
+        _ => (),
+    }
+
+    let cb_key = match &event {
+        GapEvent::RawAdvertisingDatasetComplete(_) => Some(GapCallbacks::RawAdvertisingDataset),
+        GapEvent::RawScanResponseDatasetComplete(_) => Some(GapCallbacks::RawScanResponseDataset),
+        GapEvent::AdvertisingDatasetComplete(_) => Some(GapCallbacks::AdvertisingDataset),
+        GapEvent::ScanResponseDatasetComplete(_) => Some(GapCallbacks::ScanResponseDataset),
+        GapEvent::AdvertisingStartComplete(_) => Some(GapCallbacks::AdvertisingStart),
+        GapEvent::AdvertisingStopComplete(_) => Some(GapCallbacks::AdvertisingStop),
+        GapEvent::ScanParameterDatasetComplete(_) => Some(GapCallbacks::ScanParameterDataset),
+        GapEvent::ScanStartComplete(_) => Some(GapCallbacks::ScanStart),
+        GapEvent::ScanResult(_) => Some(GapCallbacks::ScanResult),
+        GapEvent::ScanStopComplete(_) => Some(GapCallbacks::ScanStop),
+        GapEvent::UpdateConnectionParamsComplete(params) => {
+            Some(GapCallbacks::UpdateConnectionParams(params.bda))
+        }
+        GapEvent::RemoveDeviceBondComplete(bond) => Some(GapCallbacks::RemoveBond(bond.bda)),
+        GapEvent::PasskeyNotification(_) => Some(GapCallbacks::PasskeyNotify),
+        GapEvent::Key(_) => Some(GapCallbacks::KeyEvent),
+        GapEvent::AuthenticationComplete(_) => Some(GapCallbacks::AuthComplete),
+        GapEvent::NumericComparisonRequest(_) => Some(GapCallbacks::NumericComparisonRequest),
+        GapEvent::SecurityRequest(_) => Some(GapCallbacks::SecurityRequest),
+        GapEvent::PasskeyRequest(_) => Some(GapCallbacks::PasskeyRequest),
+        GapEvent::OOBRequest(_) => Some(GapCallbacks::OOBRequest),
+        GapEvent::SecureConnectionsOOBRequest(_) => Some(GapCallbacks::SecureConnectionsOOBRequest),
+        GapEvent::LocalOOBDataCreated(_) => Some(GapCallbacks::LocalOOBData),
+        GapEvent::ExtendedAdvertisingSetRandomAddressComplete(_) => {
+            Some(GapCallbacks::ExtendedAdvertisingRandomAddress)
+        }
+        GapEvent::ExtendedAdvertisingSetParamsComplete(_) => {
+            Some(GapCallbacks::ExtendedAdvertisingParams)
+        }
+        GapEvent::ExtendedAdvertisingDatasetComplete(_) => {
+            Some(GapCallbacks::ExtendedAdvertisingDataset)
+        }
+        GapEvent::ExtendedScanResponseDatasetComplete(_) => {
+            Some(GapCallbacks::ExtendedScanResponseDataset)
+        }
+        GapEvent::ExtendedAdvertisingStartComplete(_) => {
+            Some(GapCallbacks::ExtendedAdvertisingStart)
+        }
+        GapEvent::ExtendedAdvertisingStopComplete(_) => Some(GapCallbacks::ExtendedAdvertisingStop),
+        GapEvent::ExtendedAdvertisingSetRemoveComplete(_) => {
+            Some(GapCallbacks::ExtendedAdvertisingRemove)
+        }
+        GapEvent::ExtendedAdvertisingSetClearComplete(_) => {
+            Some(GapCallbacks::ExtendedAdvertisingClear)
+        }
+        GapEvent::AdvertisingTerminated(_) => Some(GapCallbacks::AdvertisingTerminated),
+        GapEvent::PeriodicAdvertisingSetParamsComplete(_) => {
+            Some(GapCallbacks::PeriodicAdvertisingParams)
+        }
+        GapEvent::PeriodicAdvertisingDatasetComplete(_) => {
+            Some(GapCallbacks::PeriodicAdvertisingDataset)
+        }
+        GapEvent::PeriodicAdvertisingStartComplete(_) => {
+            Some(GapCallbacks::PeriodicAdvertisingStart)
+        }
+        GapEvent::PeriodicAdvertisingStopComplete(_) => Some(GapCallbacks::PeriodicAdvertisingStop),
+        _ => None,
+    };
+
+    if GAP_CALLBACKS.dispatch(cb_key, event) == Dispatched::Dropped {
+        warn_dropped(cb_key);
+    }
+}
+
+#[cfg(target_os = "espidf")]
+pub(crate) fn dispatch_raw_gatts_event(gatts_if: u8, event: RawGattServiceEvent) {
+    GATT_RAW_CALLBACKS.dispatch(None, (gatts_if, event));
+}
+
+/// Call the callback registered for a GATT server event reported by the backend.
+#[cfg_attr(
+    not(any(target_os = "espidf", feature = "simulated", test)),
+    allow(dead_code)
+)]
+pub(crate) fn dispatch_gatts_event(gatts_if: u8, event: GattServiceEvent) {
+    debug!(
+        "Called gatt service event handler with gatts_if: {}, event {{ {:#?} }}",
+        gatts_if, &event
+    );
+
+    let change = CONNECTIONS
+        .lock()
+        .ok()
+        .and_then(|mut connections| connections.handle_gatts_event(&event));
+    let mut subscriptions = vec![];
+    if let Ok(mut notifications) = NOTIFICATIONS.lock() {
+        subscriptions.extend(notifications.handle_event(gatts_if, &event));
+    }
+    match &event {
+        GattServiceEvent::Connect(connect) if is_bonded(connect.bda) => {
+            if let Ok(mut notifications) = NOTIFICATIONS.lock() {
+                subscriptions.extend(notifications.restore(connect.conn_id, connect.bda));
+            }
+        }
+        GattServiceEvent::Write(write) if !subscriptions.is_empty() && is_bonded(write.bda) => {
+            save_subscriptions(write.bda);
+        }
+        _ => (),
+    }
+    match change {
+        Some(ConnectionChange::Connected(connection)) => {
+            CONNECTED_CALLBACKS.dispatch(Some(()), connection);
+        }
+        Some(ConnectionChange::Disconnected(connection, reason)) => {
+            DISCONNECTED_CALLBACKS.dispatch(Some(()), (connection, reason));
+        }
+        None => (),
+    }
+    for subscription in subscriptions {
+        SUBSCRIPTION_CALLBACKS.dispatch(Some(()), subscription);
+    }
+
+    // The callbacks only get the complete value of the prepared writes
+    match &event {
+        GattServiceEvent::Read(read) => {
+            if let Ok(mut long_attributes) = LONG_ATTRIBUTES.lock() {
+                long_attributes.read(read);
+            }
+        }
+        GattServiceEvent::Write(write) if write.is_prep => {
+            prepare_write(gatts_if, write);
+            return;
+        }
+        GattServiceEvent::ExecWrite(exec) => {
+            execute_writes(gatts_if, exec);
+            return;
+        }
+        GattServiceEvent::Disconnect(disconnect) => {
+            if let Ok(mut long_attributes) = LONG_ATTRIBUTES.lock() {
+                long_attributes.disconnected(disconnect.conn_id);
+            }
+        }
+        _ => (),
+    }
+
+    // Notifications are confirmed as well, there is nothing to report for them
+    let mut expected = true;
+    let cb_key = match &event {
+        GattServiceEvent::Register(reg) => Some(GattCallbacks::Register(reg.app_id)),
+        GattServiceEvent::Create(_) => Some(GattCallbacks::Create(gatts_if)),
+        GattServiceEvent::CreateAttributeTableComplete(_) => {
+            Some(GattCallbacks::CreateAttributeTable(gatts_if))
+        }
+        GattServiceEvent::StartComplete(start) => Some(GattCallbacks::Start(start.service_handle)),
+        GattServiceEvent::AddCharacteristicComplete(add_char) => {
+            Some(GattCallbacks::AddCharacteristic(add_char.service_handle))
+        }
+        GattServiceEvent::AddDescriptorComplete(add_desc) => Some(
+            GattCallbacks::AddCharacteristicDesc(add_desc.service_handle),
+        ),
+        GattServiceEvent::Connect(conn) => {
+            info!("Connection from: {:?}", conn);
+            request_connection_params(gatts_if, conn);
+            Some(GattCallbacks::Connect(gatts_if))
+        }
+        GattServiceEvent::Mtu(_) => Some(GattCallbacks::Mtu(gatts_if)),
+        GattServiceEvent::Read(read) => Some(GattCallbacks::Read(read.handle)),
+        GattServiceEvent::Write(write) => Some(GattCallbacks::Write(write.handle)),
+        GattServiceEvent::Confirm(conf) => {
+            let indication = NOTIFICATIONS
+                .lock()
+                .map(|mut notifications| notifications.end_indication(conf.conn_id, conf.handle))
+                .unwrap_or(false);
+            expected = indication;
+            indication.then_some(GattCallbacks::Confirm(conf.conn_id, conf.handle))
+        }
+        _ => None,
+    };
+
+    if GATT_CALLBACKS.dispatch(cb_key, (gatts_if, event)) == Dispatched::Dropped && expected {
+        warn_dropped(cb_key);
+    }
+}
+
+/// Request the parameters the policy of the application `gatts_if` chooses
+/// for a new connection.
+fn request_connection_params(gatts_if: u8, connect: &ConnectEvent) {
+    // The policy is called without the lock, it can look at the connections
+    let policy = CONNECTIONS
+        .lock()
+        .map(|connections| connections.policy(gatts_if))
+        .unwrap_or_default();
+    let params = match policy.parameters(connect) {
+        Some(params) => params,
+        None => return,
+    };
+    if let Err(err) = params.validate() {
+        warn!(
+            "Invalid connection parameters for {:?}: {}",
+            connect.bda, err
+        );
+        return;
+    }
+
+    if let Err(err) = current_backend()
+        .and_then(|backend| Ok(backend.update_conn_params(&params.update(connect.bda))?))
+    {
+        warn!("Unable to update the connection parameters: {}", err);
+    }
+}
+
+/// Queue a prepared write until the client executes it, the response carries
+/// the value back to the client.
+fn prepare_write(gatts_if: u8, write: &WriteEvent) {
+    let status = LONG_ATTRIBUTES
+        .lock()
+        .map_err(|_| GattStatus::Unlikely)
+        .and_then(|mut long_attributes| long_attributes.prepare(write))
+        .err()
+        .unwrap_or(GattStatus::Ok);
+    if write.need_rsp {
+        let response = GattResponse {
+            status,
+            handle: write.handle,
+            offset: write.offset,
+            value: &write.value,
+        };
+        respond(gatts_if, write.conn_id, write.trans_id, &response);
+    }
+}
+
+/// Answer the client executing or cancelling its prepared writes, and report
+/// the values they make to the write callbacks.
+fn execute_writes(gatts_if: u8, exec: &ExecWriteEvent) {
+    let writes = LONG_ATTRIBUTES
+        .lock()
+        .map_err(|_| GattStatus::Unlikely)
+        .and_then(|mut long_attributes| long_attributes.execute(exec));
+    let response = GattResponse {
+        status: writes.as_ref().err().copied().unwrap_or(GattStatus::Ok),
+        handle: 0,
+        offset: 0,
+        value: &[],
+    };
+    respond(gatts_if, exec.conn_id, exec.trans_id, &response);
+
+    for write in writes.unwrap_or_default() {
+        dispatch_gatts_event(gatts_if, GattServiceEvent::Write(write));
+    }
+}
+
+fn respond(gatts_if: u8, conn_id: u16, trans_id: u32, response: &GattResponse) {
+    if let Err(err) = current_backend()
+        .and_then(|backend| Ok(backend.gatts_send_response(gatts_if, conn_id, trans_id, response)?))
+    {
+        warn!(
+            "Unable to respond to request {} of connection {}: {}",
+            trans_id, conn_id, err
+        );
+    }
+}
+
+#[cfg(target_os = "espidf")]
+pub(crate) fn dispatch_raw_gattc_event(gattc_if: u8, event: RawGattClientEvent) {
+    GATTC_RAW_CALLBACKS.dispatch(None, (gattc_if, event));
+}
+
+/// Call the callback registered for a GATT client event reported by the backend.
+#[cfg_attr(
+    not(any(target_os = "espidf", feature = "simulated", test)),
+    allow(dead_code)
+)]
+pub(crate) fn dispatch_gattc_event(gattc_if: u8, event: GattClientEvent) {
+    debug!(
+        "Called gatt client event handler with gattc_if: {}, event {{ {:#?} }}",
+        gattc_if, &event
+    );
+
+    let cb_key = match &event {
+        GattClientEvent::Register(reg) => Some(GattClientCallbacks::Register(reg.app_id)),
+        GattClientEvent::Open(open) => Some(GattClientCallbacks::Open(gattc_if, open.bda)),
+        GattClientEvent::SearchComplete(cmpl) => {
+            Some(GattClientCallbacks::SearchComplete(cmpl.conn_id))
+        }
+        GattClientEvent::ReadCharacteristic(read) => Some(GattClientCallbacks::ReadCharacteristic(
+            read.conn_id,
+            read.handle,
+        )),
+        GattClientEvent::WriteCharacteristic(write) => Some(
+            GattClientCallbacks::WriteCharacteristic(write.conn_id, write.handle),
+        ),
+        GattClientEvent::ReadDescriptor(read) => Some(GattClientCallbacks::ReadDescriptor(
+            read.conn_id,
+            read.handle,
+        )),
+        GattClientEvent::WriteDescriptor(write) => Some(GattClientCallbacks::WriteDescriptor(
+            write.conn_id,
+            write.handle,
+        )),
+        GattClientEvent::Connect(_) => Some(GattClientCallbacks::Connect(gattc_if)),
+        GattClientEvent::Disconnect(_) => Some(GattClientCallbacks::Disconnect(gattc_if)),
+        GattClientEvent::Notify(notify) => {
+            Some(GattClientCallbacks::Notify(notify.bda, notify.handle))
+        }
+        _ => None,
+    };
+
+    if GATTC_CALLBACKS.dispatch(cb_key, (gattc_if, event)) == Dispatched::Dropped {
+        warn_dropped(cb_key);
+    }
+}
+
+#[allow(dead_code)]
+pub struct EspBle {
+    device_name: String,
+    backend: Arc<dyn BleBackend>,
+    #[cfg(target_os = "espidf")]
+    nvs: Option<Arc<Mutex<EspDefaultNvs>>>,
+}
+
+impl EspBle {
+    /// Start the Bluedroid stack. The subscriptions of the bonded peers are
+    /// saved in the namespace of `nvs`, which is locked to write them.
+    ///
+    /// `local_mtu` is checked as by `with_backend`.
+    #[cfg(target_os = "espidf")]
+    pub fn new(
+        device_name: String,
+        local_mtu: u16,
+        nvs: Arc<Mutex<EspDefaultNvs>>,
+    ) -> Result<EspBle, BleError> {
+        let mut ble = Self::with_backend(device_name, local_mtu, BluedroidBackend)?;
+        if let Ok(mut notifications) = NOTIFICATIONS.lock() {
+            notifications.set_store(Arc::new(NvsSubscriptionStore::new(nvs.clone())));
+        }
+        ble.nvs = Some(nvs);
+        Ok(ble)
+    }
+
+    /// Start the Bluetooth stack through `backend`, e.g. a `SimulatedBackend`
+    /// in tests. Only one `EspBle` can exist at a time.
+    ///
+    /// `local_mtu`, from `ATT_DEFAULT_MTU` to `ATT_MAX_MTU`, is the MTU offered
+    /// to the clients exchanging theirs.
+    pub fn with_backend(
+        device_name: String,
+        local_mtu: u16,
+        backend: impl BleBackend + 'static,
+    ) -> Result<EspBle, BleError> {
+        if !(ATT_DEFAULT_MTU..=ATT_MAX_MTU).contains(&local_mtu) {
+            warn!(
+                "Invalid local MTU {}, it must be from {} to {}",
+                local_mtu, ATT_DEFAULT_MTU, ATT_MAX_MTU
+            );
+            return Err(BleError::InvalidArgument(ArgumentError::LocalMtu(
+                local_mtu,
+            )));
+        }
+
+        let backend: Arc<dyn BleBackend> = Arc::new(backend);
+        {
+            let mut current = BACKEND
+                .lock()
+                .map_err(|_| BleError::InvalidState(StateError::LockPoisoned))?;
+            if current.is_some() {
+                return Err(BleError::InvalidState(StateError::AlreadyRunning));
+            }
+            backend.init()?;
+            *current = Some(backend.clone());
+        }
+
+        // From here on, dropping `ble` on an error stops the stack and
+        // releases `BACKEND` for the next `EspBle`.
+        let ble = EspBle {
+            device_name,
+            backend,
+            #[cfg(target_os = "espidf")]
+            nvs: None,
+        };
+
+        ble.backend.set_local_mtu(local_mtu)?;
+        ble.backend.set_device_name(&ble.device_name)?;
+
+        Ok(ble)
+    }
+
+    pub fn configure_advertising_data_raw(
+        &self,
+        data: RawAdvertiseData,
+        cb: impl Fn(GapEvent) + 'static + Send,
+    ) -> Result<(), BleError> {
+        info!("configure_advertising_data_raw enter");
+
+        if data.len() > ADV_DATA_MAX_LEN {
+            warn!(
+                "Advertising data too long: {} bytes, at most {} are allowed",
+                data.len(),
+                ADV_DATA_MAX_LEN
+            );
+            return Err(BleError::InvalidArgument(ArgumentError::PayloadTooLong {
+                len: data.len(),
+                max: ADV_DATA_MAX_LEN,
+            }));
+        }
+
+        if data.set_scan_rsp {
+            insert_gap_completion(GapCallbacks::RawScanResponseDataset, cb)?;
+            gap_call(
+                GapCallbacks::RawScanResponseDataset,
+                self.backend.config_scan_rsp_data_raw(&data.to_vec()),
+            )
+        } else {
+            insert_gap_completion(GapCallbacks::RawAdvertisingDataset, cb)?;
+            gap_call(
+                GapCallbacks::RawAdvertisingDataset,
+                self.backend.config_adv_data_raw(&data.to_vec()),
+            )
+        }
+    }
+
+    pub fn configure_advertising_data(
+        &self,
+        data: advertise::AdvertiseData,
+        cb: impl Fn(GapEvent) + 'static + Send,
+    ) -> Result<(), BleError> {
+        info!("configure_advertising enter");
+
+        let cb_key = if data.set_scan_rsp {
+            GapCallbacks::ScanResponseDataset
+        } else {
+            GapCallbacks::AdvertisingDataset
+        };
+        insert_gap_completion(cb_key, cb)?;
+        gap_call(cb_key, self.backend.config_adv_data(&data))
+    }
+
+    pub fn start_advertise(
+        &self,
+        params: AdvertisingParameters,
+        cb: impl Fn(GapEvent) + 'static + Send,
+    ) -> Result<(), BleError> {
+        info!("start_advertise enter");
+
+        if let Err(err) = params.validate() {
+            warn!("Invalid advertising parameters: {}", err);
+            return Err(BleError::InvalidArgument(
+                ArgumentError::AdvertisingParameters(err),
+            ));
+        }
+
+        insert_gap_completion(GapCallbacks::AdvertisingStart, cb)?;
+        gap_call(
+            GapCallbacks::AdvertisingStart,
+            self.backend.start_advertising(&params),
+        )
+    }
+
+    pub fn stop_advertise(&self, cb: impl Fn(GapEvent) + 'static + Send) -> Result<(), BleError> {
+        info!("stop_advertise enter");
+
+        insert_gap_completion(GapCallbacks::AdvertisingStop, cb)?;
+        gap_call(
+            GapCallbacks::AdvertisingStop,
+            self.backend.stop_advertising(),
+        )
+    }
+
+    /// Start scanning for `duration` seconds (0 scans until `stop_scan` is called),
+    /// `cb` is called for every advertising report received.
+    pub fn start_scan(
+        &self,
+        params: ScanParameters,
+        duration: u32,
+        cb: impl Fn(ScanResult) + 'static + Send + Sync,
+    ) -> Result<(), BleError> {
+        self.scan(params, duration, cb, |result| {
+            if let Err(err) = result {
+                warn!("Unable to start scanning: {}", err);
+            }
+        })
+    }
+
+    /// `start_scan` reporting whether scanning started to `started`.
+    pub(crate) fn scan(
+        &self,
+        params: ScanParameters,
+        duration: u32,
+        cb: impl Fn(ScanResult) + 'static + Send + Sync,
+        started: impl Fn(Result<(), BleError>) + 'static + Send + Sync,
+    ) -> Result<(), BleError> {
+        info!("start_scan enter");
+
+        let started = Arc::new(started);
+        let scan_started = started.clone();
+        let backend = self.backend.clone();
+        insert_gap_completion(GapCallbacks::ScanParameterDataset, move |evt| {
+            if let GapEvent::ScanParameterDatasetComplete(status) = evt {
+                if let Err(err) = bt_result(status) {
+                    started(Err(err));
+                    return;
+                }
+                let scan_started = scan_started.clone();
+                let registered = insert_gap_completion(GapCallbacks::ScanStart, move |evt| {
+                    if let GapEvent::ScanStartComplete(status) = evt {
+                        scan_started(bt_result(status));
+                    }
+                });
+                if let Err(err) = registered.and_then(|_| {
+                    gap_call(GapCallbacks::ScanStart, backend.start_scanning(duration))
+                }) {
+                    started(Err(err));
+                }
+            }
+        })?;
+        insert_gap_cb(GapCallbacks::ScanResult, move |evt| match evt {
+            GapEvent::ScanResult(ScanSearchEvent::Result(result)) => cb(result),
+            GapEvent::ScanResult(ScanSearchEvent::Complete { .. }) => info!("Scan complete"),
+            _ => (),
+        });
+
+        gap_call(
+            GapCallbacks::ScanParameterDataset,
+            self.backend.set_scan_params(&params),
+        )
+    }
+
+    pub fn stop_scan(&self, cb: impl Fn(GapEvent) + 'static + Send) -> Result<(), BleError> {
+        info!("stop_scan enter");
+
+        insert_gap_completion(GapCallbacks::ScanStop, cb)?;
+        gap_call(GapCallbacks::ScanStop, self.backend.stop_scanning())
+    }
+
+    pub fn register_gatt_service_application(
+        &mut self,
+        app_id: u16,
+        cb: impl Fn(u8, GattServiceEvent) + 'static + Send,
+    ) -> Result<(), BleError> {
+        info!(
+            "register_gatt_service_application enter for app_id: {}",
+            app_id
+        );
+        insert_gatt_cb_onetime(GattCallbacks::Register(app_id), cb);
+        gatt_call(
+            GattCallbacks::Register(app_id),
+            self.backend.gatts_app_register(app_id),
+        )
+    }
+
+    pub fn create_service(
+        &self,
+        gatt_if: u8,
+        svc: GattService,
+        cb: impl Fn(u8, GattServiceEvent) + 'static + Send,
+    ) -> Result<(), BleError> {
+        insert_gatt_cb_onetime(GattCallbacks::Create(gatt_if), cb);
+        gatt_call(
+            GattCallbacks::Create(gatt_if),
+            self.backend.gatts_create_service(gatt_if, &svc),
+        )
+    }
+
+    /// Create the service described by `table` and start it, `cb` is called
+    /// with the handles of its attributes once it is started.
+    ///
+    /// The CCCDs of the table are tracked for `notify` and `indicate`.
+    pub fn create_attribute_table(
+        &self,
+        gatts_if: u8,
+        table: AttributeTable,
+        cb: impl Fn(Result<ServiceHandles, GattStatus>) + 'static + Send,
+    ) -> Result<(), BleError> {
+        let table = Arc::new(table);
+        let cb: ServiceCallback = Arc::new(Mutex::new(cb));
+
+        let created = table.clone();
+        let cb_key = GattCallbacks::CreateAttributeTable(gatts_if);
+        insert_gatt_cb_onetime(cb_key, move |gatts_if, event| {
+            if let GattServiceEvent::CreateAttributeTableComplete(event) = event {
+                attribute_table_created(gatts_if, &created, event, cb.clone());
+            }
+        });
+
+        gatt_call(
+            cb_key,
+            self.backend
+                .gatts_create_attr_tab(gatts_if, table.attributes(), table.instance_id()),
+        )
+    }
+
+    pub fn start_service(
+        &self,
+        svc_handle: u16,
+        cb: impl Fn(u8, GattServiceEvent) + 'static + Send,
+    ) -> Result<(), BleError> {
+        insert_gatt_cb_onetime(GattCallbacks::Start(svc_handle), cb);
+        gatt_call(
+            GattCallbacks::Start(svc_handle),
+            self.backend.gatts_start_service(svc_handle),
+        )
+    }
+
+    pub fn read_attribute_value(&self, attr_handle: u16) -> Result<Vec<u8>, BleError> {
+        Ok(self.backend.gatts_get_attr_value(attr_handle)?)
+    }
+
+    /// Several characteristics can be added to a service without waiting,
+    /// `cb` is called when this one is added.
+    pub fn add_characteristic<const S: usize>(
+        &self,
+        svc_handle: u16,
+        charac: GattCharacteristic<S>,
+        cb: impl Fn(u8, GattServiceEvent) + 'static + Send,
+    ) -> Result<(), BleError> {
+        self.queue_characteristic(svc_handle, charac, cb)
+            .map(|_| ())
+    }
+
+    /// `add_characteristic`, returns the id of the queued callback.
+    pub(crate) fn queue_characteristic<const S: usize>(
+        &self,
+        svc_handle: u16,
+        charac: GattCharacteristic<S>,
+        cb: impl Fn(u8, GattServiceEvent) + 'static + Send,
+    ) -> Result<usize, BleError> {
+        let cb_key = GattCallbacks::AddCharacteristic(svc_handle);
+        let id = insert_gatt_cb_queued(cb_key, cb);
+
+        let result = self.backend.gatts_add_char(
+            svc_handle,
+            &charac.uuid,
+            charac.permissions,
+            charac.property,
+            charac.value.as_slice(),
+            charac.auto_rsp,
+        );
+        if result.is_err() {
+            GATT_CALLBACKS.remove_queued(cb_key, id);
+        }
+        result?;
+        Ok(id)
+    }
+
+    /// Add a descriptor to the characteristic added last to the service.
+    /// Several descriptors can be added without waiting, `cb` is called when
+    /// this one is added.
+    pub fn add_descriptor(
+        &self,
+        svc_handle: u16,
+        char_desc: GattDescriptor,
+        cb: impl Fn(u8, GattServiceEvent) + 'static + Send,
+    ) -> Result<(), BleError> {
+        self.queue_descriptor(svc_handle, char_desc, cb).map(|_| ())
+    }
+
+    /// `add_descriptor`, returns the id of the queued callback.
+    pub(crate) fn queue_descriptor(
+        &self,
+        svc_handle: u16,
+        char_desc: GattDescriptor,
+        cb: impl Fn(u8, GattServiceEvent) + 'static + Send,
+    ) -> Result<usize, BleError> {
+        let cb_key = GattCallbacks::AddCharacteristicDesc(svc_handle);
+        let id = insert_gatt_cb_queued(cb_key, cb);
+
+        let result =
+            self.backend
+                .gatts_add_char_descr(svc_handle, &char_desc.uuid, char_desc.permissions);
+        if result.is_err() {
+            GATT_CALLBACKS.remove_queued(cb_key, id);
+        }
+        result?;
+        Ok(id)
+    }
+
+    pub fn register_connect_handler(
+        &self,
+        gatts_if: u8,
+        cb: impl Fn(u8, GattServiceEvent) + 'static + Send + Sync,
+    ) {
+        insert_gatt_cb_kept(GattCallbacks::Connect(gatts_if), cb);
+    }
+
+    pub fn register_read_handler(
+        &self,
+        attr_handle: u16,
+        cb: impl Fn(u8, GattServiceEvent) + 'static + Send + Sync,
+    ) {
+        insert_gatt_cb_kept(GattCallbacks::Read(attr_handle), cb);
+    }
+
+    pub fn register_write_handler(
+        &self,
+        attr_handle: u16,
+        cb: impl Fn(u8, GattServiceEvent) + 'static + Send + Sync,
+    ) {
+        insert_gatt_cb_kept(GattCallbacks::Write(attr_handle), cb);
+    }
+
+    /// `cb` is called with the `GattServiceEvent::Mtu` of the connections of
+    /// the application `gatts_if`, once the client exchanged its MTU.
+    pub fn register_mtu_handler(
+        &self,
+        gatts_if: u8,
+        cb: impl Fn(u8, GattServiceEvent) + 'static + Send + Sync,
+    ) {
+        insert_gatt_cb_kept(GattCallbacks::Mtu(gatts_if), cb);
+    }
+
+    /// MTU of the connection `conn_id`: `ATT_DEFAULT_MTU` until the client
+    /// exchanges its MTU, `None` when it is not connected. Notifications and
+    /// indications must fit in it, responses to reads are cut to fit in it.
+    pub fn mtu(&self, conn_id: u16) -> Option<u16> {
+        self.connection(conn_id).map(|connection| connection.mtu)
+    }
+
+    /// Set the connection parameters the application `gatts_if` requests
+    /// from the centrals connecting to it.
+    pub fn set_connection_params_policy(
+        &self,
+        gatts_if: u8,
+        policy: ConnectionParametersPolicy,
+    ) -> Result<(), BleError> {
+        if let ConnectionParametersPolicy::Fixed(params) = &policy {
+            if let Err(err) = params.validate() {
+                warn!("Invalid connection parameters: {}", err);
+                return Err(BleError::InvalidArgument(
+                    ArgumentError::ConnectionParameters(err),
+                ));
+            }
+        }
+
+        CONNECTIONS
+            .lock()
+            .map_err(|_| BleError::InvalidState(StateError::LockPoisoned))?
+            .set_policy(gatts_if, policy);
+        Ok(())
+    }
+
+    /// Request `params` from the central of the link `conn_id`, `cb` is
+    /// called once with the next `GapEvent::UpdateConnectionParamsComplete`
+    /// of its peer. Only one update per peer can be pending.
+    pub fn update_connection_params(
+        &self,
+        conn_id: u16,
+        params: ConnectionParameters,
+        cb: impl Fn(GapEvent) + 'static + Send,
+    ) -> Result<(), BleError> {
+        if let Err(err) = params.validate() {
+            warn!("Invalid connection parameters: {}", err);
+            return Err(BleError::InvalidArgument(
+                ArgumentError::ConnectionParameters(err),
+            ));
+        }
+        let connection = match self.connection(conn_id) {
+            Some(connection) => connection,
+            None => {
+                warn!("No connection {} to update", conn_id);
+                return Err(BleError::InvalidState(StateError::NotConnected(conn_id)));
+            }
+        };
+
+        let cb_key = GapCallbacks::UpdateConnectionParams(connection.bda);
+        insert_gap_completion(cb_key, cb)?;
+        gap_call(
+            cb_key,
+            self.backend
+                .update_conn_params(&params.update(connection.bda)),
+        )
+    }
+
+    /// `cb` is called with each new link of the GATT server.
+    pub fn register_connected_handler(&self, cb: impl Fn(Connection) + 'static + Send + Sync) {
+        CONNECTED_CALLBACKS.insert_kept((), cb);
+    }
+
+    /// `cb` is called with the link and the reason when a link of the GATT
+    /// server drops, once the state of the connection is cleared, e.g. to
+    /// restart advertising.
+    pub fn register_disconnected_handler(
+        &self,
+        cb: impl Fn(Connection, DisconnectReason) + 'static + Send + Sync,
+    ) {
+        DISCONNECTED_CALLBACKS.insert_kept((), move |(connection, reason)| cb(connection, reason));
+    }
+
+    /// The link `conn_id`, `None` when it is not connected.
+    pub fn connection(&self, conn_id: u16) -> Option<Connection> {
+        CONNECTIONS
+            .lock()
+            .ok()
+            .and_then(|connections| connections.get(conn_id))
+    }
+
+    /// The active links of the GATT server.
+    pub fn connections(&self) -> Vec<Connection> {
+        CONNECTIONS
+            .lock()
+            .map(|connections| connections.connections())
+            .unwrap_or_default()
+    }
+
+    /// Drop the link `conn_id`, the disconnected handler is called once it is
+    /// down.
+    pub fn disconnect(&self, conn_id: u16) -> Result<(), BleError> {
+        let connection = match self.connection(conn_id) {
+            Some(connection) => connection,
+            None => {
+                warn!("No connection {} to disconnect", conn_id);
+                return Err(BleError::InvalidState(StateError::NotConnected(conn_id)));
+            }
+        };
+
+        Ok(self.backend.disconnect(connection.bda)?)
+    }
+
+    /// Notify the client `conn_id` of the value of the characteristic `attr_handle`.
+    ///
+    /// The client must have enabled notifications in the CCCD of the
+    /// characteristic, and `value` must fit in the MTU of the connection.
+    pub fn notify(&self, conn_id: u16, attr_handle: u16, value: &[u8]) -> Result<(), BleError> {
+        let mtu = connection_mtu(conn_id);
+        let gatts_if = NOTIFICATIONS
+            .lock()
+            .map_err(|_| BleError::InvalidState(StateError::LockPoisoned))
+            .and_then(|notifications| {
+                check_notification(&notifications, conn_id, attr_handle, value, mtu, false)
+            })?;
+
+        Ok(self
+            .backend
+            .gatts_send_indicate(gatts_if, conn_id, attr_handle, value, false)?)
+    }
+
+    /// Same as `notify` for an indication, `cb` is called with the
+    /// `GattServiceEvent::Confirm` once the client confirms it. Only one
+    /// indication at a time can wait for its confirmation on a connection.
+    pub fn indicate(
+        &self,
+        conn_id: u16,
+        attr_handle: u16,
+        value: &[u8],
+        cb: impl Fn(u8, GattServiceEvent) + 'static + Send,
+    ) -> Result<(), BleError> {
+        let mtu = connection_mtu(conn_id);
+        let gatts_if = {
+            let mut notifications = NOTIFICATIONS
+                .lock()
+                .map_err(|_| BleError::InvalidState(StateError::LockPoisoned))?;
+            let gatts_if =
+                check_notification(&notifications, conn_id, attr_handle, value, mtu, true)?;
+            notifications.start_indication(conn_id, attr_handle);
+            gatts_if
+        };
+
+        let cb_key = GattCallbacks::Confirm(conn_id, attr_handle);
+        insert_gatt_cb_onetime(cb_key, cb);
+
+        let result = self
+            .backend
+            .gatts_send_indicate(gatts_if, conn_id, attr_handle, value, true);
+        if result.is_err() {
+            remove_gatt_completion(cb_key);
+            if let Ok(mut notifications) = NOTIFICATIONS.lock() {
+                notifications.end_indication(conn_id, attr_handle);
+            }
+        }
+        Ok(result?)
+    }
+
+    /// `cb` is called when a client enables or disables notifications or
+    /// indications in a CCCD, and when a bonded peer reconnects with the
+    /// subscriptions of its previous connection. Subscriptions end with the
+    /// connection, without a call.
+    ///
+    /// The subscriptions of the bonded peers are kept in the nvs namespace
+    /// given to `EspBle::new`, under keys starting with `cc`.
+    pub fn register_subscription_handler(
+        &self,
+        cb: impl Fn(SubscriptionEvent) + 'static + Send + Sync,
+    ) {
+        SUBSCRIPTION_CALLBACKS.insert_kept((), cb);
+    }
+
+    /// Register a handler for the GATT server events that have no callback
+    /// registered, including the ones unknown to this crate.
+    pub fn register_gatts_catch_all_handler(
+        &self,
+        cb: impl Fn(u8, GattServiceEvent) + 'static + Send + Sync,
+    ) {
+        GATT_CALLBACKS.set_catch_all(move |(gatts_if, event)| cb(gatts_if, event));
+    }
+
+    /// Register a handler for the GAP events that have no callback registered,
+    /// including the ones unknown to this crate.
+    pub fn register_gap_catch_all_handler(&self, cb: impl Fn(GapEvent) + 'static + Send + Sync) {
+        GAP_CALLBACKS.set_catch_all(cb);
+    }
+
+    /// Configure the Security Manager, and answer the pairing requests with
+    /// the delegate, see `set_pairing_delegate`. Nothing is set when `config`
+    /// is invalid, see `SecurityConfig::validate`.
+    pub fn configure_security(&self, config: SecurityConfig) -> Result<(), BleError> {
+        if let Err(err) = config.validate() {
+            warn!("Invalid security configuration: {}", err);
+            return Err(BleError::Security(err));
+        }
+
+        self.backend.set_security_param(
+            SecurityParam::AuthenticationRequestMode,
+            &[config.auth_req_mode as u8],
+        )?;
+
+        self.backend.set_security_param(
+            SecurityParam::IoCapabilities,
+            &[config.io_capabilities as u8],
+        )?;
+
+        if let Some(initiator_key) = config.initiator_key {
+            self.backend
+                .set_security_param(SecurityParam::InitiatorKey, &[initiator_key.bits()])?;
+        }
+
+        if let Some(responder_key) = config.responder_key {
+            self.backend
+                .set_security_param(SecurityParam::ResponderKey, &[responder_key.bits()])?;
+        }
+        if let Some(max_key_size) = config.max_key_size {
+            self.backend
+                .set_security_param(SecurityParam::MaxKeySize, &[max_key_size])?;
+        }
+        if let Some(min_key_size) = config.min_key_size {
+            self.backend
+                .set_security_param(SecurityParam::MinKeySize, &[min_key_size])?;
+        }
+        match config.static_passkey {
+            Some(passkey) => self
+                .backend
+                .set_security_param(SecurityParam::StaticPasskey, &passkey.to_le_bytes())?,
+            None => self
+                .backend
+                .set_security_param(SecurityParam::ClearStaticPasskey, &[0])?,
+        }
+        self.backend.set_security_param(
+            SecurityParam::OnlyAcceptSpecifiedAuthentication,
+            &[u8::from(config.only_accept_specified_auth)],
+        )?;
+        self.backend
+            .set_security_param(SecurityParam::OobSupport, &[u8::from(config.enable_oob)])?;
+
+        insert_gap_cb(GapCallbacks::KeyEvent, |key| {
+            if let GapEvent::Key(key) = key {
+                info!("Key: {:?}", key.key_type);
+            }
+        });
+        insert_pairing_callbacks(&self.backend);
+
+        Ok(())
+    }
+
+    /// Answer the pairing requests with `delegate`. Until one is set, the
+    /// numeric comparisons and passkey requests are rejected, and the passkeys
+    /// to display are logged.
+    pub fn set_pairing_delegate(&self, delegate: impl PairingDelegate + 'static) {
+        if let Ok(mut current) = PAIRING_DELEGATE.lock() {
+            *current = Some(Arc::new(delegate));
+        }
+        insert_pairing_callbacks(&self.backend);
+    }
+
+    /// Create the Secure Connections OOB data of this device, to be passed to
+    /// the peer out of band. `cb` is called with the
+    /// `GapEvent::LocalOOBDataCreated` carrying it.
+    ///
+    /// Requires ESP-IDF 5, the stack refuses the call with older versions.
+    pub fn create_local_oob_data(
+        &self,
+        cb: impl Fn(GapEvent) + 'static + Send,
+    ) -> Result<(), BleError> {
+        insert_gap_completion(GapCallbacks::LocalOOBData, cb)?;
+        gap_call(
+            GapCallbacks::LocalOOBData,
+            self.backend.create_sc_oob_data(),
+        )
+    }
+
+    /// The peers this device is bonded with.
+    pub fn bonded_devices(&self) -> Result<Vec<BondedDevice>, BleError> {
+        Ok(self.backend.bonded_devices()?)
+    }
+
+    /// Remove the bond with `bda`, and the subscriptions kept for it. `cb` is
+    /// called with the `GapEvent::RemoveDeviceBondComplete` of `bda`.
+    pub fn remove_bond(
+        &self,
+        bda: BdAddr,
+        cb: impl Fn(GapEvent) + 'static + Send,
+    ) -> Result<(), BleError> {
+        insert_gap_completion(GapCallbacks::RemoveBond(bda), cb)?;
+        gap_call(
+            GapCallbacks::RemoveBond(bda),
+            self.backend.remove_bond_device(bda),
+        )
+    }
+
+    /// Remove the bonds with every peer, and return their addresses. `cb` is
+    /// called once with a `GapEvent::ClearDeviceBondComplete`, right away when
+    /// there is no bond, with the status of the first removal that failed or
+    /// `BtStatus::Success` once they all completed.
+    pub fn clear_bonds(
+        &self,
+        cb: impl Fn(GapEvent) + 'static + Send,
+    ) -> Result<Vec<BdAddr>, BleError> {
+        let bonds: Vec<BdAddr> = self
+            .bonded_devices()?
+            .into_iter()
+            .map(|bond| bond.bda)
+            .collect();
+        if bonds.is_empty() {
+            cb(GapEvent::ClearDeviceBondComplete(BtStatus::Success));
+            return Ok(bonds);
+        }
+
+        // The bonds still being removed, and the callback until it is called
+        let state = Arc::new(Mutex::new((bonds.clone(), Some(cb))));
+        for (index, bda) in bonds.iter().enumerate() {
+            let removal = state.clone();
+            let result = self.remove_bond(*bda, move |event| {
+                let bond = match event {
+                    GapEvent::RemoveDeviceBondComplete(bond) => bond,
+                    _ => return,
+                };
+                let cb = match removal.lock() {
+                    Ok(mut removal) => {
+                        removal.0.retain(|bda| *bda != bond.bda);
+                        if bond.status.is_success() && !removal.0.is_empty() {
+                            return;
+                        }
+                        removal.1.take()
+                    }
+                    Err(_) => return,
+                };
+                if let Some(cb) = cb {
+                    cb(GapEvent::ClearDeviceBondComplete(bond.status));
+                }
+            });
+
+            if let Err(err) = result {
+                for bda in &bonds[..index] {
+                    remove_gap_completion(GapCallbacks::RemoveBond(*bda));
+                }
+                return Err(err);
+            }
+        }
+        Ok(bonds)
+    }
+
+    pub fn configure_gatt_encryption(
+        remote_bda: [u8; 6],
+        encryption_config: BleEncryption,
+    ) -> Result<(), BleError> {
+        Ok(current_backend()?.set_encryption(remote_bda.into(), encryption_config)?)
+    }
+}
+
+#[cfg(target_os = "espidf")]
+impl EspBle {
+    /// Register a handler called with every GATT server event as received from
+    /// Bluedroid, before it is dispatched. The pointers it contains are only
+    /// valid during the call.
+    pub fn register_gatts_raw_handler(
+        &self,
+        cb: impl Fn(u8, RawGattServiceEvent) + 'static + Send + Sync,
+    ) {
+        GATT_RAW_CALLBACKS.set_catch_all(move |(gatts_if, event)| cb(gatts_if, event));
+    }
+
+    /// Register a handler called with every GATT client event as received from
+    /// Bluedroid, before it is dispatched. The pointers it contains are only
+    /// valid during the call.
+    pub fn register_gattc_raw_handler(
+        &self,
+        cb: impl Fn(u8, RawGattClientEvent) + 'static + Send + Sync,
+    ) {
+        GATTC_RAW_CALLBACKS.set_catch_all(move |(gattc_if, event)| cb(gattc_if, event));
+    }
+
+    /// Register a handler called with every GAP event as received from
+    /// Bluedroid, before it is dispatched.
+    pub fn register_gap_raw_handler(&self, cb: impl Fn(RawGapEvent) + 'static + Send + Sync) {
+        GAP_RAW_CALLBACKS.set_catch_all(cb);
+    }
+}
+
+impl EspBle {
+    pub fn register_gatt_client_application(
+        &mut self,
+        app_id: u16,
+        cb: impl Fn(u8, GattClientEvent) + 'static + Send,
+    ) -> Result<(), BleError> {
+        info!(
+            "register_gatt_client_application enter for app_id: {}",
+            app_id
+        );
+        insert_gattc_cb_onetime(GattClientCallbacks::Register(app_id), cb);
+        gattc_call(
+            GattClientCallbacks::Register(app_id),
+            self.backend.gattc_app_register(app_id),
+        )
+    }
+
+    pub fn open(
+        &self,
+        gattc_if: u8,
+        remote_bda: BdAddr,
+        remote_addr_type: AddressType,
+        cb: impl Fn(u8, GattClientEvent) + 'static + Send,
+    ) -> Result<(), BleError> {
+        info!("open enter for remote_bda: {}", remote_bda);
+        let cb_key = GattClientCallbacks::Open(gattc_if, remote_bda);
+        insert_gattc_cb_onetime(cb_key, cb);
+        gattc_call(
+            cb_key,
+            self.backend
+                .gattc_open(gattc_if, remote_bda, remote_addr_type),
+        )
+    }
+
+    pub fn close(&self, gattc_if: u8, conn_id: u16) -> Result<(), BleError> {
+        Ok(self.backend.gattc_close(gattc_if, conn_id)?)
+    }
+
+    pub fn search_services(
+        &self,
+        gattc_if: u8,
+        conn_id: u16,
+        filter: Option<BtUuid>,
+        cb: impl Fn(u8, GattClientEvent) + 'static + Send,
+    ) -> Result<(), BleError> {
+        let cb_key = GattClientCallbacks::SearchComplete(conn_id);
+        insert_gattc_cb_onetime(cb_key, cb);
+        gattc_call(
+            cb_key,
+            self.backend
+                .gattc_search_service(gattc_if, conn_id, filter.as_ref()),
+        )
+    }
+
+    /// Read the services found by `search_services` from the local cache.
+    pub fn get_services(&self, gattc_if: u8, conn_id: u16) -> Result<Vec<RemoteService>, BleError> {
+        Ok(self.backend.gattc_get_services(gattc_if, conn_id)?)
+    }
+
+    pub fn get_characteristics(
+        &self,
+        gattc_if: u8,
+        conn_id: u16,
+        svc: &RemoteService,
+    ) -> Result<Vec<RemoteCharacteristic>, BleError> {
+        Ok(self.backend.gattc_get_characteristics(
+            gattc_if,
+            conn_id,
+            svc.start_handle,
+            svc.end_handle,
+        )?)
+    }
+
+    pub fn get_descriptors(
+        &self,
+        gattc_if: u8,
+        conn_id: u16,
+        charac: &RemoteCharacteristic,
+    ) -> Result<Vec<RemoteDescriptor>, BleError> {
+        Ok(self
+            .backend
+            .gattc_get_descriptors(gattc_if, conn_id, charac.handle)?)
+    }
+
+    pub fn read_characteristic(
+        &self,
+        gattc_if: u8,
+        conn_id: u16,
+        handle: u16,
+        cb: impl Fn(u8, GattClientEvent) + 'static + Send,
+    ) -> Result<(), BleError> {
+        let cb_key = GattClientCallbacks::ReadCharacteristic(conn_id, handle);
+        insert_gattc_cb_onetime(cb_key, cb);
+        gattc_call(
+            cb_key,
+            self.backend.gattc_read_char(gattc_if, conn_id, handle),
+        )
+    }
+
+    pub fn write_characteristic(
+        &self,
+        gattc_if: u8,
+        conn_id: u16,
+        handle: u16,
+        value: &[u8],
+        write_type: WriteType,
+        cb: impl Fn(u8, GattClientEvent) + 'static + Send,
+    ) -> Result<(), BleError> {
+        let cb_key = GattClientCallbacks::WriteCharacteristic(conn_id, handle);
+        insert_gattc_cb_onetime(cb_key, cb);
+        gattc_call(
+            cb_key,
+            self.backend
+                .gattc_write_char(gattc_if, conn_id, handle, value, write_type),
+        )
+    }
+
+    pub fn read_descriptor(
+        &self,
+        gattc_if: u8,
+        conn_id: u16,
+        handle: u16,
+        cb: impl Fn(u8, GattClientEvent) + 'static + Send,
+    ) -> Result<(), BleError> {
+        let cb_key = GattClientCallbacks::ReadDescriptor(conn_id, handle);
+        insert_gattc_cb_onetime(cb_key, cb);
+        gattc_call(
+            cb_key,
+            self.backend
+                .gattc_read_char_descr(gattc_if, conn_id, handle),
+        )
+    }
+
+    pub fn write_descriptor(
+        &self,
+        gattc_if: u8,
+        conn_id: u16,
+        handle: u16,
+        value: &[u8],
+        write_type: WriteType,
+        cb: impl Fn(u8, GattClientEvent) + 'static + Send,
+    ) -> Result<(), BleError> {
+        let cb_key = GattClientCallbacks::WriteDescriptor(conn_id, handle);
+        insert_gattc_cb_onetime(cb_key, cb);
+        gattc_call(
+            cb_key,
+            self.backend
+                .gattc_write_char_descr(gattc_if, conn_id, handle, value, write_type),
+        )
+    }
+
+    pub fn register_client_connect_handler(
+        &self,
+        gattc_if: u8,
+        cb: impl Fn(u8, GattClientEvent) + 'static + Send + Sync,
+    ) {
+        insert_gattc_cb_kept(GattClientCallbacks::Connect(gattc_if), cb);
+    }
+
+    pub fn register_client_disconnect_handler(
+        &self,
+        gattc_if: u8,
+        cb: impl Fn(u8, GattClientEvent) + 'static + Send + Sync,
+    ) {
+        insert_gattc_cb_kept(GattClientCallbacks::Disconnect(gattc_if), cb);
+    }
+
+    /// Ask the server to send the notifications and indications of the
+    /// characteristic at `handle`, `cb` is called with each of them.
+    ///
+    /// The server only sends them once the client characteristic
+    /// configuration descriptor of the characteristic is written.
+    pub fn register_for_notify(
+        &self,
+        gattc_if: u8,
+        remote_bda: BdAddr,
+        handle: u16,
+        cb: impl Fn(u8, GattClientEvent) + 'static + Send + Sync,
+    ) -> Result<(), BleError> {
+        insert_gattc_cb_kept(GattClientCallbacks::Notify(remote_bda, handle), cb);
+        let result = self
+            .backend
+            .gattc_register_for_notify(gattc_if, remote_bda, handle);
+        if result.is_err() {
+            GATTC_CALLBACKS.remove_kept(GattClientCallbacks::Notify(remote_bda, handle));
+        }
+        Ok(result?)
+    }
+
+    /// Stop receiving the notifications and indications of the characteristic
+    /// at `handle`.
+    pub fn unregister_for_notify(
+        &self,
+        gattc_if: u8,
+        remote_bda: BdAddr,
+        handle: u16,
+    ) -> Result<(), BleError> {
+        GATTC_CALLBACKS.remove_kept(GattClientCallbacks::Notify(remote_bda, handle));
+        Ok(self
+            .backend
+            .gattc_unregister_for_notify(gattc_if, remote_bda, handle)?)
+    }
+
+    /// Register a handler for the GATT client events that have no callback
+    /// registered, including the ones unknown to this crate.
+    pub fn register_gattc_catch_all_handler(
+        &self,
+        cb: impl Fn(u8, GattClientEvent) + 'static + Send + Sync,
+    ) {
+        GATTC_CALLBACKS.set_catch_all(move |(gattc_if, event)| cb(gattc_if, event));
+    }
+}
+
+impl EspBle {
+    /// Allocate a new advertising set and configure it with `params`.
+    ///
+    /// The advertising sets need a stack built with BLE 5.0 features, the
+    /// calls fail with `ESP_ERR_NOT_SUPPORTED` otherwise.
+    pub fn create_extended_advertising_set(
+        &self,
+        params: ExtendedAdvertisingParameters,
+        cb: impl Fn(GapEvent) + 'static + Send,
+    ) -> Result<ExtendedAdvertisingSet, BleError> {
+        info!("create_extended_advertising_set enter");
+
+        if let Err(err) = params.validate() {
+            warn!("Invalid extended advertising parameters: {}", err);
+            return Err(BleError::InvalidArgument(
+                ArgumentError::ExtendedAdvertising(err),
+            ));
+        }
+
+        insert_gap_completion(GapCallbacks::ExtendedAdvertisingParams, cb)?;
+        let set = match EXT_ADV_SETS.lock().map(|mut sets| sets.allocate()) {
+            Ok(Ok(set)) => set,
+            Ok(Err(err)) => {
+                warn!("Unable to create advertising set: {}", err);
+                remove_gap_completion(GapCallbacks::ExtendedAdvertisingParams);
+                return Err(BleError::InvalidState(StateError::NoAdvertisingSet));
+            }
+            Err(_) => {
+                remove_gap_completion(GapCallbacks::ExtendedAdvertisingParams);
+                return Err(BleError::InvalidState(StateError::LockPoisoned));
+            }
+        };
+
+        let result = self.backend.ext_adv_set_params(set.instance, &params);
+        if let Err(err) = gap_call(GapCallbacks::ExtendedAdvertisingParams, result) {
+            release_extended_advertising_set(set);
+            return Err(err);
+        }
+
+        Ok(set)
+    }
+
+    /// Set the random address used by `set` when advertising with `AddressType::Random`.
+    pub fn set_extended_advertising_random_address(
+        &self,
+        set: &ExtendedAdvertisingSet,
+        addr: [u8; 6],
+        cb: impl Fn(GapEvent) + 'static + Send,
+    ) -> Result<(), BleError> {
+        info!("set_extended_advertising_random_address enter");
+
+        insert_gap_completion(GapCallbacks::ExtendedAdvertisingRandomAddress, cb)?;
+        gap_call(
+            GapCallbacks::ExtendedAdvertisingRandomAddress,
+            self.backend
+                .ext_adv_set_rand_addr(set.instance, addr.into()),
+        )
+    }
+
+    /// Set the advertising data of `set`, up to `EXT_ADV_DATA_MAX_LEN` bytes.
+    ///
+    /// The payload can be built with `AdvertiseType::encode_all`.
+    pub fn set_extended_advertising_data(
+        &self,
+        set: &ExtendedAdvertisingSet,
+        data: &[u8],
+        cb: impl Fn(GapEvent) + 'static + Send,
+    ) -> Result<(), BleError> {
+        info!("set_extended_advertising_data enter");
+
+        if let Err(err) = validate_extended_data(data) {
+            warn!("Invalid extended advertising data: {}", err);
+            return Err(BleError::InvalidArgument(
+                ArgumentError::ExtendedAdvertising(err),
+            ));
+        }
+
+        insert_gap_completion(GapCallbacks::ExtendedAdvertisingDataset, cb)?;
+        gap_call(
+            GapCallbacks::ExtendedAdvertisingDataset,
+            self.backend.config_ext_adv_data_raw(set.instance, data),
+        )
+    }
+
+    /// Set the scan response data of `set`, up to `EXT_ADV_DATA_MAX_LEN` bytes.
+    pub fn set_extended_scan_response_data(
+        &self,
+        set: &ExtendedAdvertisingSet,
+        data: &[u8],
+        cb: impl Fn(GapEvent) + 'static + Send,
+    ) -> Result<(), BleError> {
+        info!("set_extended_scan_response_data enter");
+
+        if let Err(err) = validate_extended_data(data) {
+            warn!("Invalid extended scan response data: {}", err);
+            return Err(BleError::InvalidArgument(
+                ArgumentError::ExtendedAdvertising(err),
+            ));
+        }
+
+        insert_gap_completion(GapCallbacks::ExtendedScanResponseDataset, cb)?;
+        gap_call(
+            GapCallbacks::ExtendedScanResponseDataset,
+            self.backend
+                .config_ext_scan_rsp_data_raw(set.instance, data),
+        )
+    }
+
+    /// Start advertising the given sets, each one until stopped or until its duration elapses,
+    /// see `register_advertising_terminated_handler`.
+    pub fn start_extended_advertising(
+        &self,
+        sets: &[(&ExtendedAdvertisingSet, ExtendedAdvertisingDuration)],
+        cb: impl Fn(GapEvent) + 'static + Send,
+    ) -> Result<(), BleError> {
+        info!("start_extended_advertising enter");
+
+        let sets: Vec<(u8, ExtendedAdvertisingDuration)> = sets
+            .iter()
+            .map(|(set, duration)| (set.instance, *duration))
+            .collect();
+
+        insert_gap_completion(GapCallbacks::ExtendedAdvertisingStart, cb)?;
+        gap_call(
+            GapCallbacks::ExtendedAdvertisingStart,
+            self.backend.ext_adv_start(&sets),
+        )
+    }
+
+    pub fn stop_extended_advertising(
+        &self,
+        sets: &[&ExtendedAdvertisingSet],
+        cb: impl Fn(GapEvent) + 'static + Send,
+    ) -> Result<(), BleError> {
+        info!("stop_extended_advertising enter");
+
+        let instances: Vec<u8> = sets.iter().map(|set| set.instance).collect();
+
+        insert_gap_completion(GapCallbacks::ExtendedAdvertisingStop, cb)?;
+        gap_call(
+            GapCallbacks::ExtendedAdvertisingStop,
+            self.backend.ext_adv_stop(&instances),
+        )
+    }
+
+    /// `cb` is called when a set stops advertising because its duration elapsed,
+    /// or because a connection was established.
+    pub fn register_advertising_terminated_handler(
+        &self,
+        cb: impl Fn(GapEvent) + 'static + Send + Sync,
+    ) {
+        insert_gap_cb(GapCallbacks::AdvertisingTerminated, cb);
+    }
+
+    /// Remove `set`, its instance can be allocated again once the stack
+    /// reports the removal succeeded. The set stays allocated until
+    /// `clear_extended_advertising_sets` if another removal is pending.
+    pub fn remove_extended_advertising_set(
+        &self,
+        set: ExtendedAdvertisingSet,
+        cb: impl Fn(GapEvent) + 'static + Send,
+    ) -> Result<(), BleError> {
+        info!("remove_extended_advertising_set enter");
+
+        let instance = set.instance;
+        let removed = Mutex::new(Some(set));
+        insert_gap_completion(GapCallbacks::ExtendedAdvertisingRemove, move |event| {
+            if let GapEvent::ExtendedAdvertisingSetRemoveComplete(BtStatus::Success) = event {
+                if let Some(set) = removed.lock().ok().and_then(|mut set| set.take()) {
+                    release_extended_advertising_set(set);
+                }
+            }
+            cb(event)
+        })?;
+        gap_call(
+            GapCallbacks::ExtendedAdvertisingRemove,
+            self.backend.ext_adv_set_remove(instance),
+        )
+    }
+
+    /// Remove all the advertising sets, their instances can be allocated
+    /// again once the stack reports the removal succeeded.
+    pub fn clear_extended_advertising_sets(
+        &self,
+        cb: impl Fn(GapEvent) + 'static + Send,
+    ) -> Result<(), BleError> {
+        info!("clear_extended_advertising_sets enter");
+
+        insert_gap_completion(GapCallbacks::ExtendedAdvertisingClear, move |event| {
+            if let GapEvent::ExtendedAdvertisingSetClearComplete(BtStatus::Success) = event {
+                if let Ok(mut sets) = EXT_ADV_SETS.lock() {
+                    sets.clear();
+                }
+            }
+            cb(event)
+        })?;
+        gap_call(
+            GapCallbacks::ExtendedAdvertisingClear,
+            self.backend.ext_adv_set_clear(),
+        )
+    }
+
+    /// Configure periodic advertising on `set`, which must be neither connectable nor scannable.
+    pub fn set_periodic_advertising_params(
+        &self,
+        set: &ExtendedAdvertisingSet,
+        params: PeriodicAdvertisingParameters,
+        cb: impl Fn(GapEvent) + 'static + Send,
+    ) -> Result<(), BleError> {
+        info!("set_periodic_advertising_params enter");
+
+        if let Err(err) = params.validate() {
+            warn!("Invalid periodic advertising parameters: {}", err);
+            return Err(BleError::InvalidArgument(
+                ArgumentError::ExtendedAdvertising(err),
+            ));
+        }
+
+        insert_gap_completion(GapCallbacks::PeriodicAdvertisingParams, cb)?;
+        gap_call(
+            GapCallbacks::PeriodicAdvertisingParams,
+            self.backend.periodic_adv_set_params(set.instance, &params),
+        )
+    }
+
+    /// Set the periodic advertising data of `set`, up to `EXT_ADV_DATA_MAX_LEN` bytes.
+    pub fn set_periodic_advertising_data(
+        &self,
+        set: &ExtendedAdvertisingSet,
+        data: &[u8],
+        cb: impl Fn(GapEvent) + 'static + Send,
+    ) -> Result<(), BleError> {
+        info!("set_periodic_advertising_data enter");
+
+        if let Err(err) = validate_extended_data(data) {
+            warn!("Invalid periodic advertising data: {}", err);
+            return Err(BleError::InvalidArgument(
+                ArgumentError::ExtendedAdvertising(err),
+            ));
+        }
+
+        insert_gap_completion(GapCallbacks::PeriodicAdvertisingDataset, cb)?;
+        gap_call(
+            GapCallbacks::PeriodicAdvertisingDataset,
+            self.backend
+                .config_periodic_adv_data_raw(set.instance, data),
+        )
+    }
+
+    pub fn start_periodic_advertising(
+        &self,
+        set: &ExtendedAdvertisingSet,
+        cb: impl Fn(GapEvent) + 'static + Send,
+    ) -> Result<(), BleError> {
+        info!("start_periodic_advertising enter");
+
+        insert_gap_completion(GapCallbacks::PeriodicAdvertisingStart, cb)?;
+        gap_call(
+            GapCallbacks::PeriodicAdvertisingStart,
+            self.backend.periodic_adv_start(set.instance),
+        )
+    }
+
+    pub fn stop_periodic_advertising(
+        &self,
+        set: &ExtendedAdvertisingSet,
+        cb: impl Fn(GapEvent) + 'static + Send,
+    ) -> Result<(), BleError> {
+        info!("stop_periodic_advertising enter");
+
+        insert_gap_completion(GapCallbacks::PeriodicAdvertisingStop, cb)?;
+        gap_call(
+            GapCallbacks::PeriodicAdvertisingStop,
+            self.backend.periodic_adv_stop(set.instance),
+        )
+    }
+}
+
+impl Drop for EspBle {
+    fn drop(&mut self) {
+        if let Err(err) = self.backend.deinit() {
+            warn!("Unable to stop the Bluetooth stack: {}", err);
+        }
+
+        GAP_CALLBACKS.clear();
+        GATT_CALLBACKS.clear();
+        SUBSCRIPTION_CALLBACKS.clear();
+        CONNECTED_CALLBACKS.clear();
+        DISCONNECTED_CALLBACKS.clear();
+        if let Ok(mut delegate) = PAIRING_DELEGATE.lock() {
+            *delegate = None;
+        }
+        if let Ok(mut connections) = CONNECTIONS.lock() {
+            *connections = ConnectionManager::default();
+        }
+        if let Ok(mut notifications) = NOTIFICATIONS.lock() {
+            *notifications = NotificationState::default();
+        }
+        if let Ok(mut long_attributes) = LONG_ATTRIBUTES.lock() {
+            *long_attributes = LongAttributeState::default();
+        }
+        GATTC_CALLBACKS.clear();
+        #[cfg(target_os = "espidf")]
+        {
+            GAP_RAW_CALLBACKS.clear();
+            GATT_RAW_CALLBACKS.clear();
+            GATTC_RAW_CALLBACKS.clear();
+        }
+        if let Ok(mut sets) = EXT_ADV_SETS.lock() {
+            sets.clear();
+        }
+
+        if let Ok(mut backend) = BACKEND.lock() {
+            *backend = None;
+        }
+    }
+}
+
+/// Respond to the request `trans_id` of a connection. The response to a read
+/// gets the part of `data`, the whole value of the attribute, at the offset
+/// of the request that fits in the MTU of the connection.
+pub fn send(
+    gatts_if: u8,
+    handle: u16,
+    conn_id: u16,
+    trans_id: u32,
+    status: u32,
+    data: &[u8],
+) -> Result<(), BleError> {
+    let backend = current_backend()?;
+    let read_offset = LONG_ATTRIBUTES
+        .lock()
+        .ok()
+        .and_then(|mut long_attributes| long_attributes.take_read(conn_id, trans_id));
+
+    let mut response = GattResponse {
+        status: status.into(),
+        handle,
+        offset: read_offset.unwrap_or(0),
+        value: data,
+    };
+    if let (Some(offset), true) = (read_offset, response.status.is_ok()) {
+        match read_response(data, offset, connection_mtu(conn_id)) {
+            Ok(value) => response.value = value,
+            Err(status) => {
+                response.status = status;
+                response.value = &[];
+            }
+        }
+    }
+    Ok(backend.gatts_send_response(gatts_if, conn_id, trans_id, &response)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::notification::{MemoryStore, SubscriptionStore};
+    use crate::simulated::{simulated_ble, SimulatedBle, SIMULATED_OOB_DATA};
+    use crate::SimulatedBackend;
+    use crate::{
+        AddressType, AdvertiseType, AdvertisingParametersError, AttributeValue,
+        AuthenticationCompleteEvent, AuthenticationRequest, AutoResponse, BackendCall, BdAddr,
+        BtStatus, BtUuid, CharacteristicBuilder, ClientConfiguration, ConfirmEvent, ConnectEvent,
+        ConnectionParametersError, ConnectionUpdate, DeviceType, DisconnectEvent, DisconnectReason,
+        GattDescriptor, GattStatus, IOCapabilities, KeyMask, LinkRole, MtuEvent, NotifyEvent,
+        OobTemporaryKey, PasskeyEvent, ReadEvent, ScanEventType, SecurityConfigError,
+        ServiceBuilder, ServiceEvent, WriteEvent, CCCD_UUID, OOB_VALUE_LEN,
+    };
+
+    const PEER: BdAddr = BdAddr([0x11, 0x22, 0x33, 0x44, 0x55, 0x66]);
+
+    #[test]
+    fn lifecycle() {
+        let t = simulated_ble();
+        assert_eq!(
+            EspBle::with_backend("second".into(), ATT_MAX_MTU, SimulatedBackend::new()).map(|_| ()),
+            Err(BleError::InvalidState(StateError::AlreadyRunning))
+        );
+
+        drop(t.ble);
+        assert_eq!(t.sim.take_calls(), [BackendCall::Deinit]);
+
+        for mtu in [ATT_DEFAULT_MTU - 1, ATT_MAX_MTU + 1] {
+            let sim = SimulatedBackend::new();
+            assert_eq!(
+                EspBle::with_backend("esp".into(), mtu, sim.clone()).map(|_| ()),
+                Err(BleError::InvalidArgument(ArgumentError::LocalMtu(mtu)))
+            );
+            assert_eq!(sim.take_calls(), []);
+        }
+
+        // The stack is stopped when it can't be set up
+        let sim = SimulatedBackend::new();
+        assert!(EspBle::with_backend("e".repeat(249), 247, sim.clone()).is_err());
+        assert_eq!(
+            sim.take_calls(),
+            [
+                BackendCall::Init,
+                BackendCall::SetLocalMtu(247),
+                BackendCall::SetDeviceName("e".repeat(249)),
+                BackendCall::Deinit
+            ]
+        );
+
+        let sim = SimulatedBackend::new();
+        let _ble = EspBle::with_backend("esp".into(), 247, sim.clone()).unwrap();
+        assert_eq!(
+            sim.take_calls(),
+            [
+                BackendCall::Init,
+                BackendCall::SetLocalMtu(247),
+                BackendCall::SetDeviceName("esp".into())
+            ]
+        );
+    }
+
+    #[test]
+    fn gatt_server_session() {
+        let mut t = simulated_ble();
+        let (s, r) = channel();
+
+        let events = s.clone();
+        t.ble
+            .register_gatt_service_application(1, move |gatts_if, _| {
+                events.send(gatts_if as u16).unwrap()
+            })
+            .unwrap();
+        let gatts_if = r.try_recv().unwrap() as u8;
+
+        let events = s.clone();
+        let svc = GattService::new_primary(BtUuid::Uuid16(0x180f), 4, 0);
+        t.ble
+            .create_service(gatts_if, svc, move |_, create| {
+                if let GattServiceEvent::Create(create) = create {
+                    events.send(create.service_handle).unwrap();
+                }
+            })
+            .unwrap();
+        let svc_handle = r.try_recv().unwrap();
+
+        let events = s.clone();
+        let charac = GattCharacteristic::new(
+            BtUuid::Uuid16(0x2a19),
+            0x11,
+            0x0a,
+            AttributeValue::<1>::new_with_value(&[42]),
+            AutoResponse::ByApp,
+        );
+        t.ble
+            .add_characteristic(svc_handle, charac, move |_, add_char| {
+                if let GattServiceEvent::AddCharacteristicComplete(add_char) = add_char {
+                    events.send(add_char.attr_handle).unwrap();
+                }
+            })
+            .unwrap();
+        let char_handle = r.try_recv().unwrap();
+        assert_eq!(t.ble.read_attribute_value(char_handle), Ok(vec![42]));
+
+        t.ble.register_read_handler(char_handle, |gatts_if, read| {
+            if let GattServiceEvent::Read(read) = read {
+                send(gatts_if, read.handle, read.conn_id, read.trans_id, 0, &[7]).unwrap();
+            }
+        });
+        let events = s.clone();
+        t.ble
+            .register_write_handler(char_handle, move |gatts_if, write| {
+                if let GattServiceEvent::Write(write) = write {
+                    events.send(write.value[0] as u16).unwrap();
+                    send(
+                        gatts_if,
+                        write.handle,
+                        write.conn_id,
+                        write.trans_id,
+                        0,
+                        &[],
+                    )
+                    .unwrap();
+                }
+            });
+        t.ble.register_connect_handler(gatts_if, move |_, connect| {
+            if let GattServiceEvent::Connect(connect) = connect {
+                s.send(connect.conn_id).unwrap();
+            }
+        });
+        let (s, disconnects) = channel();
+        t.ble.register_gatts_catch_all_handler(move |_, event| {
+            if let GattServiceEvent::Disconnect(disconnect) = event {
+                s.send(disconnect.reason).unwrap();
+            }
+        });
+        t.sim.take_calls();
+
+        t.sim.inject_gatts_event(
+            gatts_if,
+            GattServiceEvent::Connect(ConnectEvent {
+                conn_id: 0,
+                link_role: LinkRole::Peripheral,
+                bda: PEER,
+                interval: 24,
+                latency: 0,
+                timeout: 500,
+            }),
+        );
+        assert_eq!(r.try_recv(), Ok(0));
+
+        t.sim.inject_gatts_event(
+            gatts_if,
+            GattServiceEvent::Read(ReadEvent {
+                conn_id: 0,
+                trans_id: 1,
+                bda: PEER,
+                handle: char_handle,
+                offset: 0,
+                is_long: false,
+                need_rsp: true,
+            }),
+        );
+        t.sim.inject_gatts_event(
+            gatts_if,
+            GattServiceEvent::Write(WriteEvent {
+                conn_id: 0,
+                trans_id: 2,
+                bda: PEER,
+                handle: char_handle,
+                offset: 0,
+                need_rsp: true,
+                is_prep: false,
+                value: vec![3],
+            }),
+        );
+        assert_eq!(r.try_recv(), Ok(3));
+
+        t.sim.inject_gatts_event(
+            gatts_if,
+            GattServiceEvent::Disconnect(DisconnectEvent {
+                conn_id: 0,
+                bda: PEER,
+                reason: DisconnectReason::RemoteUserTerminated,
+            }),
+        );
+        assert_eq!(
+            disconnects.try_iter().collect::<Vec<_>>(),
+            [DisconnectReason::RemoteUserTerminated]
+        );
+
+        let response = |trans_id, value: &[u8]| BackendCall::GattsSendResponse {
+            gatts_if,
+            conn_id: 0,
+            trans_id,
+            status: GattStatus::Ok,
+            handle: char_handle,
+            offset: 0,
+            value: value.to_vec(),
+        };
+        assert_eq!(
+            t.sim.take_calls(),
+            [
+                BackendCall::UpdateConnParams(ConnectionUpdate {
+                    bda: PEER,
+                    min_int: 0x10,
+                    max_int: 0x20,
+                    latency: 0,
+                    timeout: 400,
+                }),
+                response(1, &[7]),
+                response(2, &[]),
+            ]
+        );
+    }
+
+    /// Create a service with a characteristic that has a CCCD, returns the
+    /// interface and the handles of the characteristic and of the CCCD.
+    fn service_with_cccd(t: &mut SimulatedBle) -> (u8, u16, u16) {
+        let (s, r) = channel();
+
+        let events = s.clone();
+        t.ble
+            .register_gatt_service_application(1, move |gatts_if, _| {
+                events.send(gatts_if as u16).unwrap()
+            })
+            .unwrap();
+        let gatts_if = r.try_recv().unwrap() as u8;
+
+        let events = s.clone();
+        let svc = GattService::new_primary(BtUuid::Uuid16(0x180f), 4, 0);
+        t.ble
+            .create_service(gatts_if, svc, move |_, create| {
+                if let GattServiceEvent::Create(create) = create {
+                    events.send(create.service_handle).unwrap();
+                }
+            })
+            .unwrap();
+        let svc_handle = r.try_recv().unwrap();
+
+        let events = s.clone();
+        let charac = GattCharacteristic::new(
+            BtUuid::Uuid16(0x2a19),
+            0x01,
+            0x32,
+            AttributeValue::<1>::new_with_value(&[42]),
+            AutoResponse::ByApp,
+        );
+        t.ble
+            .add_characteristic(svc_handle, charac, move |_, add_char| {
+                if let GattServiceEvent::AddCharacteristicComplete(add_char) = add_char {
+                    events.send(add_char.attr_handle).unwrap();
+                }
+            })
+            .unwrap();
+        let char_handle = r.try_recv().unwrap();
+
+        let cccd = GattDescriptor::new(BtUuid::Uuid16(CCCD_UUID), 0x11);
+        t.ble
+            .add_descriptor(svc_handle, cccd, move |_, add_desc| {
+                if let GattServiceEvent::AddDescriptorComplete(add_desc) = add_desc {
+                    s.send(add_desc.attr_handle).unwrap();
+                }
+            })
+            .unwrap();
+        let cccd_handle = r.try_recv().unwrap();
+        t.sim.take_calls();
+
+        (gatts_if, char_handle, cccd_handle)
+    }
+
+    #[test]
+    fn notifications() {
+        let mut t = simulated_ble();
+        let (gatts_if, char_handle, cccd_handle) = service_with_cccd(&mut t);
+        let (s, r) = channel();
+        t.sim.inject_gatts_event(
+            gatts_if,
+            GattServiceEvent::Connect(ConnectEvent {
+                conn_id: 0,
+                link_role: LinkRole::Peripheral,
+                bda: PEER,
+                interval: 24,
+                latency: 0,
+                timeout: 500,
+            }),
+        );
+        t.sim.take_calls();
+
+        let subscribe = |trans_id, value: &[u8]| {
+            t.sim.inject_gatts_event(
+                gatts_if,
+                GattServiceEvent::Write(WriteEvent {
+                    conn_id: 0,
+                    trans_id,
+                    bda: PEER,
+                    handle: cccd_handle,
+                    offset: 0,
+                    need_rsp: false,
+                    is_prep: false,
+                    value: value.to_vec(),
+                }),
+            )
+        };
+
+        assert_eq!(
+            t.ble.notify(0, char_handle, &[1]),
+            Err(BleError::InvalidState(StateError::NotSubscribed {
+                conn_id: 0,
+                handle: char_handle
+            }))
+        );
+        assert_eq!(
+            t.ble.notify(0, 0x99, &[1]),
+            Err(BleError::InvalidArgument(
+                ArgumentError::UnknownCharacteristic(0x99)
+            ))
+        );
+
+        subscribe(1, &[0x01, 0x00]);
+        assert_eq!(t.ble.notify(0, char_handle, &[1]), Ok(()));
+        assert_eq!(
+            t.ble.notify(0, char_handle, &[0; 21]),
+            Err(BleError::InvalidArgument(ArgumentError::PayloadTooLong {
+                len: 21,
+                max: 20
+            }))
+        );
+        t.sim.inject_gatts_event(
+            gatts_if,
+            GattServiceEvent::Mtu(MtuEvent {
+                conn_id: 0,
+                mtu: 100,
+            }),
+        );
+        assert_eq!(t.ble.notify(0, char_handle, &[0; 21]), Ok(()));
+        assert!(t.ble.indicate(0, char_handle, &[2], |_, _| ()).is_err());
+
+        subscribe(2, &[0x02, 0x00]);
+        let events = s.clone();
+        t.ble
+            .indicate(0, char_handle, &[2], move |_, confirm| {
+                if let GattServiceEvent::Confirm(confirm) = confirm {
+                    events.send(confirm.handle).unwrap();
+                }
+            })
+            .unwrap();
+        assert_eq!(
+            t.ble.indicate(0, char_handle, &[3], |_, _| ()),
+            Err(BleError::InvalidState(StateError::IndicationPending(0)))
+        );
+        assert!(r.try_recv().is_err());
+
+        t.sim.inject_gatts_event(
+            gatts_if,
+            GattServiceEvent::Confirm(ConfirmEvent {
+                status: GattStatus::Ok,
+                conn_id: 0,
+                handle: char_handle,
+                value: vec![2],
+            }),
+        );
+        assert_eq!(r.try_recv(), Ok(char_handle));
+
+        let sent = |value: &[u8], need_confirm| BackendCall::GattsSendIndicate {
+            gatts_if,
+            conn_id: 0,
+            attr_handle: char_handle,
+            value: value.to_vec(),
+            need_confirm,
+        };
+        assert_eq!(
+            t.sim.take_calls(),
+            [sent(&[1], false), sent(&[0; 21], false), sent(&[2], true)]
+        );
+    }
+
+    #[test]
+    fn long_attributes() {
+        let mut t = simulated_ble();
+        let (gatts_if, char_handle, _) = service_with_cccd(&mut t);
+        let (s, r) = channel();
+
+        let value: Vec<u8> = (0..40).collect();
+        let read_value = value.clone();
+        t.ble
+            .register_read_handler(char_handle, move |gatts_if, read| {
+                if let GattServiceEvent::Read(read) = read {
+                    send(
+                        gatts_if,
+                        read.handle,
+                        read.conn_id,
+                        read.trans_id,
+                        0,
+                        &read_value,
+                    )
+                    .unwrap();
+                }
+            });
+        t.ble.register_write_handler(char_handle, move |_, write| {
+            if let GattServiceEvent::Write(write) = write {
+                s.send(write).unwrap();
+            }
+        });
+
+        for (trans_id, offset) in [(1, 0), (2, 22), (3, 41)] {
+            t.sim.inject_gatts_event(
+                gatts_if,
+                GattServiceEvent::Read(ReadEvent {
+                    conn_id: 0,
+                    trans_id,
+                    bda: PEER,
+                    handle: char_handle,
+                    offset,
+                    is_long: offset > 0,
+                    need_rsp: true,
+                }),
+            );
+        }
+
+        for (trans_id, offset, value) in [(4, 0, vec![1, 2]), (5, 2, vec![3])] {
+            t.sim.inject_gatts_event(
+                gatts_if,
+                GattServiceEvent::Write(WriteEvent {
+                    conn_id: 0,
+                    trans_id,
+                    bda: PEER,
+                    handle: char_handle,
+                    offset,
+                    need_rsp: true,
+                    is_prep: true,
+                    value,
+                }),
+            );
+        }
+        assert!(r.try_recv().is_err());
+        t.sim.inject_gatts_event(
+            gatts_if,
+            GattServiceEvent::ExecWrite(ExecWriteEvent {
+                conn_id: 0,
+                trans_id: 6,
+                bda: PEER,
+                execute: true,
+            }),
+        );
+        assert_eq!(
+            r.try_iter().collect::<Vec<_>>(),
+            [WriteEvent {
+                conn_id: 0,
+                trans_id: 6,
+                bda: PEER,
+                handle: char_handle,
+                offset: 0,
+                need_rsp: false,
+                is_prep: false,
+                value: vec![1, 2, 3],
+            }]
+        );
+
+        let response =
+            |trans_id, status, handle, offset, value: &[u8]| BackendCall::GattsSendResponse {
+                gatts_if,
+                conn_id: 0,
+                trans_id,
+                status,
+                handle,
+                offset,
+                value: value.to_vec(),
+            };
+        assert_eq!(
+            t.sim.take_calls(),
+            [
+                response(1, GattStatus::Ok, char_handle, 0, &value[..22]),
+                response(2, GattStatus::Ok, char_handle, 22, &value[22..]),
+                response(3, GattStatus::InvalidOffset, char_handle, 41, &[]),
+                response(4, GattStatus::Ok, char_handle, 0, &[1, 2]),
+                response(5, GattStatus::Ok, char_handle, 2, &[3]),
+                response(6, GattStatus::Ok, 0, 0, &[]),
+            ]
+        );
+    }
+
+    #[test]
+    fn mtu_exchange() {
+        let mut t = simulated_ble();
+        let (gatts_if, char_handle, cccd_handle) = service_with_cccd(&mut t);
+        let (s, r) = channel();
+
+        t.ble.register_mtu_handler(gatts_if, move |_, mtu| {
+            if let GattServiceEvent::Mtu(mtu) = mtu {
+                s.send(mtu.mtu).unwrap();
+            }
+        });
+        assert_eq!(t.ble.mtu(0), None);
+        t.sim.inject_gatts_event(
+            gatts_if,
+            GattServiceEvent::Connect(ConnectEvent {
+                conn_id: 0,
+                link_role: LinkRole::Peripheral,
+                bda: PEER,
+                interval: 24,
+                latency: 0,
+                timeout: 500,
+            }),
+        );
+        assert_eq!(t.ble.mtu(0), Some(ATT_DEFAULT_MTU));
+
+        t.sim.inject_gatts_event(
+            gatts_if,
+            GattServiceEvent::Write(WriteEvent {
+                conn_id: 0,
+                trans_id: 1,
+                bda: PEER,
+                handle: cccd_handle,
+                offset: 0,
+                need_rsp: false,
+                is_prep: false,
+                value: vec![0x01, 0x00],
+            }),
+        );
+        assert!(t.ble.notify(0, char_handle, &[0; 100]).is_err());
+        t.sim.inject_gatts_event(
+            gatts_if,
+            GattServiceEvent::Mtu(MtuEvent {
+                conn_id: 0,
+                mtu: 247,
+            }),
+        );
+        assert_eq!(r.try_recv(), Ok(247));
+        assert_eq!(t.ble.mtu(0), Some(247));
+        assert_eq!(t.ble.notify(0, char_handle, &[0; 100]), Ok(()));
+
+        t.sim.inject_gatts_event(
+            gatts_if,
+            GattServiceEvent::Disconnect(DisconnectEvent {
+                conn_id: 0,
+                bda: PEER,
+                reason: DisconnectReason::RemoteUserTerminated,
+            }),
+        );
+        assert_eq!(t.ble.mtu(0), None);
+    }
+
+    #[test]
+    fn connection_lifecycle() {
+        let t = simulated_ble();
+        let (s, r) = channel();
+
+        let connected = s.clone();
+        t.ble.register_connected_handler(move |connection| {
+            connected.send((connection, None)).unwrap()
+        });
+        t.ble
+            .register_disconnected_handler(move |connection, reason| {
+                s.send((connection, Some(reason))).unwrap()
+            });
+        assert_eq!(
+            t.ble.disconnect(0),
+            Err(BleError::InvalidState(StateError::NotConnected(0)))
+        );
+
+        t.sim.inject_gatts_event(
+            3,
+            GattServiceEvent::Connect(ConnectEvent {
+                conn_id: 0,
+                link_role: LinkRole::Peripheral,
+                bda: PEER,
+                interval: 24,
+                latency: 0,
+                timeout: 500,
+            }),
+        );
+        let (connection, _) = r.try_recv().unwrap();
+        assert_eq!((connection.bda, connection.interval), (PEER, 24));
+        // Updated by the connection parameters requested on connection
+        let connection = t.ble.connection(0).unwrap();
+        assert_eq!(connection.interval, 0x20);
+        assert_eq!(t.ble.connections(), [connection]);
+        t.sim.take_calls();
+
+        assert_eq!(t.ble.disconnect(0), Ok(()));
+        assert_eq!(t.sim.take_calls(), [BackendCall::Disconnect(PEER)]);
+        t.sim.inject_gatts_event(
+            3,
+            GattServiceEvent::Disconnect(DisconnectEvent {
+                conn_id: 0,
+                bda: PEER,
+                reason: DisconnectReason::LocalHostTerminated,
+            }),
+        );
+        assert_eq!(
+            r.try_recv(),
+            Ok((connection, Some(DisconnectReason::LocalHostTerminated)))
+        );
+        assert_eq!(t.ble.connections(), []);
+    }
+
+    #[test]
+    fn connection_params_policy() {
+        let t = simulated_ble();
+        let connect = |conn_id| {
+            t.sim.inject_gatts_event(
+                3,
+                GattServiceEvent::Connect(ConnectEvent {
+                    conn_id,
+                    link_role: LinkRole::Peripheral,
+                    bda: PEER,
+                    interval: 24,
+                    latency: 0,
+                    timeout: 500,
+                }),
+            )
+        };
+        let fast = ConnectionParameters {
+            min_interval: 6,
+            max_interval: 6,
+            latency: 0,
+            timeout: 100,
+        };
+        let update = |params: ConnectionParameters| {
+            BackendCall::UpdateConnParams(ConnectionUpdate {
+                bda: PEER,
+                min_int: params.min_interval,
+                max_int: params.max_interval,
+                latency: params.latency,
+                timeout: params.timeout,
+            })
+        };
+        t.sim.take_calls();
+
+        assert_eq!(
+            t.ble.set_connection_params_policy(
+                3,
+                ConnectionParametersPolicy::Fixed(ConnectionParameters { timeout: 0, ..fast })
+            ),
+            Err(BleError::InvalidArgument(
+                ArgumentError::ConnectionParameters(ConnectionParametersError::TimeoutOutOfRange(
+                    0
+                ))
+            ))
+        );
+        t.ble
+            .set_connection_params_policy(3, ConnectionParametersPolicy::Keep)
+            .unwrap();
+        connect(0);
+        assert_eq!(t.sim.take_calls(), []);
+
+        t.ble
+            .set_connection_params_policy(
+                3,
+                ConnectionParametersPolicy::per_peer(move |connect| {
+                    (connect.bda == PEER).then_some(fast)
+                }),
+            )
+            .unwrap();
+        connect(1);
+        assert_eq!(t.sim.take_calls(), [update(fast)]);
+
+        let slow = ConnectionParameters {
+            min_interval: 0x50,
+            max_interval: 0x60,
+            latency: 4,
+            timeout: 600,
+        };
+        assert_eq!(
+            t.ble
+                .update_connection_params_blocking(1, slow, Duration::from_secs(1)),
+            Ok(())
+        );
+        assert_eq!(t.sim.take_calls(), [update(slow)]);
+        assert_eq!(t.ble.connection(1).map(|c| c.latency), Some(4));
+        assert_eq!(
+            t.ble.update_connection_params(2, slow, |_| ()),
+            Err(BleError::InvalidState(StateError::NotConnected(2)))
+        );
+
+        // The callback only gets the completion of its own request
+        let (s, r) = channel();
+        t.sim.hold_events();
+        t.ble
+            .update_connection_params(1, fast, move |event| s.send(event).unwrap())
+            .unwrap();
+        assert_eq!(
+            t.ble.update_connection_params(1, slow, |_| ()),
+            Err(BleError::InvalidState(StateError::OperationPending))
+        );
+        t.sim.release_events();
+        assert!(matches!(
+            r.try_recv(),
+            Ok(GapEvent::UpdateConnectionParamsComplete(update)) if update.latency == 0
+        ));
+        connect(3);
+        assert_eq!(t.ble.connection(3).map(|c| c.interval), Some(6));
+        assert!(r.try_recv().is_err());
+    }
+
+    #[test]
+    fn bonded_subscriptions() {
+        let mut t = simulated_ble();
+        let store = Arc::new(MemoryStore::default());
+        NOTIFICATIONS.lock().unwrap().set_store(store.clone());
+        let (gatts_if, char_handle, cccd_handle) = service_with_cccd(&mut t);
+
+        let (s, subscriptions) = channel();
+        t.ble
+            .register_subscription_handler(move |event| s.send(event).unwrap());
+
+        const OTHER: BdAddr = BdAddr([0x66, 0x55, 0x44, 0x33, 0x22, 0x11]);
+        let connect = |conn_id, bda| {
+            t.sim.inject_gatts_event(
+                gatts_if,
+                GattServiceEvent::Connect(ConnectEvent {
+                    conn_id,
+                    link_role: LinkRole::Peripheral,
+                    bda,
+                    interval: 24,
+                    latency: 0,
+                    timeout: 500,
+                }),
+            )
+        };
+        let subscribe = |conn_id, bda| {
+            t.sim.inject_gatts_event(
+                gatts_if,
+                GattServiceEvent::Write(WriteEvent {
+                    conn_id,
+                    trans_id: 1,
+                    bda,
+                    handle: cccd_handle,
+                    offset: 0,
+                    need_rsp: false,
+                    is_prep: false,
+                    value: vec![0x01, 0x00],
+                }),
+            )
+        };
+        let subscribed = |conn_id, bda| SubscriptionEvent {
+            conn_id,
+            bda,
+            handle: char_handle,
+            configuration: ClientConfiguration {
+                notifications: true,
+                indications: false,
+            },
+        };
+
+        t.sim.add_bond(PEER);
+        connect(0, PEER);
+        assert!(subscriptions.try_recv().is_err());
+        subscribe(0, PEER);
+        assert_eq!(subscriptions.try_recv(), Ok(subscribed(0, PEER)));
+
+        t.sim.inject_gatts_event(
+            gatts_if,
+            GattServiceEvent::Disconnect(DisconnectEvent {
+                conn_id: 0,
+                bda: PEER,
+                reason: DisconnectReason::RemoteUserTerminated,
+            }),
+        );
+        connect(1, PEER);
+        assert_eq!(subscriptions.try_recv(), Ok(subscribed(1, PEER)));
+        assert_eq!(t.ble.notify(1, char_handle, &[1]), Ok(()));
+
+        connect(2, OTHER);
+        subscribe(2, OTHER);
+        assert_eq!(subscriptions.try_recv(), Ok(subscribed(2, OTHER)));
+        assert_eq!(store.load(OTHER), None);
+
+        // Bonding after subscribing keeps the subscriptions as well
+        t.sim.add_bond(OTHER);
+        t.sim.inject_gap_event(GapEvent::AuthenticationComplete(
+            AuthenticationCompleteEvent {
+                bda: OTHER,
+                success: true,
+                fail_reason: 0,
+                key_present: true,
+                addr_type: AddressType::Public,
+                dev_type: DeviceType::Ble,
+                auth_mode: 0x01,
+            },
+        ));
+        assert!(store.load(OTHER).is_some());
+    }
+
+    #[test]
+    fn bond_management() {
+        let t = simulated_ble();
+        let store = Arc::new(MemoryStore::default());
+        NOTIFICATIONS.lock().unwrap().set_store(store.clone());
+
+        const OTHER: BdAddr = BdAddr([0x66, 0x55, 0x44, 0x33, 0x22, 0x11]);
+        for bda in [PEER, OTHER] {
+            t.sim.add_bond(bda);
+            store.save(bda, &[42, 0, 1]);
+        }
+        let bonds = t.ble.bonded_devices().unwrap();
+        assert_eq!(
+            bonds.iter().map(|bond| bond.bda).collect::<Vec<_>>(),
+            [PEER, OTHER]
+        );
+
+        let timeout = Duration::from_secs(1);
+        assert_eq!(t.ble.remove_bond_blocking(PEER, timeout), Ok(()));
+        assert_eq!(store.load(PEER), None);
+        assert_eq!(store.load(OTHER), Some(vec![42, 0, 1]));
+        assert_eq!(
+            t.ble.remove_bond_blocking(PEER, timeout),
+            Err(BleError::Bt(BtStatus::Fail))
+        );
+
+        t.sim.add_bond(PEER);
+        t.sim.take_calls();
+        assert_eq!(t.ble.clear_bonds_blocking(timeout), Ok(()));
+        assert_eq!(
+            t.sim.take_calls(),
+            [
+                BackendCall::RemoveBondDevice(OTHER),
+                BackendCall::RemoveBondDevice(PEER)
+            ]
+        );
+        assert_eq!(t.ble.bonded_devices(), Ok(vec![]));
+        assert_eq!(store.load(OTHER), None);
+        assert_eq!(t.ble.clear_bonds_blocking(timeout), Ok(()));
+
+        let (s, r) = channel();
+        let cleared = s.clone();
+        t.ble
+            .clear_bonds(move |event| cleared.send(event).unwrap())
+            .unwrap();
+        assert_eq!(
+            r.try_recv(),
+            Ok(GapEvent::ClearDeviceBondComplete(BtStatus::Success))
+        );
+
+        // A removal completes once, another one for the same peer waits for it
+        t.sim.add_bond(PEER);
+        t.sim.hold_events();
+        t.ble
+            .remove_bond(PEER, move |event| s.send(event).unwrap())
+            .unwrap();
+        assert_eq!(
+            t.ble.remove_bond(PEER, |_| ()),
+            Err(BleError::InvalidState(StateError::OperationPending))
+        );
+        t.sim.release_events();
+        assert!(matches!(
+            r.try_recv(),
+            Ok(GapEvent::RemoveDeviceBondComplete(_))
+        ));
+        assert_eq!(
+            t.ble.remove_bond_blocking(PEER, timeout),
+            Err(BleError::Bt(BtStatus::Fail))
+        );
+        assert!(r.try_recv().is_err());
+    }
+
+    #[test]
+    fn configure_security() {
+        let t = simulated_ble();
+        let config = SecurityConfig {
+            auth_req_mode: AuthenticationRequest::SecureMitmBonding,
+            io_capabilities: IOCapabilities::KeyboardOnly,
+            initiator_key: Some(KeyMask::IDENTITY_RESOLVING_KEY | KeyMask::LINK_KEY),
+            responder_key: Some(
+                KeyMask::IDENTITY_RESOLVING_KEY | KeyMask::CONNECTION_SIGNATURE_RESOLVING_KEY,
+            ),
+            max_key_size: Some(16),
+            min_key_size: Some(10),
+            static_passkey: Some(123456),
+            only_accept_specified_auth: true,
+            enable_oob: true,
+        };
+        assert_eq!(t.ble.configure_security(config), Ok(()));
+        assert_eq!(
+            t.sim.take_calls(),
+            [
+                BackendCall::SetSecurityParam(SecurityParam::AuthenticationRequestMode, vec![7]),
+                BackendCall::SetSecurityParam(SecurityParam::IoCapabilities, vec![2]),
+                BackendCall::SetSecurityParam(SecurityParam::InitiatorKey, vec![0b1010]),
+                BackendCall::SetSecurityParam(SecurityParam::ResponderKey, vec![0b0110]),
+                BackendCall::SetSecurityParam(SecurityParam::MaxKeySize, vec![16]),
+                BackendCall::SetSecurityParam(SecurityParam::MinKeySize, vec![10]),
+                BackendCall::SetSecurityParam(
+                    SecurityParam::StaticPasskey,
+                    vec![0x40, 0xe2, 0x01, 0x00]
+                ),
+                BackendCall::SetSecurityParam(
+                    SecurityParam::OnlyAcceptSpecifiedAuthentication,
+                    vec![1]
+                ),
+                BackendCall::SetSecurityParam(SecurityParam::OobSupport, vec![1]),
+            ]
+        );
+
+        assert_eq!(t.ble.configure_security(SecurityConfig::default()), Ok(()));
+        assert!(t.sim.take_calls().contains(&BackendCall::SetSecurityParam(
+            SecurityParam::ClearStaticPasskey,
+            vec![0]
+        )));
+
+        let invalid = SecurityConfig {
+            min_key_size: Some(16),
+            max_key_size: Some(7),
+            ..config
+        };
+        assert_eq!(
+            t.ble.configure_security(invalid),
+            Err(BleError::Security(
+                SecurityConfigError::InvalidKeySizeRange { min: 16, max: 7 }
+            ))
+        );
+        assert_eq!(t.sim.take_calls(), []);
+    }
+
+    struct TestDelegate {
+        completed: std::sync::mpsc::Sender<Result<(), PairingFailure>>,
+    }
+
+    impl PairingDelegate for TestDelegate {
+        fn on_security_request(&self, _bda: BdAddr) -> bool {
+            false
+        }
+
+        fn on_passkey_request(&self, _bda: BdAddr) -> Option<u32> {
+            Some(123456)
+        }
+
+        fn on_numeric_comparison(&self, _bda: BdAddr, passkey: u32) -> bool {
+            passkey == 123456
+        }
+
+        fn on_oob_request(&self, _bda: BdAddr) -> Option<OobTemporaryKey> {
+            Some([7; OOB_VALUE_LEN])
+        }
+
+        fn on_pairing_complete(&self, _bda: BdAddr, result: Result<(), PairingFailure>) {
+            self.completed.send(result).unwrap();
+        }
+    }
+
+    /// The calls answering the pairing request `event`, the delegate answers
+    /// from a thread of its own.
+    fn answer_pairing(t: &SimulatedBle, event: GapEvent) -> Vec<BackendCall> {
+        t.sim.take_calls();
+        t.sim.inject_gap_event(event);
+        for _ in 0..100 {
+            let calls = t.sim.take_calls();
+            if !calls.is_empty() {
+                return calls;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        vec![]
+    }
+
+    #[test]
+    fn pairing_delegate() {
+        let t = simulated_ble();
+        let (s, r) = channel();
+        t.ble.set_pairing_delegate(TestDelegate { completed: s });
+
+        let answer = |event| answer_pairing(&t, event);
+        assert_eq!(
+            answer(GapEvent::SecurityRequest(PEER)),
+            [BackendCall::SecurityRsp {
+                bda: PEER,
+                accept: false
+            }]
+        );
+        assert_eq!(
+            answer(GapEvent::PasskeyRequest(PEER)),
+            [BackendCall::PasskeyReply {
+                bda: PEER,
+                accept: true,
+                passkey: 123456
+            }]
+        );
+        for (passkey, accept) in [(123456, true), (654321, false)] {
+            assert_eq!(
+                answer(GapEvent::NumericComparisonRequest(PasskeyEvent {
+                    bda: PEER,
+                    passkey
+                })),
+                [BackendCall::ConfirmReply { bda: PEER, accept }]
+            );
+        }
+
+        t.sim.inject_gap_event(GapEvent::AuthenticationComplete(
+            AuthenticationCompleteEvent {
+                bda: PEER,
+                success: false,
+                fail_reason: 0x04,
+                key_present: false,
+                addr_type: AddressType::Public,
+                dev_type: DeviceType::Ble,
+                auth_mode: 0,
+            },
+        ));
+        assert_eq!(r.try_recv(), Ok(Err(PairingFailure { reason: 0x04 })));
+    }
+
+    #[test]
+    fn default_pairing_delegate() {
+        let t = simulated_ble();
+        assert_eq!(t.ble.configure_security(SecurityConfig::default()), Ok(()));
+
+        assert_eq!(
+            answer_pairing(
+                &t,
+                GapEvent::NumericComparisonRequest(PasskeyEvent {
+                    bda: PEER,
+                    passkey: 123456
+                })
+            ),
+            [BackendCall::ConfirmReply {
+                bda: PEER,
+                accept: false
+            }]
+        );
+        assert_eq!(
+            answer_pairing(&t, GapEvent::PasskeyRequest(PEER)),
+            [BackendCall::PasskeyReply {
+                bda: PEER,
+                accept: false,
+                passkey: 0
+            }]
+        );
+    }
+
+    #[test]
+    fn oob_pairing() {
+        let t = simulated_ble();
+        let (s, _r) = channel();
+        t.ble.set_pairing_delegate(TestDelegate { completed: s });
+
+        assert_eq!(
+            answer_pairing(&t, GapEvent::OOBRequest(PEER)),
+            [BackendCall::OobReqReply {
+                bda: PEER,
+                tk: [7; OOB_VALUE_LEN]
+            }]
+        );
+        // Without the data of the peer the pairing is stopped
+        assert_eq!(
+            answer_pairing(&t, GapEvent::SecureConnectionsOOBRequest(PEER)),
+            [BackendCall::Disconnect(PEER)]
+        );
+
+        assert_eq!(
+            t.ble.create_local_oob_data_blocking(Duration::from_secs(1)),
+            Ok(SIMULATED_OOB_DATA)
+        );
+    }
+
+    #[test]
+    fn attribute_table() {
+        let mut t = simulated_ble();
+        let (s, r) = channel();
+
+        let events = s.clone();
+        t.ble
+            .register_gatt_service_application(1, move |gatts_if, _| events.send(gatts_if).unwrap())
+            .unwrap();
+        let gatts_if = r.try_recv().unwrap();
+
+        let table = ServiceBuilder::primary(BtUuid::Uuid16(0x180f))
+            .characteristic(
+                CharacteristicBuilder::new("level", BtUuid::Uuid16(0x2a19))
+                    .properties(0x12)
+                    .permissions(0x01)
+                    .value(&[100])
+                    .auto_response(AutoResponse::ByGatt)
+                    .cccd(),
+            )
+            .build()
+            .unwrap();
+        let attributes = table.attributes().to_vec();
+        t.sim.take_calls();
+
+        let (s, created) = channel();
+        t.ble
+            .create_attribute_table(gatts_if, table, move |handles| s.send(handles).unwrap())
+            .unwrap();
+        let handles = created.try_recv().unwrap().unwrap();
+        let level = handles.characteristic("level").unwrap();
+        assert_eq!(handles.service_handle, 40);
+        assert_eq!(level.value_handle, 42);
+        assert_eq!(
+            t.ble.read_attribute_value(level.value_handle),
+            Ok(vec![100])
+        );
+        assert_eq!(
+            t.sim.take_calls(),
+            [
+                BackendCall::GattsCreateAttrTab {
+                    gatts_if,
+                    attributes,
+                    service_instance_id: 0,
+                },
+                BackendCall::GattsStartService(40),
+            ]
+        );
+
+        // The CCCD of the table is tracked like the ones added one by one
+        t.sim.inject_gatts_event(
+            gatts_if,
+            GattServiceEvent::Write(WriteEvent {
+                conn_id: 0,
+                trans_id: 1,
+                bda: PEER,
+                handle: level.cccd().unwrap(),
+                offset: 0,
+                need_rsp: false,
+                is_prep: false,
+                value: vec![0x01, 0x00],
+            }),
+        );
+        assert_eq!(t.ble.notify(0, level.value_handle, &[99]), Ok(()));
+    }
+
+    #[test]
+    fn pending_attributes() {
+        let mut t = simulated_ble();
+        let (s, r) = channel();
+
+        let events = s.clone();
+        t.ble
+            .register_gatt_service_application(1, move |gatts_if, _| {
+                events.send(gatts_if as u16).unwrap()
+            })
+            .unwrap();
+        let gatts_if = r.try_recv().unwrap() as u8;
+
+        let svc = GattService::new_primary(BtUuid::Uuid16(0x180f), 16, 0);
+        t.ble
+            .create_service(gatts_if, svc, move |_, create| {
+                if let GattServiceEvent::Create(create) = create {
+                    s.send(create.service_handle).unwrap();
+                }
+            })
+            .unwrap();
+        let svc_handle = r.try_recv().unwrap();
+
+        let charac = |uuid| {
+            GattCharacteristic::new(
+                BtUuid::Uuid16(uuid),
+                0x01,
+                0x02,
+                AttributeValue::<1>::new_with_value(&[0]),
+                AutoResponse::ByGatt,
+            )
+        };
+        let cccd = || GattDescriptor::new(BtUuid::Uuid16(CCCD_UUID), 0x11);
+
+        // Completed in order, each by the callback of its own call
+        let (s, added) = channel();
+        t.sim.hold_events();
+        for (name, uuid) in [("first", 0x2a19), ("second", 0x2a1a)] {
+            let s = s.clone();
+            t.ble
+                .add_characteristic(svc_handle, charac(uuid), move |_, event| {
+                    if let GattServiceEvent::AddCharacteristicComplete(event) = event {
+                        s.send((name, event.attr_handle)).unwrap();
+                    }
+                })
+                .unwrap();
+        }
+        t.ble
+            .add_descriptor(svc_handle, cccd(), move |_, event| {
+                if let GattServiceEvent::AddDescriptorComplete(event) = event {
+                    s.send(("cccd", event.attr_handle)).unwrap();
+                }
+            })
+            .unwrap();
+        assert!(added.try_recv().is_err());
+        t.sim.release_events();
+        assert_eq!(
+            added.try_iter().collect::<Vec<_>>(),
+            [("first", 42), ("second", 44), ("cccd", 45)]
+        );
+
+        let timeout = Duration::from_millis(10);
+        assert_eq!(
+            t.ble
+                .add_characteristic_blocking(svc_handle, charac(0x2a1b), timeout),
+            Ok(47)
+        );
+        assert_eq!(
+            t.ble.add_descriptor_blocking(svc_handle, cccd(), timeout),
+            Ok(48)
+        );
+
+        // A late completion does not shift the ones after it
+        t.sim.hold_events();
+        assert_eq!(
+            t.ble
+                .add_characteristic_blocking(svc_handle, charac(0x2a1c), timeout),
+            Err(BleError::Timeout)
+        );
+        t.sim.release_events();
+        assert_eq!(
+            t.ble
+                .add_characteristic_blocking(svc_handle, charac(0x2a1d), timeout),
+            Ok(52)
+        );
+    }
+
+    #[test]
+    fn advertising() {
+        let t = simulated_ble();
+        let (s, r) = channel();
+
+        let (data, _) = RawAdvertiseData::builder()
+            .field(AdvertiseType::DeviceNameComplete("esp".into()))
+            .build()
+            .unwrap();
+        let events = s.clone();
+        t.ble
+            .configure_advertising_data_raw(data.clone(), move |evt| events.send(evt).unwrap())
+            .unwrap();
+        t.ble
+            .start_advertise(AdvertisingParameters::default(), move |evt| {
+                s.send(evt).unwrap()
+            })
+            .unwrap();
+
+        let invalid = AdvertisingParameters {
+            interval_min: 0x40,
+            interval_max: 0x20,
+            ..Default::default()
+        };
+        assert_eq!(
+            t.ble.start_advertise(invalid, |_| ()),
+            Err(BleError::InvalidArgument(
+                ArgumentError::AdvertisingParameters(
+                    AdvertisingParametersError::InvalidIntervalRange {
+                        min: 0x40,
+                        max: 0x20
+                    }
+                )
+            ))
+        );
+
+        assert_eq!(
+            r.try_iter().collect::<Vec<_>>(),
+            [
+                GapEvent::RawAdvertisingDatasetComplete(BtStatus::Success),
+                GapEvent::AdvertisingStartComplete(BtStatus::Success),
+            ]
+        );
+        assert_eq!(
+            t.sim.take_calls(),
+            [
+                BackendCall::ConfigAdvDataRaw(data.to_vec()),
+                BackendCall::StartAdvertising(AdvertisingParameters::default()),
+            ]
+        );
+    }
+
+    #[test]
+    fn scanning() {
+        let t = simulated_ble();
+        let (s, r) = channel();
+
+        t.ble
+            .start_scan(ScanParameters::default(), 10, move |result| {
+                s.send(result).unwrap()
+            })
+            .unwrap();
+        assert_eq!(
+            t.sim.take_calls(),
+            [
+                BackendCall::SetScanParams(ScanParameters::default()),
+                BackendCall::StartScanning(10),
+            ]
+        );
+
+        let result = ScanResult {
+            bda: PEER,
+            addr_type: crate::AddressType::Public,
+            rssi: -42,
+            event_type: ScanEventType::ConnectableAdvertising,
+            adv_data: vec![0x02, 0x01, 0x06],
+            scan_rsp_data: vec![],
+        };
+        t.sim
+            .inject_gap_event(GapEvent::ScanResult(ScanSearchEvent::Result(
+                result.clone(),
+            )));
+        t.sim
+            .inject_gap_event(GapEvent::ScanResult(ScanSearchEvent::Complete {
+                num_resps: 1,
+            }));
+        assert_eq!(r.try_iter().collect::<Vec<_>>(), [result]);
+    }
+
+    #[test]
+    fn failed_calls_drop_their_callback() {
+        let t = simulated_ble();
+        let (s, r) = channel();
+
+        assert!(t
+            .ble
+            .start_service(0x99, move |_, event| s.send(event).unwrap())
+            .is_err());
+        t.sim.inject_gatts_event(
+            3,
+            GattServiceEvent::StartComplete(ServiceEvent {
+                status: GattStatus::Ok,
+                service_handle: 0x99,
+            }),
+        );
+        assert!(r.try_recv().is_err());
+    }
+
+    #[test]
+    fn opens_are_matched_by_peer() {
+        let mut t = simulated_ble();
+        t.ble
+            .register_gatt_client_application(1, |_, _| ())
+            .unwrap();
+
+        const OTHER: BdAddr = BdAddr([0x66, 0x55, 0x44, 0x33, 0x22, 0x11]);
+        let (s, r) = channel();
+        t.sim.hold_events();
+        for bda in [PEER, OTHER] {
+            let opened = s.clone();
+            t.ble
+                .open(3, bda, AddressType::Public, move |_, event| {
+                    if let GattClientEvent::Open(open) = event {
+                        opened.send((bda, open.bda)).unwrap();
+                    }
+                })
+                .unwrap();
+        }
+        t.sim.release_events();
+        assert_eq!(
+            r.try_iter().collect::<Vec<_>>(),
+            [(PEER, PEER), (OTHER, OTHER)]
+        );
+    }
+
+    #[test]
+    fn gatt_client() {
+        let mut t = simulated_ble();
+        let (s, r) = channel();
+
+        let battery = RemoteService {
+            service: GattService::new_primary(BtUuid::Uuid16(0x180f), 0, 0),
+            start_handle: 1,
+            end_handle: 4,
+        };
+        let level = RemoteCharacteristic {
+            uuid: BtUuid::Uuid16(0x2a19),
+            handle: 3,
+            properties: 0x12,
+        };
+        let cccd = RemoteDescriptor {
+            uuid: BtUuid::Uuid16(CCCD_UUID),
+            handle: 4,
+        };
+        t.sim.add_remote_service(battery.clone());
+        t.sim.add_remote_characteristic(level.clone(), &[42]);
+        t.sim
+            .add_remote_descriptor(level.handle, cccd.clone(), &[0, 0]);
+
+        let events = s.clone();
+        t.ble
+            .register_gatt_client_application(1, move |_, event| events.send(event).unwrap())
+            .unwrap();
+        let gattc_if = match r.try_recv() {
+            Ok(GattClientEvent::Register(register)) => {
+                assert_eq!(register.status, GattStatus::Ok);
+                3
+            }
+            other => panic!("unexpected {other:?}"),
+        };
+
+        let events = s.clone();
+        t.ble
+            .open(gattc_if, PEER, AddressType::Public, move |_, event| {
+                events.send(event).unwrap()
+            })
+            .unwrap();
+        let conn_id = match r.try_recv() {
+            Ok(GattClientEvent::Open(open)) => {
+                assert_eq!((open.bda, open.mtu), (PEER, 23));
+                open.conn_id
+            }
+            other => panic!("unexpected {other:?}"),
+        };
+
+        let events = s.clone();
+        t.ble
+            .search_services(gattc_if, conn_id, None, move |_, event| {
+                events.send(event).unwrap()
+            })
+            .unwrap();
+        assert!(matches!(
+            r.try_recv(),
+            Ok(GattClientEvent::SearchComplete(search)) if search.status == GattStatus::Ok
+        ));
+        assert_eq!(
+            t.ble.get_services(gattc_if, conn_id),
+            Ok(vec![battery.clone()])
+        );
+        assert_eq!(
+            t.ble.get_characteristics(gattc_if, conn_id, &battery),
+            Ok(vec![level.clone()])
+        );
+        assert_eq!(
+            t.ble.get_descriptors(gattc_if, conn_id, &level),
+            Ok(vec![cccd.clone()])
+        );
+
+        let events = s.clone();
+        t.ble
+            .register_for_notify(gattc_if, PEER, level.handle, move |_, event| {
+                events.send(event).unwrap()
+            })
+            .unwrap();
+        let events = s.clone();
+        t.ble
+            .write_descriptor(
+                gattc_if,
+                conn_id,
+                cccd.handle,
+                &[1, 0],
+                WriteType::WithResponse,
+                move |_, event| events.send(event).unwrap(),
+            )
+            .unwrap();
+        assert!(matches!(
+            r.try_recv(),
+            Ok(GattClientEvent::WriteDescriptor(write)) if write.status == GattStatus::Ok
+        ));
+        assert_eq!(t.sim.remote_value(cccd.handle), Some(vec![1, 0]));
+
+        let notify = GattClientEvent::Notify(NotifyEvent {
+            conn_id,
+            bda: PEER,
+            handle: level.handle,
+            is_notify: true,
+            value: vec![41],
+        });
+        t.sim.inject_gattc_event(gattc_if, notify.clone());
+        assert_eq!(r.try_recv(), Ok(notify.clone()));
+
+        t.ble
+            .unregister_for_notify(gattc_if, PEER, level.handle)
+            .unwrap();
+        t.sim.inject_gattc_event(gattc_if, notify);
+        assert!(r.try_recv().is_err());
+
+        let events = s.clone();
+        t.ble
+            .read_characteristic(gattc_if, conn_id, 9, move |_, event| {
+                events.send(event).unwrap()
+            })
+            .unwrap();
+        assert!(matches!(
+            r.try_recv(),
+            Ok(GattClientEvent::ReadCharacteristic(read)) if read.status == GattStatus::InvalidHandle
+        ));
+
+        t.ble.close(gattc_if, conn_id).unwrap();
+        assert_eq!(
+            t.sim.take_calls(),
+            [
+                BackendCall::GattcAppRegister(1),
+                BackendCall::GattcOpen {
+                    gattc_if,
+                    bda: PEER,
+                    addr_type: AddressType::Public,
+                },
+                BackendCall::GattcSearchService {
+                    gattc_if,
+                    conn_id,
+                    filter: None,
+                },
+                BackendCall::GattcRegisterForNotify {
+                    gattc_if,
+                    bda: PEER,
+                    handle: level.handle,
+                },
+                BackendCall::GattcWriteCharDescr {
+                    gattc_if,
+                    conn_id,
+                    handle: cccd.handle,
+                    value: vec![1, 0],
+                    write_type: WriteType::WithResponse,
+                },
+                BackendCall::GattcUnregisterForNotify {
+                    gattc_if,
+                    bda: PEER,
+                    handle: level.handle,
+                },
+                BackendCall::GattcReadChar {
+                    gattc_if,
+                    conn_id,
+                    handle: 9,
+                },
+                BackendCall::GattcClose { gattc_if, conn_id },
+            ]
+        );
+    }
+
+    #[test]
+    fn extended_advertising() {
+        let t = simulated_ble();
+        let (s, r) = channel();
+
+        let params = ExtendedAdvertisingParameters {
+            connectable: false,
+            ..Default::default()
+        };
+        let events = s.clone();
+        let set = t
+            .ble
+            .create_extended_advertising_set(params, move |evt| events.send(evt).unwrap())
+            .unwrap();
+        let events = s.clone();
+        t.ble
+            .set_extended_advertising_data(&set, &[0x02, 0x01, 0x06], move |evt| {
+                events.send(evt).unwrap()
+            })
+            .unwrap();
+        let events = s.clone();
+        t.ble
+            .set_periodic_advertising_params(
+                &set,
+                PeriodicAdvertisingParameters::default(),
+                move |evt| events.send(evt).unwrap(),
+            )
+            .unwrap();
+        let events = s.clone();
+        t.ble
+            .start_extended_advertising(
+                &[(&set, ExtendedAdvertisingDuration::default())],
+                move |evt| events.send(evt).unwrap(),
+            )
+            .unwrap();
+        let events = s.clone();
+        t.ble
+            .start_periodic_advertising(&set, move |evt| events.send(evt).unwrap())
+            .unwrap();
+
+        assert_eq!(
+            r.try_iter().collect::<Vec<_>>(),
+            [
+                GapEvent::ExtendedAdvertisingSetParamsComplete(BtStatus::Success),
+                GapEvent::ExtendedAdvertisingDatasetComplete(BtStatus::Success),
+                GapEvent::PeriodicAdvertisingSetParamsComplete(BtStatus::Success),
+                GapEvent::ExtendedAdvertisingStartComplete(BtStatus::Success),
+                GapEvent::PeriodicAdvertisingStartComplete(BtStatus::Success),
+            ]
+        );
+        let instance = set.instance();
+        assert_eq!(
+            t.sim.take_calls(),
+            [
+                BackendCall::ExtAdvSetParams { instance, params },
+                BackendCall::ConfigExtAdvDataRaw {
+                    instance,
+                    data: vec![0x02, 0x01, 0x06],
+                },
+                BackendCall::PeriodicAdvSetParams {
+                    instance,
+                    params: PeriodicAdvertisingParameters::default(),
+                },
+                BackendCall::ExtAdvStart(vec![(instance, ExtendedAdvertisingDuration::default())]),
+                BackendCall::PeriodicAdvStart(instance),
+            ]
+        );
+
+        t.sim.hold_events();
+        let create = || {
+            let set = t
+                .ble
+                .create_extended_advertising_set(params, |_| ())
+                .unwrap();
+            t.sim
+                .inject_gap_event(GapEvent::ExtendedAdvertisingSetParamsComplete(
+                    BtStatus::Success,
+                ));
+            set
+        };
+        let other = create();
+        t.ble.remove_extended_advertising_set(set, |_| ()).unwrap();
+        assert_eq!(
+            t.ble.remove_extended_advertising_set(other, |_| ()),
+            Err(BleError::InvalidState(StateError::OperationPending))
+        );
+
+        // The instance stays in use until the stack removed the set
+        assert_eq!(create().instance(), instance + 2);
+        t.sim
+            .inject_gap_event(GapEvent::ExtendedAdvertisingSetRemoveComplete(
+                BtStatus::Success,
+            ));
+        assert_eq!(create().instance(), instance);
+
+        t.ble.clear_extended_advertising_sets(|_| ()).unwrap();
+        t.sim
+            .inject_gap_event(GapEvent::ExtendedAdvertisingSetClearComplete(
+                BtStatus::Busy,
+            ));
+        assert_eq!(create().instance(), instance + 3);
+        t.ble.clear_extended_advertising_sets(|_| ()).unwrap();
+        t.sim
+            .inject_gap_event(GapEvent::ExtendedAdvertisingSetClearComplete(
+                BtStatus::Success,
+            ));
+        assert_eq!(create().instance(), instance);
+    }
+}
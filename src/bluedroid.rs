@@ -0,0 +1,1124 @@
+use std::collections::VecDeque;
+use std::ffi::{c_void, CString};
+use std::sync::{Arc, Mutex};
+
+use ::log::*;
+
+use esp_idf_svc::nvs::EspDefaultNvs;
+use esp_idf_sys::*;
+
+use crate::backend::{BleBackend, ConnectionUpdate, GattResponse, SecurityParam};
+use crate::ble::{dispatch_gap_event, dispatch_gattc_event, dispatch_gatts_event};
+use crate::ble::{dispatch_raw_gap_event, dispatch_raw_gattc_event, dispatch_raw_gatts_event};
+#[cfg(any(esp_idf_version_major = "4", not(esp_idf_bt_ble_50_features_supported)))]
+use crate::error::ESP_ERR_NOT_SUPPORTED;
+use crate::error::{esp_error, ESP_ERR_INVALID_ARG, ESP_ERR_INVALID_SIZE, ESP_ERR_INVALID_STATE};
+use crate::notification::SubscriptionStore;
+use crate::service_builder::uuid_bytes;
+use crate::{
+    AddressType, AdvertiseData, AdvertisingParameters, Attribute, AutoResponse, BdAddr,
+    BleEncryption, BondedDevice, BtUuid, ExtendedAdvertisingDuration,
+    ExtendedAdvertisingParameters, GapEvent, GattClientEvent, GattService, GattServiceEvent,
+    GattStatus, KeyType, OobTemporaryKey, PeriodicAdvertisingParameters, RawGapEvent,
+    RawGattClientEvent, RawGattServiceEvent, RemoteCharacteristic, RemoteDescriptor, RemoteService,
+    ScOobData, ScanParameters, WriteType,
+};
+
+/// `BleBackend` calling the ESP-IDF Bluedroid API.
+pub struct BluedroidBackend;
+
+/// Attribute tables being created: Bluedroid reads the attributes after
+/// `esp_ble_gatts_create_attr_tab` returns, until it reports the table.
+static ATTRIBUTE_TABLES: Mutex<VecDeque<AttributeTableBuffers>> = Mutex::new(VecDeque::new());
+
+struct AttributeTableBuffers {
+    _uuids: Vec<Vec<u8>>,
+    _values: Vec<Vec<u8>>,
+    _db: Vec<esp_gatts_attr_db_t>,
+}
+
+// The pointers of the table only point to the buffers next to it
+unsafe impl Send for AttributeTableBuffers {}
+
+unsafe extern "C" fn gap_event_handler(
+    event: esp_gap_ble_cb_event_t,
+    param: *mut esp_ble_gap_cb_param_t,
+) {
+    let raw = match RawGapEvent::build(event, param) {
+        Some(raw) => raw,
+        None => return,
+    };
+    dispatch_raw_gap_event(raw);
+    dispatch_gap_event(GapEvent::from(raw));
+}
+
+unsafe extern "C" fn gatts_event_handler(
+    event: esp_gatts_cb_event_t,
+    gatts_if: esp_gatt_if_t,
+    param: *mut esp_ble_gatts_cb_param_t,
+) {
+    let raw = match RawGattServiceEvent::build(event, param) {
+        Some(raw) => raw,
+        None => return,
+    };
+    dispatch_raw_gatts_event(gatts_if, raw);
+    dispatch_gatts_event(gatts_if, GattServiceEvent::from(raw));
+
+    if event == esp_gatts_cb_event_t_ESP_GATTS_CREAT_ATTR_TAB_EVT {
+        if let Ok(mut tables) = ATTRIBUTE_TABLES.lock() {
+            tables.pop_front();
+        }
+    }
+}
+
+unsafe extern "C" fn gattc_event_handler(
+    event: esp_gattc_cb_event_t,
+    gattc_if: esp_gatt_if_t,
+    param: *mut esp_ble_gattc_cb_param_t,
+) {
+    let raw = match RawGattClientEvent::build(event, param) {
+        Some(raw) => raw,
+        None => return,
+    };
+    dispatch_raw_gattc_event(gattc_if, raw);
+    dispatch_gattc_event(gattc_if, GattClientEvent::from(raw));
+}
+
+impl From<SecurityParam> for esp_ble_sm_param_t {
+    fn from(param: SecurityParam) -> Self {
+        match param {
+            SecurityParam::AuthenticationRequestMode => {
+                esp_ble_sm_param_t_ESP_BLE_SM_AUTHEN_REQ_MODE
+            }
+            SecurityParam::IoCapabilities => esp_ble_sm_param_t_ESP_BLE_SM_IOCAP_MODE,
+            SecurityParam::InitiatorKey => esp_ble_sm_param_t_ESP_BLE_SM_SET_INIT_KEY,
+            SecurityParam::ResponderKey => esp_ble_sm_param_t_ESP_BLE_SM_SET_RSP_KEY,
+            SecurityParam::MaxKeySize => esp_ble_sm_param_t_ESP_BLE_SM_MAX_KEY_SIZE,
+            SecurityParam::MinKeySize => esp_ble_sm_param_t_ESP_BLE_SM_MIN_KEY_SIZE,
+            SecurityParam::StaticPasskey => esp_ble_sm_param_t_ESP_BLE_SM_SET_STATIC_PASSKEY,
+            SecurityParam::ClearStaticPasskey => esp_ble_sm_param_t_ESP_BLE_SM_CLEAR_STATIC_PASSKEY,
+            SecurityParam::OnlyAcceptSpecifiedAuthentication => {
+                esp_ble_sm_param_t_ESP_BLE_SM_ONLY_ACCEPT_SPECIFIED_SEC_AUTH
+            }
+            SecurityParam::OobSupport => esp_ble_sm_param_t_ESP_BLE_SM_OOB_SUPPORT,
+        }
+    }
+}
+
+/// Type of the identity address of a bonded peer, only known when it
+/// distributed its identity key.
+fn identity_addr_type(keys: &esp_ble_bond_key_info_t) -> AddressType {
+    if keys.key_mask & ESP_LE_KEY_PID as esp_ble_key_mask_t != 0 {
+        keys.pid_key.addr_type.into()
+    } else {
+        AddressType::Public
+    }
+}
+
+impl BleBackend for BluedroidBackend {
+    fn init(&self) -> Result<(), EspError> {
+        #[cfg(esp32)]
+        let mut bt_cfg = esp_bt_controller_config_t {
+            controller_task_stack_size: ESP_TASK_BT_CONTROLLER_STACK as _,
+            controller_task_prio: ESP_TASK_BT_CONTROLLER_PRIO as _,
+            hci_uart_no: BT_HCI_UART_NO_DEFAULT as _,
+            hci_uart_baudrate: BT_HCI_UART_BAUDRATE_DEFAULT,
+            scan_duplicate_mode: SCAN_DUPLICATE_MODE as _,
+            scan_duplicate_type: SCAN_DUPLICATE_TYPE_VALUE as _,
+            normal_adv_size: NORMAL_SCAN_DUPLICATE_CACHE_SIZE as _,
+            mesh_adv_size: MESH_DUPLICATE_SCAN_CACHE_SIZE as _,
+            send_adv_reserved_size: SCAN_SEND_ADV_RESERVED_SIZE as _,
+            controller_debug_flag: CONTROLLER_ADV_LOST_DEBUG_BIT,
+            mode: esp_bt_mode_t_ESP_BT_MODE_BLE as _,
+            ble_max_conn: CONFIG_BTDM_CTRL_BLE_MAX_CONN_EFF as _,
+            bt_max_acl_conn: CONFIG_BTDM_CTRL_BR_EDR_MAX_ACL_CONN_EFF as _,
+            bt_sco_datapath: CONFIG_BTDM_CTRL_BR_EDR_SCO_DATA_PATH_EFF as _,
+            auto_latency: BTDM_CTRL_AUTO_LATENCY_EFF != 0,
+            bt_legacy_auth_vs_evt: BTDM_CTRL_LEGACY_AUTH_VENDOR_EVT_EFF != 0,
+            bt_max_sync_conn: CONFIG_BTDM_CTRL_BR_EDR_MAX_SYNC_CONN_EFF as _,
+            ble_sca: CONFIG_BTDM_BLE_SLEEP_CLOCK_ACCURACY_INDEX_EFF as _,
+            pcm_role: CONFIG_BTDM_CTRL_PCM_ROLE_EFF as _,
+            pcm_polar: CONFIG_BTDM_CTRL_PCM_POLAR_EFF as _,
+            hli: BTDM_CTRL_HLI != 0,
+            magic: ESP_BT_CONTROLLER_CONFIG_MAGIC_VAL,
+            dup_list_refresh_period: SCAN_DUPL_CACHE_REFRESH_PERIOD as _,
+        };
+
+        #[cfg(esp32c3)]
+        let mut bt_cfg = esp_bt_controller_config_t {
+            magic: esp_idf_sys::ESP_BT_CTRL_CONFIG_MAGIC_VAL,
+            version: esp_idf_sys::ESP_BT_CTRL_CONFIG_VERSION,
+            controller_task_stack_size: esp_idf_sys::ESP_TASK_BT_CONTROLLER_STACK as _,
+            controller_task_prio: esp_idf_sys::ESP_TASK_BT_CONTROLLER_PRIO as _,
+            controller_task_run_cpu: esp_idf_sys::CONFIG_BT_CTRL_PINNED_TO_CORE as _,
+            bluetooth_mode: esp_idf_sys::CONFIG_BT_CTRL_MODE_EFF as _,
+            ble_max_act: esp_idf_sys::CONFIG_BT_CTRL_BLE_MAX_ACT_EFF as _,
+            sleep_mode: esp_idf_sys::CONFIG_BT_CTRL_SLEEP_MODE_EFF as _,
+            sleep_clock: esp_idf_sys::CONFIG_BT_CTRL_SLEEP_CLOCK_EFF as _,
+            ble_st_acl_tx_buf_nb: esp_idf_sys::CONFIG_BT_CTRL_BLE_STATIC_ACL_TX_BUF_NB as _,
+            ble_hw_cca_check: esp_idf_sys::CONFIG_BT_CTRL_HW_CCA_EFF as _,
+            ble_adv_dup_filt_max: esp_idf_sys::CONFIG_BT_CTRL_ADV_DUP_FILT_MAX as _,
+            ce_len_type: esp_idf_sys::CONFIG_BT_CTRL_CE_LENGTH_TYPE_EFF as _,
+            hci_tl_type: esp_idf_sys::CONFIG_BT_CTRL_HCI_TL_EFF as _,
+            hci_tl_funcs: std::ptr::null_mut(),
+            txant_dft: esp_idf_sys::CONFIG_BT_CTRL_TX_ANTENNA_INDEX_EFF as _,
+            rxant_dft: esp_idf_sys::CONFIG_BT_CTRL_RX_ANTENNA_INDEX_EFF as _,
+            txpwr_dft: esp_idf_sys::CONFIG_BT_CTRL_DFT_TX_POWER_LEVEL_EFF as _,
+            cfg_mask: esp_idf_sys::CFG_MASK,
+            scan_duplicate_mode: esp_idf_sys::SCAN_DUPLICATE_MODE as _,
+            scan_duplicate_type: esp_idf_sys::SCAN_DUPLICATE_TYPE_VALUE as _,
+            normal_adv_size: esp_idf_sys::NORMAL_SCAN_DUPLICATE_CACHE_SIZE as _,
+            mesh_adv_size: esp_idf_sys::MESH_DUPLICATE_SCAN_CACHE_SIZE as _,
+            coex_phy_coded_tx_rx_time_limit:
+                esp_idf_sys::CONFIG_BT_CTRL_COEX_PHY_CODED_TX_RX_TLIM_EFF as _,
+            hw_target_code: esp_idf_sys::BLE_HW_TARGET_CODE_CHIP_ECO0 as _,
+            slave_ce_len_min: esp_idf_sys::SLAVE_CE_LEN_MIN_DEFAULT as _,
+            hw_recorrect_en: esp_idf_sys::AGC_RECORRECT_EN as _,
+            cca_thresh: esp_idf_sys::CONFIG_BT_CTRL_HW_CCA_VAL as _,
+            coex_param_en: false,
+            coex_use_hooks: false,
+            ble_50_feat_supp: esp_idf_sys::BT_CTRL_50_FEATURE_SUPPORT != 0,
+            dup_list_refresh_period: esp_idf_sys::DUPL_SCAN_CACHE_REFRESH_PERIOD as _,
+            scan_backoff_upperlimitmax: esp_idf_sys::BT_CTRL_SCAN_BACKOFF_UPPERLIMITMAX as _,
+        };
+
+        #[cfg(esp32s3)]
+        let mut bt_cfg = esp_bt_controller_config_t {
+            magic: esp_idf_sys::ESP_BT_CTRL_CONFIG_MAGIC_VAL as _,
+            version: esp_idf_sys::ESP_BT_CTRL_CONFIG_VERSION as _,
+            controller_task_stack_size: esp_idf_sys::ESP_TASK_BT_CONTROLLER_STACK as _,
+            controller_task_prio: esp_idf_sys::ESP_TASK_BT_CONTROLLER_PRIO as _,
+            controller_task_run_cpu: esp_idf_sys::CONFIG_BT_CTRL_PINNED_TO_CORE as _,
+            bluetooth_mode: esp_idf_sys::CONFIG_BT_CTRL_MODE_EFF as _,
+            ble_max_act: esp_idf_sys::CONFIG_BT_CTRL_BLE_MAX_ACT_EFF as _,
+            sleep_mode: esp_idf_sys::CONFIG_BT_CTRL_SLEEP_MODE_EFF as _,
+            sleep_clock: esp_idf_sys::CONFIG_BT_CTRL_SLEEP_CLOCK_EFF as _,
+            ble_st_acl_tx_buf_nb: esp_idf_sys::CONFIG_BT_CTRL_BLE_STATIC_ACL_TX_BUF_NB as _,
+            ble_hw_cca_check: esp_idf_sys::CONFIG_BT_CTRL_HW_CCA_EFF as _,
+            ble_adv_dup_filt_max: esp_idf_sys::CONFIG_BT_CTRL_ADV_DUP_FILT_MAX as _,
+            coex_param_en: false,
+            ce_len_type: esp_idf_sys::CONFIG_BT_CTRL_CE_LENGTH_TYPE_EFF as _,
+            coex_use_hooks: false,
+            hci_tl_type: esp_idf_sys::CONFIG_BT_CTRL_HCI_TL_EFF as _,
+            hci_tl_funcs: std::ptr::null_mut(),
+            txant_dft: esp_idf_sys::CONFIG_BT_CTRL_TX_ANTENNA_INDEX_EFF as _,
+            rxant_dft: esp_idf_sys::CONFIG_BT_CTRL_RX_ANTENNA_INDEX_EFF as _,
+            txpwr_dft: esp_idf_sys::CONFIG_BT_CTRL_DFT_TX_POWER_LEVEL_EFF as _,
+            cfg_mask: esp_idf_sys::CFG_MASK as _,
+            scan_duplicate_mode: esp_idf_sys::SCAN_DUPLICATE_MODE as _,
+            scan_duplicate_type: esp_idf_sys::SCAN_DUPLICATE_TYPE_VALUE as _,
+            normal_adv_size: esp_idf_sys::NORMAL_SCAN_DUPLICATE_CACHE_SIZE as _,
+            mesh_adv_size: esp_idf_sys::MESH_DUPLICATE_SCAN_CACHE_SIZE as _,
+            coex_phy_coded_tx_rx_time_limit:
+                esp_idf_sys::CONFIG_BT_CTRL_COEX_PHY_CODED_TX_RX_TLIM_EFF as _,
+            hw_target_code: esp_idf_sys::BLE_HW_TARGET_CODE_CHIP_ECO0 as _,
+            slave_ce_len_min: esp_idf_sys::SLAVE_CE_LEN_MIN_DEFAULT as _,
+            hw_recorrect_en: esp_idf_sys::AGC_RECORRECT_EN as _,
+            cca_thresh: esp_idf_sys::CONFIG_BT_CTRL_HW_CCA_VAL as _,
+            ble_50_feat_supp: esp_idf_sys::BT_CTRL_50_FEATURE_SUPPORT != 0,
+            dup_list_refresh_period: esp_idf_sys::DUPL_SCAN_CACHE_REFRESH_PERIOD as _,
+            scan_backoff_upperlimitmax: esp_idf_sys::BT_CTRL_SCAN_BACKOFF_UPPERLIMITMAX as _,
+        };
+
+        info!("Init bluetooth controller.");
+        esp!(unsafe { esp_bt_controller_init(&mut bt_cfg) })?;
+
+        info!("Enable bluetooth controller.");
+        esp!(unsafe { esp_bt_controller_enable(esp_bt_mode_t_ESP_BT_MODE_BLE) })?;
+
+        info!("Init bluedroid");
+        esp!(unsafe { esp_bluedroid_init() })?;
+
+        info!("Enable bluedroid");
+        esp!(unsafe { esp_bluedroid_enable() })?;
+
+        esp!(unsafe { esp_ble_gatts_register_callback(Some(gatts_event_handler)) })?;
+
+        esp!(unsafe { esp_ble_gattc_register_callback(Some(gattc_event_handler)) })?;
+
+        esp!(unsafe { esp_ble_gap_register_callback(Some(gap_event_handler)) })
+    }
+
+    fn deinit(&self) -> Result<(), EspError> {
+        info!("Disable bluedroid");
+        esp!(unsafe { esp_bluedroid_disable() })?;
+        esp!(unsafe { esp_bluedroid_deinit() })?;
+
+        info!("Disable bluetooth controller.");
+        esp!(unsafe { esp_bt_controller_disable() })?;
+        esp!(unsafe { esp_bt_controller_deinit() })
+    }
+
+    fn set_local_mtu(&self, mtu: u16) -> Result<(), EspError> {
+        esp!(unsafe { esp_ble_gatt_set_local_mtu(mtu) })
+    }
+
+    fn set_device_name(&self, name: &str) -> Result<(), EspError> {
+        let name = CString::new(name).map_err(|_| esp_error(ESP_ERR_INVALID_ARG))?;
+        esp!(unsafe { esp_ble_gap_set_device_name(name.as_ptr() as _) })
+    }
+
+    fn config_adv_data(&self, data: &AdvertiseData) -> Result<(), EspError> {
+        let mut manufacturer = data.manufacturer.clone().map(String::into_bytes);
+        let mut service = data.service.clone().map(String::into_bytes);
+
+        #[repr(C, align(4))]
+        struct aligned_uuid {
+            uuid: [u8; 16],
+        }
+        let mut svc_uuid: aligned_uuid = aligned_uuid { uuid: [0; 16] };
+
+        let svc_uuid_len = data
+            .service_uuid
+            .as_ref()
+            .map(|bt_uuid| match bt_uuid {
+                BtUuid::Uuid16(uuid) => {
+                    svc_uuid.uuid[0..2].copy_from_slice(&uuid.to_le_bytes());
+                    2
+                }
+                BtUuid::Uuid32(uuid) => {
+                    svc_uuid.uuid[0..4].copy_from_slice(&uuid.to_le_bytes());
+                    4
+                }
+                BtUuid::Uuid128(uuid) => {
+                    svc_uuid.uuid.copy_from_slice(uuid);
+                    16
+                }
+            })
+            .unwrap_or(0);
+
+        let mut adv_data = esp_ble_adv_data_t {
+            set_scan_rsp: data.set_scan_rsp,
+            include_name: data.include_name,
+            include_txpower: data.include_txpower,
+            min_interval: data.min_interval,
+            max_interval: data.max_interval,
+            manufacturer_len: manufacturer.as_ref().map_or(0, |m| m.len()) as _,
+            p_manufacturer_data: manufacturer
+                .as_mut()
+                .map_or(std::ptr::null_mut(), |m| m.as_mut_ptr()),
+            service_data_len: service.as_ref().map_or(0, |s| s.len()) as _,
+            p_service_data: service
+                .as_mut()
+                .map_or(std::ptr::null_mut(), |s| s.as_mut_ptr()),
+            service_uuid_len: svc_uuid_len,
+            p_service_uuid: if svc_uuid_len == 0 {
+                std::ptr::null_mut()
+            } else {
+                svc_uuid.uuid.as_mut_ptr()
+            },
+            appearance: data.appearance.into(),
+            flag: data.flag,
+        };
+
+        info!("Configuring advertising with {{ {:?} }}", &adv_data);
+
+        // The stack copies the payload before returning.
+        esp!(unsafe { esp_ble_gap_config_adv_data(&mut adv_data) })
+    }
+
+    fn config_adv_data_raw(&self, data: &[u8]) -> Result<(), EspError> {
+        esp!(unsafe { esp_ble_gap_config_adv_data_raw(data.as_ptr() as _, data.len() as _) })
+    }
+
+    fn config_scan_rsp_data_raw(&self, data: &[u8]) -> Result<(), EspError> {
+        esp!(unsafe { esp_ble_gap_config_scan_rsp_data_raw(data.as_ptr() as _, data.len() as _) })
+    }
+
+    fn start_advertising(&self, params: &AdvertisingParameters) -> Result<(), EspError> {
+        let mut adv_param: esp_ble_adv_params_t = (*params).into();
+        esp!(unsafe { esp_ble_gap_start_advertising(&mut adv_param) })
+    }
+
+    fn stop_advertising(&self) -> Result<(), EspError> {
+        esp!(unsafe { esp_ble_gap_stop_advertising() })
+    }
+
+    fn set_scan_params(&self, params: &ScanParameters) -> Result<(), EspError> {
+        let mut scan_params: esp_ble_scan_params_t = (*params).into();
+        esp!(unsafe { esp_ble_gap_set_scan_params(&mut scan_params) })
+    }
+
+    fn start_scanning(&self, duration: u32) -> Result<(), EspError> {
+        esp!(unsafe { esp_ble_gap_start_scanning(duration) })
+    }
+
+    fn stop_scanning(&self) -> Result<(), EspError> {
+        esp!(unsafe { esp_ble_gap_stop_scanning() })
+    }
+
+    fn update_conn_params(&self, params: &ConnectionUpdate) -> Result<(), EspError> {
+        let mut conn_params = esp_ble_conn_update_params_t {
+            bda: params.bda.into(),
+            min_int: params.min_int,
+            max_int: params.max_int,
+            latency: params.latency,
+            timeout: params.timeout,
+        };
+        esp!(unsafe { esp_ble_gap_update_conn_params(&mut conn_params) })
+    }
+
+    fn set_security_param(&self, param: SecurityParam, value: &[u8]) -> Result<(), EspError> {
+        let mut value = value.to_vec();
+        esp!(unsafe {
+            esp_ble_gap_set_security_param(
+                param.into(),
+                value.as_mut_ptr() as *mut c_void,
+                value.len() as _,
+            )
+        })
+    }
+
+    fn security_rsp(&self, bda: BdAddr, accept: bool) -> Result<(), EspError> {
+        let mut bda: [u8; 6] = bda.into();
+        esp!(unsafe { esp_ble_gap_security_rsp(bda.as_mut_ptr(), accept) })
+    }
+
+    fn confirm_reply(&self, bda: BdAddr, accept: bool) -> Result<(), EspError> {
+        let mut bda: [u8; 6] = bda.into();
+        esp!(unsafe { esp_ble_confirm_reply(bda.as_mut_ptr(), accept) })
+    }
+
+    fn passkey_reply(&self, bda: BdAddr, accept: bool, passkey: u32) -> Result<(), EspError> {
+        let mut bda: [u8; 6] = bda.into();
+        esp!(unsafe { esp_ble_passkey_reply(bda.as_mut_ptr(), accept, passkey) })
+    }
+
+    fn oob_req_reply(&self, bda: BdAddr, tk: &OobTemporaryKey) -> Result<(), EspError> {
+        let mut bda: [u8; 6] = bda.into();
+        let mut tk = *tk;
+        esp!(unsafe { esp_ble_oob_req_reply(bda.as_mut_ptr(), tk.as_mut_ptr(), tk.len() as u8) })
+    }
+
+    // Secure Connections OOB came with ESP-IDF 5
+    #[cfg(not(esp_idf_version_major = "4"))]
+    fn sc_oob_req_reply(&self, bda: BdAddr, data: &ScOobData) -> Result<(), EspError> {
+        let mut bda: [u8; 6] = bda.into();
+        let mut data = *data;
+        esp!(unsafe {
+            esp_ble_sc_oob_req_reply(
+                bda.as_mut_ptr(),
+                data.confirm.as_mut_ptr(),
+                data.random.as_mut_ptr(),
+            )
+        })
+    }
+
+    #[cfg(esp_idf_version_major = "4")]
+    fn sc_oob_req_reply(&self, _bda: BdAddr, _data: &ScOobData) -> Result<(), EspError> {
+        Err(esp_error(ESP_ERR_NOT_SUPPORTED))
+    }
+
+    #[cfg(not(esp_idf_version_major = "4"))]
+    fn create_sc_oob_data(&self) -> Result<(), EspError> {
+        esp!(unsafe { esp_ble_create_sc_oob_data() })
+    }
+
+    #[cfg(esp_idf_version_major = "4")]
+    fn create_sc_oob_data(&self) -> Result<(), EspError> {
+        Err(esp_error(ESP_ERR_NOT_SUPPORTED))
+    }
+
+    fn set_encryption(&self, bda: BdAddr, encryption: BleEncryption) -> Result<(), EspError> {
+        let mut bda: [u8; 6] = bda.into();
+        esp!(unsafe { esp_ble_set_encryption(bda.as_mut_ptr(), encryption as u32) })
+    }
+
+    fn disconnect(&self, bda: BdAddr) -> Result<(), EspError> {
+        let mut bda: [u8; 6] = bda.into();
+        esp!(unsafe { esp_ble_gap_disconnect(bda.as_mut_ptr()) })
+    }
+
+    fn bonded_devices(&self) -> Result<Vec<BondedDevice>, EspError> {
+        let mut count = unsafe { esp_ble_get_bond_device_num() };
+        if count <= 0 {
+            return Ok(vec![]);
+        }
+
+        let mut devices: Vec<esp_ble_bond_dev_t> =
+            (0..count).map(|_| unsafe { std::mem::zeroed() }).collect();
+        esp!(unsafe { esp_ble_get_bond_device_list(&mut count, devices.as_mut_ptr()) })?;
+        devices.truncate(count as usize);
+
+        Ok(devices
+            .iter()
+            .map(|device| BondedDevice {
+                bda: device.bd_addr.into(),
+                addr_type: identity_addr_type(&device.bond_key),
+                // One bit of the mask for each key type
+                keys: (0..8)
+                    .map(|bit| 1 << bit)
+                    .filter(|key| device.bond_key.key_mask & key != 0)
+                    .map(KeyType::from)
+                    .collect(),
+            })
+            .collect())
+    }
+
+    fn remove_bond_device(&self, bda: BdAddr) -> Result<(), EspError> {
+        let mut bda: [u8; 6] = bda.into();
+        esp!(unsafe { esp_ble_remove_bond_device(bda.as_mut_ptr()) })
+    }
+
+    fn gatts_app_register(&self, app_id: u16) -> Result<(), EspError> {
+        esp!(unsafe { esp_ble_gatts_app_register(app_id) })
+    }
+
+    fn gatts_create_service(&self, gatts_if: u8, service: &GattService) -> Result<(), EspError> {
+        let mut svc_id: esp_gatt_srvc_id_t = esp_gatt_srvc_id_t {
+            is_primary: service.is_primary,
+            id: esp_gatt_id_t {
+                uuid: service.id.clone().into(),
+                inst_id: service.instance_id,
+            },
+        };
+        esp!(unsafe { esp_ble_gatts_create_service(gatts_if, &mut svc_id, service.handle) })
+    }
+
+    fn gatts_start_service(&self, service_handle: u16) -> Result<(), EspError> {
+        esp!(unsafe { esp_ble_gatts_start_service(service_handle) })
+    }
+
+    fn gatts_create_attr_tab(
+        &self,
+        gatts_if: u8,
+        attributes: &[Attribute],
+        service_instance_id: u8,
+    ) -> Result<(), EspError> {
+        let mut uuids: Vec<Vec<u8>> = attributes
+            .iter()
+            .map(|attribute| uuid_bytes(&attribute.uuid))
+            .collect();
+        let mut values: Vec<Vec<u8>> = attributes
+            .iter()
+            .map(|attribute| attribute.value.clone())
+            .collect();
+        let db: Vec<esp_gatts_attr_db_t> = attributes
+            .iter()
+            .zip(uuids.iter_mut())
+            .zip(values.iter_mut())
+            .map(|((attribute, uuid), value)| esp_gatts_attr_db_t {
+                attr_control: attribute.auto_rsp.into(),
+                att_desc: esp_attr_desc_t {
+                    uuid_length: uuid.len() as _,
+                    uuid_p: uuid.as_mut_ptr(),
+                    perm: attribute.permissions,
+                    max_length: attribute.max_len,
+                    length: value.len() as _,
+                    value: value.as_mut_ptr(),
+                },
+            })
+            .collect();
+
+        // Locked until the table is queued, before the event can release it
+        let mut tables = ATTRIBUTE_TABLES
+            .lock()
+            .map_err(|_| esp_error(ESP_ERR_INVALID_STATE))?;
+        esp!(unsafe {
+            esp_ble_gatts_create_attr_tab(db.as_ptr(), gatts_if, db.len() as _, service_instance_id)
+        })?;
+        tables.push_back(AttributeTableBuffers {
+            _uuids: uuids,
+            _values: values,
+            _db: db,
+        });
+        Ok(())
+    }
+
+    fn gatts_add_char(
+        &self,
+        service_handle: u16,
+        uuid: &BtUuid,
+        permissions: u16,
+        property: u8,
+        value: &[u8],
+        auto_rsp: AutoResponse,
+    ) -> Result<(), EspError> {
+        let mut uuid = uuid.clone().into();
+        let mut value = value.to_vec();
+        let mut attr_value = esp_attr_value_t {
+            attr_max_len: value.len() as _,
+            attr_len: value.len() as _,
+            attr_value: value.as_mut_ptr(),
+        };
+        let mut auto_rsp = auto_rsp.into();
+
+        esp!(unsafe {
+            esp_ble_gatts_add_char(
+                service_handle,
+                &mut uuid,
+                permissions,
+                property,
+                &mut attr_value,
+                &mut auto_rsp,
+            )
+        })
+    }
+
+    fn gatts_add_char_descr(
+        &self,
+        service_handle: u16,
+        uuid: &BtUuid,
+        permissions: u16,
+    ) -> Result<(), EspError> {
+        let mut uuid = uuid.clone().into();
+        esp!(unsafe {
+            esp_ble_gatts_add_char_descr(
+                service_handle,
+                &mut uuid,
+                permissions,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+            )
+        })
+    }
+
+    fn gatts_send_indicate(
+        &self,
+        gatts_if: u8,
+        conn_id: u16,
+        attr_handle: u16,
+        value: &[u8],
+        need_confirm: bool,
+    ) -> Result<(), EspError> {
+        let mut value = value.to_vec();
+        esp!(unsafe {
+            esp_ble_gatts_send_indicate(
+                gatts_if,
+                conn_id,
+                attr_handle,
+                value.len() as _,
+                value.as_mut_ptr(),
+                need_confirm,
+            )
+        })
+    }
+
+    fn gatts_get_attr_value(&self, attr_handle: u16) -> Result<Vec<u8>, EspError> {
+        let mut len: u16 = 0;
+        let mut data: *const u8 = std::ptr::null_mut();
+
+        unsafe {
+            esp!(esp_ble_gatts_get_attr_value(
+                attr_handle,
+                &mut len,
+                &mut data
+            ))?;
+
+            let data = std::slice::from_raw_parts(data, len as usize);
+            info!("len: {:?}, data: {:p}", len, data);
+            Ok(data.to_vec())
+        }
+    }
+
+    fn gatts_send_response(
+        &self,
+        gatts_if: u8,
+        conn_id: u16,
+        trans_id: u32,
+        response: &GattResponse,
+    ) -> Result<(), EspError> {
+        let mut rsp: esp_gatt_rsp_t = esp_gatt_rsp_t::default();
+        let value = response.value;
+
+        if value.len() > rsp.attr_value.value.len() {
+            return Err(esp_error(ESP_ERR_INVALID_SIZE));
+        }
+
+        esp!(unsafe {
+            rsp.handle = response.handle;
+            rsp.attr_value.handle = response.handle;
+            rsp.attr_value.offset = response.offset;
+            rsp.attr_value.len = value.len() as u16;
+            rsp.attr_value.value[..value.len()].copy_from_slice(value);
+
+            esp_ble_gatts_send_response(
+                gatts_if,
+                conn_id,
+                trans_id,
+                response.status.into(),
+                &mut rsp,
+            )
+        })
+    }
+
+    fn gattc_app_register(&self, app_id: u16) -> Result<(), EspError> {
+        esp!(unsafe { esp_ble_gattc_app_register(app_id) })
+    }
+
+    fn gattc_open(
+        &self,
+        gattc_if: u8,
+        bda: BdAddr,
+        addr_type: AddressType,
+    ) -> Result<(), EspError> {
+        let mut bda: [u8; 6] = bda.into();
+        esp!(unsafe { esp_ble_gattc_open(gattc_if, bda.as_mut_ptr(), addr_type.into(), true) })
+    }
+
+    fn gattc_close(&self, gattc_if: u8, conn_id: u16) -> Result<(), EspError> {
+        esp!(unsafe { esp_ble_gattc_close(gattc_if, conn_id) })
+    }
+
+    fn gattc_search_service(
+        &self,
+        gattc_if: u8,
+        conn_id: u16,
+        filter: Option<&BtUuid>,
+    ) -> Result<(), EspError> {
+        let mut filter: Option<esp_bt_uuid_t> = filter.map(|uuid| uuid.clone().into());
+        esp!(unsafe {
+            esp_ble_gattc_search_service(
+                gattc_if,
+                conn_id,
+                filter
+                    .as_mut()
+                    .map_or(std::ptr::null_mut(), |uuid| uuid as *mut _),
+            )
+        })
+    }
+
+    fn gattc_get_services(
+        &self,
+        gattc_if: u8,
+        conn_id: u16,
+    ) -> Result<Vec<RemoteService>, EspError> {
+        let mut count = attribute_count(
+            gattc_if,
+            conn_id,
+            esp_gatt_db_attr_type_t_ESP_GATT_DB_PRIMARY_SERVICE,
+            0x0001,
+            0xffff,
+            0,
+        )?;
+        if count == 0 {
+            return Ok(vec![]);
+        }
+
+        let mut result = vec![esp_gattc_service_elem_t::default(); count as usize];
+        gatt_status(unsafe {
+            esp_ble_gattc_get_service(
+                gattc_if,
+                conn_id,
+                std::ptr::null_mut(),
+                result.as_mut_ptr(),
+                &mut count,
+                0,
+            )
+        })?;
+        result.truncate(count as usize);
+
+        Ok(result.into_iter().map(RemoteService::from).collect())
+    }
+
+    fn gattc_get_characteristics(
+        &self,
+        gattc_if: u8,
+        conn_id: u16,
+        start_handle: u16,
+        end_handle: u16,
+    ) -> Result<Vec<RemoteCharacteristic>, EspError> {
+        let mut count = attribute_count(
+            gattc_if,
+            conn_id,
+            esp_gatt_db_attr_type_t_ESP_GATT_DB_CHARACTERISTIC,
+            start_handle,
+            end_handle,
+            0,
+        )?;
+        if count == 0 {
+            return Ok(vec![]);
+        }
+
+        let mut result = vec![esp_gattc_char_elem_t::default(); count as usize];
+        gatt_status(unsafe {
+            esp_ble_gattc_get_all_char(
+                gattc_if,
+                conn_id,
+                start_handle,
+                end_handle,
+                result.as_mut_ptr(),
+                &mut count,
+                0,
+            )
+        })?;
+        result.truncate(count as usize);
+
+        Ok(result.into_iter().map(RemoteCharacteristic::from).collect())
+    }
+
+    fn gattc_get_descriptors(
+        &self,
+        gattc_if: u8,
+        conn_id: u16,
+        char_handle: u16,
+    ) -> Result<Vec<RemoteDescriptor>, EspError> {
+        let mut count = attribute_count(
+            gattc_if,
+            conn_id,
+            esp_gatt_db_attr_type_t_ESP_GATT_DB_DESCRIPTOR,
+            0,
+            0,
+            char_handle,
+        )?;
+        if count == 0 {
+            return Ok(vec![]);
+        }
+
+        let mut result = vec![esp_gattc_descr_elem_t::default(); count as usize];
+        gatt_status(unsafe {
+            esp_ble_gattc_get_all_descr(
+                gattc_if,
+                conn_id,
+                char_handle,
+                result.as_mut_ptr(),
+                &mut count,
+                0,
+            )
+        })?;
+        result.truncate(count as usize);
+
+        Ok(result.into_iter().map(RemoteDescriptor::from).collect())
+    }
+
+    fn gattc_read_char(&self, gattc_if: u8, conn_id: u16, handle: u16) -> Result<(), EspError> {
+        esp!(unsafe {
+            esp_ble_gattc_read_char(
+                gattc_if,
+                conn_id,
+                handle,
+                esp_gatt_auth_req_t_ESP_GATT_AUTH_REQ_NONE,
+            )
+        })
+    }
+
+    fn gattc_write_char(
+        &self,
+        gattc_if: u8,
+        conn_id: u16,
+        handle: u16,
+        value: &[u8],
+        write_type: WriteType,
+    ) -> Result<(), EspError> {
+        let mut value = value.to_vec();
+        esp!(unsafe {
+            esp_ble_gattc_write_char(
+                gattc_if,
+                conn_id,
+                handle,
+                value.len() as _,
+                value.as_mut_ptr(),
+                write_type as _,
+                esp_gatt_auth_req_t_ESP_GATT_AUTH_REQ_NONE,
+            )
+        })
+    }
+
+    fn gattc_read_char_descr(
+        &self,
+        gattc_if: u8,
+        conn_id: u16,
+        handle: u16,
+    ) -> Result<(), EspError> {
+        esp!(unsafe {
+            esp_ble_gattc_read_char_descr(
+                gattc_if,
+                conn_id,
+                handle,
+                esp_gatt_auth_req_t_ESP_GATT_AUTH_REQ_NONE,
+            )
+        })
+    }
+
+    fn gattc_write_char_descr(
+        &self,
+        gattc_if: u8,
+        conn_id: u16,
+        handle: u16,
+        value: &[u8],
+        write_type: WriteType,
+    ) -> Result<(), EspError> {
+        let mut value = value.to_vec();
+        esp!(unsafe {
+            esp_ble_gattc_write_char_descr(
+                gattc_if,
+                conn_id,
+                handle,
+                value.len() as _,
+                value.as_mut_ptr(),
+                write_type as _,
+                esp_gatt_auth_req_t_ESP_GATT_AUTH_REQ_NONE,
+            )
+        })
+    }
+
+    fn gattc_register_for_notify(
+        &self,
+        gattc_if: u8,
+        bda: BdAddr,
+        handle: u16,
+    ) -> Result<(), EspError> {
+        let mut bda: [u8; 6] = bda.into();
+        esp!(unsafe { esp_ble_gattc_register_for_notify(gattc_if, bda.as_mut_ptr(), handle) })
+    }
+
+    fn gattc_unregister_for_notify(
+        &self,
+        gattc_if: u8,
+        bda: BdAddr,
+        handle: u16,
+    ) -> Result<(), EspError> {
+        let mut bda: [u8; 6] = bda.into();
+        esp!(unsafe { esp_ble_gattc_unregister_for_notify(gattc_if, bda.as_mut_ptr(), handle) })
+    }
+
+    // The advertising sets need a stack built with the BLE 5.0 features
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    fn ext_adv_set_params(
+        &self,
+        instance: u8,
+        params: &ExtendedAdvertisingParameters,
+    ) -> Result<(), EspError> {
+        let params: esp_ble_gap_ext_adv_params_t = (*params).into();
+        esp!(unsafe { esp_ble_gap_ext_adv_set_params(instance, &params) })
+    }
+
+    #[cfg(not(esp_idf_bt_ble_50_features_supported))]
+    fn ext_adv_set_params(
+        &self,
+        _instance: u8,
+        _params: &ExtendedAdvertisingParameters,
+    ) -> Result<(), EspError> {
+        Err(esp_error(ESP_ERR_NOT_SUPPORTED))
+    }
+
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    fn ext_adv_set_rand_addr(&self, instance: u8, addr: BdAddr) -> Result<(), EspError> {
+        let mut addr: [u8; 6] = addr.into();
+        esp!(unsafe { esp_ble_gap_ext_adv_set_rand_addr(instance, addr.as_mut_ptr()) })
+    }
+
+    #[cfg(not(esp_idf_bt_ble_50_features_supported))]
+    fn ext_adv_set_rand_addr(&self, _instance: u8, _addr: BdAddr) -> Result<(), EspError> {
+        Err(esp_error(ESP_ERR_NOT_SUPPORTED))
+    }
+
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    fn config_ext_adv_data_raw(&self, instance: u8, data: &[u8]) -> Result<(), EspError> {
+        esp!(unsafe {
+            esp_ble_gap_config_ext_adv_data_raw(instance, data.len() as _, data.as_ptr())
+        })
+    }
+
+    #[cfg(not(esp_idf_bt_ble_50_features_supported))]
+    fn config_ext_adv_data_raw(&self, _instance: u8, _data: &[u8]) -> Result<(), EspError> {
+        Err(esp_error(ESP_ERR_NOT_SUPPORTED))
+    }
+
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    fn config_ext_scan_rsp_data_raw(&self, instance: u8, data: &[u8]) -> Result<(), EspError> {
+        esp!(unsafe {
+            esp_ble_gap_config_ext_scan_rsp_data_raw(instance, data.len() as _, data.as_ptr())
+        })
+    }
+
+    #[cfg(not(esp_idf_bt_ble_50_features_supported))]
+    fn config_ext_scan_rsp_data_raw(&self, _instance: u8, _data: &[u8]) -> Result<(), EspError> {
+        Err(esp_error(ESP_ERR_NOT_SUPPORTED))
+    }
+
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    fn ext_adv_start(&self, sets: &[(u8, ExtendedAdvertisingDuration)]) -> Result<(), EspError> {
+        let ext_adv: Vec<esp_ble_gap_ext_adv_t> = sets
+            .iter()
+            .map(|(instance, duration)| esp_ble_gap_ext_adv_t {
+                instance: *instance,
+                duration: duration.duration as _,
+                max_events: duration.max_events as _,
+            })
+            .collect();
+        esp!(unsafe { esp_ble_gap_ext_adv_start(ext_adv.len() as _, ext_adv.as_ptr()) })
+    }
+
+    #[cfg(not(esp_idf_bt_ble_50_features_supported))]
+    fn ext_adv_start(&self, _sets: &[(u8, ExtendedAdvertisingDuration)]) -> Result<(), EspError> {
+        Err(esp_error(ESP_ERR_NOT_SUPPORTED))
+    }
+
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    fn ext_adv_stop(&self, instances: &[u8]) -> Result<(), EspError> {
+        esp!(unsafe { esp_ble_gap_ext_adv_stop(instances.len() as _, instances.as_ptr()) })
+    }
+
+    #[cfg(not(esp_idf_bt_ble_50_features_supported))]
+    fn ext_adv_stop(&self, _instances: &[u8]) -> Result<(), EspError> {
+        Err(esp_error(ESP_ERR_NOT_SUPPORTED))
+    }
+
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    fn ext_adv_set_remove(&self, instance: u8) -> Result<(), EspError> {
+        esp!(unsafe { esp_ble_gap_ext_adv_set_remove(instance) })
+    }
+
+    #[cfg(not(esp_idf_bt_ble_50_features_supported))]
+    fn ext_adv_set_remove(&self, _instance: u8) -> Result<(), EspError> {
+        Err(esp_error(ESP_ERR_NOT_SUPPORTED))
+    }
+
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    fn ext_adv_set_clear(&self) -> Result<(), EspError> {
+        esp!(unsafe { esp_ble_gap_ext_adv_set_clear() })
+    }
+
+    #[cfg(not(esp_idf_bt_ble_50_features_supported))]
+    fn ext_adv_set_clear(&self) -> Result<(), EspError> {
+        Err(esp_error(ESP_ERR_NOT_SUPPORTED))
+    }
+
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    fn periodic_adv_set_params(
+        &self,
+        instance: u8,
+        params: &PeriodicAdvertisingParameters,
+    ) -> Result<(), EspError> {
+        let params: esp_ble_gap_periodic_adv_params_t = (*params).into();
+        esp!(unsafe { esp_ble_gap_periodic_adv_set_params(instance, &params) })
+    }
+
+    #[cfg(not(esp_idf_bt_ble_50_features_supported))]
+    fn periodic_adv_set_params(
+        &self,
+        _instance: u8,
+        _params: &PeriodicAdvertisingParameters,
+    ) -> Result<(), EspError> {
+        Err(esp_error(ESP_ERR_NOT_SUPPORTED))
+    }
+
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    fn config_periodic_adv_data_raw(&self, instance: u8, data: &[u8]) -> Result<(), EspError> {
+        esp!(unsafe {
+            esp_ble_gap_config_periodic_adv_data_raw(instance, data.len() as _, data.as_ptr())
+        })
+    }
+
+    #[cfg(not(esp_idf_bt_ble_50_features_supported))]
+    fn config_periodic_adv_data_raw(&self, _instance: u8, _data: &[u8]) -> Result<(), EspError> {
+        Err(esp_error(ESP_ERR_NOT_SUPPORTED))
+    }
+
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    fn periodic_adv_start(&self, instance: u8) -> Result<(), EspError> {
+        esp!(unsafe { esp_ble_gap_periodic_adv_start(instance) })
+    }
+
+    #[cfg(not(esp_idf_bt_ble_50_features_supported))]
+    fn periodic_adv_start(&self, _instance: u8) -> Result<(), EspError> {
+        Err(esp_error(ESP_ERR_NOT_SUPPORTED))
+    }
+
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    fn periodic_adv_stop(&self, instance: u8) -> Result<(), EspError> {
+        esp!(unsafe { esp_ble_gap_periodic_adv_stop(instance) })
+    }
+
+    #[cfg(not(esp_idf_bt_ble_50_features_supported))]
+    fn periodic_adv_stop(&self, _instance: u8) -> Result<(), EspError> {
+        Err(esp_error(ESP_ERR_NOT_SUPPORTED))
+    }
+}
+
+/// Number of attributes of the type `attr_type` in the local cache of the
+/// server attributes.
+fn attribute_count(
+    gattc_if: u8,
+    conn_id: u16,
+    attr_type: esp_gatt_db_attr_type_t,
+    start_handle: u16,
+    end_handle: u16,
+    char_handle: u16,
+) -> Result<u16, EspError> {
+    let mut count: u16 = 0;
+    gatt_status(unsafe {
+        esp_ble_gattc_get_attr_count(
+            gattc_if,
+            conn_id,
+            attr_type,
+            start_handle,
+            end_handle,
+            char_handle,
+            &mut count,
+        )
+    })?;
+    Ok(count)
+}
+
+/// The cache lookups report a GATT status, it is logged and reported as
+/// `ESP_FAIL`.
+fn gatt_status(status: esp_gatt_status_t) -> Result<(), EspError> {
+    if status == esp_gatt_status_t_ESP_GATT_OK {
+        Ok(())
+    } else {
+        warn!("GATT operation failed with status: {}", status);
+        Err(esp_error(ESP_FAIL))
+    }
+}
+
+/// Largest subscriptions value stored, 3 bytes for each subscribed
+/// characteristic.
+const SUBSCRIPTIONS_MAX_LEN: usize = 3 * 64;
+
+/// `SubscriptionStore` in the nvs namespace held by `EspBle`.
+pub(crate) struct NvsSubscriptionStore {
+    nvs: Arc<Mutex<EspDefaultNvs>>,
+}
+
+impl NvsSubscriptionStore {
+    pub(crate) fn new(nvs: Arc<Mutex<EspDefaultNvs>>) -> Self {
+        Self { nvs }
+    }
+}
+
+/// nvs keys are limited to 15 characters: `cc` then the address in hex.
+fn subscriptions_key(bda: BdAddr) -> String {
+    let mut key = String::from("cc");
+    for byte in bda.as_bytes() {
+        key.push_str(&format!("{:02x}", byte));
+    }
+    key
+}
+
+impl SubscriptionStore for NvsSubscriptionStore {
+    fn load(&self, bda: BdAddr) -> Option<Vec<u8>> {
+        let nvs = self.nvs.lock().ok()?;
+        let mut buf = [0; SUBSCRIPTIONS_MAX_LEN];
+        match nvs.get_raw(&subscriptions_key(bda), &mut buf) {
+            Ok(value) => value.map(|value| value.to_vec()),
+            Err(err) => {
+                warn!("Unable to load the subscriptions of {}: {}", bda, err);
+                None
+            }
+        }
+    }
+
+    fn save(&self, bda: BdAddr, value: &[u8]) {
+        if value.len() > SUBSCRIPTIONS_MAX_LEN {
+            warn!("Too many subscriptions to save for {}", bda);
+            return;
+        }
+
+        let mut nvs = match self.nvs.lock() {
+            Ok(nvs) => nvs,
+            Err(_) => return,
+        };
+        let key = subscriptions_key(bda);
+        let result = if value.is_empty() {
+            nvs.remove(&key).map(|_| ())
+        } else {
+            nvs.set_raw(&key, value).map(|_| ())
+        };
+
+        if let Err(err) = result {
+            warn!("Unable to save the subscriptions of {}: {}", bda, err);
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+/// Result of an operation completed by a callback, for the caller of the
+/// operation to wait for, or to await with any executor.
+///
+/// The callbacks run in the Bluedroid task, waiting from a callback would
+/// prevent the completion from ever being reported.
+pub(crate) struct Completion<T> {
+    state: Arc<(Mutex<State<T>>, Condvar)>,
+}
+
+struct State<T> {
+    result: Option<T>,
+    waker: Option<Waker>,
+}
+
+impl<T> Clone for Completion<T> {
+    fn clone(&self) -> Self {
+        Self {
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<T> Completion<T> {
+    pub(crate) fn new() -> Self {
+        let state = State {
+            result: None,
+            waker: None,
+        };
+        Self {
+            state: Arc::new((Mutex::new(state), Condvar::new())),
+        }
+    }
+
+    pub(crate) fn complete(&self, value: T) {
+        let (state, completed) = &*self.state;
+        if let Ok(mut state) = state.lock() {
+            state.result = Some(value);
+            completed.notify_all();
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+
+    /// `None` if the operation is not completed within `timeout`.
+    pub(crate) fn wait(&self, timeout: Duration) -> Option<T> {
+        let (state, completed) = &*self.state;
+        let state = state.lock().ok()?;
+        let (mut state, _) = completed
+            .wait_timeout_while(state, timeout, |state| state.result.is_none())
+            .ok()?;
+        state.result.take()
+    }
+}
+
+/// Only the executor's waker is needed, the future can be awaited on any
+/// executor. It has no timeout of its own.
+impl<T> Future for Completion<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let (state, _) = &*self.state;
+        let mut state = match state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        match state.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Minimal executor for the tests of the async operations.
+#[cfg(test)]
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    use std::task::Wake;
+    use std::thread::Thread;
+
+    struct Unpark(Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let waker = Waker::from(Arc::new(Unpark(std::thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = Box::pin(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        std::thread::park();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wait_for_completion() {
+        let completion = Completion::new();
+        let completed = completion.clone();
+        let thread = std::thread::spawn(move || completed.complete(42));
+
+        assert_eq!(completion.wait(Duration::from_secs(5)), Some(42));
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn completed_before_waiting() {
+        let completion = Completion::new();
+        completion.complete(42);
+        assert_eq!(completion.wait(Duration::ZERO), Some(42));
+    }
+
+    #[test]
+    fn timeout() {
+        let completion = Completion::<u16>::new();
+        assert_eq!(completion.wait(Duration::from_millis(10)), None);
+    }
+
+    #[test]
+    fn await_completion() {
+        let completion = Completion::new();
+        let completed = completion.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(10));
+            completed.complete(42)
+        });
+
+        assert_eq!(block_on(completion), 42);
+        thread.join().unwrap();
+    }
+}
@@ -0,0 +1,461 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::backend::ConnectionUpdate;
+use crate::notification::ATT_DEFAULT_MTU;
+use crate::{BdAddr, ConnectEvent, DisconnectReason, GapEvent, GattServiceEvent, LinkRole};
+
+/// Range of the connection interval, in 1.25ms units: 7.5ms to 4s
+pub const CONN_INTERVAL_MIN: u16 = 0x0006;
+pub const CONN_INTERVAL_MAX: u16 = 0x0c80;
+
+/// Largest number of connection events the peripheral can skip
+pub const CONN_LATENCY_MAX: u16 = 0x01f3;
+
+/// Range of the supervision timeout, in 10ms units: 100ms to 32s
+pub const SUPERVISION_TIMEOUT_MIN: u16 = 0x000a;
+pub const SUPERVISION_TIMEOUT_MAX: u16 = 0x0c80;
+
+/// Connection parameters requested from the central, intervals in 1.25ms
+/// units and timeout in 10ms units.
+///
+/// The default is a 20ms to 40ms interval, no latency and a 4s timeout.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConnectionParameters {
+    pub min_interval: u16,
+    pub max_interval: u16,
+    /// Connection events the peripheral can skip when it has nothing to send
+    pub latency: u16,
+    pub timeout: u16,
+}
+
+impl Default for ConnectionParameters {
+    fn default() -> Self {
+        Self {
+            min_interval: 0x10,
+            max_interval: 0x20,
+            latency: 0,
+            timeout: 400,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionParametersError {
+    /// The interval is outside of `CONN_INTERVAL_MIN..=CONN_INTERVAL_MAX`
+    IntervalOutOfRange(u16),
+    /// `min_interval` is greater than `max_interval`
+    InvalidIntervalRange { min: u16, max: u16 },
+    /// The latency is greater than `CONN_LATENCY_MAX`
+    LatencyOutOfRange(u16),
+    /// The timeout is outside of `SUPERVISION_TIMEOUT_MIN..=SUPERVISION_TIMEOUT_MAX`
+    TimeoutOutOfRange(u16),
+    /// The timeout expires before the peripheral can answer twice with the
+    /// latency and the maximum interval, it must be greater than `min`
+    TimeoutTooShort { timeout: u16, min: u16 },
+}
+
+impl std::fmt::Display for ConnectionParametersError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConnectionParametersError::IntervalOutOfRange(interval) => write!(
+                f,
+                "Connection interval {:#06x} is not in {:#06x}..={:#06x}",
+                interval, CONN_INTERVAL_MIN, CONN_INTERVAL_MAX
+            ),
+            ConnectionParametersError::InvalidIntervalRange { min, max } => write!(
+                f,
+                "Minimum connection interval {:#06x} is greater than the maximum {:#06x}",
+                min, max
+            ),
+            ConnectionParametersError::LatencyOutOfRange(latency) => write!(
+                f,
+                "Latency {} is greater than {}",
+                latency, CONN_LATENCY_MAX
+            ),
+            ConnectionParametersError::TimeoutOutOfRange(timeout) => write!(
+                f,
+                "Supervision timeout {:#06x} is not in {:#06x}..={:#06x}",
+                timeout, SUPERVISION_TIMEOUT_MIN, SUPERVISION_TIMEOUT_MAX
+            ),
+            ConnectionParametersError::TimeoutTooShort { timeout, min } => write!(
+                f,
+                "Supervision timeout {:#06x} must be greater than {:#06x}",
+                timeout, min
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConnectionParametersError {}
+
+impl ConnectionParameters {
+    pub fn validate(&self) -> Result<(), ConnectionParametersError> {
+        for interval in [self.min_interval, self.max_interval] {
+            if !(CONN_INTERVAL_MIN..=CONN_INTERVAL_MAX).contains(&interval) {
+                return Err(ConnectionParametersError::IntervalOutOfRange(interval));
+            }
+        }
+        if self.min_interval > self.max_interval {
+            return Err(ConnectionParametersError::InvalidIntervalRange {
+                min: self.min_interval,
+                max: self.max_interval,
+            });
+        }
+
+        if self.latency > CONN_LATENCY_MAX {
+            return Err(ConnectionParametersError::LatencyOutOfRange(self.latency));
+        }
+
+        if !(SUPERVISION_TIMEOUT_MIN..=SUPERVISION_TIMEOUT_MAX).contains(&self.timeout) {
+            return Err(ConnectionParametersError::TimeoutOutOfRange(self.timeout));
+        }
+        // timeout * 10ms > (1 + latency) * max_interval * 1.25ms * 2
+        let min = (1 + u32::from(self.latency)) * u32::from(self.max_interval) / 4;
+        if u32::from(self.timeout) <= min {
+            return Err(ConnectionParametersError::TimeoutTooShort {
+                timeout: self.timeout,
+                min: min.try_into().unwrap_or(u16::MAX),
+            });
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn update(&self, bda: BdAddr) -> ConnectionUpdate {
+        ConnectionUpdate {
+            bda,
+            min_int: self.min_interval,
+            max_int: self.max_interval,
+            latency: self.latency,
+            timeout: self.timeout,
+        }
+    }
+}
+
+/// Chooses the connection parameters for a central, see
+/// `ConnectionParametersPolicy::PerPeer`.
+pub type ChooseConnectionParameters =
+    dyn Fn(&ConnectEvent) -> Option<ConnectionParameters> + Send + Sync;
+
+/// Connection parameters an application of the GATT server requests from
+/// the centrals connecting to it. The default is `Fixed` with the default
+/// `ConnectionParameters`.
+#[derive(Clone)]
+pub enum ConnectionParametersPolicy {
+    /// Keep the parameters the central chose
+    Keep,
+    Fixed(ConnectionParameters),
+    /// Parameters chosen for each central, `None` keeps the ones it chose
+    PerPeer(Arc<ChooseConnectionParameters>),
+}
+
+impl ConnectionParametersPolicy {
+    pub fn per_peer(
+        choose: impl Fn(&ConnectEvent) -> Option<ConnectionParameters> + Send + Sync + 'static,
+    ) -> Self {
+        ConnectionParametersPolicy::PerPeer(Arc::new(choose))
+    }
+
+    /// Parameters to request from the central of `connect`.
+    pub(crate) fn parameters(&self, connect: &ConnectEvent) -> Option<ConnectionParameters> {
+        match self {
+            ConnectionParametersPolicy::Keep => None,
+            ConnectionParametersPolicy::Fixed(params) => Some(*params),
+            ConnectionParametersPolicy::PerPeer(choose) => choose(connect),
+        }
+    }
+}
+
+impl Default for ConnectionParametersPolicy {
+    fn default() -> Self {
+        ConnectionParametersPolicy::Fixed(ConnectionParameters::default())
+    }
+}
+
+impl std::fmt::Debug for ConnectionParametersPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConnectionParametersPolicy::Keep => write!(f, "Keep"),
+            ConnectionParametersPolicy::Fixed(params) => {
+                f.debug_tuple("Fixed").field(params).finish()
+            }
+            ConnectionParametersPolicy::PerPeer(_) => write!(f, "PerPeer"),
+        }
+    }
+}
+
+/// An active link of the GATT server, intervals in 1.25ms units and timeout
+/// in 10ms units.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Connection {
+    pub conn_id: u16,
+    pub bda: BdAddr,
+    pub link_role: LinkRole,
+    pub interval: u16,
+    pub latency: u16,
+    pub timeout: u16,
+    /// `ATT_DEFAULT_MTU` until the client exchanges its MTU
+    pub mtu: u16,
+    /// The link was encrypted by pairing, or with the keys of a bonded peer
+    pub encrypted: bool,
+    /// The stack can't take more notifications for now
+    pub congested: bool,
+}
+
+/// A link went up or down, as reported to the connection callbacks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ConnectionChange {
+    Connected(Connection),
+    Disconnected(Connection, DisconnectReason),
+}
+
+/// The active links of the GATT server, kept up to date from the server and
+/// GAP events.
+#[derive(Default)]
+pub(crate) struct ConnectionManager {
+    connections: HashMap<u16, Connection>,
+    /// Policy of each application, by interface.
+    policies: HashMap<u8, ConnectionParametersPolicy>,
+}
+
+impl ConnectionManager {
+    /// Update the links from `event`, and return the change it makes when a
+    /// link goes up or down.
+    pub(crate) fn handle_gatts_event(
+        &mut self,
+        event: &GattServiceEvent,
+    ) -> Option<ConnectionChange> {
+        match event {
+            GattServiceEvent::Connect(connect) => {
+                let connection = Connection {
+                    conn_id: connect.conn_id,
+                    bda: connect.bda,
+                    link_role: connect.link_role,
+                    interval: connect.interval,
+                    latency: connect.latency,
+                    timeout: connect.timeout,
+                    mtu: ATT_DEFAULT_MTU,
+                    encrypted: false,
+                    congested: false,
+                };
+                self.connections.insert(connect.conn_id, connection);
+                return Some(ConnectionChange::Connected(connection));
+            }
+            GattServiceEvent::Disconnect(disconnect) => {
+                let connection = self.connections.remove(&disconnect.conn_id)?;
+                return Some(ConnectionChange::Disconnected(
+                    connection,
+                    disconnect.reason,
+                ));
+            }
+            GattServiceEvent::Mtu(mtu) => {
+                if let Some(connection) = self.connections.get_mut(&mtu.conn_id) {
+                    connection.mtu = mtu.mtu;
+                }
+            }
+            GattServiceEvent::Congest(congest) => {
+                if let Some(connection) = self.connections.get_mut(&congest.conn_id) {
+                    connection.congested = congest.congested;
+                }
+            }
+            _ => (),
+        }
+        None
+    }
+
+    pub(crate) fn handle_gap_event(&mut self, event: &GapEvent) {
+        match event {
+            GapEvent::UpdateConnectionParamsComplete(params) if params.status.is_success() => {
+                for connection in self.peer_connections(params.bda) {
+                    connection.interval = params.conn_int;
+                    connection.latency = params.latency;
+                    connection.timeout = params.timeout;
+                }
+            }
+            GapEvent::AuthenticationComplete(auth) if auth.success => {
+                for connection in self.peer_connections(auth.bda) {
+                    connection.encrypted = true;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    pub(crate) fn get(&self, conn_id: u16) -> Option<Connection> {
+        self.connections.get(&conn_id).copied()
+    }
+
+    /// The active links, by connection id.
+    pub(crate) fn connections(&self) -> Vec<Connection> {
+        let mut connections: Vec<_> = self.connections.values().copied().collect();
+        connections.sort_by_key(|connection| connection.conn_id);
+        connections
+    }
+
+    pub(crate) fn set_policy(&mut self, gatts_if: u8, policy: ConnectionParametersPolicy) {
+        self.policies.insert(gatts_if, policy);
+    }
+
+    pub(crate) fn policy(&self, gatts_if: u8) -> ConnectionParametersPolicy {
+        self.policies.get(&gatts_if).cloned().unwrap_or_default()
+    }
+
+    fn peer_connections(&mut self, bda: BdAddr) -> impl Iterator<Item = &mut Connection> {
+        self.connections
+            .values_mut()
+            .filter(move |connection| connection.bda == bda)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        AddressType, AuthenticationCompleteEvent, BtStatus, CongestEvent, ConnectEvent,
+        ConnectionParamsEvent, DeviceType, DisconnectEvent, MtuEvent,
+    };
+
+    const PEER: BdAddr = BdAddr([0x11, 0x22, 0x33, 0x44, 0x55, 0x66]);
+
+    fn connect(manager: &mut ConnectionManager, conn_id: u16) -> Option<ConnectionChange> {
+        manager.handle_gatts_event(&GattServiceEvent::Connect(ConnectEvent {
+            conn_id,
+            link_role: LinkRole::Peripheral,
+            bda: PEER,
+            interval: 24,
+            latency: 0,
+            timeout: 500,
+        }))
+    }
+
+    #[test]
+    fn links_go_up_and_down() {
+        let mut manager = ConnectionManager::default();
+        let connection = Connection {
+            conn_id: 0,
+            bda: PEER,
+            link_role: LinkRole::Peripheral,
+            interval: 24,
+            latency: 0,
+            timeout: 500,
+            mtu: ATT_DEFAULT_MTU,
+            encrypted: false,
+            congested: false,
+        };
+        assert_eq!(
+            connect(&mut manager, 0),
+            Some(ConnectionChange::Connected(connection))
+        );
+        assert_eq!(manager.get(0), Some(connection));
+
+        let disconnect = GattServiceEvent::Disconnect(DisconnectEvent {
+            conn_id: 0,
+            bda: PEER,
+            reason: DisconnectReason::Timeout,
+        });
+        assert_eq!(
+            manager.handle_gatts_event(&disconnect),
+            Some(ConnectionChange::Disconnected(
+                connection,
+                DisconnectReason::Timeout
+            ))
+        );
+        assert_eq!(manager.get(0), None);
+        assert_eq!(manager.handle_gatts_event(&disconnect), None);
+    }
+
+    #[test]
+    fn links_are_kept_up_to_date() {
+        let mut manager = ConnectionManager::default();
+        connect(&mut manager, 1);
+        connect(&mut manager, 0);
+
+        manager.handle_gatts_event(&GattServiceEvent::Mtu(MtuEvent {
+            conn_id: 1,
+            mtu: 247,
+        }));
+        manager.handle_gatts_event(&GattServiceEvent::Congest(CongestEvent {
+            conn_id: 1,
+            congested: true,
+        }));
+        manager.handle_gap_event(&GapEvent::UpdateConnectionParamsComplete(
+            ConnectionParamsEvent {
+                status: BtStatus::Success,
+                bda: PEER,
+                min_int: 0x10,
+                max_int: 0x20,
+                latency: 2,
+                conn_int: 0x18,
+                timeout: 400,
+            },
+        ));
+        manager.handle_gap_event(&GapEvent::AuthenticationComplete(
+            AuthenticationCompleteEvent {
+                bda: PEER,
+                success: true,
+                fail_reason: 0,
+                key_present: true,
+                addr_type: AddressType::Public,
+                dev_type: DeviceType::Ble,
+                auth_mode: 0,
+            },
+        ));
+
+        let connections = manager.connections();
+        assert_eq!(
+            connections.iter().map(|c| c.conn_id).collect::<Vec<_>>(),
+            [0, 1]
+        );
+        assert_eq!((connections[1].mtu, connections[1].congested), (247, true));
+        assert_eq!((connections[0].mtu, connections[0].congested), (23, false));
+        for connection in connections {
+            assert_eq!(
+                (connection.interval, connection.latency, connection.timeout),
+                (0x18, 2, 400)
+            );
+            assert!(connection.encrypted);
+        }
+    }
+
+    #[test]
+    fn connection_parameters_are_validated() {
+        let params = |min_interval, max_interval, latency, timeout| ConnectionParameters {
+            min_interval,
+            max_interval,
+            latency,
+            timeout,
+        };
+
+        assert_eq!(ConnectionParameters::default().validate(), Ok(()));
+        assert_eq!(params(6, 6, 0, 10).validate(), Ok(()));
+        assert_eq!(params(0x0c80, 0x0c80, 0, 0x0c80).validate(), Ok(()));
+        assert_eq!(params(800, 800, 4, 1001).validate(), Ok(()));
+        assert_eq!(
+            params(5, 6, 0, 10).validate(),
+            Err(ConnectionParametersError::IntervalOutOfRange(5))
+        );
+        assert_eq!(
+            params(0x20, 0x10, 0, 400).validate(),
+            Err(ConnectionParametersError::InvalidIntervalRange {
+                min: 0x20,
+                max: 0x10
+            })
+        );
+        assert_eq!(
+            params(0x10, 0x20, 500, 400).validate(),
+            Err(ConnectionParametersError::LatencyOutOfRange(500))
+        );
+        assert_eq!(
+            params(0x10, 0x20, 0, 9).validate(),
+            Err(ConnectionParametersError::TimeoutOutOfRange(9))
+        );
+        assert_eq!(
+            params(800, 800, 4, 1000).validate(),
+            Err(ConnectionParametersError::TimeoutTooShort {
+                timeout: 1000,
+                min: 1000
+            })
+        );
+    }
+}
@@ -0,0 +1,401 @@
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+type Callback<E> = Box<dyn Fn(E) + Send>;
+type SharedCallback<E> = Arc<dyn Fn(E) + Send + Sync>;
+/// Queued callbacks with their id.
+type Queue<E> = VecDeque<(usize, Callback<E>)>;
+
+/// Callbacks registered for the events of a Bluedroid profile.
+///
+/// One-time callbacks are removed once called, e.g. for the completion of an
+/// operation, kept callbacks are called for every matching event. Queued
+/// callbacks are one-time callbacks for operations which can be pending
+/// together under the same key, they are called in the order they were queued,
+/// the stack completing these operations in order. Events which
+/// do not match any callback, like the ones unknown to this crate, are given to
+/// the catch-all handler if there is one.
+///
+/// Callbacks are called without holding any lock, so they can register or
+/// remove other callbacks. Kept callbacks and the catch-all handler stay
+/// registered while they are called, so they can be called again from another
+/// thread meanwhile.
+pub(crate) struct Dispatcher<K, E> {
+    queued: Mutex<HashMap<K, Queue<E>>>,
+    next_queued: AtomicUsize,
+    one_time: Mutex<HashMap<K, Callback<E>>>,
+    kept: Mutex<HashMap<K, SharedCallback<E>>>,
+    catch_all: Mutex<Option<SharedCallback<E>>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Dispatched {
+    Queued,
+    OneTime,
+    Kept,
+    CatchAll,
+    Dropped,
+}
+
+impl<K: Copy + Eq + Hash, E> Dispatcher<K, E> {
+    pub(crate) fn new() -> Self {
+        Self {
+            queued: Mutex::new(HashMap::new()),
+            next_queued: AtomicUsize::new(0),
+            one_time: Mutex::new(HashMap::new()),
+            kept: Mutex::new(HashMap::new()),
+            catch_all: Mutex::new(None),
+        }
+    }
+
+    pub(crate) fn insert_one_time(&self, key: K, cb: impl Fn(E) + Send + 'static) {
+        if let Ok(mut callbacks) = self.one_time.lock() {
+            callbacks.insert(key, Box::new(cb));
+        }
+    }
+
+    /// Insert a one-time callback unless one is already registered for `key`,
+    /// returns whether `cb` was inserted.
+    pub(crate) fn try_insert_one_time(&self, key: K, cb: impl Fn(E) + Send + 'static) -> bool {
+        match self.one_time.lock() {
+            Ok(mut callbacks) if !callbacks.contains_key(&key) => {
+                callbacks.insert(key, Box::new(cb));
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub(crate) fn insert_kept(&self, key: K, cb: impl Fn(E) + Send + Sync + 'static) {
+        if let Ok(mut callbacks) = self.kept.lock() {
+            callbacks.insert(key, Arc::new(cb));
+        }
+    }
+
+    /// Queue `cb` for `key`, and return its id for `remove_queued`.
+    pub(crate) fn push_queued(&self, key: K, cb: impl Fn(E) + Send + 'static) -> usize {
+        let id = self.next_queued.fetch_add(1, Ordering::Relaxed);
+        if let Ok(mut callbacks) = self.queued.lock() {
+            callbacks
+                .entry(key)
+                .or_default()
+                .push_back((id, Box::new(cb)));
+        }
+        id
+    }
+
+    /// Remove the callback `id` queued for `key`, when its operation could not
+    /// be started.
+    pub(crate) fn remove_queued(&self, key: K, id: usize) {
+        if let Ok(mut callbacks) = self.queued.lock() {
+            if let Some(queue) = callbacks.get_mut(&key) {
+                queue.retain(|(queued, _)| *queued != id);
+                if queue.is_empty() {
+                    callbacks.remove(&key);
+                }
+            }
+        }
+    }
+
+    /// Drop the callback `id` queued for `key`, when its operation is given up
+    /// although it was started. Its completion is still awaited, for the next
+    /// ones to go to their own callback.
+    pub(crate) fn abandon_queued(&self, key: K, id: usize) {
+        if let Ok(mut callbacks) = self.queued.lock() {
+            if let Some(queued) = callbacks
+                .get_mut(&key)
+                .and_then(|queue| queue.iter_mut().find(|(queued, _)| *queued == id))
+            {
+                queued.1 = Box::new(|_| ());
+            }
+        }
+    }
+
+    pub(crate) fn remove_one_time(&self, key: K) {
+        if let Ok(mut callbacks) = self.one_time.lock() {
+            callbacks.remove(&key);
+        }
+    }
+
+    pub(crate) fn remove_kept(&self, key: K) {
+        if let Ok(mut callbacks) = self.kept.lock() {
+            callbacks.remove(&key);
+        }
+    }
+
+    pub(crate) fn set_catch_all(&self, cb: impl Fn(E) + Send + Sync + 'static) {
+        if let Ok(mut catch_all) = self.catch_all.lock() {
+            *catch_all = Some(Arc::new(cb));
+        }
+    }
+
+    /// Remove all the callbacks, including the catch-all handler.
+    pub(crate) fn clear(&self) {
+        if let Ok(mut callbacks) = self.queued.lock() {
+            callbacks.clear();
+        }
+        if let Ok(mut callbacks) = self.one_time.lock() {
+            callbacks.clear();
+        }
+        if let Ok(mut callbacks) = self.kept.lock() {
+            callbacks.clear();
+        }
+        if let Ok(mut catch_all) = self.catch_all.lock() {
+            *catch_all = None;
+        }
+    }
+
+    /// Call the callback registered for `key`, or the catch-all handler when
+    /// there is none or when `key` is `None`.
+    pub(crate) fn dispatch(&self, key: Option<K>, event: E) -> Dispatched {
+        if let Some(key) = key {
+            let queued = self.queued.lock().ok().and_then(|mut m| {
+                let queue = m.get_mut(&key)?;
+                let cb = queue.pop_front();
+                if queue.is_empty() {
+                    m.remove(&key);
+                }
+                cb.map(|(_, cb)| cb)
+            });
+            if let Some(cb) = queued {
+                cb(event);
+                return Dispatched::Queued;
+            }
+
+            let one_time = self.one_time.lock().ok().and_then(|mut m| m.remove(&key));
+            if let Some(cb) = one_time {
+                cb(event);
+                return Dispatched::OneTime;
+            }
+
+            let kept = self.kept.lock().ok().and_then(|m| m.get(&key).cloned());
+            if let Some(cb) = kept {
+                cb(event);
+                return Dispatched::Kept;
+            }
+        }
+
+        let catch_all = self.catch_all.lock().ok().and_then(|cb| cb.clone());
+        if let Some(cb) = catch_all {
+            cb(event);
+            return Dispatched::CatchAll;
+        }
+
+        Dispatched::Dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+    use std::sync::Arc;
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    enum Key {
+        Start,
+        Read(u16),
+    }
+
+    #[derive(Debug, PartialEq, Eq)]
+    enum Event {
+        StartComplete,
+        Read(u16),
+        Unknown(u32),
+    }
+
+    // Mirrors the way the event handlers map the Bluedroid event codes
+    fn build(code: u32, handle: u16) -> Event {
+        match code {
+            0 => Event::StartComplete,
+            1 => Event::Read(handle),
+            _ => Event::Unknown(code),
+        }
+    }
+
+    fn key(event: &Event) -> Option<Key> {
+        match event {
+            Event::StartComplete => Some(Key::Start),
+            Event::Read(handle) => Some(Key::Read(*handle)),
+            Event::Unknown(_) => None,
+        }
+    }
+
+    fn dispatch(dispatcher: &Dispatcher<Key, Event>, code: u32, handle: u16) -> Dispatched {
+        let event = build(code, handle);
+        dispatcher.dispatch(key(&event), event)
+    }
+
+    #[test]
+    fn one_time_callbacks_are_removed() {
+        let dispatcher = Dispatcher::new();
+        let (s, r) = channel();
+        dispatcher.insert_one_time(Key::Start, move |evt| s.send(evt).unwrap());
+
+        assert_eq!(dispatch(&dispatcher, 0, 0), Dispatched::OneTime);
+        assert_eq!(r.try_recv(), Ok(Event::StartComplete));
+        assert_eq!(dispatch(&dispatcher, 0, 0), Dispatched::Dropped);
+    }
+
+    #[test]
+    fn pending_one_time_callback_is_not_replaced() {
+        let dispatcher = Dispatcher::new();
+        let (s, r) = channel();
+        let first = s.clone();
+        assert!(dispatcher.try_insert_one_time(Key::Start, move |_| first.send(1).unwrap()));
+        let second = s.clone();
+        assert!(!dispatcher.try_insert_one_time(Key::Start, move |_| second.send(2).unwrap()));
+
+        assert_eq!(dispatch(&dispatcher, 0, 0), Dispatched::OneTime);
+        assert_eq!(r.try_recv(), Ok(1));
+        assert!(dispatcher.try_insert_one_time(Key::Start, move |_| s.send(3).unwrap()));
+        dispatcher.remove_one_time(Key::Start);
+        assert_eq!(dispatch(&dispatcher, 0, 0), Dispatched::Dropped);
+    }
+
+    #[test]
+    fn queued_callbacks_are_called_in_order() {
+        let dispatcher = Dispatcher::new();
+        let (s, r) = channel();
+        let ids: Vec<usize> = (0..4)
+            .map(|i| {
+                let s = s.clone();
+                dispatcher.push_queued(Key::Read(42), move |evt| s.send((i, evt)).unwrap())
+            })
+            .collect();
+        dispatcher.remove_queued(Key::Read(42), ids[3]);
+        dispatcher.remove_queued(Key::Read(42), ids[1]);
+
+        assert_eq!(dispatch(&dispatcher, 1, 42), Dispatched::Queued);
+        assert_eq!(dispatch(&dispatcher, 1, 42), Dispatched::Queued);
+        assert_eq!(dispatch(&dispatcher, 1, 42), Dispatched::Dropped);
+        assert_eq!(
+            r.try_iter().collect::<Vec<_>>(),
+            [(0, Event::Read(42)), (2, Event::Read(42))]
+        );
+    }
+
+    #[test]
+    fn abandoned_callbacks_keep_their_place() {
+        let dispatcher = Dispatcher::new();
+        let (s, r) = channel();
+        let ids: Vec<usize> = (0..2)
+            .map(|i| {
+                let s = s.clone();
+                dispatcher.push_queued(Key::Read(42), move |evt| s.send((i, evt)).unwrap())
+            })
+            .collect();
+        dispatcher.abandon_queued(Key::Read(42), ids[0]);
+
+        assert_eq!(dispatch(&dispatcher, 1, 42), Dispatched::Queued);
+        assert_eq!(r.try_recv().ok(), None);
+        assert_eq!(dispatch(&dispatcher, 1, 42), Dispatched::Queued);
+        assert_eq!(r.try_recv(), Ok((1, Event::Read(42))));
+    }
+
+    #[test]
+    fn kept_callbacks_are_matched_by_key() {
+        let dispatcher = Dispatcher::new();
+        let (s, r) = channel();
+        dispatcher.insert_kept(Key::Read(42), move |evt| s.send(evt).unwrap());
+
+        assert_eq!(dispatch(&dispatcher, 1, 42), Dispatched::Kept);
+        assert_eq!(dispatch(&dispatcher, 1, 42), Dispatched::Kept);
+        assert_eq!(dispatch(&dispatcher, 1, 43), Dispatched::Dropped);
+        assert_eq!(
+            r.try_iter().collect::<Vec<_>>(),
+            [Event::Read(42), Event::Read(42)]
+        );
+    }
+
+    #[test]
+    fn unknown_events_go_to_catch_all() {
+        let dispatcher = Dispatcher::new();
+        assert_eq!(dispatch(&dispatcher, 0xdead, 0), Dispatched::Dropped);
+
+        let (s, r) = channel();
+        dispatcher.set_catch_all(move |evt| s.send(evt).unwrap());
+        assert_eq!(dispatch(&dispatcher, 0xdead, 0), Dispatched::CatchAll);
+        assert_eq!(dispatch(&dispatcher, 1, 7), Dispatched::CatchAll);
+        assert_eq!(
+            r.try_iter().collect::<Vec<_>>(),
+            [Event::Unknown(0xdead), Event::Read(7)]
+        );
+    }
+
+    #[test]
+    fn callbacks_can_register_callbacks() {
+        let dispatcher = Arc::new(Dispatcher::new());
+        let (s, r) = channel();
+
+        let inner = dispatcher.clone();
+        dispatcher.insert_kept(Key::Start, move |_| {
+            let s = s.clone();
+            inner.insert_one_time(Key::Read(1), move |evt| s.send(evt).unwrap());
+        });
+
+        assert_eq!(dispatch(&dispatcher, 0, 0), Dispatched::Kept);
+        assert_eq!(dispatch(&dispatcher, 1, 1), Dispatched::OneTime);
+        assert_eq!(r.try_recv(), Ok(Event::Read(1)));
+    }
+
+    #[test]
+    fn kept_callback_can_replace_itself() {
+        let dispatcher = Arc::new(Dispatcher::new());
+        let (s, r) = channel();
+
+        let inner = dispatcher.clone();
+        dispatcher.insert_kept(Key::Start, move |_| {
+            let s = s.clone();
+            inner.insert_kept(Key::Start, move |evt| s.send(evt).unwrap());
+        });
+
+        assert_eq!(dispatch(&dispatcher, 0, 0), Dispatched::Kept);
+        assert_eq!(r.try_recv().ok(), None);
+        assert_eq!(dispatch(&dispatcher, 0, 0), Dispatched::Kept);
+        assert_eq!(r.try_recv(), Ok(Event::StartComplete));
+    }
+
+    #[test]
+    fn kept_callback_can_remove_itself() {
+        let dispatcher = Arc::new(Dispatcher::new());
+        let (s, r) = channel();
+
+        let inner = dispatcher.clone();
+        dispatcher.insert_kept(Key::Start, move |evt| {
+            s.send(evt).unwrap();
+            inner.remove_kept(Key::Start);
+        });
+        let inner = dispatcher.clone();
+        dispatcher.set_catch_all(move |_| inner.clear());
+
+        assert_eq!(dispatch(&dispatcher, 0, 0), Dispatched::Kept);
+        assert_eq!(r.try_recv(), Ok(Event::StartComplete));
+        assert_eq!(dispatch(&dispatcher, 0, 0), Dispatched::CatchAll);
+        assert_eq!(dispatch(&dispatcher, 0, 0), Dispatched::Dropped);
+    }
+
+    #[test]
+    fn kept_callback_stays_registered_while_called() {
+        let dispatcher = Arc::new(Dispatcher::new());
+        let (s, r) = channel();
+
+        // The event nested in the call is delivered like one from another thread
+        let inner = dispatcher.clone();
+        dispatcher.insert_kept(Key::Read(42), move |evt| {
+            if evt == Event::Read(42) {
+                s.send(dispatch(&inner, 1, 43)).unwrap();
+            }
+        });
+        dispatcher.insert_kept(Key::Read(43), |_| ());
+        let inner = dispatcher.clone();
+        dispatcher.insert_kept(Key::Start, move |_| {
+            assert_eq!(dispatch(&inner, 1, 42), Dispatched::Kept);
+        });
+
+        assert_eq!(dispatch(&dispatcher, 0, 0), Dispatched::Kept);
+        assert_eq!(r.try_recv(), Ok(Dispatched::Kept));
+    }
+}
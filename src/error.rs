@@ -0,0 +1,286 @@
+use std::num::NonZeroI32;
+
+use crate::{
+    AdvertisingParametersError, BtStatus, ConnectionParametersError, ExtendedAdvertisingError,
+    GattStatus, SecurityConfigError,
+};
+
+#[cfg(target_os = "espidf")]
+pub(crate) use esp_idf_sys::EspError;
+
+#[cfg(not(target_os = "espidf"))]
+pub(crate) const ESP_FAIL: i32 = -1;
+pub(crate) const ESP_ERR_INVALID_ARG: i32 = 0x102;
+pub(crate) const ESP_ERR_INVALID_STATE: i32 = 0x103;
+pub(crate) const ESP_ERR_INVALID_SIZE: i32 = 0x104;
+#[cfg(all(
+    target_os = "espidf",
+    any(esp_idf_version_major = "4", not(esp_idf_bt_ble_50_features_supported))
+))]
+pub(crate) const ESP_ERR_NOT_SUPPORTED: i32 = 0x106;
+
+/// `EspError` for one of the `ESP_*` codes above, for the backends.
+#[cfg_attr(
+    not(any(target_os = "espidf", feature = "simulated", test)),
+    allow(dead_code)
+)]
+pub(crate) fn esp_error(code: i32) -> EspError {
+    EspError::from_non_zero(NonZeroI32::new(code).unwrap_or(NonZeroI32::MIN))
+}
+
+/// Error of the operations of `EspBle`.
+///
+/// The status of the completion events is checked by the blocking and async
+/// variants of the operations.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BleError {
+    /// The Bluetooth stack refused the call
+    Stack(EspError),
+    /// The GATT operation completed with a non success status
+    Gatt(GattStatus),
+    /// The GAP operation completed with a non success status
+    Bt(BtStatus),
+    /// The peer could not be authenticated, `AuthenticationFailure` or
+    /// `AuthenticationRejected`
+    Authentication(BtStatus),
+    /// The operation was not completed in time
+    Timeout,
+    /// The operation is not possible in the current state, e.g. without a
+    /// running `EspBle` or a subscription to the characteristic
+    InvalidState(StateError),
+    /// An argument of the operation is invalid
+    InvalidArgument(ArgumentError),
+    /// The Security Manager configuration is invalid
+    Security(SecurityConfigError),
+}
+
+/// Why an operation is not possible in the current state.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StateError {
+    /// No `EspBle` is running
+    NotRunning,
+    /// Only one `EspBle` can run at a time
+    AlreadyRunning,
+    /// There is no link with this connection id
+    NotConnected(u16),
+    /// The client did not enable notifications, or indications, in the CCCD
+    NotSubscribed { conn_id: u16, handle: u16 },
+    /// An indication is still waiting for its confirmation on the connection
+    IndicationPending(u16),
+    /// Another GAP operation of the same kind is waiting for its completion
+    OperationPending,
+    /// All the advertising sets are in use
+    NoAdvertisingSet,
+    /// A callback panicked while the state of `EspBle` was locked
+    LockPoisoned,
+}
+
+impl std::fmt::Display for StateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StateError::NotRunning => write!(f, "No EspBle running"),
+            StateError::AlreadyRunning => write!(f, "EspBle already running"),
+            StateError::NotConnected(conn_id) => write!(f, "Connection {} not found", conn_id),
+            StateError::NotSubscribed { conn_id, handle } => write!(
+                f,
+                "Connection {} is not subscribed to characteristic {}",
+                conn_id, handle
+            ),
+            StateError::IndicationPending(conn_id) => write!(
+                f,
+                "An indication is waiting for its confirmation on connection {}",
+                conn_id
+            ),
+            StateError::OperationPending => write!(f, "Operation already pending"),
+            StateError::NoAdvertisingSet => write!(f, "No advertising set available"),
+            StateError::LockPoisoned => write!(f, "EspBle state lock poisoned"),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+/// Why an argument of an operation is invalid.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArgumentError {
+    /// The handle is not the one of a characteristic with a CCCD
+    UnknownCharacteristic(u16),
+    /// The value or data does not fit, at most `max` bytes are allowed
+    PayloadTooLong { len: usize, max: usize },
+    /// The MTU is not from `ATT_DEFAULT_MTU` to `ATT_MAX_MTU`
+    LocalMtu(u16),
+    /// The advertising parameters are out of range
+    AdvertisingParameters(AdvertisingParametersError),
+    /// The extended or periodic advertising data or parameters are invalid
+    ExtendedAdvertising(ExtendedAdvertisingError),
+    /// The connection parameters are out of range
+    ConnectionParameters(ConnectionParametersError),
+}
+
+impl std::fmt::Display for ArgumentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArgumentError::UnknownCharacteristic(handle) => {
+                write!(f, "No characteristic with a CCCD for handle {}", handle)
+            }
+            ArgumentError::PayloadTooLong { len, max } => write!(
+                f,
+                "Payload too long: {} bytes, at most {} are allowed",
+                len, max
+            ),
+            ArgumentError::LocalMtu(mtu) => write!(f, "Invalid local MTU {}", mtu),
+            ArgumentError::AdvertisingParameters(err) => err.fmt(f),
+            ArgumentError::ExtendedAdvertising(err) => err.fmt(f),
+            ArgumentError::ConnectionParameters(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for ArgumentError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ArgumentError::AdvertisingParameters(err) => Some(err),
+            ArgumentError::ExtendedAdvertising(err) => Some(err),
+            ArgumentError::ConnectionParameters(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<EspError> for BleError {
+    fn from(err: EspError) -> Self {
+        BleError::Stack(err)
+    }
+}
+
+impl std::fmt::Display for BleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BleError::Stack(err) => write!(f, "Bluetooth stack error: {}", err),
+            BleError::Gatt(status) => write!(f, "GATT operation failed: {:?}", status),
+            BleError::Bt(status) => write!(f, "GAP operation failed: {:?}", status),
+            BleError::Authentication(status) => write!(f, "Authentication failed: {:?}", status),
+            BleError::Timeout => write!(f, "Operation not completed in time"),
+            BleError::InvalidState(err) => write!(f, "Invalid state: {}", err),
+            BleError::InvalidArgument(err) => write!(f, "Invalid argument: {}", err),
+            BleError::Security(err) => write!(f, "Invalid security configuration: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for BleError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BleError::Stack(err) => Some(err),
+            BleError::InvalidState(err) => Some(err),
+            BleError::InvalidArgument(err) => Some(err),
+            BleError::Security(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<StateError> for BleError {
+    fn from(err: StateError) -> Self {
+        BleError::InvalidState(err)
+    }
+}
+
+impl From<ArgumentError> for BleError {
+    fn from(err: ArgumentError) -> Self {
+        BleError::InvalidArgument(err)
+    }
+}
+
+impl From<SecurityConfigError> for BleError {
+    fn from(err: SecurityConfigError) -> Self {
+        BleError::Security(err)
+    }
+}
+
+/// `Ok` for a successful GAP completion.
+pub(crate) fn bt_result(status: BtStatus) -> Result<(), BleError> {
+    match status {
+        BtStatus::Success => Ok(()),
+        BtStatus::AuthenticationFailure | BtStatus::AuthenticationRejected => {
+            Err(BleError::Authentication(status))
+        }
+        _ => Err(BleError::Bt(status)),
+    }
+}
+
+/// `Ok` for a successful GATT completion.
+pub(crate) fn gatt_result(status: GattStatus) -> Result<(), BleError> {
+    if status.is_ok() {
+        Ok(())
+    } else {
+        Err(BleError::Gatt(status))
+    }
+}
+
+/// Stand-in for `esp_idf_sys::EspError` when building for a target without
+/// ESP-IDF, e.g. to run `EspBle` on top of the `SimulatedBackend`.
+#[cfg(not(target_os = "espidf"))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EspError(NonZeroI32);
+
+#[cfg(not(target_os = "espidf"))]
+impl EspError {
+    pub const fn from_non_zero(error: NonZeroI32) -> Self {
+        Self(error)
+    }
+
+    pub fn from(error: i32) -> Option<Self> {
+        NonZeroI32::new(error).map(Self)
+    }
+
+    pub fn convert(error: i32) -> Result<(), Self> {
+        match Self::from(error) {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    pub fn code(&self) -> i32 {
+        self.0.get()
+    }
+}
+
+#[cfg(not(target_os = "espidf"))]
+impl std::fmt::Display for EspError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self.code() {
+            ESP_FAIL => "ESP_FAIL",
+            ESP_ERR_INVALID_ARG => "ESP_ERR_INVALID_ARG",
+            ESP_ERR_INVALID_STATE => "ESP_ERR_INVALID_STATE",
+            ESP_ERR_INVALID_SIZE => "ESP_ERR_INVALID_SIZE",
+            _ => "ESP_ERR",
+        };
+        write!(f, "{} ({})", name, self.code())
+    }
+}
+
+#[cfg(not(target_os = "espidf"))]
+impl std::error::Error for EspError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn completion_statuses() {
+        assert_eq!(bt_result(BtStatus::Success), Ok(()));
+        assert_eq!(bt_result(BtStatus::Busy), Err(BleError::Bt(BtStatus::Busy)));
+        assert_eq!(
+            bt_result(BtStatus::AuthenticationRejected),
+            Err(BleError::Authentication(BtStatus::AuthenticationRejected))
+        );
+
+        assert_eq!(gatt_result(GattStatus::Ok), Ok(()));
+        let err = gatt_result(GattStatus::InsufficientEncryption).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "GATT operation failed: InsufficientEncryption"
+        );
+    }
+}
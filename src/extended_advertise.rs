@@ -0,0 +1,555 @@
+#[cfg(target_os = "espidf")]
+use esp_idf_sys::*;
+
+use crate::{AddressType, AdvertisingFilterPolicy, ADV_CHANNEL_ALL};
+
+/// Maximum size of the extended advertising, scan response or periodic advertising data
+pub const EXT_ADV_DATA_MAX_LEN: usize = 1650;
+
+/// Maximum number of advertising sets supported by the stack
+pub const EXT_ADV_MAX_SETS: u8 = 10;
+
+/// Bounds of the extended advertising interval, in 0.625ms units
+pub const EXT_ADV_INTERVAL_MIN: u32 = 0x0000_0020;
+pub const EXT_ADV_INTERVAL_MAX: u32 = 0x00ff_ffff;
+
+/// Bounds of the periodic advertising interval, in 1.25ms units
+pub const PERIODIC_ADV_INTERVAL_MIN: u16 = 0x0006;
+pub const PERIODIC_ADV_INTERVAL_MAX: u16 = 0xffff;
+
+/// `tx_power` value letting the controller choose the TX power
+pub const EXT_ADV_TX_POWER_NO_PREFERENCE: i8 = 127;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phy {
+    OneM,
+    /// Only available on the secondary advertising channels
+    TwoM,
+    Coded,
+}
+
+impl From<Phy> for u8 {
+    fn from(phy: Phy) -> Self {
+        match phy {
+            Phy::OneM => 0x01,
+            Phy::TwoM => 0x02,
+            Phy::Coded => 0x03,
+        }
+    }
+}
+
+impl Phy {
+    pub fn from_u8(phy: u8) -> Option<Self> {
+        match phy {
+            0x01 => Some(Phy::OneM),
+            0x02 => Some(Phy::TwoM),
+            0x03 => Some(Phy::Coded),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExtendedAdvertisingParameters {
+    /// Minimum advertising interval in 0.625ms units (0x000020 - 0xffffff)
+    pub interval_min: u32,
+    /// Maximum advertising interval in 0.625ms units, must not be lower than `interval_min`
+    pub interval_max: u32,
+    pub connectable: bool,
+    pub scannable: bool,
+    /// Use legacy advertising PDUs, which limits the data to 31 bytes
+    pub legacy: bool,
+    /// Omit the advertiser address from the PDUs
+    pub anonymous: bool,
+    pub include_tx_power: bool,
+    /// Use high duty cycle directed advertising, requires a `peer_addr`
+    pub high_duty_cycle: bool,
+    pub own_addr_type: AddressType,
+    /// Peer to advertise to, the advertising is undirected when `None`
+    pub peer_addr: Option<([u8; 6], AddressType)>,
+    /// Combination of the `ADV_CHANNEL_*` bits
+    pub channel_map: u8,
+    pub filter_policy: AdvertisingFilterPolicy,
+    /// TX power in dBm (-127 - 20), or `EXT_ADV_TX_POWER_NO_PREFERENCE`
+    pub tx_power: i8,
+    /// PHY used on the primary advertising channels, `Phy::TwoM` is not allowed
+    pub primary_phy: Phy,
+    /// Number of advertising events which can be skipped before sending the secondary PDUs
+    pub max_skip: u8,
+    pub secondary_phy: Phy,
+    /// Advertising set identifier (0 - 15)
+    pub sid: u8,
+    pub scan_request_notifications: bool,
+}
+
+impl Default for ExtendedAdvertisingParameters {
+    fn default() -> Self {
+        Self {
+            interval_min: 0x20,
+            interval_max: 0x40,
+            connectable: true,
+            scannable: false,
+            legacy: false,
+            anonymous: false,
+            include_tx_power: false,
+            high_duty_cycle: false,
+            own_addr_type: AddressType::Public,
+            peer_addr: None,
+            channel_map: ADV_CHANNEL_ALL,
+            filter_policy: AdvertisingFilterPolicy::AllowAll,
+            tx_power: EXT_ADV_TX_POWER_NO_PREFERENCE,
+            primary_phy: Phy::OneM,
+            max_skip: 0,
+            secondary_phy: Phy::OneM,
+            sid: 0,
+            scan_request_notifications: false,
+        }
+    }
+}
+
+/// Stop advertising a set after `duration` or `max_events`, whichever comes first.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ExtendedAdvertisingDuration {
+    /// Duration in 10ms units, 0 to advertise until stopped
+    pub duration: u16,
+    /// Maximum number of advertising events, 0 for no limit
+    pub max_events: u8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PeriodicAdvertisingParameters {
+    /// Minimum periodic advertising interval in 1.25ms units (0x0006 - 0xffff)
+    pub interval_min: u16,
+    /// Maximum periodic advertising interval in 1.25ms units, must not be lower than `interval_min`
+    pub interval_max: u16,
+    pub include_tx_power: bool,
+}
+
+impl Default for PeriodicAdvertisingParameters {
+    fn default() -> Self {
+        Self {
+            interval_min: 0x40,
+            interval_max: 0x80,
+            include_tx_power: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExtendedAdvertisingError {
+    /// The interval is outside of the range allowed for this kind of advertising
+    IntervalOutOfRange(u32),
+    /// `interval_min` is greater than `interval_max`
+    InvalidIntervalRange {
+        min: u32,
+        max: u32,
+    },
+    /// The channel map is empty or has bits other than `ADV_CHANNEL_ALL`
+    InvalidChannelMap(u8),
+    InvalidTxPower(i8),
+    /// `Phy::TwoM` cannot be used on the primary advertising channels
+    InvalidPrimaryPhy,
+    InvalidSid(u8),
+    /// Extended advertising cannot be both connectable and scannable
+    ConnectableAndScannable,
+    /// Anonymous advertising cannot be connectable, scannable or use legacy PDUs
+    InvalidAnonymous,
+    /// High duty cycle advertising must be directed
+    HighDutyCycleUndirected,
+    /// Legacy directed advertising cannot be scannable, and legacy
+    /// undirected connectable advertising must be scannable
+    InvalidLegacyType,
+    /// All the advertising sets are already used
+    NoFreeSet,
+    /// The data is `len` bytes long, more than `EXT_ADV_DATA_MAX_LEN`
+    DataTooLong {
+        len: usize,
+    },
+}
+
+impl std::fmt::Display for ExtendedAdvertisingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExtendedAdvertisingError::IntervalOutOfRange(interval) => {
+                write!(f, "Advertising interval {:#x} is out of range", interval)
+            }
+            ExtendedAdvertisingError::InvalidIntervalRange { min, max } => write!(
+                f,
+                "Minimum advertising interval {:#x} is greater than the maximum {:#x}",
+                min, max
+            ),
+            ExtendedAdvertisingError::InvalidChannelMap(map) => {
+                write!(f, "Invalid advertising channel map {:#04x}", map)
+            }
+            ExtendedAdvertisingError::InvalidTxPower(power) => {
+                write!(f, "Invalid TX power {}dBm", power)
+            }
+            ExtendedAdvertisingError::InvalidPrimaryPhy => {
+                write!(
+                    f,
+                    "2M PHY cannot be used on the primary advertising channels"
+                )
+            }
+            ExtendedAdvertisingError::InvalidSid(sid) => {
+                write!(f, "Invalid advertising set identifier {}", sid)
+            }
+            ExtendedAdvertisingError::ConnectableAndScannable => write!(
+                f,
+                "Extended advertising cannot be both connectable and scannable"
+            ),
+            ExtendedAdvertisingError::InvalidAnonymous => write!(
+                f,
+                "Anonymous advertising cannot be connectable, scannable or legacy"
+            ),
+            ExtendedAdvertisingError::HighDutyCycleUndirected => {
+                write!(f, "High duty cycle advertising requires a peer address")
+            }
+            ExtendedAdvertisingError::InvalidLegacyType => {
+                write!(f, "Unsupported legacy advertising type")
+            }
+            ExtendedAdvertisingError::NoFreeSet => write!(f, "No advertising set available"),
+            ExtendedAdvertisingError::DataTooLong { len } => write!(
+                f,
+                "Advertising data is {} bytes long, at most {} are allowed",
+                len, EXT_ADV_DATA_MAX_LEN
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ExtendedAdvertisingError {}
+
+fn validate_interval<T: Copy + Into<u32> + PartialOrd>(
+    min: T,
+    max: T,
+    bounds: std::ops::RangeInclusive<T>,
+) -> Result<(), ExtendedAdvertisingError> {
+    for interval in [min, max] {
+        if !bounds.contains(&interval) {
+            return Err(ExtendedAdvertisingError::IntervalOutOfRange(
+                interval.into(),
+            ));
+        }
+    }
+    if min > max {
+        return Err(ExtendedAdvertisingError::InvalidIntervalRange {
+            min: min.into(),
+            max: max.into(),
+        });
+    }
+    Ok(())
+}
+
+impl ExtendedAdvertisingParameters {
+    pub fn validate(&self) -> Result<(), ExtendedAdvertisingError> {
+        let directed = self.peer_addr.is_some();
+
+        // High duty cycle directed advertising does not use the interval
+        if !self.high_duty_cycle {
+            validate_interval(
+                self.interval_min,
+                self.interval_max,
+                EXT_ADV_INTERVAL_MIN..=EXT_ADV_INTERVAL_MAX,
+            )?;
+        }
+
+        if self.channel_map == 0 || self.channel_map & !ADV_CHANNEL_ALL != 0 {
+            return Err(ExtendedAdvertisingError::InvalidChannelMap(
+                self.channel_map,
+            ));
+        }
+
+        if self.tx_power > 20 && self.tx_power != EXT_ADV_TX_POWER_NO_PREFERENCE {
+            return Err(ExtendedAdvertisingError::InvalidTxPower(self.tx_power));
+        }
+
+        if self.primary_phy == Phy::TwoM {
+            return Err(ExtendedAdvertisingError::InvalidPrimaryPhy);
+        }
+
+        if self.sid > 0x0f {
+            return Err(ExtendedAdvertisingError::InvalidSid(self.sid));
+        }
+
+        if self.high_duty_cycle && !directed {
+            return Err(ExtendedAdvertisingError::HighDutyCycleUndirected);
+        }
+
+        if self.anonymous && (self.connectable || self.scannable || self.legacy) {
+            return Err(ExtendedAdvertisingError::InvalidAnonymous);
+        }
+
+        if self.legacy {
+            // Legacy PDUs are ADV_IND, ADV_DIRECT_IND, ADV_SCAN_IND and ADV_NONCONN_IND
+            let valid = if directed {
+                self.connectable && !self.scannable
+            } else {
+                !self.connectable || self.scannable
+            };
+            if !valid {
+                return Err(ExtendedAdvertisingError::InvalidLegacyType);
+            }
+        } else if self.connectable && self.scannable {
+            return Err(ExtendedAdvertisingError::ConnectableAndScannable);
+        }
+
+        Ok(())
+    }
+
+    /// Advertising event properties, as expected by the controller
+    pub fn properties(&self) -> u16 {
+        [
+            (self.connectable, 1 << 0),
+            (self.scannable, 1 << 1),
+            (self.peer_addr.is_some(), 1 << 2),
+            (self.high_duty_cycle, 1 << 3),
+            (self.legacy, 1 << 4),
+            (self.anonymous, 1 << 5),
+            (self.include_tx_power, 1 << 6),
+        ]
+        .iter()
+        .filter(|(set, _)| *set)
+        .fold(0, |props, (_, bit)| props | bit)
+    }
+}
+
+impl PeriodicAdvertisingParameters {
+    pub fn validate(&self) -> Result<(), ExtendedAdvertisingError> {
+        validate_interval(
+            self.interval_min,
+            self.interval_max,
+            PERIODIC_ADV_INTERVAL_MIN..=PERIODIC_ADV_INTERVAL_MAX,
+        )
+    }
+}
+
+/// Check that `data` fits in an extended advertising, scan response or periodic advertising payload.
+pub fn validate_extended_data(data: &[u8]) -> Result<(), ExtendedAdvertisingError> {
+    if data.len() > EXT_ADV_DATA_MAX_LEN {
+        return Err(ExtendedAdvertisingError::DataTooLong { len: data.len() });
+    }
+    Ok(())
+}
+
+/// Handle to an advertising set created with `EspBle::create_extended_advertising_set`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ExtendedAdvertisingSet {
+    pub(crate) instance: u8,
+}
+
+impl ExtendedAdvertisingSet {
+    pub fn instance(&self) -> u8 {
+        self.instance
+    }
+}
+
+/// Keep track of the advertising set instances in use.
+#[derive(Debug, Default)]
+pub(crate) struct ExtendedAdvertisingSets {
+    used: u16,
+}
+
+impl ExtendedAdvertisingSets {
+    pub(crate) const fn new() -> Self {
+        Self { used: 0 }
+    }
+
+    pub(crate) fn allocate(&mut self) -> Result<ExtendedAdvertisingSet, ExtendedAdvertisingError> {
+        let instance = (0..EXT_ADV_MAX_SETS)
+            .find(|instance| self.used & (1 << instance) == 0)
+            .ok_or(ExtendedAdvertisingError::NoFreeSet)?;
+        self.used |= 1 << instance;
+        Ok(ExtendedAdvertisingSet { instance })
+    }
+
+    pub(crate) fn release(&mut self, set: ExtendedAdvertisingSet) {
+        self.used &= !(1 << set.instance);
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.used = 0;
+    }
+}
+
+#[cfg(target_os = "espidf")]
+impl From<ExtendedAdvertisingParameters> for esp_ble_gap_ext_adv_params_t {
+    fn from(params: ExtendedAdvertisingParameters) -> Self {
+        let (peer_addr, peer_addr_type) = params.peer_addr.unwrap_or(([0; 6], AddressType::Public));
+        Self {
+            type_: params.properties(),
+            interval_min: params.interval_min,
+            interval_max: params.interval_max,
+            channel_map: params.channel_map as _,
+            own_addr_type: params.own_addr_type.into(),
+            peer_addr_type: peer_addr_type.into(),
+            peer_addr,
+            filter_policy: params.filter_policy.into(),
+            tx_power: params.tx_power,
+            primary_phy: params.primary_phy.into(),
+            max_skip: params.max_skip,
+            secondary_phy: params.secondary_phy.into(),
+            sid: params.sid,
+            scan_req_notif: params.scan_request_notifications,
+        }
+    }
+}
+
+#[cfg(target_os = "espidf")]
+impl From<PeriodicAdvertisingParameters> for esp_ble_gap_periodic_adv_params_t {
+    fn from(params: PeriodicAdvertisingParameters) -> Self {
+        Self {
+            interval_min: params.interval_min,
+            interval_max: params.interval_max,
+            properties: if params.include_tx_power { 1 << 6 } else { 0 },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_parameters_are_valid() {
+        assert_eq!(ExtendedAdvertisingParameters::default().validate(), Ok(()));
+        assert_eq!(PeriodicAdvertisingParameters::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn interval_is_validated() {
+        let params = ExtendedAdvertisingParameters {
+            interval_max: 0x0100_0000,
+            ..Default::default()
+        };
+        assert_eq!(
+            params.validate(),
+            Err(ExtendedAdvertisingError::IntervalOutOfRange(0x0100_0000))
+        );
+
+        let params = PeriodicAdvertisingParameters {
+            interval_min: 0x100,
+            interval_max: 0x80,
+            ..Default::default()
+        };
+        assert_eq!(
+            params.validate(),
+            Err(ExtendedAdvertisingError::InvalidIntervalRange {
+                min: 0x100,
+                max: 0x80
+            })
+        );
+    }
+
+    #[test]
+    fn advertising_type_is_validated() {
+        let params = ExtendedAdvertisingParameters {
+            scannable: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            params.validate(),
+            Err(ExtendedAdvertisingError::ConnectableAndScannable)
+        );
+
+        let params = ExtendedAdvertisingParameters {
+            legacy: true,
+            scannable: true,
+            ..Default::default()
+        };
+        assert_eq!(params.validate(), Ok(()));
+        assert_eq!(params.properties(), 0x13);
+
+        let params = ExtendedAdvertisingParameters {
+            legacy: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            params.validate(),
+            Err(ExtendedAdvertisingError::InvalidLegacyType)
+        );
+
+        let params = ExtendedAdvertisingParameters {
+            anonymous: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            params.validate(),
+            Err(ExtendedAdvertisingError::InvalidAnonymous)
+        );
+
+        let params = ExtendedAdvertisingParameters {
+            high_duty_cycle: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            params.validate(),
+            Err(ExtendedAdvertisingError::HighDutyCycleUndirected)
+        );
+    }
+
+    #[test]
+    fn radio_parameters_are_validated() {
+        let params = ExtendedAdvertisingParameters {
+            primary_phy: Phy::TwoM,
+            ..Default::default()
+        };
+        assert_eq!(
+            params.validate(),
+            Err(ExtendedAdvertisingError::InvalidPrimaryPhy)
+        );
+
+        let params = ExtendedAdvertisingParameters {
+            secondary_phy: Phy::TwoM,
+            primary_phy: Phy::Coded,
+            tx_power: -12,
+            ..Default::default()
+        };
+        assert_eq!(params.validate(), Ok(()));
+
+        let params = ExtendedAdvertisingParameters {
+            tx_power: 21,
+            ..Default::default()
+        };
+        assert_eq!(
+            params.validate(),
+            Err(ExtendedAdvertisingError::InvalidTxPower(21))
+        );
+
+        let params = ExtendedAdvertisingParameters {
+            sid: 16,
+            ..Default::default()
+        };
+        assert_eq!(
+            params.validate(),
+            Err(ExtendedAdvertisingError::InvalidSid(16))
+        );
+    }
+
+    #[test]
+    fn extended_data_size_is_validated() {
+        assert_eq!(validate_extended_data(&[0; EXT_ADV_DATA_MAX_LEN]), Ok(()));
+        assert_eq!(
+            validate_extended_data(&[0; EXT_ADV_DATA_MAX_LEN + 1]),
+            Err(ExtendedAdvertisingError::DataTooLong { len: 1651 })
+        );
+    }
+
+    #[test]
+    fn sets_are_allocated_and_released() {
+        let mut sets = ExtendedAdvertisingSets::default();
+        let first = sets.allocate().unwrap();
+        let second = sets.allocate().unwrap();
+        assert_eq!((first.instance(), second.instance()), (0, 1));
+
+        sets.release(first);
+        assert_eq!(sets.allocate().unwrap().instance(), 0);
+
+        for _ in 2..EXT_ADV_MAX_SETS {
+            sets.allocate().unwrap();
+        }
+        assert_eq!(sets.allocate(), Err(ExtendedAdvertisingError::NoFreeSet));
+
+        sets.clear();
+        assert_eq!(sets.allocate().unwrap().instance(), 0);
+    }
+}
@@ -1,7 +1,314 @@
+#[cfg(target_os = "espidf")]
 use esp_idf_sys::*;
 
-#[derive(Clone, Copy)]
+use crate::Phy;
+use crate::{AddressType, BdAddr, BtStatus, ScOobData, ScanSearchEvent};
+
+/// Event reported by the GAP layer.
+///
+/// The raw Bluedroid event is available through [`RawGapEvent`] and
+/// `EspBle::register_gap_raw_handler`.
+#[derive(Clone, Debug, PartialEq)]
 pub enum GapEvent {
+    AdvertisingDatasetComplete(BtStatus),
+    ScanResponseDatasetComplete(BtStatus),
+    ScanParameterDatasetComplete(BtStatus),
+    ScanResult(ScanSearchEvent),
+    RawAdvertisingDatasetComplete(BtStatus),
+    RawScanResponseDatasetComplete(BtStatus),
+    AdvertisingStartComplete(BtStatus),
+    ScanStartComplete(BtStatus),
+    AuthenticationComplete(AuthenticationCompleteEvent),
+    Key(KeyEvent),
+    SecurityRequest(BdAddr),
+    PasskeyNotification(PasskeyEvent),
+    PasskeyRequest(BdAddr),
+    /// The peer pairs with legacy OOB and the temporary key is requested
+    OOBRequest(BdAddr),
+    /// The peer pairs with Secure Connections OOB and its data is requested
+    SecureConnectionsOOBRequest(BdAddr),
+    /// Data created by `EspBle::create_local_oob_data`
+    LocalOOBDataCreated(ScOobData),
+    LocalIR,
+    LocalER,
+    NumericComparisonRequest(PasskeyEvent),
+    AdvertisingStopComplete(BtStatus),
+    ScanStopComplete(BtStatus),
+    SetStaticRandomAddressComplete(BtStatus),
+    UpdateConnectionParamsComplete(ConnectionParamsEvent),
+    SetPacketLengthComplete(PacketLengthEvent),
+    SetLocalPrivacy(BtStatus),
+    RemoveDeviceBondComplete(BondEvent),
+    ClearDeviceBondComplete(BtStatus),
+    GetDeviceBondComplete(BondListEvent),
+    ReadRssiComplete(RssiEvent),
+    UpdateWhitelistComplete(WhitelistEvent),
+    UpdateDuplicateListComplete(BtStatus),
+    SetChannelsComplete(BtStatus),
+    ReadPhyComplete(PhyEvent),
+    SetPreferredDefaultPhyComplete(BtStatus),
+    SetPreferredPhyComplete(BtStatus),
+    ExtendedAdvertisingSetRandomAddressComplete(BtStatus),
+    ExtendedAdvertisingSetParamsComplete(BtStatus),
+    ExtendedAdvertisingDatasetComplete(BtStatus),
+    ExtendedScanResponseDatasetComplete(BtStatus),
+    ExtendedAdvertisingStartComplete(BtStatus),
+    ExtendedAdvertisingStopComplete(BtStatus),
+    ExtendedAdvertisingSetRemoveComplete(BtStatus),
+    ExtendedAdvertisingSetClearComplete(BtStatus),
+    PeriodicAdvertisingSetParamsComplete(BtStatus),
+    PeriodicAdvertisingDatasetComplete(BtStatus),
+    PeriodicAdvertisingStartComplete(BtStatus),
+    PeriodicAdvertisingStopComplete(BtStatus),
+    PeriodicAdvertisingCreateSyncComplete(BtStatus),
+    PeriodicAdvertisingSyncCancelComplete(BtStatus),
+    PeriodicAdvertisingSyncTerminateComplete(BtStatus),
+    PeriodicAdvertisingAddDeviceComplete(BtStatus),
+    PeriodicAdvertisingRemoveDeviceComplete(BtStatus),
+    PeriodicAdvertisingClearDeviceComplete(BtStatus),
+    SetExtendedScanParamsComplete(BtStatus),
+    ExtendedScanStartComplete(BtStatus),
+    ExtendedScanStopComplete(BtStatus),
+    PreferExtendedConnectionParamsComplete(BtStatus),
+    PhyUpdateComplete(PhyEvent),
+    ExtendedAdvertisingReport(ExtendedAdvertisingReport),
+    ScanTimeout,
+    AdvertisingTerminated(AdvertisingTerminatedEvent),
+    ScanRequestReceived(ScanRequestEvent),
+    ChannelSelectionAlgorithm(ChannelSelectionEvent),
+    PeriodicAdvertisingReport(PeriodicAdvertisingReport),
+    PeriodicAdvertisingSyncLost(u16),
+    PeriodicAdvertisingSyncEstablished(PeriodicSyncEvent),
+    /// An event this crate does not know about, with its raw event code.
+    Unknown(u32),
+}
+
+/// Type of a key distributed during pairing, mirrors `esp_ble_key_type_t`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyType {
+    None,
+    PeerEncryption,
+    PeerIdentity,
+    PeerSigning,
+    PeerLinkKey,
+    LocalLinkKey,
+    LocalEncryption,
+    LocalIdentity,
+    LocalSigning,
+    Other(u8),
+}
+
+impl From<u8> for KeyType {
+    fn from(key_type: u8) -> Self {
+        match key_type {
+            0x00 => KeyType::None,
+            0x01 => KeyType::PeerEncryption,
+            0x02 => KeyType::PeerIdentity,
+            0x04 => KeyType::PeerSigning,
+            0x08 => KeyType::PeerLinkKey,
+            0x10 => KeyType::LocalLinkKey,
+            0x20 => KeyType::LocalEncryption,
+            0x40 => KeyType::LocalIdentity,
+            0x80 => KeyType::LocalSigning,
+            other => KeyType::Other(other),
+        }
+    }
+}
+
+/// Type of a remote device, mirrors `esp_bt_dev_type_t`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceType {
+    BrEdr,
+    Ble,
+    Dual,
+    Other(u32),
+}
+
+impl From<u32> for DeviceType {
+    fn from(dev_type: u32) -> Self {
+        match dev_type {
+            0x01 => DeviceType::BrEdr,
+            0x02 => DeviceType::Ble,
+            0x03 => DeviceType::Dual,
+            other => DeviceType::Other(other),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AuthenticationCompleteEvent {
+    pub bda: BdAddr,
+    pub success: bool,
+    /// SMP failure reason, only meaningful when `success` is false
+    pub fail_reason: u8,
+    pub key_present: bool,
+    pub addr_type: AddressType,
+    pub dev_type: DeviceType,
+    /// Authentication requirements negotiated, as `esp_ble_auth_req_t` flags
+    pub auth_mode: u8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub bda: BdAddr,
+    pub key_type: KeyType,
+}
+
+/// Passkey to display, or to compare for a numeric comparison.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PasskeyEvent {
+    pub bda: BdAddr,
+    pub passkey: u32,
+}
+
+/// Connection parameters in use after an update, intervals in 1.25ms units
+/// and timeout in 10ms units.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConnectionParamsEvent {
+    pub status: BtStatus,
+    pub bda: BdAddr,
+    pub min_int: u16,
+    pub max_int: u16,
+    pub latency: u16,
+    pub conn_int: u16,
+    pub timeout: u16,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PacketLengthEvent {
+    pub status: BtStatus,
+    pub rx_len: u16,
+    pub tx_len: u16,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BondEvent {
+    pub status: BtStatus,
+    pub bda: BdAddr,
+}
+
+/// A peer this device is bonded with, and the keys kept for it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BondedDevice {
+    pub bda: BdAddr,
+    /// Type of the identity address of the peer
+    pub addr_type: AddressType,
+    pub keys: Vec<KeyType>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BondListEvent {
+    pub status: BtStatus,
+    pub dev_num: u8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RssiEvent {
+    pub status: BtStatus,
+    pub bda: BdAddr,
+    pub rssi: i8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WhitelistOperation {
+    Remove,
+    Add,
+    Clear,
+    Other(u32),
+}
+
+impl From<u32> for WhitelistOperation {
+    fn from(operation: u32) -> Self {
+        match operation {
+            0 => WhitelistOperation::Remove,
+            1 => WhitelistOperation::Add,
+            2 => WhitelistOperation::Clear,
+            other => WhitelistOperation::Other(other),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WhitelistEvent {
+    pub status: BtStatus,
+    pub operation: WhitelistOperation,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PhyEvent {
+    pub status: BtStatus,
+    pub bda: BdAddr,
+    pub tx_phy: Option<Phy>,
+    pub rx_phy: Option<Phy>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExtendedAdvertisingReport {
+    /// Advertising event properties, as `esp_ble_gap_adv_type_t` flags
+    pub event_type: u16,
+    pub addr_type: AddressType,
+    pub addr: BdAddr,
+    pub primary_phy: Option<Phy>,
+    pub secondary_phy: Option<Phy>,
+    pub sid: u8,
+    pub tx_power: i8,
+    pub rssi: i8,
+    pub periodic_interval: u16,
+    /// 0x00 for complete data, 0x01 when more data is to come
+    pub data_status: u8,
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AdvertisingTerminatedEvent {
+    /// HCI status, 0x3c when the duration expired and 0x43 when the maximum
+    /// number of events was reached
+    pub status: u8,
+    pub instance: u8,
+    pub conn_idx: u16,
+    pub completed_events: u8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScanRequestEvent {
+    pub instance: u8,
+    pub scanner_addr_type: AddressType,
+    pub scanner_addr: BdAddr,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChannelSelectionEvent {
+    pub conn_handle: u16,
+    pub algorithm: u8,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PeriodicAdvertisingReport {
+    pub sync_handle: u16,
+    pub tx_power: i8,
+    pub rssi: i8,
+    /// 0x00 for complete data, 0x01 when more data is to come
+    pub data_status: u8,
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PeriodicSyncEvent {
+    pub status: BtStatus,
+    pub sync_handle: u16,
+    pub sid: u8,
+    pub adv_addr_type: AddressType,
+    pub adv_addr: BdAddr,
+    pub adv_phy: Option<Phy>,
+    pub interval: u16,
+    pub clock_accuracy: u8,
+}
+
+/// Event as received from Bluedroid, its raw parameters are only valid during
+/// the call of the raw handler.
+#[cfg(target_os = "espidf")]
+#[derive(Clone, Copy)]
+pub enum RawGapEvent {
     AdvertisingDatasetComplete(esp_ble_gap_cb_param_t_ble_adv_data_cmpl_evt_param),
     ScanResponseDatasetComplete(esp_ble_gap_cb_param_t_ble_scan_rsp_data_cmpl_evt_param),
     ScanParameterDatasetComplete(esp_ble_gap_cb_param_t_ble_scan_param_cmpl_evt_param),
@@ -15,7 +322,11 @@ pub enum GapEvent {
     SecurityRequest(esp_ble_sec_t),
     PasskeyNotification(esp_ble_sec_t),
     PasskeyRequest(esp_ble_sec_t),
-    OOBRequest,
+    OOBRequest(esp_ble_sec_t),
+    #[cfg(not(esp_idf_version_major = "4"))]
+    SecureConnectionsOOBRequest(esp_ble_sec_t),
+    #[cfg(not(esp_idf_version_major = "4"))]
+    LocalOOBDataCreated(esp_ble_sec_t),
     LocalIR,
     LocalER,
     NumericComparisonRequest(esp_ble_sec_t),
@@ -34,181 +345,812 @@ pub enum GapEvent {
         esp_ble_gap_cb_param_t_ble_update_duplicate_exceptional_list_cmpl_evt_param,
     ),
     SetChannelsComplete(esp_ble_gap_cb_param_t_ble_set_channels_evt_param),
-    /*
-    #if (BLE_50_FEATURE_SUPPORT == TRUE)
-        READ_PHY_COMPLETE_EVT,
-        SET_PREFERED_DEFAULT_PHY_COMPLETE_EVT,
-        SET_PREFERED_PHY_COMPLETE_EVT,
-        EXT_ADV_SET_RAND_ADDR_COMPLETE_EVT,
-        EXT_ADV_SET_PARAMS_COMPLETE_EVT,
-        EXT_ADV_DATA_SET_COMPLETE_EVT,
-        EXT_SCAN_RSP_DATA_SET_COMPLETE_EVT,
-        EXT_ADV_START_COMPLETE_EVT,
-        EXT_ADV_STOP_COMPLETE_EVT,
-        EXT_ADV_SET_REMOVE_COMPLETE_EVT,
-        EXT_ADV_SET_CLEAR_COMPLETE_EVT,
-        PERIODIC_ADV_SET_PARAMS_COMPLETE_EVT,
-        PERIODIC_ADV_DATA_SET_COMPLETE_EVT,
-        PERIODIC_ADV_START_COMPLETE_EVT,
-        PERIODIC_ADV_STOP_COMPLETE_EVT,
-        PERIODIC_ADV_CREATE_SYNC_COMPLETE_EVT,
-        PERIODIC_ADV_SYNC_CANCEL_COMPLETE_EVT,
-        PERIODIC_ADV_SYNC_TERMINATE_COMPLETE_EVT,
-        PERIODIC_ADV_ADD_DEV_COMPLETE_EVT,
-        PERIODIC_ADV_REMOVE_DEV_COMPLETE_EVT,
-        PERIODIC_ADV_CLEAR_DEV_COMPLETE_EVT,
-        SET_EXT_SCAN_PARAMS_COMPLETE_EVT,
-        EXT_SCAN_START_COMPLETE_EVT,
-        EXT_SCAN_STOP_COMPLETE_EVT,
-        PREFER_EXT_CONN_PARAMS_SET_COMPLETE_EVT,
-        PHY_UPDATE_COMPLETE_EVT,
-        EXT_ADV_REPORT_EVT,
-        SCAN_TIMEOUT_EVT,
-        ADV_TERMINATED_EVT,
-        SCAN_REQ_RECEIVED_EVT,
-        CHANNEL_SELETE_ALGORITHM_EVT,
-        PERIODIC_ADV_REPORT_EVT,
-        PERIODIC_ADV_SYNC_LOST_EVT,
-        PERIODIC_ADV_SYNC_ESTAB_EVT,
-    #endif // #if (BLE_50_FEATURE_SUPPORT == TRUE)
-        EVT_MAX,
-    */
-}
-
-impl std::fmt::Debug for GapEvent {
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    ReadPhyComplete(esp_ble_gap_cb_param_t_ble_read_phy_cmpl_evt_param),
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    SetPreferredDefaultPhyComplete(esp_ble_gap_cb_param_t_ble_set_perf_def_phy_cmpl_evt_param),
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    SetPreferredPhyComplete(esp_ble_gap_cb_param_t_ble_set_perf_phy_cmpl_evt_param),
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    ExtendedAdvertisingSetRandomAddressComplete(
+        esp_ble_gap_cb_param_t_ble_ext_adv_set_rand_addr_cmpl_evt_param,
+    ),
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    ExtendedAdvertisingSetParamsComplete(
+        esp_ble_gap_cb_param_t_ble_ext_adv_set_params_cmpl_evt_param,
+    ),
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    ExtendedAdvertisingDatasetComplete(esp_ble_gap_cb_param_t_ble_ext_adv_data_set_cmpl_evt_param),
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    ExtendedScanResponseDatasetComplete(
+        esp_ble_gap_cb_param_t_ble_ext_adv_scan_rsp_set_cmpl_evt_param,
+    ),
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    ExtendedAdvertisingStartComplete(esp_ble_gap_cb_param_t_ble_ext_adv_start_cmpl_evt_param),
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    ExtendedAdvertisingStopComplete(esp_ble_gap_cb_param_t_ble_ext_adv_stop_cmpl_evt_param),
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    ExtendedAdvertisingSetRemoveComplete(
+        esp_ble_gap_cb_param_t_ble_ext_adv_set_remove_cmpl_evt_param,
+    ),
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    ExtendedAdvertisingSetClearComplete(
+        esp_ble_gap_cb_param_t_ble_ext_adv_set_clear_cmpl_evt_param,
+    ),
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    PeriodicAdvertisingSetParamsComplete(
+        esp_ble_gap_cb_param_t_ble_periodic_adv_set_params_cmpl_param,
+    ),
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    PeriodicAdvertisingDatasetComplete(esp_ble_gap_cb_param_t_ble_periodic_adv_data_set_cmpl_param),
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    PeriodicAdvertisingStartComplete(esp_ble_gap_cb_param_t_ble_periodic_adv_start_cmpl_param),
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    PeriodicAdvertisingStopComplete(esp_ble_gap_cb_param_t_ble_periodic_adv_stop_cmpl_param),
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    PeriodicAdvertisingCreateSyncComplete(
+        esp_ble_gap_cb_param_t_ble_period_adv_create_sync_cmpl_param,
+    ),
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    PeriodicAdvertisingSyncCancelComplete(
+        esp_ble_gap_cb_param_t_ble_period_adv_sync_cancel_cmpl_param,
+    ),
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    PeriodicAdvertisingSyncTerminateComplete(
+        esp_ble_gap_cb_param_t_ble_period_adv_sync_terminate_cmpl_param,
+    ),
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    PeriodicAdvertisingAddDeviceComplete(esp_ble_gap_cb_param_t_ble_period_adv_add_dev_cmpl_param),
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    PeriodicAdvertisingRemoveDeviceComplete(
+        esp_ble_gap_cb_param_t_ble_period_adv_remove_dev_cmpl_param,
+    ),
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    PeriodicAdvertisingClearDeviceComplete(
+        esp_ble_gap_cb_param_t_ble_period_adv_clear_dev_cmpl_param,
+    ),
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    SetExtendedScanParamsComplete(esp_ble_gap_cb_param_t_ble_set_ext_scan_params_cmpl_param),
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    ExtendedScanStartComplete(esp_ble_gap_cb_param_t_ble_ext_scan_start_cmpl_param),
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    ExtendedScanStopComplete(esp_ble_gap_cb_param_t_ble_ext_scan_stop_cmpl_param),
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    PreferExtendedConnectionParamsComplete(
+        esp_ble_gap_cb_param_t_ble_ext_conn_params_set_cmpl_param,
+    ),
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    PhyUpdateComplete(esp_ble_gap_cb_param_t_ble_phy_update_cmpl_param),
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    ExtendedAdvertisingReport(esp_ble_gap_cb_param_t_ble_ext_adv_report_param),
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    ScanTimeout,
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    AdvertisingTerminated(esp_ble_gap_cb_param_t_ble_adv_terminate_param),
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    ScanRequestReceived(esp_ble_gap_cb_param_t_ble_scan_req_received_param),
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    ChannelSelectionAlgorithm(esp_ble_gap_cb_param_t_ble_channel_sel_alg_param),
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    PeriodicAdvertisingReport(esp_ble_gap_cb_param_t_ble_periodic_adv_report_param),
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    PeriodicAdvertisingSyncLost(esp_ble_gap_cb_param_t_ble_periodic_adv_sync_lost_param),
+    #[cfg(esp_idf_bt_ble_50_features_supported)]
+    PeriodicAdvertisingSyncEstablished(esp_ble_gap_cb_param_t_ble_periodic_adv_sync_estab_param),
+    /// An event this crate does not know about, with its raw event code.
+    Unknown(u32),
+}
+
+#[cfg(target_os = "espidf")]
+impl std::fmt::Debug for RawGapEvent {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
         write!(
             f,
             "{}",
             match self {
-                GapEvent::AdvertisingDatasetComplete(_) => "AdvertisingDatasetComplete",
-                GapEvent::ScanResponseDatasetComplete(_) => "ScanResponseDatasetComplete",
-                GapEvent::ScanParameterDatasetComplete(_) => "ScanParameterDatasetComplete",
-                GapEvent::ScanResult(_) => "ScanResult",
-                GapEvent::RawAdvertisingDatasetComplete(_) => "RawAdvertisingDatasetComplete",
-                GapEvent::RawScanResponseDatasetComplete(_) => "RawScanResponseDatasetComplete",
-                GapEvent::AdvertisingStartComplete(_) => "AdvertisingStartComplete",
-                GapEvent::ScanStartComplete(_) => "ScanStartComplete",
-                GapEvent::AuthenticationComplete(_) => "AuthenticationComplete",
-                GapEvent::Key(_) => "Key",
-                GapEvent::SecurityRequest(_) => "SecurityRequest",
-                GapEvent::PasskeyNotification(_) => "PasskeyNotification",
-                GapEvent::PasskeyRequest(_) => "PasskeyRequest",
-                GapEvent::OOBRequest => "OOBRequest",
-                GapEvent::LocalIR => "LocalIR",
-                GapEvent::LocalER => "LocalER",
-                GapEvent::NumericComparisonRequest(_) => "NumericComparisonRequest",
-                GapEvent::AdvertisingStopComplete(_) => "AdvertisingStopComplete",
-                GapEvent::ScanStopComplete(_) => "ScanStopComplete",
-                GapEvent::SetStaticRandomAddressComplete(_) => "SetStaticRandomAddressComplete",
-                GapEvent::UpdateConnectionParamsComplete(_) => "UpdateConnectionParamsComplete",
-                GapEvent::SetPacketLengthComplete(_) => "SetPacketLengthComplete",
-                GapEvent::SetLocalPrivacy(_) => "SetLocalPrivacy",
-                GapEvent::RemoveDeviceBondComplete(_) => "RemoveDeviceBondComplete",
-                GapEvent::ClearDeviceBondComplete(_) => "ClearDeviceBondComplete",
-                GapEvent::GetDeviceBondComplete(_) => "GetDeviceBondComplete",
-                GapEvent::ReadRssiComplete(_) => "ReadRssiComplete",
-                GapEvent::UpdateWhitelistComplete(_) => "UpdateWhitelistComplete",
-                GapEvent::UpdateDuplicateListComplete(_) => "UpdateDuplicateListComplete",
-                GapEvent::SetChannelsComplete(_) => "SetChannelsComplete",
+                RawGapEvent::AdvertisingDatasetComplete(_) => "AdvertisingDatasetComplete",
+                RawGapEvent::ScanResponseDatasetComplete(_) => "ScanResponseDatasetComplete",
+                RawGapEvent::ScanParameterDatasetComplete(_) => "ScanParameterDatasetComplete",
+                RawGapEvent::ScanResult(_) => "ScanResult",
+                RawGapEvent::RawAdvertisingDatasetComplete(_) => "RawAdvertisingDatasetComplete",
+                RawGapEvent::RawScanResponseDatasetComplete(_) => "RawScanResponseDatasetComplete",
+                RawGapEvent::AdvertisingStartComplete(_) => "AdvertisingStartComplete",
+                RawGapEvent::ScanStartComplete(_) => "ScanStartComplete",
+                RawGapEvent::AuthenticationComplete(_) => "AuthenticationComplete",
+                RawGapEvent::Key(_) => "Key",
+                RawGapEvent::SecurityRequest(_) => "SecurityRequest",
+                RawGapEvent::PasskeyNotification(_) => "PasskeyNotification",
+                RawGapEvent::PasskeyRequest(_) => "PasskeyRequest",
+                RawGapEvent::OOBRequest(_) => "OOBRequest",
+                #[cfg(not(esp_idf_version_major = "4"))]
+                RawGapEvent::SecureConnectionsOOBRequest(_) => "SecureConnectionsOOBRequest",
+                #[cfg(not(esp_idf_version_major = "4"))]
+                RawGapEvent::LocalOOBDataCreated(_) => "LocalOOBDataCreated",
+                RawGapEvent::LocalIR => "LocalIR",
+                RawGapEvent::LocalER => "LocalER",
+                RawGapEvent::NumericComparisonRequest(_) => "NumericComparisonRequest",
+                RawGapEvent::AdvertisingStopComplete(_) => "AdvertisingStopComplete",
+                RawGapEvent::ScanStopComplete(_) => "ScanStopComplete",
+                RawGapEvent::SetStaticRandomAddressComplete(_) => "SetStaticRandomAddressComplete",
+                RawGapEvent::UpdateConnectionParamsComplete(_) => "UpdateConnectionParamsComplete",
+                RawGapEvent::SetPacketLengthComplete(_) => "SetPacketLengthComplete",
+                RawGapEvent::SetLocalPrivacy(_) => "SetLocalPrivacy",
+                RawGapEvent::RemoveDeviceBondComplete(_) => "RemoveDeviceBondComplete",
+                RawGapEvent::ClearDeviceBondComplete(_) => "ClearDeviceBondComplete",
+                RawGapEvent::GetDeviceBondComplete(_) => "GetDeviceBondComplete",
+                RawGapEvent::ReadRssiComplete(_) => "ReadRssiComplete",
+                RawGapEvent::UpdateWhitelistComplete(_) => "UpdateWhitelistComplete",
+                RawGapEvent::UpdateDuplicateListComplete(_) => "UpdateDuplicateListComplete",
+                RawGapEvent::SetChannelsComplete(_) => "SetChannelsComplete",
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::ReadPhyComplete(_) => "ReadPhyComplete",
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::SetPreferredDefaultPhyComplete(_) => "SetPreferredDefaultPhyComplete",
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::SetPreferredPhyComplete(_) => "SetPreferredPhyComplete",
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::ExtendedAdvertisingSetRandomAddressComplete(_) =>
+                    "ExtendedAdvertisingSetRandomAddressComplete",
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::ExtendedAdvertisingSetParamsComplete(_) =>
+                    "ExtendedAdvertisingSetParamsComplete",
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::ExtendedAdvertisingDatasetComplete(_) =>
+                    "ExtendedAdvertisingDatasetComplete",
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::ExtendedScanResponseDatasetComplete(_) =>
+                    "ExtendedScanResponseDatasetComplete",
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::ExtendedAdvertisingStartComplete(_) =>
+                    "ExtendedAdvertisingStartComplete",
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::ExtendedAdvertisingStopComplete(_) =>
+                    "ExtendedAdvertisingStopComplete",
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::ExtendedAdvertisingSetRemoveComplete(_) =>
+                    "ExtendedAdvertisingSetRemoveComplete",
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::ExtendedAdvertisingSetClearComplete(_) =>
+                    "ExtendedAdvertisingSetClearComplete",
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::PeriodicAdvertisingSetParamsComplete(_) =>
+                    "PeriodicAdvertisingSetParamsComplete",
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::PeriodicAdvertisingDatasetComplete(_) =>
+                    "PeriodicAdvertisingDatasetComplete",
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::PeriodicAdvertisingStartComplete(_) =>
+                    "PeriodicAdvertisingStartComplete",
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::PeriodicAdvertisingStopComplete(_) =>
+                    "PeriodicAdvertisingStopComplete",
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::PeriodicAdvertisingCreateSyncComplete(_) =>
+                    "PeriodicAdvertisingCreateSyncComplete",
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::PeriodicAdvertisingSyncCancelComplete(_) =>
+                    "PeriodicAdvertisingSyncCancelComplete",
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::PeriodicAdvertisingSyncTerminateComplete(_) =>
+                    "PeriodicAdvertisingSyncTerminateComplete",
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::PeriodicAdvertisingAddDeviceComplete(_) =>
+                    "PeriodicAdvertisingAddDeviceComplete",
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::PeriodicAdvertisingRemoveDeviceComplete(_) =>
+                    "PeriodicAdvertisingRemoveDeviceComplete",
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::PeriodicAdvertisingClearDeviceComplete(_) =>
+                    "PeriodicAdvertisingClearDeviceComplete",
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::SetExtendedScanParamsComplete(_) => "SetExtendedScanParamsComplete",
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::ExtendedScanStartComplete(_) => "ExtendedScanStartComplete",
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::ExtendedScanStopComplete(_) => "ExtendedScanStopComplete",
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::PreferExtendedConnectionParamsComplete(_) =>
+                    "PreferExtendedConnectionParamsComplete",
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::PhyUpdateComplete(_) => "PhyUpdateComplete",
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::ExtendedAdvertisingReport(_) => "ExtendedAdvertisingReport",
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::ScanTimeout => "ScanTimeout",
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::AdvertisingTerminated(_) => "AdvertisingTerminated",
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::ScanRequestReceived(_) => "ScanRequestReceived",
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::ChannelSelectionAlgorithm(_) => "ChannelSelectionAlgorithm",
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::PeriodicAdvertisingReport(_) => "PeriodicAdvertisingReport",
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::PeriodicAdvertisingSyncLost(_) => "PeriodicAdvertisingSyncLost",
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::PeriodicAdvertisingSyncEstablished(_) =>
+                    "PeriodicAdvertisingSyncEstablished",
+                RawGapEvent::Unknown(evt) => return write!(f, "Unknown({})", evt),
             }
         )
     }
 }
 
-impl GapEvent {
+#[cfg(target_os = "espidf")]
+impl RawGapEvent {
     #[allow(non_upper_case_globals)]
     pub(crate) unsafe fn build(
         evt: esp_gap_ble_cb_event_t,
         param: *mut esp_ble_gap_cb_param_t,
-    ) -> Self {
-        let param = param.as_ref().unwrap();
-        match evt {
+    ) -> Option<Self> {
+        let param = match param.as_ref() {
+            Some(param) => param,
+            None => {
+                log::warn!("GAP event {} without parameters", evt);
+                return None;
+            }
+        };
+        Some(match evt {
             esp_gap_ble_cb_event_t_ESP_GAP_BLE_ADV_DATA_SET_COMPLETE_EVT => {
-                GapEvent::AdvertisingDatasetComplete(param.adv_data_cmpl)
+                RawGapEvent::AdvertisingDatasetComplete(param.adv_data_cmpl)
             }
             esp_gap_ble_cb_event_t_ESP_GAP_BLE_SCAN_RSP_DATA_SET_COMPLETE_EVT => {
-                GapEvent::ScanResponseDatasetComplete(param.scan_rsp_data_cmpl)
+                RawGapEvent::ScanResponseDatasetComplete(param.scan_rsp_data_cmpl)
             }
             esp_gap_ble_cb_event_t_ESP_GAP_BLE_SCAN_PARAM_SET_COMPLETE_EVT => {
-                GapEvent::ScanParameterDatasetComplete(param.scan_param_cmpl)
+                RawGapEvent::ScanParameterDatasetComplete(param.scan_param_cmpl)
             }
             esp_gap_ble_cb_event_t_ESP_GAP_BLE_SCAN_RESULT_EVT => {
-                GapEvent::ScanResult(param.scan_rst)
+                RawGapEvent::ScanResult(param.scan_rst)
             }
             esp_gap_ble_cb_event_t_ESP_GAP_BLE_ADV_DATA_RAW_SET_COMPLETE_EVT => {
-                GapEvent::RawAdvertisingDatasetComplete(param.adv_data_raw_cmpl)
+                RawGapEvent::RawAdvertisingDatasetComplete(param.adv_data_raw_cmpl)
             }
             esp_gap_ble_cb_event_t_ESP_GAP_BLE_SCAN_RSP_DATA_RAW_SET_COMPLETE_EVT => {
-                GapEvent::RawScanResponseDatasetComplete(param.scan_rsp_data_raw_cmpl)
+                RawGapEvent::RawScanResponseDatasetComplete(param.scan_rsp_data_raw_cmpl)
             }
             esp_gap_ble_cb_event_t_ESP_GAP_BLE_ADV_START_COMPLETE_EVT => {
-                GapEvent::AdvertisingStartComplete(param.adv_start_cmpl)
+                RawGapEvent::AdvertisingStartComplete(param.adv_start_cmpl)
             }
             esp_gap_ble_cb_event_t_ESP_GAP_BLE_SCAN_START_COMPLETE_EVT => {
-                GapEvent::ScanStartComplete(param.scan_start_cmpl)
+                RawGapEvent::ScanStartComplete(param.scan_start_cmpl)
             }
             esp_gap_ble_cb_event_t_ESP_GAP_BLE_AUTH_CMPL_EVT => {
-                GapEvent::AuthenticationComplete(param.ble_security)
+                RawGapEvent::AuthenticationComplete(param.ble_security)
             }
-            esp_gap_ble_cb_event_t_ESP_GAP_BLE_KEY_EVT => GapEvent::Key(param.ble_security),
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_KEY_EVT => RawGapEvent::Key(param.ble_security),
             esp_gap_ble_cb_event_t_ESP_GAP_BLE_SEC_REQ_EVT => {
-                GapEvent::SecurityRequest(param.ble_security)
+                RawGapEvent::SecurityRequest(param.ble_security)
             }
             esp_gap_ble_cb_event_t_ESP_GAP_BLE_PASSKEY_NOTIF_EVT => {
-                GapEvent::PasskeyNotification(param.ble_security)
+                RawGapEvent::PasskeyNotification(param.ble_security)
             }
             esp_gap_ble_cb_event_t_ESP_GAP_BLE_PASSKEY_REQ_EVT => {
-                GapEvent::PasskeyRequest(param.ble_security)
+                RawGapEvent::PasskeyRequest(param.ble_security)
+            }
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_OOB_REQ_EVT => {
+                RawGapEvent::OOBRequest(param.ble_security)
+            }
+            #[cfg(not(esp_idf_version_major = "4"))]
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_SC_OOB_REQ_EVT => {
+                RawGapEvent::SecureConnectionsOOBRequest(param.ble_security)
             }
-            esp_gap_ble_cb_event_t_ESP_GAP_BLE_OOB_REQ_EVT => GapEvent::OOBRequest,
-            esp_gap_ble_cb_event_t_ESP_GAP_BLE_LOCAL_IR_EVT => GapEvent::LocalIR,
-            esp_gap_ble_cb_event_t_ESP_GAP_BLE_LOCAL_ER_EVT => GapEvent::LocalER,
+            #[cfg(not(esp_idf_version_major = "4"))]
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_SC_CR_LOC_OOB_EVT => {
+                RawGapEvent::LocalOOBDataCreated(param.ble_security)
+            }
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_LOCAL_IR_EVT => RawGapEvent::LocalIR,
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_LOCAL_ER_EVT => RawGapEvent::LocalER,
             esp_gap_ble_cb_event_t_ESP_GAP_BLE_NC_REQ_EVT => {
-                GapEvent::NumericComparisonRequest(param.ble_security)
+                RawGapEvent::NumericComparisonRequest(param.ble_security)
             }
             esp_gap_ble_cb_event_t_ESP_GAP_BLE_ADV_STOP_COMPLETE_EVT => {
-                GapEvent::AdvertisingStopComplete(param.adv_stop_cmpl)
+                RawGapEvent::AdvertisingStopComplete(param.adv_stop_cmpl)
             }
             esp_gap_ble_cb_event_t_ESP_GAP_BLE_SCAN_STOP_COMPLETE_EVT => {
-                GapEvent::ScanStopComplete(param.scan_stop_cmpl)
+                RawGapEvent::ScanStopComplete(param.scan_stop_cmpl)
             }
             esp_gap_ble_cb_event_t_ESP_GAP_BLE_SET_STATIC_RAND_ADDR_EVT => {
-                GapEvent::SetStaticRandomAddressComplete(param.set_rand_addr_cmpl)
+                RawGapEvent::SetStaticRandomAddressComplete(param.set_rand_addr_cmpl)
             }
             esp_gap_ble_cb_event_t_ESP_GAP_BLE_UPDATE_CONN_PARAMS_EVT => {
-                GapEvent::UpdateConnectionParamsComplete(param.update_conn_params)
+                RawGapEvent::UpdateConnectionParamsComplete(param.update_conn_params)
             }
             esp_gap_ble_cb_event_t_ESP_GAP_BLE_SET_PKT_LENGTH_COMPLETE_EVT => {
-                GapEvent::SetPacketLengthComplete(param.pkt_data_lenth_cmpl)
+                RawGapEvent::SetPacketLengthComplete(param.pkt_data_lenth_cmpl)
             }
             esp_gap_ble_cb_event_t_ESP_GAP_BLE_SET_LOCAL_PRIVACY_COMPLETE_EVT => {
-                GapEvent::SetLocalPrivacy(param.local_privacy_cmpl)
+                RawGapEvent::SetLocalPrivacy(param.local_privacy_cmpl)
             }
             esp_gap_ble_cb_event_t_ESP_GAP_BLE_REMOVE_BOND_DEV_COMPLETE_EVT => {
-                GapEvent::RemoveDeviceBondComplete(param.remove_bond_dev_cmpl)
+                RawGapEvent::RemoveDeviceBondComplete(param.remove_bond_dev_cmpl)
             }
             esp_gap_ble_cb_event_t_ESP_GAP_BLE_CLEAR_BOND_DEV_COMPLETE_EVT => {
-                GapEvent::ClearDeviceBondComplete(param.clear_bond_dev_cmpl)
+                RawGapEvent::ClearDeviceBondComplete(param.clear_bond_dev_cmpl)
             }
             esp_gap_ble_cb_event_t_ESP_GAP_BLE_GET_BOND_DEV_COMPLETE_EVT => {
-                GapEvent::GetDeviceBondComplete(param.get_bond_dev_cmpl)
+                RawGapEvent::GetDeviceBondComplete(param.get_bond_dev_cmpl)
             }
             esp_gap_ble_cb_event_t_ESP_GAP_BLE_READ_RSSI_COMPLETE_EVT => {
-                GapEvent::ReadRssiComplete(param.read_rssi_cmpl)
+                RawGapEvent::ReadRssiComplete(param.read_rssi_cmpl)
             }
             esp_gap_ble_cb_event_t_ESP_GAP_BLE_UPDATE_WHITELIST_COMPLETE_EVT => {
-                GapEvent::UpdateWhitelistComplete(param.update_whitelist_cmpl)
+                RawGapEvent::UpdateWhitelistComplete(param.update_whitelist_cmpl)
             }
             esp_gap_ble_cb_event_t_ESP_GAP_BLE_UPDATE_DUPLICATE_EXCEPTIONAL_LIST_COMPLETE_EVT => {
-                GapEvent::UpdateDuplicateListComplete(param.update_duplicate_exceptional_list_cmpl)
+                RawGapEvent::UpdateDuplicateListComplete(
+                    param.update_duplicate_exceptional_list_cmpl,
+                )
             }
             esp_gap_ble_cb_event_t_ESP_GAP_BLE_SET_CHANNELS_EVT => {
-                GapEvent::SetChannelsComplete(param.ble_set_channels)
+                RawGapEvent::SetChannelsComplete(param.ble_set_channels)
+            }
+            #[cfg(esp_idf_bt_ble_50_features_supported)]
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_READ_PHY_COMPLETE_EVT => {
+                RawGapEvent::ReadPhyComplete(param.read_phy)
+            }
+            #[cfg(esp_idf_bt_ble_50_features_supported)]
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_SET_PREFERED_DEFAULT_PHY_COMPLETE_EVT => {
+                RawGapEvent::SetPreferredDefaultPhyComplete(param.set_perf_def_phy)
+            }
+            #[cfg(esp_idf_bt_ble_50_features_supported)]
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_SET_PREFERED_PHY_COMPLETE_EVT => {
+                RawGapEvent::SetPreferredPhyComplete(param.set_perf_phy)
+            }
+            #[cfg(esp_idf_bt_ble_50_features_supported)]
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_EXT_ADV_SET_RAND_ADDR_COMPLETE_EVT => {
+                RawGapEvent::ExtendedAdvertisingSetRandomAddressComplete(
+                    param.ext_adv_set_rand_addr,
+                )
+            }
+            #[cfg(esp_idf_bt_ble_50_features_supported)]
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_EXT_ADV_SET_PARAMS_COMPLETE_EVT => {
+                RawGapEvent::ExtendedAdvertisingSetParamsComplete(param.ext_adv_set_params)
+            }
+            #[cfg(esp_idf_bt_ble_50_features_supported)]
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_EXT_ADV_DATA_SET_COMPLETE_EVT => {
+                RawGapEvent::ExtendedAdvertisingDatasetComplete(param.ext_adv_data_set)
+            }
+            #[cfg(esp_idf_bt_ble_50_features_supported)]
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_EXT_SCAN_RSP_DATA_SET_COMPLETE_EVT => {
+                RawGapEvent::ExtendedScanResponseDatasetComplete(param.scan_rsp_set)
+            }
+            #[cfg(esp_idf_bt_ble_50_features_supported)]
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_EXT_ADV_START_COMPLETE_EVT => {
+                RawGapEvent::ExtendedAdvertisingStartComplete(param.ext_adv_start)
+            }
+            #[cfg(esp_idf_bt_ble_50_features_supported)]
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_EXT_ADV_STOP_COMPLETE_EVT => {
+                RawGapEvent::ExtendedAdvertisingStopComplete(param.ext_adv_stop)
+            }
+            #[cfg(esp_idf_bt_ble_50_features_supported)]
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_EXT_ADV_SET_REMOVE_COMPLETE_EVT => {
+                RawGapEvent::ExtendedAdvertisingSetRemoveComplete(param.ext_adv_remove)
+            }
+            #[cfg(esp_idf_bt_ble_50_features_supported)]
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_EXT_ADV_SET_CLEAR_COMPLETE_EVT => {
+                RawGapEvent::ExtendedAdvertisingSetClearComplete(param.ext_adv_clear)
+            }
+            #[cfg(esp_idf_bt_ble_50_features_supported)]
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_PERIODIC_ADV_SET_PARAMS_COMPLETE_EVT => {
+                // The field name is misspelled in esp-idf
+                RawGapEvent::PeriodicAdvertisingSetParamsComplete(param.peroid_adv_set_params)
+            }
+            #[cfg(esp_idf_bt_ble_50_features_supported)]
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_PERIODIC_ADV_DATA_SET_COMPLETE_EVT => {
+                RawGapEvent::PeriodicAdvertisingDatasetComplete(param.period_adv_data_set)
+            }
+            #[cfg(esp_idf_bt_ble_50_features_supported)]
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_PERIODIC_ADV_START_COMPLETE_EVT => {
+                RawGapEvent::PeriodicAdvertisingStartComplete(param.period_adv_start)
+            }
+            #[cfg(esp_idf_bt_ble_50_features_supported)]
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_PERIODIC_ADV_STOP_COMPLETE_EVT => {
+                RawGapEvent::PeriodicAdvertisingStopComplete(param.period_adv_stop)
+            }
+            #[cfg(esp_idf_bt_ble_50_features_supported)]
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_PERIODIC_ADV_CREATE_SYNC_COMPLETE_EVT => {
+                RawGapEvent::PeriodicAdvertisingCreateSyncComplete(param.period_adv_create_sync)
+            }
+            #[cfg(esp_idf_bt_ble_50_features_supported)]
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_PERIODIC_ADV_SYNC_CANCEL_COMPLETE_EVT => {
+                RawGapEvent::PeriodicAdvertisingSyncCancelComplete(param.period_adv_sync_cancel)
+            }
+            #[cfg(esp_idf_bt_ble_50_features_supported)]
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_PERIODIC_ADV_SYNC_TERMINATE_COMPLETE_EVT => {
+                RawGapEvent::PeriodicAdvertisingSyncTerminateComplete(param.period_adv_sync_term)
+            }
+            #[cfg(esp_idf_bt_ble_50_features_supported)]
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_PERIODIC_ADV_ADD_DEV_COMPLETE_EVT => {
+                RawGapEvent::PeriodicAdvertisingAddDeviceComplete(param.period_adv_add_dev)
+            }
+            #[cfg(esp_idf_bt_ble_50_features_supported)]
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_PERIODIC_ADV_REMOVE_DEV_COMPLETE_EVT => {
+                RawGapEvent::PeriodicAdvertisingRemoveDeviceComplete(param.period_adv_remove_dev)
+            }
+            #[cfg(esp_idf_bt_ble_50_features_supported)]
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_PERIODIC_ADV_CLEAR_DEV_COMPLETE_EVT => {
+                RawGapEvent::PeriodicAdvertisingClearDeviceComplete(param.period_adv_clear_dev)
+            }
+            #[cfg(esp_idf_bt_ble_50_features_supported)]
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_SET_EXT_SCAN_PARAMS_COMPLETE_EVT => {
+                RawGapEvent::SetExtendedScanParamsComplete(param.set_ext_scan_params)
+            }
+            #[cfg(esp_idf_bt_ble_50_features_supported)]
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_EXT_SCAN_START_COMPLETE_EVT => {
+                RawGapEvent::ExtendedScanStartComplete(param.ext_scan_start)
+            }
+            #[cfg(esp_idf_bt_ble_50_features_supported)]
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_EXT_SCAN_STOP_COMPLETE_EVT => {
+                RawGapEvent::ExtendedScanStopComplete(param.ext_scan_stop)
+            }
+            #[cfg(esp_idf_bt_ble_50_features_supported)]
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_PREFER_EXT_CONN_PARAMS_SET_COMPLETE_EVT => {
+                RawGapEvent::PreferExtendedConnectionParamsComplete(param.ext_conn_params_set)
+            }
+            #[cfg(esp_idf_bt_ble_50_features_supported)]
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_PHY_UPDATE_COMPLETE_EVT => {
+                RawGapEvent::PhyUpdateComplete(param.phy_update)
+            }
+            #[cfg(esp_idf_bt_ble_50_features_supported)]
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_EXT_ADV_REPORT_EVT => {
+                RawGapEvent::ExtendedAdvertisingReport(param.ext_adv_report)
+            }
+            #[cfg(esp_idf_bt_ble_50_features_supported)]
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_SCAN_TIMEOUT_EVT => RawGapEvent::ScanTimeout,
+            #[cfg(esp_idf_bt_ble_50_features_supported)]
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_ADV_TERMINATED_EVT => {
+                RawGapEvent::AdvertisingTerminated(param.adv_terminate)
+            }
+            #[cfg(esp_idf_bt_ble_50_features_supported)]
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_SCAN_REQ_RECEIVED_EVT => {
+                RawGapEvent::ScanRequestReceived(param.scan_req_received)
+            }
+            #[cfg(esp_idf_bt_ble_50_features_supported)]
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_CHANNEL_SELETE_ALGORITHM_EVT => {
+                RawGapEvent::ChannelSelectionAlgorithm(param.channel_sel_alg)
+            }
+            #[cfg(esp_idf_bt_ble_50_features_supported)]
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_PERIODIC_ADV_REPORT_EVT => {
+                RawGapEvent::PeriodicAdvertisingReport(param.period_adv_report)
+            }
+            #[cfg(esp_idf_bt_ble_50_features_supported)]
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_PERIODIC_ADV_SYNC_LOST_EVT => {
+                RawGapEvent::PeriodicAdvertisingSyncLost(param.periodic_adv_sync_lost)
+            }
+            #[cfg(esp_idf_bt_ble_50_features_supported)]
+            esp_gap_ble_cb_event_t_ESP_GAP_BLE_PERIODIC_ADV_SYNC_ESTAB_EVT => {
+                RawGapEvent::PeriodicAdvertisingSyncEstablished(param.periodic_adv_sync_estab)
             }
             _ => {
                 log::warn!("Unhandled event {:?}", evt);
-                panic!("Unhandled event {:?}", evt)
+                RawGapEvent::Unknown(evt)
+            }
+        })
+    }
+}
+
+#[cfg(target_os = "espidf")]
+impl From<RawGapEvent> for GapEvent {
+    fn from(raw: RawGapEvent) -> Self {
+        // Safe as the union members of `esp_ble_sec_t` are selected by the
+        // event they were received with.
+        unsafe {
+            match raw {
+                RawGapEvent::AdvertisingDatasetComplete(param) => {
+                    GapEvent::AdvertisingDatasetComplete(param.status.into())
+                }
+                RawGapEvent::ScanResponseDatasetComplete(param) => {
+                    GapEvent::ScanResponseDatasetComplete(param.status.into())
+                }
+                RawGapEvent::ScanParameterDatasetComplete(param) => {
+                    GapEvent::ScanParameterDatasetComplete(param.status.into())
+                }
+                RawGapEvent::ScanResult(param) => GapEvent::ScanResult((&param).into()),
+                RawGapEvent::RawAdvertisingDatasetComplete(param) => {
+                    GapEvent::RawAdvertisingDatasetComplete(param.status.into())
+                }
+                RawGapEvent::RawScanResponseDatasetComplete(param) => {
+                    GapEvent::RawScanResponseDatasetComplete(param.status.into())
+                }
+                RawGapEvent::AdvertisingStartComplete(param) => {
+                    GapEvent::AdvertisingStartComplete(param.status.into())
+                }
+                RawGapEvent::ScanStartComplete(param) => {
+                    GapEvent::ScanStartComplete(param.status.into())
+                }
+                RawGapEvent::AuthenticationComplete(sec) => {
+                    let auth = sec.auth_cmpl;
+                    GapEvent::AuthenticationComplete(AuthenticationCompleteEvent {
+                        bda: auth.bd_addr.into(),
+                        success: auth.success,
+                        fail_reason: auth.fail_reason,
+                        key_present: auth.key_present,
+                        addr_type: auth.addr_type.into(),
+                        dev_type: auth.dev_type.into(),
+                        auth_mode: auth.auth_mode,
+                    })
+                }
+                RawGapEvent::Key(sec) => GapEvent::Key(KeyEvent {
+                    bda: sec.ble_key.bd_addr.into(),
+                    key_type: sec.ble_key.key_type.into(),
+                }),
+                RawGapEvent::SecurityRequest(sec) => {
+                    GapEvent::SecurityRequest(sec.ble_req.bd_addr.into())
+                }
+                RawGapEvent::PasskeyNotification(sec) => {
+                    GapEvent::PasskeyNotification(PasskeyEvent {
+                        bda: sec.key_notif.bd_addr.into(),
+                        passkey: sec.key_notif.passkey,
+                    })
+                }
+                RawGapEvent::PasskeyRequest(sec) => {
+                    GapEvent::PasskeyRequest(sec.ble_req.bd_addr.into())
+                }
+                RawGapEvent::OOBRequest(sec) => GapEvent::OOBRequest(sec.ble_req.bd_addr.into()),
+                #[cfg(not(esp_idf_version_major = "4"))]
+                RawGapEvent::SecureConnectionsOOBRequest(sec) => {
+                    GapEvent::SecureConnectionsOOBRequest(sec.ble_req.bd_addr.into())
+                }
+                #[cfg(not(esp_idf_version_major = "4"))]
+                RawGapEvent::LocalOOBDataCreated(sec) => GapEvent::LocalOOBDataCreated(ScOobData {
+                    confirm: sec.oob_data.oob_c,
+                    random: sec.oob_data.oob_r,
+                }),
+                RawGapEvent::LocalIR => GapEvent::LocalIR,
+                RawGapEvent::LocalER => GapEvent::LocalER,
+                RawGapEvent::NumericComparisonRequest(sec) => {
+                    GapEvent::NumericComparisonRequest(PasskeyEvent {
+                        bda: sec.key_notif.bd_addr.into(),
+                        passkey: sec.key_notif.passkey,
+                    })
+                }
+                RawGapEvent::AdvertisingStopComplete(param) => {
+                    GapEvent::AdvertisingStopComplete(param.status.into())
+                }
+                RawGapEvent::ScanStopComplete(param) => {
+                    GapEvent::ScanStopComplete(param.status.into())
+                }
+                RawGapEvent::SetStaticRandomAddressComplete(param) => {
+                    GapEvent::SetStaticRandomAddressComplete(param.status.into())
+                }
+                RawGapEvent::UpdateConnectionParamsComplete(param) => {
+                    GapEvent::UpdateConnectionParamsComplete(ConnectionParamsEvent {
+                        status: param.status.into(),
+                        bda: param.bda.into(),
+                        min_int: param.min_int,
+                        max_int: param.max_int,
+                        latency: param.latency,
+                        conn_int: param.conn_int,
+                        timeout: param.timeout,
+                    })
+                }
+                RawGapEvent::SetPacketLengthComplete(param) => {
+                    GapEvent::SetPacketLengthComplete(PacketLengthEvent {
+                        status: param.status.into(),
+                        rx_len: param.params.rx_len,
+                        tx_len: param.params.tx_len,
+                    })
+                }
+                RawGapEvent::SetLocalPrivacy(param) => {
+                    GapEvent::SetLocalPrivacy(param.status.into())
+                }
+                RawGapEvent::RemoveDeviceBondComplete(param) => {
+                    GapEvent::RemoveDeviceBondComplete(BondEvent {
+                        status: param.status.into(),
+                        bda: param.bd_addr.into(),
+                    })
+                }
+                RawGapEvent::ClearDeviceBondComplete(param) => {
+                    GapEvent::ClearDeviceBondComplete(param.status.into())
+                }
+                RawGapEvent::GetDeviceBondComplete(param) => {
+                    GapEvent::GetDeviceBondComplete(BondListEvent {
+                        status: param.status.into(),
+                        dev_num: param.dev_num,
+                    })
+                }
+                RawGapEvent::ReadRssiComplete(param) => GapEvent::ReadRssiComplete(RssiEvent {
+                    status: param.status.into(),
+                    bda: param.remote_addr.into(),
+                    rssi: param.rssi,
+                }),
+                RawGapEvent::UpdateWhitelistComplete(param) => {
+                    GapEvent::UpdateWhitelistComplete(WhitelistEvent {
+                        status: param.status.into(),
+                        operation: param.wl_opration.into(),
+                    })
+                }
+                RawGapEvent::UpdateDuplicateListComplete(param) => {
+                    GapEvent::UpdateDuplicateListComplete(param.status.into())
+                }
+                RawGapEvent::SetChannelsComplete(param) => {
+                    GapEvent::SetChannelsComplete(param.stat.into())
+                }
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::ReadPhyComplete(param) => GapEvent::ReadPhyComplete(PhyEvent {
+                    status: param.status.into(),
+                    bda: param.bda.into(),
+                    tx_phy: Phy::from_u8(param.tx_phy as _),
+                    rx_phy: Phy::from_u8(param.rx_phy as _),
+                }),
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::SetPreferredDefaultPhyComplete(param) => {
+                    GapEvent::SetPreferredDefaultPhyComplete(param.status.into())
+                }
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::SetPreferredPhyComplete(param) => {
+                    GapEvent::SetPreferredPhyComplete(param.status.into())
+                }
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::ExtendedAdvertisingSetRandomAddressComplete(param) => {
+                    GapEvent::ExtendedAdvertisingSetRandomAddressComplete(param.status.into())
+                }
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::ExtendedAdvertisingSetParamsComplete(param) => {
+                    GapEvent::ExtendedAdvertisingSetParamsComplete(param.status.into())
+                }
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::ExtendedAdvertisingDatasetComplete(param) => {
+                    GapEvent::ExtendedAdvertisingDatasetComplete(param.status.into())
+                }
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::ExtendedScanResponseDatasetComplete(param) => {
+                    GapEvent::ExtendedScanResponseDatasetComplete(param.status.into())
+                }
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::ExtendedAdvertisingStartComplete(param) => {
+                    GapEvent::ExtendedAdvertisingStartComplete(param.status.into())
+                }
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::ExtendedAdvertisingStopComplete(param) => {
+                    GapEvent::ExtendedAdvertisingStopComplete(param.status.into())
+                }
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::ExtendedAdvertisingSetRemoveComplete(param) => {
+                    GapEvent::ExtendedAdvertisingSetRemoveComplete(param.status.into())
+                }
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::ExtendedAdvertisingSetClearComplete(param) => {
+                    GapEvent::ExtendedAdvertisingSetClearComplete(param.status.into())
+                }
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::PeriodicAdvertisingSetParamsComplete(param) => {
+                    GapEvent::PeriodicAdvertisingSetParamsComplete(param.status.into())
+                }
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::PeriodicAdvertisingDatasetComplete(param) => {
+                    GapEvent::PeriodicAdvertisingDatasetComplete(param.status.into())
+                }
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::PeriodicAdvertisingStartComplete(param) => {
+                    GapEvent::PeriodicAdvertisingStartComplete(param.status.into())
+                }
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::PeriodicAdvertisingStopComplete(param) => {
+                    GapEvent::PeriodicAdvertisingStopComplete(param.status.into())
+                }
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::PeriodicAdvertisingCreateSyncComplete(param) => {
+                    GapEvent::PeriodicAdvertisingCreateSyncComplete(param.status.into())
+                }
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::PeriodicAdvertisingSyncCancelComplete(param) => {
+                    GapEvent::PeriodicAdvertisingSyncCancelComplete(param.status.into())
+                }
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::PeriodicAdvertisingSyncTerminateComplete(param) => {
+                    GapEvent::PeriodicAdvertisingSyncTerminateComplete(param.status.into())
+                }
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::PeriodicAdvertisingAddDeviceComplete(param) => {
+                    GapEvent::PeriodicAdvertisingAddDeviceComplete(param.status.into())
+                }
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::PeriodicAdvertisingRemoveDeviceComplete(param) => {
+                    GapEvent::PeriodicAdvertisingRemoveDeviceComplete(param.status.into())
+                }
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::PeriodicAdvertisingClearDeviceComplete(param) => {
+                    GapEvent::PeriodicAdvertisingClearDeviceComplete(param.status.into())
+                }
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::SetExtendedScanParamsComplete(param) => {
+                    GapEvent::SetExtendedScanParamsComplete(param.status.into())
+                }
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::ExtendedScanStartComplete(param) => {
+                    GapEvent::ExtendedScanStartComplete(param.status.into())
+                }
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::ExtendedScanStopComplete(param) => {
+                    GapEvent::ExtendedScanStopComplete(param.status.into())
+                }
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::PreferExtendedConnectionParamsComplete(param) => {
+                    GapEvent::PreferExtendedConnectionParamsComplete(param.status.into())
+                }
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::PhyUpdateComplete(param) => GapEvent::PhyUpdateComplete(PhyEvent {
+                    status: param.status.into(),
+                    bda: param.bda.into(),
+                    tx_phy: Phy::from_u8(param.tx_phy as _),
+                    rx_phy: Phy::from_u8(param.rx_phy as _),
+                }),
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::ExtendedAdvertisingReport(param) => {
+                    let report = param.params;
+                    let len = (report.adv_data_len as usize).min(report.adv_data.len());
+                    GapEvent::ExtendedAdvertisingReport(ExtendedAdvertisingReport {
+                        event_type: report.event_type as _,
+                        addr_type: (report.addr_type as esp_ble_addr_type_t).into(),
+                        addr: report.addr.into(),
+                        primary_phy: Phy::from_u8(report.primary_phy as _),
+                        secondary_phy: Phy::from_u8(report.secondly_phy as _),
+                        sid: report.sid,
+                        tx_power: report.tx_power,
+                        rssi: report.rssi,
+                        periodic_interval: report.per_adv_interval,
+                        data_status: report.data_status as _,
+                        data: report.adv_data[..len].to_vec(),
+                    })
+                }
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::ScanTimeout => GapEvent::ScanTimeout,
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::AdvertisingTerminated(param) => {
+                    GapEvent::AdvertisingTerminated(AdvertisingTerminatedEvent {
+                        status: param.status,
+                        instance: param.adv_instance,
+                        conn_idx: param.conn_idx,
+                        completed_events: param.completed_event,
+                    })
+                }
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::ScanRequestReceived(param) => {
+                    GapEvent::ScanRequestReceived(ScanRequestEvent {
+                        instance: param.adv_instance,
+                        scanner_addr_type: (param.scan_addr_type as esp_ble_addr_type_t).into(),
+                        scanner_addr: param.scan_addr.into(),
+                    })
+                }
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::ChannelSelectionAlgorithm(param) => {
+                    GapEvent::ChannelSelectionAlgorithm(ChannelSelectionEvent {
+                        conn_handle: param.conn_handle,
+                        algorithm: param.channel_sel_alg,
+                    })
+                }
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::PeriodicAdvertisingReport(param) => {
+                    let report = param.params;
+                    let len = (report.data_length as usize).min(report.data.len());
+                    GapEvent::PeriodicAdvertisingReport(PeriodicAdvertisingReport {
+                        sync_handle: report.sync_handle,
+                        tx_power: report.tx_power,
+                        rssi: report.rssi,
+                        data_status: report.data_status as _,
+                        data: report.data[..len].to_vec(),
+                    })
+                }
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::PeriodicAdvertisingSyncLost(param) => {
+                    GapEvent::PeriodicAdvertisingSyncLost(param.sync_handle)
+                }
+                #[cfg(esp_idf_bt_ble_50_features_supported)]
+                RawGapEvent::PeriodicAdvertisingSyncEstablished(param) => {
+                    GapEvent::PeriodicAdvertisingSyncEstablished(PeriodicSyncEvent {
+                        status: param.status.into(),
+                        sync_handle: param.sync_handle,
+                        sid: param.sid,
+                        adv_addr_type: (param.adv_addr_type as esp_ble_addr_type_t).into(),
+                        adv_addr: param.adv_addr.into(),
+                        adv_phy: Phy::from_u8(param.adv_phy as _),
+                        interval: param.period_adv_interval,
+                        clock_accuracy: param.adv_clk_accuracy,
+                    })
+                }
+                RawGapEvent::Unknown(evt) => GapEvent::Unknown(evt),
             }
         }
     }
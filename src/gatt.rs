@@ -1,3 +1,4 @@
+#[cfg(target_os = "espidf")]
 use esp_idf_sys::*;
 
 #[repr(u16)]
@@ -68,13 +69,14 @@ pub enum ServiceUuid {
     PublicBroadcastAnnouncement,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum BtUuid {
     Uuid16(u16),
     Uuid32(u32),
     Uuid128([u8; 16]),
 }
 
+#[cfg(target_os = "espidf")]
 impl From<BtUuid> for esp_bt_uuid_t {
     fn from(svc: BtUuid) -> Self {
         let mut bt_uuid: esp_bt_uuid_t = Default::default();
@@ -96,11 +98,25 @@ impl From<BtUuid> for esp_bt_uuid_t {
     }
 }
 
+#[cfg(target_os = "espidf")]
+impl From<esp_bt_uuid_t> for BtUuid {
+    fn from(bt_uuid: esp_bt_uuid_t) -> Self {
+        unsafe {
+            match bt_uuid.len {
+                2 => BtUuid::Uuid16(bt_uuid.uuid.uuid16),
+                4 => BtUuid::Uuid32(bt_uuid.uuid.uuid32),
+                _ => BtUuid::Uuid128(bt_uuid.uuid.uuid128),
+            }
+        }
+    }
+}
+
 pub struct AttributeValue<const S: usize> {
     len: usize,
     value: [u8; S],
 }
 
+#[cfg(target_os = "espidf")]
 impl<const S: usize> From<AttributeValue<S>> for esp_attr_value_t {
     fn from(mut val: AttributeValue<S>) -> Self {
         Self {
@@ -130,13 +146,19 @@ impl<const S: usize> AttributeValue<S> {
         val.value[0..actual_len].copy_from_slice(&value[0..actual_len]);
         val
     }
+
+    pub(crate) fn as_slice(&self) -> &[u8] {
+        &self.value[..self.len]
+    }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum AutoResponse {
     ByApp,
     ByGatt,
 }
 
+#[cfg(target_os = "espidf")]
 impl From<AutoResponse> for esp_attr_control_t {
     fn from(auto: AutoResponse) -> Self {
         Self {
@@ -150,8 +172,8 @@ impl From<AutoResponse> for esp_attr_control_t {
 
 pub struct GattCharacteristic<const S: usize> {
     pub(crate) uuid: BtUuid,
-    pub(crate) permissions: esp_gatt_perm_t,
-    pub(crate) property: esp_gatt_char_prop_t,
+    pub(crate) permissions: u16,
+    pub(crate) property: u8,
     pub(crate) value: AttributeValue<S>,
     pub(crate) auto_rsp: AutoResponse,
 }
@@ -159,8 +181,8 @@ pub struct GattCharacteristic<const S: usize> {
 impl<const S: usize> GattCharacteristic<S> {
     pub fn new(
         uuid: BtUuid,
-        permissions: esp_gatt_perm_t,
-        property: esp_gatt_char_prop_t,
+        permissions: u16,
+        property: u8,
         value: AttributeValue<S>,
         auto_rsp: AutoResponse,
     ) -> Self {
@@ -176,11 +198,11 @@ impl<const S: usize> GattCharacteristic<S> {
 
 pub struct GattDescriptor {
     pub(crate) uuid: BtUuid,
-    pub(crate) permissions: esp_gatt_perm_t,
+    pub(crate) permissions: u16,
 }
 
 impl GattDescriptor {
-    pub fn new(uuid: BtUuid, permissions: esp_gatt_perm_t) -> Self {
+    pub fn new(uuid: BtUuid, permissions: u16) -> Self {
         Self { uuid, permissions }
     }
 }
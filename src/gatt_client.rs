@@ -0,0 +1,560 @@
+#[cfg(target_os = "espidf")]
+use esp_idf_sys::*;
+
+#[cfg(target_os = "espidf")]
+use crate::gatt_server::copy_event_buffer;
+#[cfg(target_os = "espidf")]
+use crate::LinkRole;
+use crate::{
+    BdAddr, BtUuid, CongestEvent, ConnectEvent, DisconnectEvent, DisconnectReason, GattService,
+    GattStatus, RegisterEvent,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteService {
+    pub service: GattService,
+    pub start_handle: u16,
+    pub end_handle: u16,
+}
+
+#[cfg(target_os = "espidf")]
+impl From<esp_gattc_service_elem_t> for RemoteService {
+    fn from(elem: esp_gattc_service_elem_t) -> Self {
+        let service = if elem.is_primary {
+            GattService::new_primary(elem.uuid.into(), 0, 0)
+        } else {
+            GattService::new(elem.uuid.into(), 0, 0)
+        };
+        Self {
+            service,
+            start_handle: elem.start_handle,
+            end_handle: elem.end_handle,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteCharacteristic {
+    pub uuid: BtUuid,
+    pub handle: u16,
+    /// `ESP_GATT_CHAR_PROP_BIT_*` flags
+    pub properties: u8,
+}
+
+#[cfg(target_os = "espidf")]
+impl From<esp_gattc_char_elem_t> for RemoteCharacteristic {
+    fn from(elem: esp_gattc_char_elem_t) -> Self {
+        Self {
+            uuid: elem.uuid.into(),
+            handle: elem.char_handle,
+            properties: elem.properties,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteDescriptor {
+    pub uuid: BtUuid,
+    pub handle: u16,
+}
+
+#[cfg(target_os = "espidf")]
+impl From<esp_gattc_descr_elem_t> for RemoteDescriptor {
+    fn from(elem: esp_gattc_descr_elem_t) -> Self {
+        Self {
+            uuid: elem.uuid.into(),
+            handle: elem.handle,
+        }
+    }
+}
+
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WriteType {
+    NoResponse = 1,
+    WithResponse = 2,
+}
+
+/// Event reported by the GATT client.
+///
+/// The raw Bluedroid event is available through `RawGattClientEvent` and
+/// `EspBle::register_gattc_raw_handler`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum GattClientEvent {
+    Register(RegisterEvent),
+    Unregister,
+    Open(OpenEvent),
+    Close(ClientCloseEvent),
+    ReadCharacteristic(ReadResultEvent),
+    WriteCharacteristic(WriteResultEvent),
+    ReadDescriptor(ReadResultEvent),
+    WriteDescriptor(WriteResultEvent),
+    SearchComplete(ClientStatusEvent),
+    SearchResult(SearchResultEvent),
+    Notify(NotifyEvent),
+    PrepareWrite(WriteResultEvent),
+    ExecWrite(ClientStatusEvent),
+    ServiceChanged(BdAddr),
+    ConfigureMtu(ClientMtuEvent),
+    Congest(CongestEvent),
+    RegisterForNotify(NotifyRegistrationEvent),
+    UnregisterForNotify(NotifyRegistrationEvent),
+    Connect(ConnectEvent),
+    Disconnect(DisconnectEvent),
+    ReadMultiple(ReadResultEvent),
+    QueueFull(QueueFullEvent),
+    SetAssociationComplete(GattStatus),
+    GetAddressListComplete(AddressListEvent),
+    DiscoverServicesComplete(ClientStatusEvent),
+    /// An event this crate does not know about, with its raw event code.
+    Unknown(u32),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OpenEvent {
+    pub status: GattStatus,
+    pub conn_id: u16,
+    pub bda: BdAddr,
+    pub mtu: u16,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClientCloseEvent {
+    pub status: GattStatus,
+    pub conn_id: u16,
+    pub bda: BdAddr,
+    pub reason: DisconnectReason,
+}
+
+/// Completion of a read, `value` is empty unless it succeeded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReadResultEvent {
+    pub status: GattStatus,
+    pub conn_id: u16,
+    pub handle: u16,
+    pub value: Vec<u8>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WriteResultEvent {
+    pub status: GattStatus,
+    pub conn_id: u16,
+    pub handle: u16,
+    pub offset: u16,
+}
+
+/// Completion of an operation on a connection carrying only a status.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClientStatusEvent {
+    pub status: GattStatus,
+    pub conn_id: u16,
+}
+
+/// A service found by `EspBle::search_services`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SearchResultEvent {
+    pub conn_id: u16,
+    pub start_handle: u16,
+    pub end_handle: u16,
+    pub uuid: BtUuid,
+    pub instance_id: u8,
+    pub is_primary: bool,
+}
+
+/// A notification, or an indication when `is_notify` is `false`, sent by the
+/// server for a handle registered with `EspBle::register_for_notify`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NotifyEvent {
+    pub conn_id: u16,
+    pub bda: BdAddr,
+    pub handle: u16,
+    pub is_notify: bool,
+    pub value: Vec<u8>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClientMtuEvent {
+    pub status: GattStatus,
+    pub conn_id: u16,
+    pub mtu: u16,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NotifyRegistrationEvent {
+    pub status: GattStatus,
+    pub handle: u16,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QueueFullEvent {
+    pub status: GattStatus,
+    pub conn_id: u16,
+    pub is_full: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AddressListEvent {
+    pub status: GattStatus,
+    pub addresses: Vec<BdAddr>,
+}
+
+/// Event as received from Bluedroid, its raw parameters are only valid during
+/// the call of the raw handler.
+#[cfg(target_os = "espidf")]
+#[derive(Copy, Clone)]
+pub enum RawGattClientEvent {
+    Register(esp_ble_gattc_cb_param_t_gattc_reg_evt_param),
+    Unregister,
+    Open(esp_ble_gattc_cb_param_t_gattc_open_evt_param),
+    Close(esp_ble_gattc_cb_param_t_gattc_close_evt_param),
+    ReadCharacteristic(esp_ble_gattc_cb_param_t_gattc_read_char_evt_param),
+    WriteCharacteristic(esp_ble_gattc_cb_param_t_gattc_write_evt_param),
+    ReadDescriptor(esp_ble_gattc_cb_param_t_gattc_read_char_evt_param),
+    WriteDescriptor(esp_ble_gattc_cb_param_t_gattc_write_evt_param),
+    SearchComplete(esp_ble_gattc_cb_param_t_gattc_search_cmpl_evt_param),
+    SearchResult(esp_ble_gattc_cb_param_t_gattc_search_res_evt_param),
+    Notify(esp_ble_gattc_cb_param_t_gattc_notify_evt_param),
+    PrepareWrite(esp_ble_gattc_cb_param_t_gattc_write_evt_param),
+    ExecWrite(esp_ble_gattc_cb_param_t_gattc_exec_cmpl_evt_param),
+    ServiceChanged(esp_ble_gattc_cb_param_t_gattc_srvc_chg_evt_param),
+    ConfigureMtu(esp_ble_gattc_cb_param_t_gattc_cfg_mtu_evt_param),
+    Congest(esp_ble_gattc_cb_param_t_gattc_congest_evt_param),
+    RegisterForNotify(esp_ble_gattc_cb_param_t_gattc_reg_for_notify_evt_param),
+    UnregisterForNotify(esp_ble_gattc_cb_param_t_gattc_unreg_for_notify_evt_param),
+    Connect(esp_ble_gattc_cb_param_t_gattc_connect_evt_param),
+    Disconnect(esp_ble_gattc_cb_param_t_gattc_disconnect_evt_param),
+    ReadMultiple(esp_ble_gattc_cb_param_t_gattc_read_char_evt_param),
+    QueueFull(esp_ble_gattc_cb_param_t_gattc_queue_full_evt_param),
+    SetAssociationComplete(esp_ble_gattc_cb_param_t_gattc_set_assoc_addr_cmp_evt_param),
+    GetAddressListComplete(esp_ble_gattc_cb_param_t_gattc_get_addr_list_evt_param),
+    DiscoverServicesComplete(esp_ble_gattc_cb_param_t_gattc_dis_srvc_cmpl_evt_param),
+    /// An event this crate does not know about, with its raw event code.
+    Unknown(u32),
+}
+
+#[cfg(target_os = "espidf")]
+impl std::fmt::Debug for RawGattClientEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            RawGattClientEvent::Register(reg) => write!(
+                f,
+                "Register {{ status: {}, app_id: {} }}",
+                reg.status, reg.app_id
+            ),
+            RawGattClientEvent::Unregister => write!(f, "Unregister"),
+            RawGattClientEvent::Open(open) => write!(
+                f,
+                "Open {{ status: {}, conn_id: {}, remote_bda: {:?}, mtu: {} }}",
+                open.status, open.conn_id, open.remote_bda, open.mtu
+            ),
+            RawGattClientEvent::Close(close) => write!(
+                f,
+                "Close {{ status: {}, conn_id: {}, reason: {} }}",
+                close.status, close.conn_id, close.reason
+            ),
+            RawGattClientEvent::ReadCharacteristic(read) => write!(
+                f,
+                "ReadCharacteristic {{ status: {}, conn_id: {}, handle: {}, len: {} }}",
+                read.status, read.conn_id, read.handle, read.value_len
+            ),
+            RawGattClientEvent::WriteCharacteristic(write) => write!(
+                f,
+                "WriteCharacteristic {{ status: {}, conn_id: {}, handle: {} }}",
+                write.status, write.conn_id, write.handle
+            ),
+            RawGattClientEvent::ReadDescriptor(read) => write!(
+                f,
+                "ReadDescriptor {{ status: {}, conn_id: {}, handle: {}, len: {} }}",
+                read.status, read.conn_id, read.handle, read.value_len
+            ),
+            RawGattClientEvent::WriteDescriptor(write) => write!(
+                f,
+                "WriteDescriptor {{ status: {}, conn_id: {}, handle: {} }}",
+                write.status, write.conn_id, write.handle
+            ),
+            RawGattClientEvent::SearchComplete(cmpl) => write!(
+                f,
+                "SearchComplete {{ status: {}, conn_id: {} }}",
+                cmpl.status, cmpl.conn_id
+            ),
+            RawGattClientEvent::SearchResult(res) => write!(
+                f,
+                "SearchResult {{ conn_id: {}, start_handle: {}, end_handle: {} }}",
+                res.conn_id, res.start_handle, res.end_handle
+            ),
+            RawGattClientEvent::Notify(_) => write!(f, "Notify"),
+            RawGattClientEvent::PrepareWrite(_) => write!(f, "PrepareWrite"),
+            RawGattClientEvent::ExecWrite(_) => write!(f, "ExecWrite"),
+            RawGattClientEvent::ServiceChanged(_) => write!(f, "ServiceChanged"),
+            RawGattClientEvent::ConfigureMtu(_) => write!(f, "ConfigureMtu"),
+            RawGattClientEvent::Congest(_) => write!(f, "Congest"),
+            RawGattClientEvent::RegisterForNotify(_) => write!(f, "RegisterForNotify"),
+            RawGattClientEvent::UnregisterForNotify(_) => write!(f, "UnregisterForNotify"),
+            RawGattClientEvent::Connect(_) => write!(f, "Connect"),
+            RawGattClientEvent::Disconnect(_) => write!(f, "Disconnect"),
+            RawGattClientEvent::ReadMultiple(_) => write!(f, "ReadMultiple"),
+            RawGattClientEvent::QueueFull(_) => write!(f, "QueueFull"),
+            RawGattClientEvent::SetAssociationComplete(_) => write!(f, "SetAssociationComplete"),
+            RawGattClientEvent::GetAddressListComplete(_) => write!(f, "GetAddressListComplete"),
+            RawGattClientEvent::DiscoverServicesComplete(_) => {
+                write!(f, "DiscoverServicesComplete")
+            }
+            RawGattClientEvent::Unknown(evt) => write!(f, "Unknown({})", evt),
+        }
+    }
+}
+
+#[cfg(target_os = "espidf")]
+impl RawGattClientEvent {
+    /// The event, `None` when Bluedroid reports it without its parameters.
+    pub(crate) unsafe fn build(
+        event: esp_idf_sys::esp_gattc_cb_event_t,
+        param: *mut esp_idf_sys::esp_ble_gattc_cb_param_t,
+    ) -> Option<Self> {
+        let param: &esp_idf_sys::esp_ble_gattc_cb_param_t = match param.as_ref() {
+            Some(param) => param,
+            None => {
+                log::warn!("GATT client event {} without parameters", event);
+                return None;
+            }
+        };
+        Some(match event {
+            esp_idf_sys::esp_gattc_cb_event_t_ESP_GATTC_REG_EVT => {
+                RawGattClientEvent::Register(param.reg)
+            }
+            esp_idf_sys::esp_gattc_cb_event_t_ESP_GATTC_UNREG_EVT => RawGattClientEvent::Unregister,
+            esp_idf_sys::esp_gattc_cb_event_t_ESP_GATTC_OPEN_EVT => {
+                RawGattClientEvent::Open(param.open)
+            }
+            esp_idf_sys::esp_gattc_cb_event_t_ESP_GATTC_CLOSE_EVT => {
+                RawGattClientEvent::Close(param.close)
+            }
+            esp_idf_sys::esp_gattc_cb_event_t_ESP_GATTC_READ_CHAR_EVT => {
+                RawGattClientEvent::ReadCharacteristic(param.read)
+            }
+            esp_idf_sys::esp_gattc_cb_event_t_ESP_GATTC_WRITE_CHAR_EVT => {
+                RawGattClientEvent::WriteCharacteristic(param.write)
+            }
+            esp_idf_sys::esp_gattc_cb_event_t_ESP_GATTC_READ_DESCR_EVT => {
+                RawGattClientEvent::ReadDescriptor(param.read)
+            }
+            esp_idf_sys::esp_gattc_cb_event_t_ESP_GATTC_WRITE_DESCR_EVT => {
+                RawGattClientEvent::WriteDescriptor(param.write)
+            }
+            esp_idf_sys::esp_gattc_cb_event_t_ESP_GATTC_SEARCH_CMPL_EVT => {
+                RawGattClientEvent::SearchComplete(param.search_cmpl)
+            }
+            esp_idf_sys::esp_gattc_cb_event_t_ESP_GATTC_SEARCH_RES_EVT => {
+                RawGattClientEvent::SearchResult(param.search_res)
+            }
+            esp_idf_sys::esp_gattc_cb_event_t_ESP_GATTC_NOTIFY_EVT => {
+                RawGattClientEvent::Notify(param.notify)
+            }
+            esp_idf_sys::esp_gattc_cb_event_t_ESP_GATTC_PREP_WRITE_EVT => {
+                RawGattClientEvent::PrepareWrite(param.write)
+            }
+            esp_idf_sys::esp_gattc_cb_event_t_ESP_GATTC_EXEC_EVT => {
+                RawGattClientEvent::ExecWrite(param.exec_cmpl)
+            }
+            esp_idf_sys::esp_gattc_cb_event_t_ESP_GATTC_SRVC_CHG_EVT => {
+                RawGattClientEvent::ServiceChanged(param.srvc_chg)
+            }
+            esp_idf_sys::esp_gattc_cb_event_t_ESP_GATTC_CFG_MTU_EVT => {
+                RawGattClientEvent::ConfigureMtu(param.cfg_mtu)
+            }
+            esp_idf_sys::esp_gattc_cb_event_t_ESP_GATTC_CONGEST_EVT => {
+                RawGattClientEvent::Congest(param.congest)
+            }
+            esp_idf_sys::esp_gattc_cb_event_t_ESP_GATTC_REG_FOR_NOTIFY_EVT => {
+                RawGattClientEvent::RegisterForNotify(param.reg_for_notify)
+            }
+            esp_idf_sys::esp_gattc_cb_event_t_ESP_GATTC_UNREG_FOR_NOTIFY_EVT => {
+                RawGattClientEvent::UnregisterForNotify(param.unreg_for_notify)
+            }
+            esp_idf_sys::esp_gattc_cb_event_t_ESP_GATTC_CONNECT_EVT => {
+                RawGattClientEvent::Connect(param.connect)
+            }
+            esp_idf_sys::esp_gattc_cb_event_t_ESP_GATTC_DISCONNECT_EVT => {
+                RawGattClientEvent::Disconnect(param.disconnect)
+            }
+            esp_idf_sys::esp_gattc_cb_event_t_ESP_GATTC_READ_MULTIPLE_EVT => {
+                RawGattClientEvent::ReadMultiple(param.read)
+            }
+            esp_idf_sys::esp_gattc_cb_event_t_ESP_GATTC_QUEUE_FULL_EVT => {
+                RawGattClientEvent::QueueFull(param.queue_full)
+            }
+            esp_idf_sys::esp_gattc_cb_event_t_ESP_GATTC_SET_ASSOC_EVT => {
+                RawGattClientEvent::SetAssociationComplete(param.set_assoc_cmp)
+            }
+            esp_idf_sys::esp_gattc_cb_event_t_ESP_GATTC_GET_ADDR_LIST_EVT => {
+                RawGattClientEvent::GetAddressListComplete(param.get_addr_list)
+            }
+            esp_idf_sys::esp_gattc_cb_event_t_ESP_GATTC_DIS_SRVC_CMPL_EVT => {
+                RawGattClientEvent::DiscoverServicesComplete(param.dis_srvc_cmpl)
+            }
+            _ => {
+                log::warn!("Unhandled event: {:?}", event);
+                RawGattClientEvent::Unknown(event)
+            }
+        })
+    }
+}
+
+#[cfg(target_os = "espidf")]
+fn read_result(read: esp_ble_gattc_cb_param_t_gattc_read_char_evt_param) -> ReadResultEvent {
+    ReadResultEvent {
+        status: read.status.into(),
+        conn_id: read.conn_id,
+        handle: read.handle,
+        value: unsafe { copy_event_buffer(read.value, read.value_len as usize) },
+    }
+}
+
+#[cfg(target_os = "espidf")]
+fn write_result(write: esp_ble_gattc_cb_param_t_gattc_write_evt_param) -> WriteResultEvent {
+    WriteResultEvent {
+        status: write.status.into(),
+        conn_id: write.conn_id,
+        handle: write.handle,
+        offset: write.offset,
+    }
+}
+
+#[cfg(target_os = "espidf")]
+impl From<RawGattClientEvent> for GattClientEvent {
+    fn from(raw: RawGattClientEvent) -> Self {
+        match raw {
+            RawGattClientEvent::Register(reg) => GattClientEvent::Register(RegisterEvent {
+                status: reg.status.into(),
+                app_id: reg.app_id,
+            }),
+            RawGattClientEvent::Unregister => GattClientEvent::Unregister,
+            RawGattClientEvent::Open(open) => GattClientEvent::Open(OpenEvent {
+                status: open.status.into(),
+                conn_id: open.conn_id,
+                bda: open.remote_bda.into(),
+                mtu: open.mtu,
+            }),
+            RawGattClientEvent::Close(close) => GattClientEvent::Close(ClientCloseEvent {
+                status: close.status.into(),
+                conn_id: close.conn_id,
+                bda: close.remote_bda.into(),
+                reason: close.reason.into(),
+            }),
+            RawGattClientEvent::ReadCharacteristic(read) => {
+                GattClientEvent::ReadCharacteristic(read_result(read))
+            }
+            RawGattClientEvent::WriteCharacteristic(write) => {
+                GattClientEvent::WriteCharacteristic(write_result(write))
+            }
+            RawGattClientEvent::ReadDescriptor(read) => {
+                GattClientEvent::ReadDescriptor(read_result(read))
+            }
+            RawGattClientEvent::WriteDescriptor(write) => {
+                GattClientEvent::WriteDescriptor(write_result(write))
+            }
+            RawGattClientEvent::SearchComplete(cmpl) => {
+                GattClientEvent::SearchComplete(ClientStatusEvent {
+                    status: cmpl.status.into(),
+                    conn_id: cmpl.conn_id,
+                })
+            }
+            RawGattClientEvent::SearchResult(res) => {
+                GattClientEvent::SearchResult(SearchResultEvent {
+                    conn_id: res.conn_id,
+                    start_handle: res.start_handle,
+                    end_handle: res.end_handle,
+                    uuid: res.srvc_id.uuid.into(),
+                    instance_id: res.srvc_id.inst_id,
+                    is_primary: res.is_primary,
+                })
+            }
+            RawGattClientEvent::Notify(notify) => GattClientEvent::Notify(NotifyEvent {
+                conn_id: notify.conn_id,
+                bda: notify.remote_bda.into(),
+                handle: notify.handle,
+                is_notify: notify.is_notify,
+                value: unsafe { copy_event_buffer(notify.value, notify.value_len as usize) },
+            }),
+            RawGattClientEvent::PrepareWrite(write) => {
+                GattClientEvent::PrepareWrite(write_result(write))
+            }
+            RawGattClientEvent::ExecWrite(exec) => GattClientEvent::ExecWrite(ClientStatusEvent {
+                status: exec.status.into(),
+                conn_id: exec.conn_id,
+            }),
+            RawGattClientEvent::ServiceChanged(chg) => {
+                GattClientEvent::ServiceChanged(chg.remote_bda.into())
+            }
+            RawGattClientEvent::ConfigureMtu(mtu) => {
+                GattClientEvent::ConfigureMtu(ClientMtuEvent {
+                    status: mtu.status.into(),
+                    conn_id: mtu.conn_id,
+                    mtu: mtu.mtu,
+                })
+            }
+            RawGattClientEvent::Congest(congest) => GattClientEvent::Congest(CongestEvent {
+                conn_id: congest.conn_id,
+                congested: congest.congested,
+            }),
+            RawGattClientEvent::RegisterForNotify(reg) => {
+                GattClientEvent::RegisterForNotify(NotifyRegistrationEvent {
+                    status: reg.status.into(),
+                    handle: reg.handle,
+                })
+            }
+            RawGattClientEvent::UnregisterForNotify(unreg) => {
+                GattClientEvent::UnregisterForNotify(NotifyRegistrationEvent {
+                    status: unreg.status.into(),
+                    handle: unreg.handle,
+                })
+            }
+            RawGattClientEvent::Connect(conn) => GattClientEvent::Connect(ConnectEvent {
+                conn_id: conn.conn_id,
+                link_role: if conn.link_role == 0 {
+                    LinkRole::Central
+                } else {
+                    LinkRole::Peripheral
+                },
+                bda: conn.remote_bda.into(),
+                interval: conn.conn_params.interval,
+                latency: conn.conn_params.latency,
+                timeout: conn.conn_params.timeout,
+            }),
+            RawGattClientEvent::Disconnect(disconn) => {
+                GattClientEvent::Disconnect(DisconnectEvent {
+                    conn_id: disconn.conn_id,
+                    bda: disconn.remote_bda.into(),
+                    reason: disconn.reason.into(),
+                })
+            }
+            RawGattClientEvent::ReadMultiple(read) => {
+                GattClientEvent::ReadMultiple(read_result(read))
+            }
+            RawGattClientEvent::QueueFull(full) => GattClientEvent::QueueFull(QueueFullEvent {
+                status: full.status.into(),
+                conn_id: full.conn_id,
+                is_full: full.is_full,
+            }),
+            RawGattClientEvent::SetAssociationComplete(assoc) => {
+                GattClientEvent::SetAssociationComplete(assoc.status.into())
+            }
+            RawGattClientEvent::GetAddressListComplete(list) => {
+                GattClientEvent::GetAddressListComplete(AddressListEvent {
+                    status: list.status.into(),
+                    addresses: unsafe { copy_event_buffer(list.addr_list, list.num_addr as usize) }
+                        .into_iter()
+                        .map(BdAddr::from)
+                        .collect(),
+                })
+            }
+            RawGattClientEvent::DiscoverServicesComplete(cmpl) => {
+                GattClientEvent::DiscoverServicesComplete(ClientStatusEvent {
+                    status: cmpl.status.into(),
+                    conn_id: cmpl.conn_id,
+                })
+            }
+            RawGattClientEvent::Unknown(evt) => GattClientEvent::Unknown(evt),
+        }
+    }
+}
@@ -1,8 +1,9 @@
+#[cfg(target_os = "espidf")]
 use esp_idf_sys::*;
 
-use crate::BtUuid;
+use crate::{BdAddr, BtUuid, DisconnectReason, GattStatus};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct GattService {
     pub(crate) is_primary: bool,
     pub(crate) id: BtUuid,
@@ -30,8 +31,179 @@ impl GattService {
     }
 }
 
-#[derive(Copy, Clone)]
+/// Event reported by the GATT server.
+///
+/// The raw Bluedroid event is available through [`RawGattServiceEvent`] and
+/// `EspBle::register_gatts_raw_handler`.
+#[derive(Clone, Debug, PartialEq)]
 pub enum GattServiceEvent {
+    Register(RegisterEvent),
+    Read(ReadEvent),
+    Write(WriteEvent),
+    ExecWrite(ExecWriteEvent),
+    Mtu(MtuEvent),
+    Confirm(ConfirmEvent),
+    Unregister,
+    Create(CreateServiceEvent),
+    AddIncludedServiceComplete(AddAttributeEvent),
+    AddCharacteristicComplete(AddAttributeEvent),
+    AddDescriptorComplete(AddAttributeEvent),
+    DeleteComplete(ServiceEvent),
+    StartComplete(ServiceEvent),
+    StopComplete(ServiceEvent),
+    Connect(ConnectEvent),
+    Disconnect(DisconnectEvent),
+    Open(GattStatus),
+    Close(CloseEvent),
+    Listen,
+    Congest(CongestEvent),
+    ResponseComplete(ResponseEvent),
+    CreateAttributeTableComplete(AttributeTableEvent),
+    SetAttributeValueComplete(SetAttributeValueEvent),
+    SendServiceChangeComplete(GattStatus),
+    /// An event this crate does not know about, with its raw event code.
+    Unknown(u32),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RegisterEvent {
+    pub status: GattStatus,
+    pub app_id: u16,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReadEvent {
+    pub conn_id: u16,
+    pub trans_id: u32,
+    pub bda: BdAddr,
+    pub handle: u16,
+    pub offset: u16,
+    pub is_long: bool,
+    pub need_rsp: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WriteEvent {
+    pub conn_id: u16,
+    pub trans_id: u32,
+    pub bda: BdAddr,
+    pub handle: u16,
+    pub offset: u16,
+    pub need_rsp: bool,
+    pub is_prep: bool,
+    pub value: Vec<u8>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExecWriteEvent {
+    pub conn_id: u16,
+    pub trans_id: u32,
+    pub bda: BdAddr,
+    /// `false` when the prepared writes are cancelled
+    pub execute: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MtuEvent {
+    pub conn_id: u16,
+    pub mtu: u16,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfirmEvent {
+    pub status: GattStatus,
+    pub conn_id: u16,
+    pub handle: u16,
+    pub value: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CreateServiceEvent {
+    pub status: GattStatus,
+    pub service_handle: u16,
+    pub service_uuid: BtUuid,
+    pub is_primary: bool,
+    pub instance_id: u8,
+}
+
+/// Completion of the addition of an included service, a characteristic or a
+/// descriptor. `uuid` is `None` for an included service.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AddAttributeEvent {
+    pub status: GattStatus,
+    pub service_handle: u16,
+    pub attr_handle: u16,
+    pub uuid: Option<BtUuid>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ServiceEvent {
+    pub status: GattStatus,
+    pub service_handle: u16,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkRole {
+    Central,
+    Peripheral,
+}
+
+/// A new connection, intervals in 1.25ms units and timeout in 10ms units.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConnectEvent {
+    pub conn_id: u16,
+    pub link_role: LinkRole,
+    pub bda: BdAddr,
+    pub interval: u16,
+    pub latency: u16,
+    pub timeout: u16,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DisconnectEvent {
+    pub conn_id: u16,
+    pub bda: BdAddr,
+    pub reason: DisconnectReason,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CloseEvent {
+    pub status: GattStatus,
+    pub conn_id: u16,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CongestEvent {
+    pub conn_id: u16,
+    pub congested: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ResponseEvent {
+    pub status: GattStatus,
+    pub handle: u16,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AttributeTableEvent {
+    pub status: GattStatus,
+    pub service_uuid: BtUuid,
+    pub service_instance_id: u8,
+    pub handles: Vec<u16>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SetAttributeValueEvent {
+    pub status: GattStatus,
+    pub service_handle: u16,
+    pub attr_handle: u16,
+}
+
+/// Event as received from Bluedroid, its raw parameters are only valid during
+/// the call of the raw handler.
+#[cfg(target_os = "espidf")]
+#[derive(Copy, Clone)]
+pub enum RawGattServiceEvent {
     Register(esp_ble_gatts_cb_param_t_gatts_reg_evt_param),
     Read(esp_ble_gatts_cb_param_t_gatts_read_evt_param),
     Write(esp_ble_gatts_cb_param_t_gatts_write_evt_param),
@@ -56,134 +228,309 @@ pub enum GattServiceEvent {
     CreateAttributeTableComplete(esp_ble_gatts_cb_param_t_gatts_add_attr_tab_evt_param),
     SetAttributeValueComplete(esp_ble_gatts_cb_param_t_gatts_set_attr_val_evt_param),
     SendServiceChangeComplete(esp_ble_gatts_cb_param_t_gatts_send_service_change_evt_param),
+    /// An event this crate does not know about, with its raw event code.
+    Unknown(u32),
 }
 
-impl std::fmt::Debug for GattServiceEvent {
+#[cfg(target_os = "espidf")]
+impl std::fmt::Debug for RawGattServiceEvent {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
         match self {
-            GattServiceEvent::Register(reg) => write!(
+            RawGattServiceEvent::Register(reg) => write!(
                 f,
                 "Register {{ status: {}, app_id: {} }}",
                 reg.status, reg.app_id
             ),
-            GattServiceEvent::Read(read) => write!(f, "Read {{ {:?} }}", read),
-            GattServiceEvent::Write(write) => write!(f, "Write {{ {:?} }}", write),
-            GattServiceEvent::ExecWrite(_) => write!(f, "ExecWrite"),
-            GattServiceEvent::Mtu(_) => write!(f, "Mtu"),
-            GattServiceEvent::Confirm(_) => write!(f, "Confirm"),
-            GattServiceEvent::Unregister(_) => write!(f, "Unregister"),
-            GattServiceEvent::Create(_) => write!(f, "Create"),
-            GattServiceEvent::AddIncludedServiceComplete(_) => {
+            RawGattServiceEvent::Read(read) => write!(f, "Read {{ {:?} }}", read),
+            RawGattServiceEvent::Write(write) => write!(f, "Write {{ {:?} }}", write),
+            RawGattServiceEvent::ExecWrite(_) => write!(f, "ExecWrite"),
+            RawGattServiceEvent::Mtu(_) => write!(f, "Mtu"),
+            RawGattServiceEvent::Confirm(_) => write!(f, "Confirm"),
+            RawGattServiceEvent::Unregister(_) => write!(f, "Unregister"),
+            RawGattServiceEvent::Create(_) => write!(f, "Create"),
+            RawGattServiceEvent::AddIncludedServiceComplete(_) => {
                 write!(f, "AddIncludedServiceComplete")
             }
-            GattServiceEvent::AddCharacteristicComplete(_) => {
+            RawGattServiceEvent::AddCharacteristicComplete(_) => {
                 write!(f, "AddCharacteristicComplete")
             }
-            GattServiceEvent::AddDescriptorComplete(_) => write!(f, "AddDescriptorComplete"),
-            GattServiceEvent::DeleteComplete(_) => write!(f, "DeleteComplete"),
-            GattServiceEvent::StartComplete(_) => write!(f, "StartComplete"),
-            GattServiceEvent::StopComplete(_) => write!(f, "StopComplete"),
-            GattServiceEvent::Connect(_) => write!(f, "Connect"),
-            GattServiceEvent::Disconnect(_) => write!(f, "Disconnect"),
-            GattServiceEvent::Open(_) => write!(f, "Open"),
-            GattServiceEvent::Close(_) => write!(f, "Close"),
-            GattServiceEvent::Listen(_) => write!(f, "Listen"),
-            GattServiceEvent::Congest(_) => write!(f, "Congest"),
-            GattServiceEvent::ResponseComplete(_) => write!(f, "ResponseComplete"),
-            GattServiceEvent::CreateAttributeTableComplete(_) => {
+            RawGattServiceEvent::AddDescriptorComplete(_) => write!(f, "AddDescriptorComplete"),
+            RawGattServiceEvent::DeleteComplete(_) => write!(f, "DeleteComplete"),
+            RawGattServiceEvent::StartComplete(_) => write!(f, "StartComplete"),
+            RawGattServiceEvent::StopComplete(_) => write!(f, "StopComplete"),
+            RawGattServiceEvent::Connect(_) => write!(f, "Connect"),
+            RawGattServiceEvent::Disconnect(_) => write!(f, "Disconnect"),
+            RawGattServiceEvent::Open(_) => write!(f, "Open"),
+            RawGattServiceEvent::Close(_) => write!(f, "Close"),
+            RawGattServiceEvent::Listen(_) => write!(f, "Listen"),
+            RawGattServiceEvent::Congest(_) => write!(f, "Congest"),
+            RawGattServiceEvent::ResponseComplete(_) => write!(f, "ResponseComplete"),
+            RawGattServiceEvent::CreateAttributeTableComplete(_) => {
                 write!(f, "CreateAttributeTableComplete")
             }
-            GattServiceEvent::SetAttributeValueComplete(_) => {
+            RawGattServiceEvent::SetAttributeValueComplete(_) => {
                 write!(f, "SetAttributeValueComplete")
             }
-            GattServiceEvent::SendServiceChangeComplete(_) => {
+            RawGattServiceEvent::SendServiceChangeComplete(_) => {
                 write!(f, "SendServiceChangeComplete")
             }
+            RawGattServiceEvent::Unknown(evt) => write!(f, "Unknown({})", evt),
         }
     }
 }
 
-impl GattServiceEvent {
+#[cfg(target_os = "espidf")]
+impl RawGattServiceEvent {
     pub(crate) unsafe fn build(
         event: esp_idf_sys::esp_gatts_cb_event_t,
         param: *mut esp_idf_sys::esp_ble_gatts_cb_param_t,
-    ) -> Self {
-        let param: &esp_idf_sys::esp_ble_gatts_cb_param_t = param.as_ref().unwrap();
-        match event {
+    ) -> Option<Self> {
+        let param: &esp_idf_sys::esp_ble_gatts_cb_param_t = match param.as_ref() {
+            Some(param) => param,
+            None => {
+                log::warn!("GATT server event {} without parameters", event);
+                return None;
+            }
+        };
+        Some(match event {
             esp_idf_sys::esp_gatts_cb_event_t_ESP_GATTS_REG_EVT => {
-                GattServiceEvent::Register(param.reg)
+                RawGattServiceEvent::Register(param.reg)
             }
             esp_idf_sys::esp_gatts_cb_event_t_ESP_GATTS_READ_EVT => {
-                GattServiceEvent::Read(param.read)
+                RawGattServiceEvent::Read(param.read)
             }
             esp_idf_sys::esp_gatts_cb_event_t_ESP_GATTS_WRITE_EVT => {
-                GattServiceEvent::Write(param.write)
+                RawGattServiceEvent::Write(param.write)
             }
             esp_idf_sys::esp_gatts_cb_event_t_ESP_GATTS_EXEC_WRITE_EVT => {
-                GattServiceEvent::ExecWrite(param.exec_write)
+                RawGattServiceEvent::ExecWrite(param.exec_write)
+            }
+            esp_idf_sys::esp_gatts_cb_event_t_ESP_GATTS_MTU_EVT => {
+                RawGattServiceEvent::Mtu(param.mtu)
             }
-            esp_idf_sys::esp_gatts_cb_event_t_ESP_GATTS_MTU_EVT => GattServiceEvent::Mtu(param.mtu),
             esp_idf_sys::esp_gatts_cb_event_t_ESP_GATTS_CONF_EVT => {
-                GattServiceEvent::Confirm(param.conf)
+                RawGattServiceEvent::Confirm(param.conf)
             }
             esp_idf_sys::esp_gatts_cb_event_t_ESP_GATTS_UNREG_EVT => {
-                GattServiceEvent::Unregister(param.create)
+                RawGattServiceEvent::Unregister(param.create)
             }
             esp_idf_sys::esp_gatts_cb_event_t_ESP_GATTS_CREATE_EVT => {
-                GattServiceEvent::Create(param.create)
+                RawGattServiceEvent::Create(param.create)
             }
             esp_idf_sys::esp_gatts_cb_event_t_ESP_GATTS_ADD_INCL_SRVC_EVT => {
-                GattServiceEvent::AddIncludedServiceComplete(param.add_incl_srvc)
+                RawGattServiceEvent::AddIncludedServiceComplete(param.add_incl_srvc)
             }
             esp_idf_sys::esp_gatts_cb_event_t_ESP_GATTS_ADD_CHAR_EVT => {
-                GattServiceEvent::AddCharacteristicComplete(param.add_char)
+                RawGattServiceEvent::AddCharacteristicComplete(param.add_char)
             }
             esp_idf_sys::esp_gatts_cb_event_t_ESP_GATTS_ADD_CHAR_DESCR_EVT => {
-                GattServiceEvent::AddDescriptorComplete(param.add_char_descr)
+                RawGattServiceEvent::AddDescriptorComplete(param.add_char_descr)
             }
             esp_idf_sys::esp_gatts_cb_event_t_ESP_GATTS_DELETE_EVT => {
-                GattServiceEvent::DeleteComplete(param.del)
+                RawGattServiceEvent::DeleteComplete(param.del)
             }
             esp_idf_sys::esp_gatts_cb_event_t_ESP_GATTS_START_EVT => {
-                GattServiceEvent::StartComplete(param.start)
+                RawGattServiceEvent::StartComplete(param.start)
             }
             esp_idf_sys::esp_gatts_cb_event_t_ESP_GATTS_STOP_EVT => {
-                GattServiceEvent::StopComplete(param.stop)
+                RawGattServiceEvent::StopComplete(param.stop)
             }
             esp_idf_sys::esp_gatts_cb_event_t_ESP_GATTS_CONNECT_EVT => {
-                GattServiceEvent::Connect(param.connect)
+                RawGattServiceEvent::Connect(param.connect)
             }
             esp_idf_sys::esp_gatts_cb_event_t_ESP_GATTS_DISCONNECT_EVT => {
-                GattServiceEvent::Disconnect(param.disconnect)
+                RawGattServiceEvent::Disconnect(param.disconnect)
             }
             esp_idf_sys::esp_gatts_cb_event_t_ESP_GATTS_OPEN_EVT => {
-                GattServiceEvent::Open(param.open)
+                RawGattServiceEvent::Open(param.open)
             }
             esp_idf_sys::esp_gatts_cb_event_t_ESP_GATTS_CLOSE_EVT => {
-                GattServiceEvent::Close(param.close)
+                RawGattServiceEvent::Close(param.close)
             }
             esp_idf_sys::esp_gatts_cb_event_t_ESP_GATTS_LISTEN_EVT => {
-                GattServiceEvent::Listen(param.congest)
+                RawGattServiceEvent::Listen(param.congest)
             }
             esp_idf_sys::esp_gatts_cb_event_t_ESP_GATTS_CONGEST_EVT => {
-                GattServiceEvent::Congest(param.congest)
+                RawGattServiceEvent::Congest(param.congest)
             }
             esp_idf_sys::esp_gatts_cb_event_t_ESP_GATTS_RESPONSE_EVT => {
-                GattServiceEvent::ResponseComplete(param.rsp)
+                RawGattServiceEvent::ResponseComplete(param.rsp)
             }
             esp_idf_sys::esp_gatts_cb_event_t_ESP_GATTS_CREAT_ATTR_TAB_EVT => {
-                GattServiceEvent::CreateAttributeTableComplete(param.add_attr_tab)
+                RawGattServiceEvent::CreateAttributeTableComplete(param.add_attr_tab)
             }
             esp_idf_sys::esp_gatts_cb_event_t_ESP_GATTS_SET_ATTR_VAL_EVT => {
-                GattServiceEvent::SetAttributeValueComplete(param.set_attr_val)
+                RawGattServiceEvent::SetAttributeValueComplete(param.set_attr_val)
             }
             esp_idf_sys::esp_gatts_cb_event_t_ESP_GATTS_SEND_SERVICE_CHANGE_EVT => {
-                GattServiceEvent::SendServiceChangeComplete(param.service_change)
+                RawGattServiceEvent::SendServiceChangeComplete(param.service_change)
             }
             _ => {
                 log::warn!("Unhandled event: {:?}", event);
-                panic!("Unhandled event: {:?}", event)
+                RawGattServiceEvent::Unknown(event)
+            }
+        })
+    }
+}
+
+/// Copy a buffer received with an event, which is only valid during the call
+/// of the event handler.
+#[cfg(target_os = "espidf")]
+pub(crate) unsafe fn copy_event_buffer<T: Copy>(ptr: *const T, len: usize) -> Vec<T> {
+    if ptr.is_null() || len == 0 {
+        vec![]
+    } else {
+        std::slice::from_raw_parts(ptr, len).to_vec()
+    }
+}
+
+#[cfg(target_os = "espidf")]
+impl From<RawGattServiceEvent> for GattServiceEvent {
+    fn from(raw: RawGattServiceEvent) -> Self {
+        match raw {
+            RawGattServiceEvent::Register(reg) => GattServiceEvent::Register(RegisterEvent {
+                status: reg.status.into(),
+                app_id: reg.app_id,
+            }),
+            RawGattServiceEvent::Read(read) => GattServiceEvent::Read(ReadEvent {
+                conn_id: read.conn_id,
+                trans_id: read.trans_id,
+                bda: read.bda.into(),
+                handle: read.handle,
+                offset: read.offset,
+                is_long: read.is_long,
+                need_rsp: read.need_rsp,
+            }),
+            RawGattServiceEvent::Write(write) => GattServiceEvent::Write(WriteEvent {
+                conn_id: write.conn_id,
+                trans_id: write.trans_id,
+                bda: write.bda.into(),
+                handle: write.handle,
+                offset: write.offset,
+                need_rsp: write.need_rsp,
+                is_prep: write.is_prep,
+                value: unsafe { copy_event_buffer(write.value, write.len as usize) },
+            }),
+            RawGattServiceEvent::ExecWrite(exec) => GattServiceEvent::ExecWrite(ExecWriteEvent {
+                conn_id: exec.conn_id,
+                trans_id: exec.trans_id,
+                bda: exec.bda.into(),
+                execute: exec.exec_write_flag != ESP_GATT_PREP_WRITE_CANCEL as u8,
+            }),
+            RawGattServiceEvent::Mtu(mtu) => GattServiceEvent::Mtu(MtuEvent {
+                conn_id: mtu.conn_id,
+                mtu: mtu.mtu,
+            }),
+            RawGattServiceEvent::Confirm(conf) => GattServiceEvent::Confirm(ConfirmEvent {
+                status: conf.status.into(),
+                conn_id: conf.conn_id,
+                handle: conf.handle,
+                value: unsafe { copy_event_buffer(conf.value, conf.len as usize) },
+            }),
+            RawGattServiceEvent::Unregister(_) => GattServiceEvent::Unregister,
+            RawGattServiceEvent::Create(create) => GattServiceEvent::Create(CreateServiceEvent {
+                status: create.status.into(),
+                service_handle: create.service_handle,
+                service_uuid: create.service_id.id.uuid.into(),
+                is_primary: create.service_id.is_primary,
+                instance_id: create.service_id.id.inst_id,
+            }),
+            RawGattServiceEvent::AddIncludedServiceComplete(add) => {
+                GattServiceEvent::AddIncludedServiceComplete(AddAttributeEvent {
+                    status: add.status.into(),
+                    service_handle: add.service_handle,
+                    attr_handle: add.attr_handle,
+                    uuid: None,
+                })
+            }
+            RawGattServiceEvent::AddCharacteristicComplete(add) => {
+                GattServiceEvent::AddCharacteristicComplete(AddAttributeEvent {
+                    status: add.status.into(),
+                    service_handle: add.service_handle,
+                    attr_handle: add.attr_handle,
+                    uuid: Some(add.char_uuid.into()),
+                })
+            }
+            RawGattServiceEvent::AddDescriptorComplete(add) => {
+                GattServiceEvent::AddDescriptorComplete(AddAttributeEvent {
+                    status: add.status.into(),
+                    service_handle: add.service_handle,
+                    attr_handle: add.attr_handle,
+                    uuid: Some(add.descr_uuid.into()),
+                })
+            }
+            RawGattServiceEvent::DeleteComplete(del) => {
+                GattServiceEvent::DeleteComplete(ServiceEvent {
+                    status: del.status.into(),
+                    service_handle: del.service_handle,
+                })
+            }
+            RawGattServiceEvent::StartComplete(start) => {
+                GattServiceEvent::StartComplete(ServiceEvent {
+                    status: start.status.into(),
+                    service_handle: start.service_handle,
+                })
+            }
+            RawGattServiceEvent::StopComplete(stop) => {
+                GattServiceEvent::StopComplete(ServiceEvent {
+                    status: stop.status.into(),
+                    service_handle: stop.service_handle,
+                })
+            }
+            RawGattServiceEvent::Connect(conn) => GattServiceEvent::Connect(ConnectEvent {
+                conn_id: conn.conn_id,
+                link_role: if conn.link_role == 0 {
+                    LinkRole::Central
+                } else {
+                    LinkRole::Peripheral
+                },
+                bda: conn.remote_bda.into(),
+                interval: conn.conn_params.interval,
+                latency: conn.conn_params.latency,
+                timeout: conn.conn_params.timeout,
+            }),
+            RawGattServiceEvent::Disconnect(disconn) => {
+                GattServiceEvent::Disconnect(DisconnectEvent {
+                    conn_id: disconn.conn_id,
+                    bda: disconn.remote_bda.into(),
+                    reason: disconn.reason.into(),
+                })
+            }
+            RawGattServiceEvent::Open(open) => GattServiceEvent::Open(open.status.into()),
+            RawGattServiceEvent::Close(close) => GattServiceEvent::Close(CloseEvent {
+                status: close.status.into(),
+                conn_id: close.conn_id,
+            }),
+            RawGattServiceEvent::Listen(_) => GattServiceEvent::Listen,
+            RawGattServiceEvent::Congest(congest) => GattServiceEvent::Congest(CongestEvent {
+                conn_id: congest.conn_id,
+                congested: congest.congested,
+            }),
+            RawGattServiceEvent::ResponseComplete(rsp) => {
+                GattServiceEvent::ResponseComplete(ResponseEvent {
+                    status: rsp.status.into(),
+                    handle: rsp.handle,
+                })
+            }
+            RawGattServiceEvent::CreateAttributeTableComplete(tab) => {
+                GattServiceEvent::CreateAttributeTableComplete(AttributeTableEvent {
+                    status: tab.status.into(),
+                    service_uuid: tab.svc_uuid.into(),
+                    service_instance_id: tab.svc_inst_id,
+                    handles: unsafe { copy_event_buffer(tab.handles, tab.num_handle as usize) },
+                })
+            }
+            RawGattServiceEvent::SetAttributeValueComplete(set) => {
+                GattServiceEvent::SetAttributeValueComplete(SetAttributeValueEvent {
+                    status: set.status.into(),
+                    service_handle: set.srvc_handle,
+                    attr_handle: set.attr_handle,
+                })
+            }
+            RawGattServiceEvent::SendServiceChangeComplete(change) => {
+                GattServiceEvent::SendServiceChangeComplete(change.status.into())
             }
+            RawGattServiceEvent::Unknown(evt) => GattServiceEvent::Unknown(evt),
         }
     }
 }
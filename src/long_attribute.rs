@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+
+use crate::{ExecWriteEvent, GattStatus, ReadEvent, WriteEvent};
+
+/// Longest value of an attribute, prepared writes can't go past it.
+pub const ATT_MAX_VALUE_LEN: usize = 512;
+
+/// Bytes a connection can queue with prepared writes until it executes them.
+const PREPARE_QUEUE_SIZE: usize = 2 * ATT_MAX_VALUE_LEN;
+
+/// Size of the header of a read response, the value gets the rest of the MTU.
+const READ_RESPONSE_HEADER_LEN: usize = 1;
+
+/// Part of `value` answering a read at `offset` on a connection with `mtu`.
+pub(crate) fn read_response(value: &[u8], offset: u16, mtu: u16) -> Result<&[u8], GattStatus> {
+    let value = value
+        .get(usize::from(offset)..)
+        .ok_or(GattStatus::InvalidOffset)?;
+    let len = usize::from(mtu).saturating_sub(READ_RESPONSE_HEADER_LEN);
+    Ok(&value[..value.len().min(len)])
+}
+
+/// What the GATT server needs to serve values longer than the MTU: the offset
+/// of the reads waiting for their response, and the prepared writes of each
+/// connection until the client executes or cancels them.
+#[derive(Default)]
+pub(crate) struct LongAttributeState {
+    /// Offset of the read requests by connection and transaction.
+    reads: HashMap<(u16, u32), u16>,
+    /// Prepared writes of each connection, in the order they came.
+    prepared: HashMap<u16, Vec<WriteEvent>>,
+}
+
+impl LongAttributeState {
+    pub(crate) fn read(&mut self, read: &ReadEvent) {
+        if read.need_rsp {
+            self.reads
+                .insert((read.conn_id, read.trans_id), read.offset);
+        }
+    }
+
+    /// Offset of the read request `trans_id`, `None` when it is not a read.
+    pub(crate) fn take_read(&mut self, conn_id: u16, trans_id: u32) -> Option<u16> {
+        self.reads.remove(&(conn_id, trans_id))
+    }
+
+    /// Queue a prepared write until its connection executes it.
+    pub(crate) fn prepare(&mut self, write: &WriteEvent) -> Result<(), GattStatus> {
+        let queue = self.prepared.entry(write.conn_id).or_default();
+        let queued: usize = queue.iter().map(|write| write.value.len()).sum();
+        if queued + write.value.len() > PREPARE_QUEUE_SIZE {
+            return Err(GattStatus::PrepareQueueFull);
+        }
+        queue.push(write.clone());
+        Ok(())
+    }
+
+    /// Empty the queue of the connection of `exec`, and return the value the
+    /// prepared writes make for each attribute when they are executed.
+    ///
+    /// The writes to an attribute must follow each other without gaps from
+    /// the offset of the first one, which is the offset of the returned write.
+    pub(crate) fn execute(&mut self, exec: &ExecWriteEvent) -> Result<Vec<WriteEvent>, GattStatus> {
+        let prepared = self.prepared.remove(&exec.conn_id).unwrap_or_default();
+        if !exec.execute {
+            return Ok(vec![]);
+        }
+
+        let mut writes: Vec<WriteEvent> = vec![];
+        for prepared in prepared {
+            let write = match writes
+                .iter_mut()
+                .find(|write| write.handle == prepared.handle)
+            {
+                Some(write) => write,
+                None => {
+                    writes.push(WriteEvent {
+                        trans_id: exec.trans_id,
+                        need_rsp: false,
+                        is_prep: false,
+                        ..prepared
+                    });
+                    continue;
+                }
+            };
+            let start = usize::from(prepared.offset)
+                .checked_sub(usize::from(write.offset))
+                .filter(|start| *start <= write.value.len())
+                .ok_or(GattStatus::InvalidOffset)?;
+            let end = start + prepared.value.len();
+            if end > write.value.len() {
+                write.value.resize(end, 0);
+            }
+            write.value[start..end].copy_from_slice(&prepared.value);
+        }
+
+        if writes
+            .iter()
+            .any(|write| usize::from(write.offset) + write.value.len() > ATT_MAX_VALUE_LEN)
+        {
+            return Err(GattStatus::InvalidAttributeLength);
+        }
+        Ok(writes)
+    }
+
+    pub(crate) fn disconnected(&mut self, conn_id: u16) {
+        self.reads
+            .retain(|(read_conn_id, _), _| *read_conn_id != conn_id);
+        self.prepared.remove(&conn_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BdAddr;
+
+    const PEER: BdAddr = BdAddr([0x11, 0x22, 0x33, 0x44, 0x55, 0x66]);
+
+    fn prepare(handle: u16, offset: u16, value: &[u8]) -> WriteEvent {
+        WriteEvent {
+            conn_id: 0,
+            trans_id: u32::from(offset),
+            bda: PEER,
+            handle,
+            offset,
+            need_rsp: true,
+            is_prep: true,
+            value: value.to_vec(),
+        }
+    }
+
+    fn exec(execute: bool) -> ExecWriteEvent {
+        ExecWriteEvent {
+            conn_id: 0,
+            trans_id: 9,
+            bda: PEER,
+            execute,
+        }
+    }
+
+    #[test]
+    fn reads_are_sliced() {
+        let value: Vec<u8> = (0..50).collect();
+
+        assert_eq!(read_response(&value, 0, 23), Ok(&value[..22]));
+        assert_eq!(read_response(&value, 44, 23), Ok(&value[44..]));
+        assert_eq!(read_response(&value, 50, 23), Ok(&[][..]));
+        assert_eq!(
+            read_response(&value, 51, 23),
+            Err(GattStatus::InvalidOffset)
+        );
+    }
+
+    #[test]
+    fn prepared_writes_are_assembled() {
+        let mut state = LongAttributeState::default();
+        state.prepare(&prepare(42, 0, &[1, 2, 3])).unwrap();
+        state.prepare(&prepare(45, 2, &[7])).unwrap();
+        state.prepare(&prepare(42, 3, &[4, 5])).unwrap();
+        state.prepare(&prepare(42, 1, &[6])).unwrap();
+
+        let write = |handle, offset, value: &[u8]| WriteEvent {
+            trans_id: 9,
+            need_rsp: false,
+            is_prep: false,
+            ..prepare(handle, offset, value)
+        };
+        assert_eq!(
+            state.execute(&exec(true)),
+            Ok(vec![write(42, 0, &[1, 6, 3, 4, 5]), write(45, 2, &[7])])
+        );
+        assert_eq!(state.execute(&exec(true)), Ok(vec![]));
+    }
+
+    #[test]
+    fn prepared_writes_are_cancelled() {
+        let mut state = LongAttributeState::default();
+        state.prepare(&prepare(42, 0, &[1, 2, 3])).unwrap();
+
+        assert_eq!(state.execute(&exec(false)), Ok(vec![]));
+        assert_eq!(state.execute(&exec(true)), Ok(vec![]));
+    }
+
+    #[test]
+    fn prepared_writes_are_checked() {
+        let mut state = LongAttributeState::default();
+        state.prepare(&prepare(42, 0, &[1, 2, 3])).unwrap();
+        state.prepare(&prepare(42, 4, &[4])).unwrap();
+        assert_eq!(state.execute(&exec(true)), Err(GattStatus::InvalidOffset));
+
+        state.prepare(&prepare(42, 500, &[0; 20])).unwrap();
+        assert_eq!(
+            state.execute(&exec(true)),
+            Err(GattStatus::InvalidAttributeLength)
+        );
+
+        for offset in [0, 512] {
+            state.prepare(&prepare(42, offset, &[0; 512])).unwrap();
+        }
+        assert_eq!(
+            state.prepare(&prepare(42, 1024, &[0])),
+            Err(GattStatus::PrepareQueueFull)
+        );
+        state.disconnected(0);
+        assert_eq!(state.execute(&exec(true)), Ok(vec![]));
+    }
+}
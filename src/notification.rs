@@ -0,0 +1,568 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::{BdAddr, BtUuid, GattServiceEvent};
+
+/// UUID of the Client Characteristic Configuration descriptor
+pub const CCCD_UUID: u16 = 0x2902;
+
+/// ATT MTU of a connection until the client negotiates a larger one
+pub const ATT_DEFAULT_MTU: u16 = 23;
+
+/// Largest ATT MTU the stack can exchange with a client
+pub const ATT_MAX_MTU: u16 = 517;
+
+/// Size of the header of a notification or an indication, the value gets the
+/// rest of the MTU.
+const NOTIFICATION_HEADER_LEN: usize = 3;
+
+/// Value written by a client in a Client Characteristic Configuration descriptor.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ClientConfiguration {
+    pub notifications: bool,
+    pub indications: bool,
+}
+
+impl ClientConfiguration {
+    /// Decode the 2 bytes little endian value of the descriptor.
+    pub fn from_value(value: &[u8]) -> Option<Self> {
+        match value {
+            [bits, 0] => Some(Self {
+                notifications: bits & 0x01 != 0,
+                indications: bits & 0x02 != 0,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn to_value(self) -> [u8; 2] {
+        [
+            u8::from(self.notifications) | u8::from(self.indications) << 1,
+            0,
+        ]
+    }
+}
+
+/// A client changed its subscription to a characteristic, by writing its CCCD
+/// or by reconnecting as a bonded peer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SubscriptionEvent {
+    pub conn_id: u16,
+    pub bda: BdAddr,
+    /// Handle of the characteristic, not of its CCCD
+    pub handle: u16,
+    pub configuration: ClientConfiguration,
+}
+
+/// Where the subscriptions of the bonded peers are kept between their
+/// connections, `EspBle::new` uses the nvs namespace it is given.
+pub(crate) trait SubscriptionStore: Send + Sync {
+    fn load(&self, bda: BdAddr) -> Option<Vec<u8>>;
+
+    /// Saving an empty `value` removes the subscriptions of `bda`.
+    fn save(&self, bda: BdAddr, value: &[u8]);
+}
+
+/// Stored subscriptions: the characteristic handle, little endian, then the
+/// configuration bits, for each subscribed characteristic.
+fn encode_subscriptions(subscriptions: &[(u16, ClientConfiguration)]) -> Vec<u8> {
+    subscriptions
+        .iter()
+        .flat_map(|(handle, config)| {
+            let [low, high] = handle.to_le_bytes();
+            [low, high, config.to_value()[0]]
+        })
+        .collect()
+}
+
+fn decode_subscriptions(value: &[u8]) -> Vec<(u16, ClientConfiguration)> {
+    value
+        .chunks_exact(3)
+        .filter_map(|entry| {
+            let config = ClientConfiguration::from_value(&[entry[2], 0])?;
+            Some((u16::from_le_bytes([entry[0], entry[1]]), config))
+        })
+        .collect()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NotifyError {
+    /// The handle is not the one of a characteristic with a CCCD
+    UnknownCharacteristic(u16),
+    /// The client did not enable notifications, or indications, in the CCCD
+    NotSubscribed { conn_id: u16, handle: u16 },
+    /// The value does not fit in the MTU of the connection
+    ValueTooLong { len: usize, max: usize },
+    /// An indication is still waiting for its confirmation on the connection
+    IndicationInProgress(u16),
+}
+
+impl std::fmt::Display for NotifyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NotifyError::UnknownCharacteristic(handle) => {
+                write!(f, "No characteristic with a CCCD for handle {}", handle)
+            }
+            NotifyError::NotSubscribed { conn_id, handle } => write!(
+                f,
+                "Connection {} is not subscribed to characteristic {}",
+                conn_id, handle
+            ),
+            NotifyError::ValueTooLong { len, max } => write!(
+                f,
+                "Value too long: {} bytes, at most {} are allowed",
+                len, max
+            ),
+            NotifyError::IndicationInProgress(conn_id) => write!(
+                f,
+                "An indication is waiting for its confirmation on connection {}",
+                conn_id
+            ),
+        }
+    }
+}
+
+impl std::error::Error for NotifyError {}
+
+/// What the GATT server needs to know to send notifications: which descriptor
+/// is the CCCD of which characteristic, what the clients wrote in them and the
+/// MTU of the connections. It is kept up to date from the server events.
+///
+/// The subscriptions are stored by characteristic handle, which only identify
+/// the same characteristics across reboots if the services are created in the
+/// same order.
+#[derive(Default)]
+pub(crate) struct NotificationState {
+    /// Last characteristic added to a service, the descriptors added next
+    /// belong to it.
+    last_characteristic: HashMap<u16, u16>,
+    /// Characteristics with a CCCD, with the interface of their application.
+    characteristics: HashMap<u16, u8>,
+    /// Characteristic of each CCCD.
+    cccds: HashMap<u16, u16>,
+    configurations: HashMap<(u16, u16), ClientConfiguration>,
+    /// Address of the peer of each connection.
+    peers: HashMap<u16, BdAddr>,
+    /// Characteristic of the indication waiting for its confirmation, for each
+    /// connection.
+    pending_indications: HashMap<u16, u16>,
+    store: Option<Arc<dyn SubscriptionStore>>,
+}
+
+#[cfg_attr(
+    not(any(target_os = "espidf", feature = "simulated", test)),
+    allow(dead_code)
+)]
+impl NotificationState {
+    #[cfg_attr(not(any(target_os = "espidf", test)), allow(dead_code))]
+    pub(crate) fn set_store(&mut self, store: Arc<dyn SubscriptionStore>) {
+        self.store = Some(store);
+    }
+
+    /// Update the state from `event`, and return the subscription it changes
+    /// when it is a write to a CCCD.
+    pub(crate) fn handle_event(
+        &mut self,
+        gatts_if: u8,
+        event: &GattServiceEvent,
+    ) -> Option<SubscriptionEvent> {
+        match event {
+            GattServiceEvent::AddCharacteristicComplete(add_char) if add_char.status.is_ok() => {
+                self.last_characteristic
+                    .insert(add_char.service_handle, add_char.attr_handle);
+            }
+            GattServiceEvent::AddDescriptorComplete(add_desc)
+                if add_desc.status.is_ok() && add_desc.uuid == Some(BtUuid::Uuid16(CCCD_UUID)) =>
+            {
+                if let Some(handle) = self.last_characteristic.get(&add_desc.service_handle) {
+                    self.add_cccd(gatts_if, *handle, add_desc.attr_handle);
+                }
+            }
+            GattServiceEvent::Write(write) if !write.is_prep && write.offset == 0 => {
+                let handle = *self.cccds.get(&write.handle)?;
+                let config = ClientConfiguration::from_value(&write.value)?;
+                let previous = self.configurations.insert((write.conn_id, handle), config);
+                if previous.unwrap_or_default() != config {
+                    return Some(SubscriptionEvent {
+                        conn_id: write.conn_id,
+                        bda: write.bda,
+                        handle,
+                        configuration: config,
+                    });
+                }
+            }
+            GattServiceEvent::Connect(connect) => {
+                self.peers.insert(connect.conn_id, connect.bda);
+            }
+            GattServiceEvent::Disconnect(disconnect) => {
+                self.configurations
+                    .retain(|(conn_id, _), _| *conn_id != disconnect.conn_id);
+                self.peers.remove(&disconnect.conn_id);
+                self.pending_indications.remove(&disconnect.conn_id);
+            }
+            _ => (),
+        }
+        None
+    }
+
+    /// Give back to the connection `conn_id` with the bonded peer `bda` the
+    /// subscriptions it had in its previous connection.
+    pub(crate) fn restore(&mut self, conn_id: u16, bda: BdAddr) -> Vec<SubscriptionEvent> {
+        let stored = match self.store.as_ref().and_then(|store| store.load(bda)) {
+            Some(stored) => decode_subscriptions(&stored),
+            None => return vec![],
+        };
+
+        stored
+            .into_iter()
+            .filter(|(handle, _)| self.characteristics.contains_key(handle))
+            .map(|(handle, config)| {
+                self.configurations.insert((conn_id, handle), config);
+                SubscriptionEvent {
+                    conn_id,
+                    bda,
+                    handle,
+                    configuration: config,
+                }
+            })
+            .collect()
+    }
+
+    /// Store the subscriptions of the bonded peer `bda`, to restore them when
+    /// it connects again.
+    pub(crate) fn save(&self, bda: BdAddr) {
+        let store = match &self.store {
+            Some(store) => store,
+            None => return,
+        };
+
+        let mut subscriptions: Vec<(u16, ClientConfiguration)> = self
+            .configurations
+            .iter()
+            .filter(|((conn_id, _), config)| {
+                self.peers.get(conn_id) == Some(&bda) && **config != ClientConfiguration::default()
+            })
+            .map(|((_, handle), config)| (*handle, *config))
+            .collect();
+        subscriptions.sort_unstable_by_key(|(handle, _)| *handle);
+
+        store.save(bda, &encode_subscriptions(&subscriptions));
+    }
+
+    /// Remove the subscriptions stored for `bda`, once it is no longer bonded.
+    pub(crate) fn forget(&self, bda: BdAddr) {
+        if let Some(store) = &self.store {
+            store.save(bda, &[]);
+        }
+    }
+
+    /// Record that `cccd` is the CCCD of the characteristic `handle`.
+    pub(crate) fn add_cccd(&mut self, gatts_if: u8, handle: u16, cccd: u16) {
+        self.cccds.insert(cccd, handle);
+        self.characteristics.insert(handle, gatts_if);
+    }
+
+    pub(crate) fn configuration(&self, conn_id: u16, handle: u16) -> ClientConfiguration {
+        self.configurations
+            .get(&(conn_id, handle))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Check that `len` bytes can be sent to `conn_id`, whose MTU is `mtu`, for
+    /// the characteristic `handle`, and return the interface to send them with.
+    pub(crate) fn check(
+        &self,
+        conn_id: u16,
+        handle: u16,
+        len: usize,
+        mtu: u16,
+        indicate: bool,
+    ) -> Result<u8, NotifyError> {
+        let gatts_if = *self
+            .characteristics
+            .get(&handle)
+            .ok_or(NotifyError::UnknownCharacteristic(handle))?;
+
+        let config = self.configuration(conn_id, handle);
+        let enabled = if indicate {
+            config.indications
+        } else {
+            config.notifications
+        };
+        if !enabled {
+            return Err(NotifyError::NotSubscribed { conn_id, handle });
+        }
+
+        let max = mtu as usize - NOTIFICATION_HEADER_LEN;
+        if len > max {
+            return Err(NotifyError::ValueTooLong { len, max });
+        }
+
+        if indicate && self.pending_indications.contains_key(&conn_id) {
+            return Err(NotifyError::IndicationInProgress(conn_id));
+        }
+
+        Ok(gatts_if)
+    }
+
+    pub(crate) fn start_indication(&mut self, conn_id: u16, handle: u16) {
+        self.pending_indications.insert(conn_id, handle);
+    }
+
+    /// Called with a `GattServiceEvent::Confirm`, which is reported for both
+    /// notifications and indications. Returns whether it confirms a pending
+    /// indication.
+    pub(crate) fn end_indication(&mut self, conn_id: u16, handle: u16) -> bool {
+        if self.pending_indications.get(&conn_id) == Some(&handle) {
+            self.pending_indications.remove(&conn_id);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// `SubscriptionStore` in memory, standing in for the nvs in tests.
+#[cfg(test)]
+#[derive(Default)]
+pub(crate) struct MemoryStore(std::sync::Mutex<HashMap<BdAddr, Vec<u8>>>);
+
+#[cfg(test)]
+impl SubscriptionStore for MemoryStore {
+    fn load(&self, bda: BdAddr) -> Option<Vec<u8>> {
+        self.0.lock().ok()?.get(&bda).cloned()
+    }
+
+    fn save(&self, bda: BdAddr, value: &[u8]) {
+        if let Ok(mut values) = self.0.lock() {
+            if value.is_empty() {
+                values.remove(&bda);
+            } else {
+                values.insert(bda, value.to_vec());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        AddAttributeEvent, ConnectEvent, DisconnectEvent, DisconnectReason, GattStatus, LinkRole,
+        WriteEvent,
+    };
+
+    const PEER: BdAddr = BdAddr([0x11, 0x22, 0x33, 0x44, 0x55, 0x66]);
+
+    const SVC: u16 = 40;
+    const CHAR: u16 = 42;
+    const CCCD: u16 = 43;
+
+    fn add(
+        complete: fn(AddAttributeEvent) -> GattServiceEvent,
+        handle: u16,
+        uuid: u16,
+    ) -> GattServiceEvent {
+        complete(AddAttributeEvent {
+            status: GattStatus::Ok,
+            service_handle: SVC,
+            attr_handle: handle,
+            uuid: Some(BtUuid::Uuid16(uuid)),
+        })
+    }
+
+    fn write(conn_id: u16, handle: u16, value: &[u8]) -> GattServiceEvent {
+        GattServiceEvent::Write(WriteEvent {
+            conn_id,
+            trans_id: 0,
+            bda: PEER,
+            handle,
+            offset: 0,
+            need_rsp: true,
+            is_prep: false,
+            value: value.to_vec(),
+        })
+    }
+
+    fn state() -> NotificationState {
+        let mut state = NotificationState::default();
+        state.handle_event(
+            3,
+            &add(GattServiceEvent::AddCharacteristicComplete, CHAR, 0x2a19),
+        );
+        state.handle_event(
+            3,
+            &add(GattServiceEvent::AddDescriptorComplete, CCCD, CCCD_UUID),
+        );
+        state
+    }
+
+    #[test]
+    fn client_configuration_value() {
+        let both = ClientConfiguration {
+            notifications: true,
+            indications: true,
+        };
+        assert_eq!(ClientConfiguration::from_value(&[0x03, 0x00]), Some(both));
+        assert_eq!(both.to_value(), [0x03, 0x00]);
+        assert_eq!(
+            ClientConfiguration::from_value(&[0x00, 0x00]),
+            Some(ClientConfiguration::default())
+        );
+        assert_eq!(ClientConfiguration::from_value(&[0x01]), None);
+        assert_eq!(ClientConfiguration::from_value(&[0x01, 0x01]), None);
+    }
+
+    fn connect(conn_id: u16) -> GattServiceEvent {
+        GattServiceEvent::Connect(ConnectEvent {
+            conn_id,
+            link_role: LinkRole::Peripheral,
+            bda: PEER,
+            interval: 24,
+            latency: 0,
+            timeout: 400,
+        })
+    }
+
+    fn disconnect(conn_id: u16) -> GattServiceEvent {
+        GattServiceEvent::Disconnect(DisconnectEvent {
+            conn_id,
+            bda: PEER,
+            reason: DisconnectReason::RemoteUserTerminated,
+        })
+    }
+
+    #[test]
+    fn stored_subscriptions() {
+        let notify = ClientConfiguration {
+            notifications: true,
+            indications: false,
+        };
+        let both = ClientConfiguration {
+            notifications: true,
+            indications: true,
+        };
+        let value = encode_subscriptions(&[(42, notify), (0x1234, both)]);
+        assert_eq!(value, [42, 0, 0x01, 0x34, 0x12, 0x03]);
+        assert_eq!(decode_subscriptions(&value), [(42, notify), (0x1234, both)]);
+        assert_eq!(decode_subscriptions(&[42, 0, 0x01, 42]), [(42, notify)]);
+    }
+
+    #[test]
+    fn cccd_writes_change_subscriptions() {
+        let mut state = state();
+        state.handle_event(3, &connect(0));
+
+        let subscribed = SubscriptionEvent {
+            conn_id: 0,
+            bda: PEER,
+            handle: CHAR,
+            configuration: ClientConfiguration {
+                notifications: true,
+                indications: false,
+            },
+        };
+        assert_eq!(
+            state.handle_event(3, &write(0, CCCD, &[0x01, 0x00])),
+            Some(subscribed)
+        );
+        assert_eq!(state.handle_event(3, &write(0, CCCD, &[0x01, 0x00])), None);
+        assert_eq!(state.handle_event(3, &write(0, CHAR, &[0x00, 0x00])), None);
+        assert_eq!(
+            state.handle_event(3, &write(0, CCCD, &[0x00, 0x00])),
+            Some(SubscriptionEvent {
+                configuration: ClientConfiguration::default(),
+                ..subscribed
+            })
+        );
+    }
+
+    #[test]
+    fn bonded_subscriptions_are_restored() {
+        let store = Arc::new(MemoryStore::default());
+        let mut state = state();
+        state.set_store(store.clone());
+        state.handle_event(3, &connect(0));
+        assert_eq!(state.restore(0, PEER), []);
+
+        state.handle_event(3, &write(0, CCCD, &[0x02, 0x00]));
+        state.save(PEER);
+        assert_eq!(store.load(PEER), Some(vec![CHAR as u8, 0, 0x02]));
+
+        state.handle_event(3, &disconnect(0));
+        assert!(state.check(1, CHAR, 1, ATT_DEFAULT_MTU, true).is_err());
+
+        state.handle_event(3, &connect(1));
+        let indicate = ClientConfiguration {
+            notifications: false,
+            indications: true,
+        };
+        assert_eq!(
+            state.restore(1, PEER),
+            [SubscriptionEvent {
+                conn_id: 1,
+                bda: PEER,
+                handle: CHAR,
+                configuration: indicate,
+            }]
+        );
+        assert_eq!(state.check(1, CHAR, 1, ATT_DEFAULT_MTU, true), Ok(3));
+
+        state.handle_event(3, &write(1, CCCD, &[0x00, 0x00]));
+        state.save(PEER);
+        assert_eq!(store.load(PEER), None);
+    }
+
+    #[test]
+    fn notifications_need_a_subscription() {
+        let mut state = state();
+        assert_eq!(
+            state.check(0, 0x99, 1, ATT_DEFAULT_MTU, false),
+            Err(NotifyError::UnknownCharacteristic(0x99))
+        );
+        assert_eq!(
+            state.check(0, CHAR, 1, ATT_DEFAULT_MTU, false),
+            Err(NotifyError::NotSubscribed {
+                conn_id: 0,
+                handle: CHAR
+            })
+        );
+
+        state.handle_event(3, &write(0, CCCD, &[0x01, 0x00]));
+        assert_eq!(state.check(0, CHAR, 1, ATT_DEFAULT_MTU, false), Ok(3));
+        assert!(state.check(0, CHAR, 1, ATT_DEFAULT_MTU, true).is_err());
+        assert!(state.check(1, CHAR, 1, ATT_DEFAULT_MTU, false).is_err());
+
+        state.handle_event(3, &disconnect(0));
+        assert!(state.check(0, CHAR, 1, ATT_DEFAULT_MTU, false).is_err());
+    }
+
+    #[test]
+    fn values_fit_in_the_mtu() {
+        let mut state = state();
+        state.handle_event(3, &write(0, CCCD, &[0x01, 0x00]));
+        assert_eq!(state.check(0, CHAR, 20, ATT_DEFAULT_MTU, false), Ok(3));
+        assert_eq!(
+            state.check(0, CHAR, 21, ATT_DEFAULT_MTU, false),
+            Err(NotifyError::ValueTooLong { len: 21, max: 20 })
+        );
+        assert_eq!(state.check(0, CHAR, 97, 100, false), Ok(3));
+    }
+
+    #[test]
+    fn one_indication_at_a_time() {
+        let mut state = state();
+        state.handle_event(3, &write(0, CCCD, &[0x02, 0x00]));
+        assert_eq!(state.check(0, CHAR, 1, ATT_DEFAULT_MTU, true), Ok(3));
+
+        state.start_indication(0, CHAR);
+        assert_eq!(
+            state.check(0, CHAR, 1, ATT_DEFAULT_MTU, true),
+            Err(NotifyError::IndicationInProgress(0))
+        );
+        assert!(!state.end_indication(0, CHAR + 1));
+        assert!(state.end_indication(0, CHAR));
+        assert_eq!(state.check(0, CHAR, 1, ATT_DEFAULT_MTU, true), Ok(3));
+    }
+}
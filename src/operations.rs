@@ -0,0 +1,1549 @@
+//! Blocking and async variants of the `EspBle` operations reporting their
+//! completion to a callback.
+//!
+//! The `*_blocking` variants wait at most `timeout` for the completion event,
+//! they must not be called from a callback, which would prevent the event
+//! from being reported. The `*_async` variants can be awaited on any
+//! executor, a timeout can be added with the timers of the executor.
+//!
+//! The status of the completion event is checked, a non success status is
+//! returned as `BleError::Gatt` or `BleError::Bt`.
+//!
+//! The GAP completion events do not tell which call they complete: only one
+//! GAP operation of each kind can be pending, starting another one returns
+//! `BleError::InvalidState` until it completes, times out or is dropped.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use crate::ble::{
+    abandon_gatt_queued, remove_gatt_completion, remove_gattc_completion, GattCallbacks,
+    GattClientCallbacks,
+};
+use crate::ble::{release_extended_advertising_set, remove_gap_completion, GapCallbacks};
+use crate::completion::Completion;
+use crate::error::{bt_result, gatt_result};
+use crate::{
+    advertise, AdvertisingParameters, AttributeTable, BdAddr, BleError, ConnectionParameters,
+    EspBle, GapEvent, GattCharacteristic, GattDescriptor, GattService, GattServiceEvent,
+    RawAdvertiseData, ScOobData, ScanParameters, ScanResult, ServiceHandles,
+};
+use crate::{AddressType, BtUuid, GattClientEvent, WriteType};
+use crate::{
+    ExtendedAdvertisingDuration, ExtendedAdvertisingParameters, ExtendedAdvertisingSet,
+    PeriodicAdvertisingParameters,
+};
+
+/// Completion of an operation, with the status of its event checked.
+///
+/// An operation dropped before it completes, e.g. after a timeout, removes
+/// the callback waiting for its completion, for another operation of the
+/// same kind to be started.
+struct Operation<T> {
+    completion: Completion<Result<T, BleError>>,
+    cancel: Option<Box<dyn FnOnce() + Send>>,
+}
+
+impl<T> Operation<T> {
+    /// Call `cancel` too if the operation is dropped before it completes.
+    fn on_cancel(mut self, cancel: impl FnOnce() + Send + 'static) -> Self {
+        let previous = self.cancel.take();
+        self.cancel = Some(Box::new(move || {
+            if let Some(previous) = previous {
+                previous();
+            }
+            cancel();
+        }));
+        self
+    }
+
+    /// Remove the completion callback of the GAP operation `cb_key` if the
+    /// operation is dropped before it completes.
+    fn cancel_gap(self, cb_key: GapCallbacks) -> Self {
+        self.on_cancel(move || remove_gap_completion(cb_key))
+    }
+
+    /// Same as `cancel_gap` for a GATT server operation.
+    fn cancel_gatt(self, cb_key: GattCallbacks) -> Self {
+        self.on_cancel(move || remove_gatt_completion(cb_key))
+    }
+
+    /// Same as `cancel_gap` for a GATT server operation with the queued
+    /// callback `id`.
+    fn cancel_gatt_queued(self, cb_key: GattCallbacks, id: usize) -> Self {
+        self.on_cancel(move || abandon_gatt_queued(cb_key, id))
+    }
+
+    /// Same as `cancel_gap` for a GATT client operation.
+    fn cancel_gattc(self, cb_key: GattClientCallbacks) -> Self {
+        self.on_cancel(move || remove_gattc_completion(cb_key))
+    }
+
+    fn wait_for(mut self, timeout: Duration) -> Result<T, BleError> {
+        match self.completion.wait(timeout) {
+            Some(result) => {
+                self.cancel = None;
+                result
+            }
+            None => Err(BleError::Timeout),
+        }
+    }
+}
+
+impl<T> Future for Operation<T> {
+    type Output = Result<T, BleError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let poll = Pin::new(&mut self.completion).poll(cx);
+        if poll.is_ready() {
+            self.cancel = None;
+        }
+        poll
+    }
+}
+
+impl<T> Drop for Operation<T> {
+    fn drop(&mut self) {
+        if let Some(cancel) = self.cancel.take() {
+            cancel();
+        }
+    }
+}
+
+/// An operation and the callback completing it, `complete` maps the events
+/// to the result of the operation, `None` for the events to ignore.
+fn operation<E, T: Send + 'static>(
+    complete: impl Fn(E) -> Option<Result<T, BleError>> + Send + 'static,
+) -> (Operation<T>, impl Fn(E) + Send + Sync + 'static) {
+    let completion = Completion::new();
+    let completed = completion.clone();
+    let complete = Mutex::new(complete);
+    let cb = move |event| {
+        if let Some(result) = complete.lock().ok().and_then(|complete| complete(event)) {
+            completed.complete(result);
+        }
+    };
+    let operation = Operation {
+        completion,
+        cancel: None,
+    };
+    (operation, cb)
+}
+
+/// Operation completed by a GAP event carrying only a status.
+fn gap_operation(
+    status: impl Fn(GapEvent) -> Option<crate::BtStatus> + Send + 'static,
+) -> (Operation<()>, impl Fn(GapEvent) + Send + Sync + 'static) {
+    operation(move |event| status(event).map(bt_result))
+}
+
+impl EspBle {
+    fn configure_advertising_data_raw_operation(
+        &self,
+        data: RawAdvertiseData,
+    ) -> Result<Operation<()>, BleError> {
+        let (operation, cb) = gap_operation(|event| match event {
+            GapEvent::RawAdvertisingDatasetComplete(status)
+            | GapEvent::RawScanResponseDatasetComplete(status) => Some(status),
+            _ => None,
+        });
+        let cb_key = if data.set_scan_rsp {
+            GapCallbacks::RawScanResponseDataset
+        } else {
+            GapCallbacks::RawAdvertisingDataset
+        };
+        self.configure_advertising_data_raw(data, cb)?;
+        Ok(operation.cancel_gap(cb_key))
+    }
+
+    /// `configure_advertising_data_raw`, waiting until the data is set.
+    pub fn configure_advertising_data_raw_blocking(
+        &self,
+        data: RawAdvertiseData,
+        timeout: Duration,
+    ) -> Result<(), BleError> {
+        self.configure_advertising_data_raw_operation(data)?
+            .wait_for(timeout)
+    }
+
+    pub async fn configure_advertising_data_raw_async(
+        &self,
+        data: RawAdvertiseData,
+    ) -> Result<(), BleError> {
+        self.configure_advertising_data_raw_operation(data)?.await
+    }
+
+    fn configure_advertising_data_operation(
+        &self,
+        data: advertise::AdvertiseData,
+    ) -> Result<Operation<()>, BleError> {
+        let (operation, cb) = gap_operation(|event| match event {
+            GapEvent::AdvertisingDatasetComplete(status)
+            | GapEvent::ScanResponseDatasetComplete(status) => Some(status),
+            _ => None,
+        });
+        let cb_key = if data.set_scan_rsp {
+            GapCallbacks::ScanResponseDataset
+        } else {
+            GapCallbacks::AdvertisingDataset
+        };
+        self.configure_advertising_data(data, cb)?;
+        Ok(operation.cancel_gap(cb_key))
+    }
+
+    /// `configure_advertising_data`, waiting until the data is set.
+    pub fn configure_advertising_data_blocking(
+        &self,
+        data: advertise::AdvertiseData,
+        timeout: Duration,
+    ) -> Result<(), BleError> {
+        self.configure_advertising_data_operation(data)?
+            .wait_for(timeout)
+    }
+
+    pub async fn configure_advertising_data_async(
+        &self,
+        data: advertise::AdvertiseData,
+    ) -> Result<(), BleError> {
+        self.configure_advertising_data_operation(data)?.await
+    }
+
+    fn start_advertise_operation(
+        &self,
+        params: AdvertisingParameters,
+    ) -> Result<Operation<()>, BleError> {
+        let (operation, cb) = gap_operation(|event| match event {
+            GapEvent::AdvertisingStartComplete(status) => Some(status),
+            _ => None,
+        });
+        self.start_advertise(params, cb)?;
+        Ok(operation.cancel_gap(GapCallbacks::AdvertisingStart))
+    }
+
+    /// `start_advertise`, waiting until advertising starts.
+    pub fn start_advertise_blocking(
+        &self,
+        params: AdvertisingParameters,
+        timeout: Duration,
+    ) -> Result<(), BleError> {
+        self.start_advertise_operation(params)?.wait_for(timeout)
+    }
+
+    pub async fn start_advertise_async(
+        &self,
+        params: AdvertisingParameters,
+    ) -> Result<(), BleError> {
+        self.start_advertise_operation(params)?.await
+    }
+
+    fn stop_advertise_operation(&self) -> Result<Operation<()>, BleError> {
+        let (operation, cb) = gap_operation(|event| match event {
+            GapEvent::AdvertisingStopComplete(status) => Some(status),
+            _ => None,
+        });
+        self.stop_advertise(cb)?;
+        Ok(operation.cancel_gap(GapCallbacks::AdvertisingStop))
+    }
+
+    /// `stop_advertise`, waiting until advertising stops.
+    pub fn stop_advertise_blocking(&self, timeout: Duration) -> Result<(), BleError> {
+        self.stop_advertise_operation()?.wait_for(timeout)
+    }
+
+    pub async fn stop_advertise_async(&self) -> Result<(), BleError> {
+        self.stop_advertise_operation()?.await
+    }
+
+    fn start_scan_operation(
+        &self,
+        params: ScanParameters,
+        duration: u32,
+        cb: impl Fn(ScanResult) + 'static + Send + Sync,
+    ) -> Result<Operation<()>, BleError> {
+        let (operation, started) = operation(Some);
+        self.scan(params, duration, cb, started)?;
+        Ok(operation
+            .cancel_gap(GapCallbacks::ScanParameterDataset)
+            .cancel_gap(GapCallbacks::ScanStart))
+    }
+
+    /// `start_scan`, waiting until scanning starts. `cb` is called for every
+    /// advertising report received, as with `start_scan`.
+    pub fn start_scan_blocking(
+        &self,
+        params: ScanParameters,
+        duration: u32,
+        timeout: Duration,
+        cb: impl Fn(ScanResult) + 'static + Send + Sync,
+    ) -> Result<(), BleError> {
+        self.start_scan_operation(params, duration, cb)?
+            .wait_for(timeout)
+    }
+
+    pub async fn start_scan_async(
+        &self,
+        params: ScanParameters,
+        duration: u32,
+        cb: impl Fn(ScanResult) + 'static + Send + Sync,
+    ) -> Result<(), BleError> {
+        self.start_scan_operation(params, duration, cb)?.await
+    }
+
+    fn stop_scan_operation(&self) -> Result<Operation<()>, BleError> {
+        let (operation, cb) = gap_operation(|event| match event {
+            GapEvent::ScanStopComplete(status) => Some(status),
+            _ => None,
+        });
+        self.stop_scan(cb)?;
+        Ok(operation.cancel_gap(GapCallbacks::ScanStop))
+    }
+
+    /// `stop_scan`, waiting until scanning stops.
+    pub fn stop_scan_blocking(&self, timeout: Duration) -> Result<(), BleError> {
+        self.stop_scan_operation()?.wait_for(timeout)
+    }
+
+    pub async fn stop_scan_async(&self) -> Result<(), BleError> {
+        self.stop_scan_operation()?.await
+    }
+
+    fn update_connection_params_operation(
+        &self,
+        conn_id: u16,
+        params: ConnectionParameters,
+    ) -> Result<Operation<()>, BleError> {
+        let bda = self.connection(conn_id).map(|connection| connection.bda);
+        let (operation, cb) = gap_operation(|event| match event {
+            GapEvent::UpdateConnectionParamsComplete(update) => Some(update.status),
+            _ => None,
+        });
+        self.update_connection_params(conn_id, params, cb)?;
+        Ok(match bda {
+            Some(bda) => operation.cancel_gap(GapCallbacks::UpdateConnectionParams(bda)),
+            None => operation,
+        })
+    }
+
+    /// `update_connection_params`, waiting until the central answers.
+    pub fn update_connection_params_blocking(
+        &self,
+        conn_id: u16,
+        params: ConnectionParameters,
+        timeout: Duration,
+    ) -> Result<(), BleError> {
+        self.update_connection_params_operation(conn_id, params)?
+            .wait_for(timeout)
+    }
+
+    pub async fn update_connection_params_async(
+        &self,
+        conn_id: u16,
+        params: ConnectionParameters,
+    ) -> Result<(), BleError> {
+        self.update_connection_params_operation(conn_id, params)?
+            .await
+    }
+
+    fn remove_bond_operation(&self, bda: BdAddr) -> Result<Operation<()>, BleError> {
+        let (operation, cb) = gap_operation(|event| match event {
+            GapEvent::RemoveDeviceBondComplete(bond) => Some(bond.status),
+            _ => None,
+        });
+        self.remove_bond(bda, cb)?;
+        Ok(operation.cancel_gap(GapCallbacks::RemoveBond(bda)))
+    }
+
+    /// `remove_bond`, waiting until the bond is removed.
+    pub fn remove_bond_blocking(&self, bda: BdAddr, timeout: Duration) -> Result<(), BleError> {
+        self.remove_bond_operation(bda)?.wait_for(timeout)
+    }
+
+    pub async fn remove_bond_async(&self, bda: BdAddr) -> Result<(), BleError> {
+        self.remove_bond_operation(bda)?.await
+    }
+
+    fn clear_bonds_operation(&self) -> Result<Operation<()>, BleError> {
+        let (operation, cb) = gap_operation(|event| match event {
+            GapEvent::ClearDeviceBondComplete(status) => Some(status),
+            _ => None,
+        });
+        let bonds = self.clear_bonds(cb)?;
+        Ok(bonds.into_iter().fold(operation, |operation, bda| {
+            operation.cancel_gap(GapCallbacks::RemoveBond(bda))
+        }))
+    }
+
+    /// `clear_bonds`, waiting until every bond is removed.
+    pub fn clear_bonds_blocking(&self, timeout: Duration) -> Result<(), BleError> {
+        self.clear_bonds_operation()?.wait_for(timeout)
+    }
+
+    pub async fn clear_bonds_async(&self) -> Result<(), BleError> {
+        self.clear_bonds_operation()?.await
+    }
+
+    fn create_local_oob_data_operation(&self) -> Result<Operation<ScOobData>, BleError> {
+        let (operation, cb) = operation(|event| match event {
+            GapEvent::LocalOOBDataCreated(data) => Some(Ok(data)),
+            _ => None,
+        });
+        self.create_local_oob_data(cb)?;
+        Ok(operation.cancel_gap(GapCallbacks::LocalOOBData))
+    }
+
+    /// `create_local_oob_data`, returns the data once created.
+    pub fn create_local_oob_data_blocking(&self, timeout: Duration) -> Result<ScOobData, BleError> {
+        self.create_local_oob_data_operation()?.wait_for(timeout)
+    }
+
+    pub async fn create_local_oob_data_async(&self) -> Result<ScOobData, BleError> {
+        self.create_local_oob_data_operation()?.await
+    }
+
+    fn register_gatt_service_application_operation(
+        &mut self,
+        app_id: u16,
+    ) -> Result<Operation<u8>, BleError> {
+        let (operation, cb) = operation(|(gatts_if, event)| match event {
+            GattServiceEvent::Register(reg) => Some(gatt_result(reg.status).map(|_| gatts_if)),
+            _ => None,
+        });
+        self.register_gatt_service_application(app_id, move |gatts_if, event| {
+            cb((gatts_if, event))
+        })?;
+        Ok(operation.cancel_gatt(GattCallbacks::Register(app_id)))
+    }
+
+    /// `register_gatt_service_application`, returns the interface of the
+    /// application once registered.
+    pub fn register_gatt_service_application_blocking(
+        &mut self,
+        app_id: u16,
+        timeout: Duration,
+    ) -> Result<u8, BleError> {
+        self.register_gatt_service_application_operation(app_id)?
+            .wait_for(timeout)
+    }
+
+    pub async fn register_gatt_service_application_async(
+        &mut self,
+        app_id: u16,
+    ) -> Result<u8, BleError> {
+        self.register_gatt_service_application_operation(app_id)?
+            .await
+    }
+
+    fn create_service_operation(
+        &self,
+        gatts_if: u8,
+        svc: GattService,
+    ) -> Result<Operation<u16>, BleError> {
+        let (operation, cb) = operation(|event| match event {
+            GattServiceEvent::Create(create) => {
+                Some(gatt_result(create.status).map(|_| create.service_handle))
+            }
+            _ => None,
+        });
+        self.create_service(gatts_if, svc, move |_, event| cb(event))?;
+        Ok(operation.cancel_gatt(GattCallbacks::Create(gatts_if)))
+    }
+
+    /// `create_service`, returns the handle of the service once created.
+    pub fn create_service_blocking(
+        &self,
+        gatts_if: u8,
+        svc: GattService,
+        timeout: Duration,
+    ) -> Result<u16, BleError> {
+        self.create_service_operation(gatts_if, svc)?
+            .wait_for(timeout)
+    }
+
+    pub async fn create_service_async(
+        &self,
+        gatts_if: u8,
+        svc: GattService,
+    ) -> Result<u16, BleError> {
+        self.create_service_operation(gatts_if, svc)?.await
+    }
+
+    fn create_attribute_table_operation(
+        &self,
+        gatts_if: u8,
+        table: AttributeTable,
+    ) -> Result<Operation<ServiceHandles>, BleError> {
+        let (operation, cb) =
+            operation(|result: Result<ServiceHandles, _>| Some(result.map_err(BleError::Gatt)));
+        self.create_attribute_table(gatts_if, table, cb)?;
+        Ok(operation.cancel_gatt(GattCallbacks::CreateAttributeTable(gatts_if)))
+    }
+
+    /// `create_attribute_table`, returns the handles of the attributes once
+    /// the service is started.
+    pub fn create_attribute_table_blocking(
+        &self,
+        gatts_if: u8,
+        table: AttributeTable,
+        timeout: Duration,
+    ) -> Result<ServiceHandles, BleError> {
+        self.create_attribute_table_operation(gatts_if, table)?
+            .wait_for(timeout)
+    }
+
+    pub async fn create_attribute_table_async(
+        &self,
+        gatts_if: u8,
+        table: AttributeTable,
+    ) -> Result<ServiceHandles, BleError> {
+        self.create_attribute_table_operation(gatts_if, table)?
+            .await
+    }
+
+    fn start_service_operation(&self, svc_handle: u16) -> Result<Operation<()>, BleError> {
+        let (operation, cb) = operation(|event| match event {
+            GattServiceEvent::StartComplete(start) => Some(gatt_result(start.status)),
+            _ => None,
+        });
+        self.start_service(svc_handle, move |_, event| cb(event))?;
+        Ok(operation.cancel_gatt(GattCallbacks::Start(svc_handle)))
+    }
+
+    /// `start_service`, waiting until the service is started.
+    pub fn start_service_blocking(
+        &self,
+        svc_handle: u16,
+        timeout: Duration,
+    ) -> Result<(), BleError> {
+        self.start_service_operation(svc_handle)?.wait_for(timeout)
+    }
+
+    pub async fn start_service_async(&self, svc_handle: u16) -> Result<(), BleError> {
+        self.start_service_operation(svc_handle)?.await
+    }
+
+    fn add_characteristic_operation<const S: usize>(
+        &self,
+        svc_handle: u16,
+        charac: GattCharacteristic<S>,
+    ) -> Result<Operation<u16>, BleError> {
+        let (operation, cb) = operation(|event| match event {
+            GattServiceEvent::AddCharacteristicComplete(added) => {
+                Some(gatt_result(added.status).map(|_| added.attr_handle))
+            }
+            _ => None,
+        });
+        let id = self.queue_characteristic(svc_handle, charac, move |_, event| cb(event))?;
+        Ok(operation.cancel_gatt_queued(GattCallbacks::AddCharacteristic(svc_handle), id))
+    }
+
+    /// `add_characteristic`, returns the handle of the characteristic once
+    /// added.
+    pub fn add_characteristic_blocking<const S: usize>(
+        &self,
+        svc_handle: u16,
+        charac: GattCharacteristic<S>,
+        timeout: Duration,
+    ) -> Result<u16, BleError> {
+        self.add_characteristic_operation(svc_handle, charac)?
+            .wait_for(timeout)
+    }
+
+    pub async fn add_characteristic_async<const S: usize>(
+        &self,
+        svc_handle: u16,
+        charac: GattCharacteristic<S>,
+    ) -> Result<u16, BleError> {
+        self.add_characteristic_operation(svc_handle, charac)?.await
+    }
+
+    fn add_descriptor_operation(
+        &self,
+        svc_handle: u16,
+        char_desc: GattDescriptor,
+    ) -> Result<Operation<u16>, BleError> {
+        let (operation, cb) = operation(|event| match event {
+            GattServiceEvent::AddDescriptorComplete(added) => {
+                Some(gatt_result(added.status).map(|_| added.attr_handle))
+            }
+            _ => None,
+        });
+        let id = self.queue_descriptor(svc_handle, char_desc, move |_, event| cb(event))?;
+        Ok(operation.cancel_gatt_queued(GattCallbacks::AddCharacteristicDesc(svc_handle), id))
+    }
+
+    /// `add_descriptor`, returns the handle of the descriptor once added.
+    pub fn add_descriptor_blocking(
+        &self,
+        svc_handle: u16,
+        char_desc: GattDescriptor,
+        timeout: Duration,
+    ) -> Result<u16, BleError> {
+        self.add_descriptor_operation(svc_handle, char_desc)?
+            .wait_for(timeout)
+    }
+
+    pub async fn add_descriptor_async(
+        &self,
+        svc_handle: u16,
+        char_desc: GattDescriptor,
+    ) -> Result<u16, BleError> {
+        self.add_descriptor_operation(svc_handle, char_desc)?.await
+    }
+
+    fn indicate_operation(
+        &self,
+        conn_id: u16,
+        attr_handle: u16,
+        value: &[u8],
+    ) -> Result<Operation<()>, BleError> {
+        let (operation, cb) = operation(|event| match event {
+            GattServiceEvent::Confirm(confirm) => Some(gatt_result(confirm.status)),
+            _ => None,
+        });
+        self.indicate(conn_id, attr_handle, value, move |_, event| cb(event))?;
+        Ok(operation.cancel_gatt(GattCallbacks::Confirm(conn_id, attr_handle)))
+    }
+
+    /// `indicate`, waiting until the client confirms the indication.
+    pub fn indicate_blocking(
+        &self,
+        conn_id: u16,
+        attr_handle: u16,
+        value: &[u8],
+        timeout: Duration,
+    ) -> Result<(), BleError> {
+        self.indicate_operation(conn_id, attr_handle, value)?
+            .wait_for(timeout)
+    }
+
+    pub async fn indicate_async(
+        &self,
+        conn_id: u16,
+        attr_handle: u16,
+        value: &[u8],
+    ) -> Result<(), BleError> {
+        self.indicate_operation(conn_id, attr_handle, value)?.await
+    }
+}
+
+impl EspBle {
+    fn register_gatt_client_application_operation(
+        &mut self,
+        app_id: u16,
+    ) -> Result<Operation<u8>, BleError> {
+        let (operation, cb) = operation(|(gattc_if, event)| match event {
+            GattClientEvent::Register(reg) => Some(gatt_result(reg.status).map(|_| gattc_if)),
+            _ => None,
+        });
+        self.register_gatt_client_application(app_id, move |gattc_if, event| {
+            cb((gattc_if, event))
+        })?;
+        Ok(operation.cancel_gattc(GattClientCallbacks::Register(app_id)))
+    }
+
+    /// `register_gatt_client_application`, returns the interface of the
+    /// application once registered.
+    pub fn register_gatt_client_application_blocking(
+        &mut self,
+        app_id: u16,
+        timeout: Duration,
+    ) -> Result<u8, BleError> {
+        self.register_gatt_client_application_operation(app_id)?
+            .wait_for(timeout)
+    }
+
+    pub async fn register_gatt_client_application_async(
+        &mut self,
+        app_id: u16,
+    ) -> Result<u8, BleError> {
+        self.register_gatt_client_application_operation(app_id)?
+            .await
+    }
+
+    fn open_operation(
+        &self,
+        gattc_if: u8,
+        remote_bda: BdAddr,
+        remote_addr_type: AddressType,
+    ) -> Result<Operation<u16>, BleError> {
+        let (operation, cb) = operation(|event| match event {
+            GattClientEvent::Open(open) => Some(gatt_result(open.status).map(|_| open.conn_id)),
+            _ => None,
+        });
+        self.open(gattc_if, remote_bda, remote_addr_type, move |_, event| {
+            cb(event)
+        })?;
+        Ok(operation.cancel_gattc(GattClientCallbacks::Open(gattc_if, remote_bda)))
+    }
+
+    /// `open`, returns the id of the connection once opened.
+    pub fn open_blocking(
+        &self,
+        gattc_if: u8,
+        remote_bda: BdAddr,
+        remote_addr_type: AddressType,
+        timeout: Duration,
+    ) -> Result<u16, BleError> {
+        self.open_operation(gattc_if, remote_bda, remote_addr_type)?
+            .wait_for(timeout)
+    }
+
+    pub async fn open_async(
+        &self,
+        gattc_if: u8,
+        remote_bda: BdAddr,
+        remote_addr_type: AddressType,
+    ) -> Result<u16, BleError> {
+        self.open_operation(gattc_if, remote_bda, remote_addr_type)?
+            .await
+    }
+
+    fn search_services_operation(
+        &self,
+        gattc_if: u8,
+        conn_id: u16,
+        filter: Option<BtUuid>,
+    ) -> Result<Operation<()>, BleError> {
+        let (operation, cb) = operation(|event| match event {
+            GattClientEvent::SearchComplete(search) => Some(gatt_result(search.status)),
+            _ => None,
+        });
+        self.search_services(gattc_if, conn_id, filter, move |_, event| cb(event))?;
+        Ok(operation.cancel_gattc(GattClientCallbacks::SearchComplete(conn_id)))
+    }
+
+    /// `search_services`, waiting until the search completes. The services
+    /// found are then read with `get_services`.
+    pub fn search_services_blocking(
+        &self,
+        gattc_if: u8,
+        conn_id: u16,
+        filter: Option<BtUuid>,
+        timeout: Duration,
+    ) -> Result<(), BleError> {
+        self.search_services_operation(gattc_if, conn_id, filter)?
+            .wait_for(timeout)
+    }
+
+    pub async fn search_services_async(
+        &self,
+        gattc_if: u8,
+        conn_id: u16,
+        filter: Option<BtUuid>,
+    ) -> Result<(), BleError> {
+        self.search_services_operation(gattc_if, conn_id, filter)?
+            .await
+    }
+
+    fn read_characteristic_operation(
+        &self,
+        gattc_if: u8,
+        conn_id: u16,
+        handle: u16,
+    ) -> Result<Operation<Vec<u8>>, BleError> {
+        let (operation, cb) = operation(|event| match event {
+            GattClientEvent::ReadCharacteristic(read) => {
+                Some(gatt_result(read.status).map(|_| read.value))
+            }
+            _ => None,
+        });
+        self.read_characteristic(gattc_if, conn_id, handle, move |_, event| cb(event))?;
+        Ok(operation.cancel_gattc(GattClientCallbacks::ReadCharacteristic(conn_id, handle)))
+    }
+
+    /// `read_characteristic`, returns the value read.
+    pub fn read_characteristic_blocking(
+        &self,
+        gattc_if: u8,
+        conn_id: u16,
+        handle: u16,
+        timeout: Duration,
+    ) -> Result<Vec<u8>, BleError> {
+        self.read_characteristic_operation(gattc_if, conn_id, handle)?
+            .wait_for(timeout)
+    }
+
+    pub async fn read_characteristic_async(
+        &self,
+        gattc_if: u8,
+        conn_id: u16,
+        handle: u16,
+    ) -> Result<Vec<u8>, BleError> {
+        self.read_characteristic_operation(gattc_if, conn_id, handle)?
+            .await
+    }
+
+    fn write_characteristic_operation(
+        &self,
+        gattc_if: u8,
+        conn_id: u16,
+        handle: u16,
+        value: &[u8],
+        write_type: WriteType,
+    ) -> Result<Operation<()>, BleError> {
+        let (operation, cb) = operation(|event| match event {
+            GattClientEvent::WriteCharacteristic(write) => Some(gatt_result(write.status)),
+            _ => None,
+        });
+        self.write_characteristic(
+            gattc_if,
+            conn_id,
+            handle,
+            value,
+            write_type,
+            move |_, event| cb(event),
+        )?;
+        Ok(operation.cancel_gattc(GattClientCallbacks::WriteCharacteristic(conn_id, handle)))
+    }
+
+    /// `write_characteristic`, waiting until the write completes.
+    pub fn write_characteristic_blocking(
+        &self,
+        gattc_if: u8,
+        conn_id: u16,
+        handle: u16,
+        value: &[u8],
+        write_type: WriteType,
+        timeout: Duration,
+    ) -> Result<(), BleError> {
+        self.write_characteristic_operation(gattc_if, conn_id, handle, value, write_type)?
+            .wait_for(timeout)
+    }
+
+    pub async fn write_characteristic_async(
+        &self,
+        gattc_if: u8,
+        conn_id: u16,
+        handle: u16,
+        value: &[u8],
+        write_type: WriteType,
+    ) -> Result<(), BleError> {
+        self.write_characteristic_operation(gattc_if, conn_id, handle, value, write_type)?
+            .await
+    }
+
+    fn read_descriptor_operation(
+        &self,
+        gattc_if: u8,
+        conn_id: u16,
+        handle: u16,
+    ) -> Result<Operation<Vec<u8>>, BleError> {
+        let (operation, cb) = operation(|event| match event {
+            GattClientEvent::ReadDescriptor(read) => {
+                Some(gatt_result(read.status).map(|_| read.value))
+            }
+            _ => None,
+        });
+        self.read_descriptor(gattc_if, conn_id, handle, move |_, event| cb(event))?;
+        Ok(operation.cancel_gattc(GattClientCallbacks::ReadDescriptor(conn_id, handle)))
+    }
+
+    /// `read_descriptor`, returns the value read.
+    pub fn read_descriptor_blocking(
+        &self,
+        gattc_if: u8,
+        conn_id: u16,
+        handle: u16,
+        timeout: Duration,
+    ) -> Result<Vec<u8>, BleError> {
+        self.read_descriptor_operation(gattc_if, conn_id, handle)?
+            .wait_for(timeout)
+    }
+
+    pub async fn read_descriptor_async(
+        &self,
+        gattc_if: u8,
+        conn_id: u16,
+        handle: u16,
+    ) -> Result<Vec<u8>, BleError> {
+        self.read_descriptor_operation(gattc_if, conn_id, handle)?
+            .await
+    }
+
+    fn write_descriptor_operation(
+        &self,
+        gattc_if: u8,
+        conn_id: u16,
+        handle: u16,
+        value: &[u8],
+        write_type: WriteType,
+    ) -> Result<Operation<()>, BleError> {
+        let (operation, cb) = operation(|event| match event {
+            GattClientEvent::WriteDescriptor(write) => Some(gatt_result(write.status)),
+            _ => None,
+        });
+        self.write_descriptor(
+            gattc_if,
+            conn_id,
+            handle,
+            value,
+            write_type,
+            move |_, event| cb(event),
+        )?;
+        Ok(operation.cancel_gattc(GattClientCallbacks::WriteDescriptor(conn_id, handle)))
+    }
+
+    /// `write_descriptor`, waiting until the write completes.
+    pub fn write_descriptor_blocking(
+        &self,
+        gattc_if: u8,
+        conn_id: u16,
+        handle: u16,
+        value: &[u8],
+        write_type: WriteType,
+        timeout: Duration,
+    ) -> Result<(), BleError> {
+        self.write_descriptor_operation(gattc_if, conn_id, handle, value, write_type)?
+            .wait_for(timeout)
+    }
+
+    pub async fn write_descriptor_async(
+        &self,
+        gattc_if: u8,
+        conn_id: u16,
+        handle: u16,
+        value: &[u8],
+        write_type: WriteType,
+    ) -> Result<(), BleError> {
+        self.write_descriptor_operation(gattc_if, conn_id, handle, value, write_type)?
+            .await
+    }
+}
+
+impl EspBle {
+    /// `create_extended_advertising_set`, returns the set once configured.
+    /// The set is released if it cannot be configured.
+    pub fn create_extended_advertising_set_blocking(
+        &self,
+        params: ExtendedAdvertisingParameters,
+        timeout: Duration,
+    ) -> Result<ExtendedAdvertisingSet, BleError> {
+        let (operation, cb) = gap_operation(|event| match event {
+            GapEvent::ExtendedAdvertisingSetParamsComplete(status) => Some(status),
+            _ => None,
+        });
+        let set = self.create_extended_advertising_set(params, cb)?;
+        let operation = operation.cancel_gap(GapCallbacks::ExtendedAdvertisingParams);
+        match operation.wait_for(timeout) {
+            Ok(()) => Ok(set),
+            Err(err) => {
+                release_extended_advertising_set(set);
+                Err(err)
+            }
+        }
+    }
+
+    pub async fn create_extended_advertising_set_async(
+        &self,
+        params: ExtendedAdvertisingParameters,
+    ) -> Result<ExtendedAdvertisingSet, BleError> {
+        let (operation, cb) = gap_operation(|event| match event {
+            GapEvent::ExtendedAdvertisingSetParamsComplete(status) => Some(status),
+            _ => None,
+        });
+        let set = self.create_extended_advertising_set(params, cb)?;
+        let operation = operation.cancel_gap(GapCallbacks::ExtendedAdvertisingParams);
+        match operation.await {
+            Ok(()) => Ok(set),
+            Err(err) => {
+                release_extended_advertising_set(set);
+                Err(err)
+            }
+        }
+    }
+
+    fn set_extended_advertising_random_address_operation(
+        &self,
+        set: &ExtendedAdvertisingSet,
+        addr: [u8; 6],
+    ) -> Result<Operation<()>, BleError> {
+        let (operation, cb) = gap_operation(|event| match event {
+            GapEvent::ExtendedAdvertisingSetRandomAddressComplete(status) => Some(status),
+            _ => None,
+        });
+        self.set_extended_advertising_random_address(set, addr, cb)?;
+        Ok(operation.cancel_gap(GapCallbacks::ExtendedAdvertisingRandomAddress))
+    }
+
+    pub fn set_extended_advertising_random_address_blocking(
+        &self,
+        set: &ExtendedAdvertisingSet,
+        addr: [u8; 6],
+        timeout: Duration,
+    ) -> Result<(), BleError> {
+        self.set_extended_advertising_random_address_operation(set, addr)?
+            .wait_for(timeout)
+    }
+
+    pub async fn set_extended_advertising_random_address_async(
+        &self,
+        set: &ExtendedAdvertisingSet,
+        addr: [u8; 6],
+    ) -> Result<(), BleError> {
+        self.set_extended_advertising_random_address_operation(set, addr)?
+            .await
+    }
+
+    fn set_extended_advertising_data_operation(
+        &self,
+        set: &ExtendedAdvertisingSet,
+        data: &[u8],
+    ) -> Result<Operation<()>, BleError> {
+        let (operation, cb) = gap_operation(|event| match event {
+            GapEvent::ExtendedAdvertisingDatasetComplete(status) => Some(status),
+            _ => None,
+        });
+        self.set_extended_advertising_data(set, data, cb)?;
+        Ok(operation.cancel_gap(GapCallbacks::ExtendedAdvertisingDataset))
+    }
+
+    pub fn set_extended_advertising_data_blocking(
+        &self,
+        set: &ExtendedAdvertisingSet,
+        data: &[u8],
+        timeout: Duration,
+    ) -> Result<(), BleError> {
+        self.set_extended_advertising_data_operation(set, data)?
+            .wait_for(timeout)
+    }
+
+    pub async fn set_extended_advertising_data_async(
+        &self,
+        set: &ExtendedAdvertisingSet,
+        data: &[u8],
+    ) -> Result<(), BleError> {
+        self.set_extended_advertising_data_operation(set, data)?
+            .await
+    }
+
+    fn set_extended_scan_response_data_operation(
+        &self,
+        set: &ExtendedAdvertisingSet,
+        data: &[u8],
+    ) -> Result<Operation<()>, BleError> {
+        let (operation, cb) = gap_operation(|event| match event {
+            GapEvent::ExtendedScanResponseDatasetComplete(status) => Some(status),
+            _ => None,
+        });
+        self.set_extended_scan_response_data(set, data, cb)?;
+        Ok(operation.cancel_gap(GapCallbacks::ExtendedScanResponseDataset))
+    }
+
+    pub fn set_extended_scan_response_data_blocking(
+        &self,
+        set: &ExtendedAdvertisingSet,
+        data: &[u8],
+        timeout: Duration,
+    ) -> Result<(), BleError> {
+        self.set_extended_scan_response_data_operation(set, data)?
+            .wait_for(timeout)
+    }
+
+    pub async fn set_extended_scan_response_data_async(
+        &self,
+        set: &ExtendedAdvertisingSet,
+        data: &[u8],
+    ) -> Result<(), BleError> {
+        self.set_extended_scan_response_data_operation(set, data)?
+            .await
+    }
+
+    fn start_extended_advertising_operation(
+        &self,
+        sets: &[(&ExtendedAdvertisingSet, ExtendedAdvertisingDuration)],
+    ) -> Result<Operation<()>, BleError> {
+        let (operation, cb) = gap_operation(|event| match event {
+            GapEvent::ExtendedAdvertisingStartComplete(status) => Some(status),
+            _ => None,
+        });
+        self.start_extended_advertising(sets, cb)?;
+        Ok(operation.cancel_gap(GapCallbacks::ExtendedAdvertisingStart))
+    }
+
+    pub fn start_extended_advertising_blocking(
+        &self,
+        sets: &[(&ExtendedAdvertisingSet, ExtendedAdvertisingDuration)],
+        timeout: Duration,
+    ) -> Result<(), BleError> {
+        self.start_extended_advertising_operation(sets)?
+            .wait_for(timeout)
+    }
+
+    pub async fn start_extended_advertising_async(
+        &self,
+        sets: &[(&ExtendedAdvertisingSet, ExtendedAdvertisingDuration)],
+    ) -> Result<(), BleError> {
+        self.start_extended_advertising_operation(sets)?.await
+    }
+
+    fn stop_extended_advertising_operation(
+        &self,
+        sets: &[&ExtendedAdvertisingSet],
+    ) -> Result<Operation<()>, BleError> {
+        let (operation, cb) = gap_operation(|event| match event {
+            GapEvent::ExtendedAdvertisingStopComplete(status) => Some(status),
+            _ => None,
+        });
+        self.stop_extended_advertising(sets, cb)?;
+        Ok(operation.cancel_gap(GapCallbacks::ExtendedAdvertisingStop))
+    }
+
+    pub fn stop_extended_advertising_blocking(
+        &self,
+        sets: &[&ExtendedAdvertisingSet],
+        timeout: Duration,
+    ) -> Result<(), BleError> {
+        self.stop_extended_advertising_operation(sets)?
+            .wait_for(timeout)
+    }
+
+    pub async fn stop_extended_advertising_async(
+        &self,
+        sets: &[&ExtendedAdvertisingSet],
+    ) -> Result<(), BleError> {
+        self.stop_extended_advertising_operation(sets)?.await
+    }
+
+    fn remove_extended_advertising_set_operation(
+        &self,
+        set: ExtendedAdvertisingSet,
+    ) -> Result<Operation<()>, BleError> {
+        let (operation, cb) = gap_operation(|event| match event {
+            GapEvent::ExtendedAdvertisingSetRemoveComplete(status) => Some(status),
+            _ => None,
+        });
+        self.remove_extended_advertising_set(set, cb)?;
+        Ok(operation.cancel_gap(GapCallbacks::ExtendedAdvertisingRemove))
+    }
+
+    pub fn remove_extended_advertising_set_blocking(
+        &self,
+        set: ExtendedAdvertisingSet,
+        timeout: Duration,
+    ) -> Result<(), BleError> {
+        self.remove_extended_advertising_set_operation(set)?
+            .wait_for(timeout)
+    }
+
+    pub async fn remove_extended_advertising_set_async(
+        &self,
+        set: ExtendedAdvertisingSet,
+    ) -> Result<(), BleError> {
+        self.remove_extended_advertising_set_operation(set)?.await
+    }
+
+    fn clear_extended_advertising_sets_operation(&self) -> Result<Operation<()>, BleError> {
+        let (operation, cb) = gap_operation(|event| match event {
+            GapEvent::ExtendedAdvertisingSetClearComplete(status) => Some(status),
+            _ => None,
+        });
+        self.clear_extended_advertising_sets(cb)?;
+        Ok(operation.cancel_gap(GapCallbacks::ExtendedAdvertisingClear))
+    }
+
+    pub fn clear_extended_advertising_sets_blocking(
+        &self,
+        timeout: Duration,
+    ) -> Result<(), BleError> {
+        self.clear_extended_advertising_sets_operation()?
+            .wait_for(timeout)
+    }
+
+    pub async fn clear_extended_advertising_sets_async(&self) -> Result<(), BleError> {
+        self.clear_extended_advertising_sets_operation()?.await
+    }
+
+    fn set_periodic_advertising_params_operation(
+        &self,
+        set: &ExtendedAdvertisingSet,
+        params: PeriodicAdvertisingParameters,
+    ) -> Result<Operation<()>, BleError> {
+        let (operation, cb) = gap_operation(|event| match event {
+            GapEvent::PeriodicAdvertisingSetParamsComplete(status) => Some(status),
+            _ => None,
+        });
+        self.set_periodic_advertising_params(set, params, cb)?;
+        Ok(operation.cancel_gap(GapCallbacks::PeriodicAdvertisingParams))
+    }
+
+    pub fn set_periodic_advertising_params_blocking(
+        &self,
+        set: &ExtendedAdvertisingSet,
+        params: PeriodicAdvertisingParameters,
+        timeout: Duration,
+    ) -> Result<(), BleError> {
+        self.set_periodic_advertising_params_operation(set, params)?
+            .wait_for(timeout)
+    }
+
+    pub async fn set_periodic_advertising_params_async(
+        &self,
+        set: &ExtendedAdvertisingSet,
+        params: PeriodicAdvertisingParameters,
+    ) -> Result<(), BleError> {
+        self.set_periodic_advertising_params_operation(set, params)?
+            .await
+    }
+
+    fn set_periodic_advertising_data_operation(
+        &self,
+        set: &ExtendedAdvertisingSet,
+        data: &[u8],
+    ) -> Result<Operation<()>, BleError> {
+        let (operation, cb) = gap_operation(|event| match event {
+            GapEvent::PeriodicAdvertisingDatasetComplete(status) => Some(status),
+            _ => None,
+        });
+        self.set_periodic_advertising_data(set, data, cb)?;
+        Ok(operation.cancel_gap(GapCallbacks::PeriodicAdvertisingDataset))
+    }
+
+    pub fn set_periodic_advertising_data_blocking(
+        &self,
+        set: &ExtendedAdvertisingSet,
+        data: &[u8],
+        timeout: Duration,
+    ) -> Result<(), BleError> {
+        self.set_periodic_advertising_data_operation(set, data)?
+            .wait_for(timeout)
+    }
+
+    pub async fn set_periodic_advertising_data_async(
+        &self,
+        set: &ExtendedAdvertisingSet,
+        data: &[u8],
+    ) -> Result<(), BleError> {
+        self.set_periodic_advertising_data_operation(set, data)?
+            .await
+    }
+
+    fn start_periodic_advertising_operation(
+        &self,
+        set: &ExtendedAdvertisingSet,
+    ) -> Result<Operation<()>, BleError> {
+        let (operation, cb) = gap_operation(|event| match event {
+            GapEvent::PeriodicAdvertisingStartComplete(status) => Some(status),
+            _ => None,
+        });
+        self.start_periodic_advertising(set, cb)?;
+        Ok(operation.cancel_gap(GapCallbacks::PeriodicAdvertisingStart))
+    }
+
+    pub fn start_periodic_advertising_blocking(
+        &self,
+        set: &ExtendedAdvertisingSet,
+        timeout: Duration,
+    ) -> Result<(), BleError> {
+        self.start_periodic_advertising_operation(set)?
+            .wait_for(timeout)
+    }
+
+    pub async fn start_periodic_advertising_async(
+        &self,
+        set: &ExtendedAdvertisingSet,
+    ) -> Result<(), BleError> {
+        self.start_periodic_advertising_operation(set)?.await
+    }
+
+    fn stop_periodic_advertising_operation(
+        &self,
+        set: &ExtendedAdvertisingSet,
+    ) -> Result<Operation<()>, BleError> {
+        let (operation, cb) = gap_operation(|event| match event {
+            GapEvent::PeriodicAdvertisingStopComplete(status) => Some(status),
+            _ => None,
+        });
+        self.stop_periodic_advertising(set, cb)?;
+        Ok(operation.cancel_gap(GapCallbacks::PeriodicAdvertisingStop))
+    }
+
+    pub fn stop_periodic_advertising_blocking(
+        &self,
+        set: &ExtendedAdvertisingSet,
+        timeout: Duration,
+    ) -> Result<(), BleError> {
+        self.stop_periodic_advertising_operation(set)?
+            .wait_for(timeout)
+    }
+
+    pub async fn stop_periodic_advertising_async(
+        &self,
+        set: &ExtendedAdvertisingSet,
+    ) -> Result<(), BleError> {
+        self.stop_periodic_advertising_operation(set)?.await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+
+    use super::*;
+    use crate::completion::block_on;
+    use crate::simulated::simulated_ble;
+    use crate::{
+        AttributeValue, AutoResponse, BtStatus, BtUuid, CharacteristicBuilder, GattStatus,
+        RemoteCharacteristic, ServiceBuilder, ServiceEvent, StateError, CCCD_UUID,
+    };
+
+    const TIMEOUT: Duration = Duration::from_millis(10);
+
+    #[test]
+    fn blocking_gatt_server() {
+        let mut t = simulated_ble();
+
+        let gatts_if = t
+            .ble
+            .register_gatt_service_application_blocking(1, TIMEOUT)
+            .unwrap();
+        assert_eq!(gatts_if, 3);
+
+        let svc = GattService::new_primary(BtUuid::Uuid16(0x180f), 4, 0);
+        let svc_handle = t
+            .ble
+            .create_service_blocking(gatts_if, svc, TIMEOUT)
+            .unwrap();
+        let charac = GattCharacteristic::new(
+            BtUuid::Uuid16(0x2a19),
+            0x01,
+            0x12,
+            AttributeValue::<1>::new_with_value(&[42]),
+            AutoResponse::ByGatt,
+        );
+        let char_handle = t
+            .ble
+            .add_characteristic_blocking(svc_handle, charac, TIMEOUT)
+            .unwrap();
+        let cccd = GattDescriptor::new(BtUuid::Uuid16(CCCD_UUID), 0x11);
+        let cccd_handle = t
+            .ble
+            .add_descriptor_blocking(svc_handle, cccd, TIMEOUT)
+            .unwrap();
+        assert_eq!((svc_handle, char_handle, cccd_handle), (40, 42, 43));
+
+        assert_eq!(t.ble.start_service_blocking(svc_handle, TIMEOUT), Ok(()));
+    }
+
+    #[test]
+    fn async_gatt_server() {
+        let mut t = simulated_ble();
+
+        let gatts_if = block_on(t.ble.register_gatt_service_application_async(1)).unwrap();
+        let table = ServiceBuilder::primary(BtUuid::Uuid16(0x180f))
+            .characteristic(
+                CharacteristicBuilder::new("level", BtUuid::Uuid16(0x2a19))
+                    .properties(0x12)
+                    .permissions(0x01)
+                    .value(&[100])
+                    .cccd(),
+            )
+            .build()
+            .unwrap();
+        let handles = block_on(t.ble.create_attribute_table_async(gatts_if, table)).unwrap();
+        assert_eq!(handles.handle("level"), Some(42));
+
+        let params = AdvertisingParameters::default();
+        assert_eq!(block_on(t.ble.start_advertise_async(params)), Ok(()));
+        assert_eq!(block_on(t.ble.stop_advertise_async()), Ok(()));
+    }
+
+    #[test]
+    fn completion_status_is_checked() {
+        let mut t = simulated_ble();
+        let gatts_if = t
+            .ble
+            .register_gatt_service_application_blocking(1, TIMEOUT)
+            .unwrap();
+        let svc = GattService::new_primary(BtUuid::Uuid16(0x180f), 4, 0);
+        let svc_handle = t
+            .ble
+            .create_service_blocking(gatts_if, svc, TIMEOUT)
+            .unwrap();
+
+        t.sim.hold_events();
+        let started = t
+            .ble
+            .start_advertise_operation(AdvertisingParameters::default())
+            .unwrap();
+        t.sim
+            .inject_gap_event(GapEvent::AdvertisingStartComplete(BtStatus::Busy));
+        assert_eq!(started.wait_for(TIMEOUT), Err(BleError::Bt(BtStatus::Busy)));
+
+        let started = t.ble.start_service_operation(svc_handle).unwrap();
+        t.sim.inject_gatts_event(
+            gatts_if,
+            GattServiceEvent::StartComplete(ServiceEvent {
+                status: GattStatus::Unlikely,
+                service_handle: svc_handle,
+            }),
+        );
+        assert_eq!(
+            started.wait_for(TIMEOUT),
+            Err(BleError::Gatt(GattStatus::Unlikely))
+        );
+    }
+
+    #[test]
+    fn pending_operations_are_serialized() {
+        let t = simulated_ble();
+        let params = AdvertisingParameters::default();
+
+        t.sim.hold_events();
+        let started = t.ble.start_advertise_operation(params).unwrap();
+        assert_eq!(
+            t.ble.start_advertise_blocking(params, TIMEOUT),
+            Err(BleError::InvalidState(StateError::OperationPending))
+        );
+        assert_eq!(t.ble.stop_advertise_operation().map(|_| ()), Ok(()));
+
+        // Timed out or dropped, the operation no longer blocks the next one
+        assert_eq!(started.wait_for(TIMEOUT), Err(BleError::Timeout));
+        drop(t.ble.start_advertise_operation(params).unwrap());
+        let started = t.ble.start_advertise_operation(params).unwrap();
+        t.sim
+            .inject_gap_event(GapEvent::AdvertisingStartComplete(BtStatus::Success));
+        assert_eq!(block_on(started), Ok(()));
+    }
+
+    #[test]
+    fn scanning() {
+        let t = simulated_ble();
+        let (s, _results) = channel();
+
+        let params = ScanParameters::default();
+        assert_eq!(
+            t.ble
+                .start_scan_blocking(params, 0, TIMEOUT, move |result| {
+                    s.send(result).unwrap()
+                }),
+            Ok(())
+        );
+
+        t.sim.hold_events();
+        assert_eq!(t.ble.stop_scan_blocking(TIMEOUT), Err(BleError::Timeout));
+        assert_eq!(t.ble.stop_scan_blocking(TIMEOUT), Err(BleError::Timeout));
+    }
+
+    #[test]
+    fn blocking_gatt_client() {
+        let mut t = simulated_ble();
+        let level = RemoteCharacteristic {
+            uuid: BtUuid::Uuid16(0x2a19),
+            handle: 3,
+            properties: 0x0a,
+        };
+        t.sim.add_remote_characteristic(level.clone(), &[42]);
+
+        let gattc_if = t
+            .ble
+            .register_gatt_client_application_blocking(1, TIMEOUT)
+            .unwrap();
+        let peer = BdAddr([0x11, 0x22, 0x33, 0x44, 0x55, 0x66]);
+        let conn_id = t
+            .ble
+            .open_blocking(gattc_if, peer, AddressType::Public, TIMEOUT)
+            .unwrap();
+        assert_eq!(
+            t.ble
+                .search_services_blocking(gattc_if, conn_id, None, TIMEOUT),
+            Ok(())
+        );
+
+        assert_eq!(
+            t.ble
+                .read_characteristic_blocking(gattc_if, conn_id, level.handle, TIMEOUT),
+            Ok(vec![42])
+        );
+        assert_eq!(
+            t.ble.write_characteristic_blocking(
+                gattc_if,
+                conn_id,
+                level.handle,
+                &[7],
+                WriteType::WithResponse,
+                TIMEOUT
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            block_on(
+                t.ble
+                    .read_characteristic_async(gattc_if, conn_id, level.handle)
+            ),
+            Ok(vec![7])
+        );
+        assert_eq!(
+            t.ble
+                .read_descriptor_blocking(gattc_if, conn_id, 4, TIMEOUT),
+            Err(BleError::Gatt(GattStatus::InvalidHandle))
+        );
+    }
+
+    #[test]
+    fn gatt_operations_given_up_drop_their_callback() {
+        let mut t = simulated_ble();
+        let (s, r) = channel();
+        let late = s.clone();
+        t.ble.register_gatts_catch_all_handler(move |_, event| {
+            late.send(format!("{event:?}")).unwrap()
+        });
+        t.ble.register_gattc_catch_all_handler(move |_, event| {
+            s.send(format!("{event:?}")).unwrap()
+        });
+
+        let gatts_if = t
+            .ble
+            .register_gatt_service_application_blocking(1, TIMEOUT)
+            .unwrap();
+        let svc = GattService::new_primary(BtUuid::Uuid16(0x180f), 6, 0);
+        let svc_handle = t
+            .ble
+            .create_service_blocking(gatts_if, svc, TIMEOUT)
+            .unwrap();
+        let gattc_if = t
+            .ble
+            .register_gatt_client_application_blocking(2, TIMEOUT)
+            .unwrap();
+        let peer = BdAddr([0x11, 0x22, 0x33, 0x44, 0x55, 0x66]);
+        let conn_id = t
+            .ble
+            .open_blocking(gattc_if, peer, AddressType::Public, TIMEOUT)
+            .unwrap();
+
+        // The completions of the operations given up no longer reach their
+        // callback, and the characteristic added next still gets its own.
+        t.sim.hold_events();
+        let charac = || {
+            GattCharacteristic::new(
+                BtUuid::Uuid16(0x2a19),
+                0x01,
+                0x12,
+                AttributeValue::<1>::new_with_value(&[42]),
+                AutoResponse::ByGatt,
+            )
+        };
+        let first = t
+            .ble
+            .add_characteristic_operation(svc_handle, charac())
+            .unwrap();
+        let second = t
+            .ble
+            .add_characteristic_operation(svc_handle, charac())
+            .unwrap();
+        assert_eq!(
+            t.ble
+                .read_characteristic_blocking(gattc_if, conn_id, 3, TIMEOUT),
+            Err(BleError::Timeout)
+        );
+        drop(first);
+        t.sim.release_events();
+
+        assert_eq!(second.wait_for(TIMEOUT), Ok(44));
+        let late: Vec<String> = r.try_iter().collect();
+        assert_eq!(late.len(), 1);
+        assert!(late[0].starts_with("ReadCharacteristic"));
+    }
+}
@@ -0,0 +1,202 @@
+#[cfg(target_os = "espidf")]
+use esp_idf_sys::*;
+
+use crate::{AdParseError, AddressType, AdvertiseType, BdAddr, Eddystone, IBeacon};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScanType {
+    Passive,
+    Active,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScanFilterPolicy {
+    AllowAll,
+    AllowOnlyWhitelist,
+    AllowUndirectedRpa,
+    AllowWhitelistAndRpa,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScanParameters {
+    pub scan_type: ScanType,
+    pub own_addr_type: AddressType,
+    pub filter_policy: ScanFilterPolicy,
+    /// Scan interval in 0.625ms units (0x0004 - 0x4000)
+    pub interval: u16,
+    /// Scan window in 0.625ms units, must not be larger than `interval`
+    pub window: u16,
+    pub filter_duplicates: bool,
+}
+
+impl Default for ScanParameters {
+    fn default() -> Self {
+        Self {
+            scan_type: ScanType::Active,
+            own_addr_type: AddressType::Public,
+            filter_policy: ScanFilterPolicy::AllowAll,
+            interval: 0x50,
+            window: 0x30,
+            filter_duplicates: false,
+        }
+    }
+}
+
+#[cfg(target_os = "espidf")]
+impl From<ScanParameters> for esp_ble_scan_params_t {
+    fn from(params: ScanParameters) -> Self {
+        Self {
+            scan_type: match params.scan_type {
+                ScanType::Passive => esp_ble_scan_type_t_BLE_SCAN_TYPE_PASSIVE,
+                ScanType::Active => esp_ble_scan_type_t_BLE_SCAN_TYPE_ACTIVE,
+            },
+            own_addr_type: params.own_addr_type.into(),
+            scan_filter_policy: match params.filter_policy {
+                ScanFilterPolicy::AllowAll => esp_ble_scan_filter_t_BLE_SCAN_FILTER_ALLOW_ALL,
+                ScanFilterPolicy::AllowOnlyWhitelist => {
+                    esp_ble_scan_filter_t_BLE_SCAN_FILTER_ALLOW_ONLY_WLST
+                }
+                ScanFilterPolicy::AllowUndirectedRpa => {
+                    esp_ble_scan_filter_t_BLE_SCAN_FILTER_ALLOW_UND_RPA_DIR
+                }
+                ScanFilterPolicy::AllowWhitelistAndRpa => {
+                    esp_ble_scan_filter_t_BLE_SCAN_FILTER_ALLOW_WLIST_RPA_DIR
+                }
+            },
+            scan_interval: params.interval,
+            scan_window: params.window,
+            scan_duplicate: if params.filter_duplicates {
+                esp_ble_scan_duplicate_t_BLE_SCAN_DUPLICATE_ENABLE
+            } else {
+                esp_ble_scan_duplicate_t_BLE_SCAN_DUPLICATE_DISABLE
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScanEventType {
+    ConnectableAdvertising,
+    ConnectableDirectedAdvertising,
+    ScannableAdvertising,
+    NonConnectableAdvertising,
+    ScanResponse,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScanResult {
+    pub bda: BdAddr,
+    pub addr_type: AddressType,
+    pub rssi: i32,
+    pub event_type: ScanEventType,
+    pub adv_data: Vec<u8>,
+    pub scan_rsp_data: Vec<u8>,
+}
+
+impl ScanResult {
+    /// Split the advertising data, followed by the scan response data, into
+    /// `(ad_type, data)` structures. A truncated trailing structure is dropped.
+    pub fn ad_structures(&self) -> Vec<(u8, &[u8])> {
+        let mut structures = vec![];
+        for payload in [&self.adv_data, &self.scan_rsp_data] {
+            let mut rest = payload.as_slice();
+            while let Some((&len, tail)) = rest.split_first() {
+                let len = len as usize;
+                if len == 0 || len > tail.len() {
+                    break;
+                }
+                structures.push((tail[0], &tail[1..len]));
+                rest = &tail[len..];
+            }
+        }
+        structures
+    }
+
+    /// Decode the advertising data.
+    pub fn advertise_data(&self) -> Result<Vec<AdvertiseType>, AdParseError> {
+        AdvertiseType::parse_all(&self.adv_data)
+    }
+
+    /// Decode the scan response data.
+    pub fn scan_response_data(&self) -> Result<Vec<AdvertiseType>, AdParseError> {
+        AdvertiseType::parse_all(&self.scan_rsp_data)
+    }
+
+    /// The iBeacon advertised by the device, if any.
+    pub fn ibeacon(&self) -> Option<IBeacon> {
+        IBeacon::parse(&self.advertise_data().ok()?)
+    }
+
+    /// The Eddystone frame advertised by the device, if any.
+    pub fn eddystone(&self) -> Option<Eddystone> {
+        Eddystone::parse(&self.advertise_data().ok()?)
+    }
+
+    /// The complete local name if advertised, otherwise the shortened one.
+    pub fn name(&self) -> Option<String> {
+        let structures = self.ad_structures();
+        structures
+            .iter()
+            .find(|(ad_type, _)| *ad_type == 0x09)
+            .or_else(|| structures.iter().find(|(ad_type, _)| *ad_type == 0x08))
+            .map(|(_, name)| String::from_utf8_lossy(name).into_owned())
+    }
+}
+
+/// Scan event reported by the GAP layer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ScanSearchEvent {
+    /// An advertising report
+    Result(ScanResult),
+    /// The scan duration has elapsed
+    Complete { num_resps: i32 },
+    /// Any other search event, with its raw `esp_gap_search_evt_t` code
+    Other(u32),
+}
+
+#[cfg(target_os = "espidf")]
+impl ScanResult {
+    /// Build a `ScanResult` out of an inquiry result event.
+    #[allow(non_upper_case_globals)]
+    fn from_event(scan_rst: &esp_ble_gap_cb_param_t_ble_scan_result_evt_param) -> Self {
+        let adv_len = (scan_rst.adv_data_len as usize).min(scan_rst.ble_adv.len());
+        let scan_rsp_end = (adv_len + scan_rst.scan_rsp_len as usize).min(scan_rst.ble_adv.len());
+        let adv_data = scan_rst.ble_adv[..adv_len].to_vec();
+        let scan_rsp_data = scan_rst.ble_adv[adv_len..scan_rsp_end].to_vec();
+
+        Self {
+            bda: scan_rst.bda.into(),
+            addr_type: scan_rst.ble_addr_type.into(),
+            rssi: scan_rst.rssi,
+            event_type: match scan_rst.ble_evt_type {
+                esp_ble_evt_type_t_ESP_BLE_EVT_CONN_ADV => ScanEventType::ConnectableAdvertising,
+                esp_ble_evt_type_t_ESP_BLE_EVT_CONN_DIR_ADV => {
+                    ScanEventType::ConnectableDirectedAdvertising
+                }
+                esp_ble_evt_type_t_ESP_BLE_EVT_DISC_ADV => ScanEventType::ScannableAdvertising,
+                esp_ble_evt_type_t_ESP_BLE_EVT_NON_CONN_ADV => {
+                    ScanEventType::NonConnectableAdvertising
+                }
+                _ => ScanEventType::ScanResponse,
+            },
+            adv_data,
+            scan_rsp_data,
+        }
+    }
+}
+
+#[cfg(target_os = "espidf")]
+impl From<&esp_ble_gap_cb_param_t_ble_scan_result_evt_param> for ScanSearchEvent {
+    #[allow(non_upper_case_globals)]
+    fn from(scan_rst: &esp_ble_gap_cb_param_t_ble_scan_result_evt_param) -> Self {
+        match scan_rst.search_evt {
+            esp_gap_search_evt_t_ESP_GAP_SEARCH_INQ_RES_EVT => {
+                ScanSearchEvent::Result(ScanResult::from_event(scan_rst))
+            }
+            esp_gap_search_evt_t_ESP_GAP_SEARCH_INQ_CMPL_EVT => ScanSearchEvent::Complete {
+                num_resps: scan_rst.num_resps,
+            },
+            other => ScanSearchEvent::Other(other),
+        }
+    }
+}
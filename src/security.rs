@@ -1,6 +1,8 @@
-use std::ops::BitOr;
+use bitflags::bitflags;
 
-#[derive(Default)]
+use crate::BdAddr;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 #[repr(u8)]
 pub enum IOCapabilities {
     #[default]
@@ -11,7 +13,7 @@ pub enum IOCapabilities {
     Keyboard = 4,
 }
 
-#[derive(Default)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 #[repr(u8)]
 pub enum AuthenticationRequest {
     #[default]
@@ -25,67 +27,251 @@ pub enum AuthenticationRequest {
     SecureMitmBonding = 0b0000_0111,
 }
 
-#[repr(u8)]
-pub enum KeyMask {
-    EncryptionKey = 0b0000_0001,
-    IdentityResolvingKey = 0b0000_0010,
-    ConnectionSignatureResolvingKey = 0b0000_0100,
-    LinkKey = 0b0000_1000,
-    Inner0011 = 0b0000_0011,
-    Inner0101 = 0b0000_0101,
-    Inner1001 = 0b0000_1001,
-    Inner1010 = 0b0000_1010,
-    Inner1100 = 0b0000_1100,
-    Inner1101 = 0b0000_1101,
-    Inner1011 = 0b0000_1011,
-    Inner1111 = 0b0000_1111,
-}
-
-impl BitOr for KeyMask {
-    type Output = KeyMask;
-
-    fn bitor(self, rhs: Self) -> Self::Output {
-        (self as u8 | rhs as u8).into()
-    }
-}
-
-impl From<u8> for KeyMask {
-    fn from(from: u8) -> Self {
-        match from {
-            0b0000_0001 => KeyMask::EncryptionKey,
-            0b0000_0010 => KeyMask::IdentityResolvingKey,
-            0b0000_0100 => KeyMask::ConnectionSignatureResolvingKey,
-            0b0000_1000 => KeyMask::LinkKey,
-            0b0000_0011 => KeyMask::Inner0011,
-            0b0000_0101 => KeyMask::Inner0101,
-            0b0000_1001 => KeyMask::Inner1001,
-            0b0000_1010 => KeyMask::Inner1010,
-            0b0000_1100 => KeyMask::Inner1100,
-            0b0000_1101 => KeyMask::Inner1101,
-            0b0000_1011 => KeyMask::Inner1011,
-            0b0000_1111 => KeyMask::Inner1111,
-            _ => unimplemented!("This does not correspond to a valid KeyMask")
-        }
+bitflags! {
+    /// Keys distributed when bonding, mirrors `esp_ble_key_mask_t`.
+    #[derive(Default)]
+    pub struct KeyMask: u8 {
+        const ENCRYPTION_KEY = 0b0000_0001;
+        const IDENTITY_RESOLVING_KEY = 0b0000_0010;
+        const CONNECTION_SIGNATURE_RESOLVING_KEY = 0b0000_0100;
+        const LINK_KEY = 0b0000_1000;
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[repr(u32)]
 pub enum BleEncryption {
     Encryption = 0x01,
     EncryptionNoMitm = 0x02,
-    EncryptionMitm = 0x03
+    EncryptionMitm = 0x03,
 }
 
-#[derive(Default)]
+/// Smallest encryption key size, in bytes.
+pub const MIN_KEY_SIZE: u8 = 7;
+
+/// Largest encryption key size, in bytes.
+pub const MAX_KEY_SIZE: u8 = 16;
+
+/// Largest static passkey, they have 6 digits.
+pub const MAX_PASSKEY: u32 = 999_999;
+
+/// Security Manager configuration set by `EspBle::configure_security`, the
+/// fields left to `None` keep the value of the stack.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub struct SecurityConfig {
     pub auth_req_mode: AuthenticationRequest,
     pub io_capabilities: IOCapabilities,
     pub initiator_key: Option<KeyMask>,
     pub responder_key: Option<KeyMask>,
+    /// Key size in `MIN_KEY_SIZE..=MAX_KEY_SIZE`
     pub max_key_size: Option<u8>,
+    /// Key size in `MIN_KEY_SIZE..=MAX_KEY_SIZE`
     pub min_key_size: Option<u8>,
+    /// Passkey used instead of a random one, `None` clears it
     pub static_passkey: Option<u32>,
     pub only_accept_specified_auth: bool,
     pub enable_oob: bool,
-    // app_key_size: u8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SecurityConfigError {
+    /// The key size is outside of `MIN_KEY_SIZE..=MAX_KEY_SIZE`
+    KeySizeOutOfRange(u8),
+    /// `min_key_size` is greater than `max_key_size`
+    InvalidKeySizeRange { min: u8, max: u8 },
+    /// The static passkey is greater than `MAX_PASSKEY`
+    PasskeyOutOfRange(u32),
+    /// OOB is enabled with `IOCapabilities::NoInputNoOutput`
+    OobWithoutInputOutput,
+}
+
+impl std::fmt::Display for SecurityConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SecurityConfigError::KeySizeOutOfRange(size) => write!(
+                f,
+                "Key size {} is not in {}..={}",
+                size, MIN_KEY_SIZE, MAX_KEY_SIZE
+            ),
+            SecurityConfigError::InvalidKeySizeRange { min, max } => write!(
+                f,
+                "Minimum key size {} is greater than the maximum {}",
+                min, max
+            ),
+            SecurityConfigError::PasskeyOutOfRange(passkey) => write!(
+                f,
+                "Static passkey {} is greater than {}",
+                passkey, MAX_PASSKEY
+            ),
+            SecurityConfigError::OobWithoutInputOutput => {
+                write!(f, "OOB can't be enabled without input or output")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SecurityConfigError {}
+
+impl SecurityConfig {
+    pub fn validate(&self) -> Result<(), SecurityConfigError> {
+        for size in [self.min_key_size, self.max_key_size].into_iter().flatten() {
+            if !(MIN_KEY_SIZE..=MAX_KEY_SIZE).contains(&size) {
+                return Err(SecurityConfigError::KeySizeOutOfRange(size));
+            }
+        }
+        if let (Some(min), Some(max)) = (self.min_key_size, self.max_key_size) {
+            if min > max {
+                return Err(SecurityConfigError::InvalidKeySizeRange { min, max });
+            }
+        }
+        if let Some(passkey) = self.static_passkey {
+            if passkey > MAX_PASSKEY {
+                return Err(SecurityConfigError::PasskeyOutOfRange(passkey));
+            }
+        }
+        if self.enable_oob && self.io_capabilities == IOCapabilities::NoInputNoOutput {
+            return Err(SecurityConfigError::OobWithoutInputOutput);
+        }
+        Ok(())
+    }
+}
+
+/// Length of the values exchanged out of band: the temporary key of legacy
+/// pairing, and the confirm and random values of Secure Connections.
+pub const OOB_VALUE_LEN: usize = 16;
+
+/// Temporary key of a legacy pairing, exchanged out of band.
+pub type OobTemporaryKey = [u8; OOB_VALUE_LEN];
+
+/// Out of band data of a device for an LE Secure Connections pairing.
+///
+/// The local data is created with `EspBle::create_local_oob_data` and passed
+/// to the peer, e.g. over NFC, the data of the peer is given back by
+/// `PairingDelegate::on_sc_oob_request`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScOobData {
+    pub confirm: [u8; OOB_VALUE_LEN],
+    pub random: [u8; OOB_VALUE_LEN],
+}
+
+/// Why a pairing failed, with the SMP reason code reported by the stack.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PairingFailure {
+    pub reason: u8,
+}
+
+impl std::fmt::Display for PairingFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Pairing failed with SMP reason {:#04x}", self.reason)
+    }
+}
+
+impl std::error::Error for PairingFailure {}
+
+/// Answers the pairing requests of the peers, set with
+/// `EspBle::set_pairing_delegate`, e.g. to show a passkey on a display and
+/// wait for a button press.
+///
+/// The requests, the methods returning an answer, are called from a thread
+/// of their own and can block until the user answers, within the 30s SMP
+/// timeout. The other methods are called from the Bluetooth task and must
+/// return quickly.
+pub trait PairingDelegate: Send + Sync {
+    /// A peer asks to pair, `false` rejects it.
+    fn on_security_request(&self, _bda: BdAddr) -> bool {
+        true
+    }
+
+    /// Show `passkey`, for the user to enter it on the peer.
+    fn on_passkey_display(&self, _bda: BdAddr, _passkey: u32) {}
+
+    /// Passkey shown by the peer, as entered by the user, `None` rejects the
+    /// pairing.
+    fn on_passkey_request(&self, _bda: BdAddr) -> Option<u32> {
+        None
+    }
+
+    /// Whether the user confirms that the peer shows `passkey` too.
+    fn on_numeric_comparison(&self, bda: BdAddr, passkey: u32) -> bool;
+
+    /// Temporary key of a legacy pairing, received out of band, `None`
+    /// rejects the pairing.
+    fn on_oob_request(&self, _bda: BdAddr) -> Option<OobTemporaryKey> {
+        None
+    }
+
+    /// Secure Connections data of the peer, received out of band, `None`
+    /// rejects the pairing.
+    fn on_sc_oob_request(&self, _bda: BdAddr) -> Option<ScOobData> {
+        None
+    }
+
+    fn on_pairing_complete(&self, _bda: BdAddr, _result: Result<(), PairingFailure>) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_masks_combine() {
+        let mask = KeyMask::IDENTITY_RESOLVING_KEY | KeyMask::CONNECTION_SIGNATURE_RESOLVING_KEY;
+        assert_eq!(mask.bits(), 0b0110);
+        assert_eq!(KeyMask::from_bits(0b1111), Some(KeyMask::all()));
+        assert_eq!(KeyMask::from_bits(0b1_0000), None);
+    }
+
+    #[test]
+    fn security_config_validation() {
+        let config = SecurityConfig {
+            min_key_size: Some(MIN_KEY_SIZE),
+            max_key_size: Some(MAX_KEY_SIZE),
+            static_passkey: Some(MAX_PASSKEY),
+            ..Default::default()
+        };
+        assert_eq!(config.validate(), Ok(()));
+        assert_eq!(SecurityConfig::default().validate(), Ok(()));
+
+        for size in [6, 17] {
+            assert_eq!(
+                SecurityConfig {
+                    min_key_size: Some(size),
+                    ..config
+                }
+                .validate(),
+                Err(SecurityConfigError::KeySizeOutOfRange(size))
+            );
+        }
+        assert_eq!(
+            SecurityConfig {
+                min_key_size: Some(12),
+                max_key_size: Some(10),
+                ..config
+            }
+            .validate(),
+            Err(SecurityConfigError::InvalidKeySizeRange { min: 12, max: 10 })
+        );
+        assert_eq!(
+            SecurityConfig {
+                static_passkey: Some(1_000_000),
+                ..config
+            }
+            .validate(),
+            Err(SecurityConfigError::PasskeyOutOfRange(1_000_000))
+        );
+
+        let oob = SecurityConfig {
+            enable_oob: true,
+            ..config
+        };
+        assert_eq!(oob.validate(), Ok(()));
+        assert_eq!(
+            SecurityConfig {
+                io_capabilities: IOCapabilities::NoInputNoOutput,
+                ..oob
+            }
+            .validate(),
+            Err(SecurityConfigError::OobWithoutInputOutput)
+        );
+    }
 }
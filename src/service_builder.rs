@@ -0,0 +1,613 @@
+use crate::notification::CCCD_UUID;
+use crate::{AutoResponse, BtUuid};
+
+const PRIMARY_SERVICE_UUID: u16 = 0x2800;
+const SECONDARY_SERVICE_UUID: u16 = 0x2801;
+const INCLUDE_UUID: u16 = 0x2802;
+const CHARACTERISTIC_UUID: u16 = 0x2803;
+
+/// `ESP_GATT_PERM_READ`, for the declarations
+const PERM_READ: u16 = 0x01;
+/// `ESP_GATT_PERM_WRITE`
+const PERM_WRITE: u16 = 0x10;
+
+/// Bluedroid takes the number of attributes of a table on a byte.
+pub const ATTRIBUTE_TABLE_MAX_LEN: usize = 255;
+
+/// Bluetooth base UUID, little endian, the 16 and 32 bits UUIDs take the 4
+/// last bytes.
+const BASE_UUID: [u8; 16] = [
+    0xfb, 0x34, 0x9b, 0x5f, 0x80, 0x00, 0x00, 0x80, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// `uuid` as it is stored in an attribute table, on 2 or 16 bytes.
+pub(crate) fn uuid_bytes(uuid: &BtUuid) -> Vec<u8> {
+    match uuid {
+        BtUuid::Uuid16(uuid) => uuid.to_le_bytes().to_vec(),
+        BtUuid::Uuid32(uuid) => {
+            let mut bytes = BASE_UUID;
+            bytes[12..].copy_from_slice(&uuid.to_le_bytes());
+            bytes.to_vec()
+        }
+        BtUuid::Uuid128(uuid) => uuid.to_vec(),
+    }
+}
+
+/// One entry of an attribute table, mirrors `esp_gatts_attr_db_t`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Attribute {
+    pub uuid: BtUuid,
+    pub permissions: u16,
+    pub max_len: u16,
+    pub value: Vec<u8>,
+    pub auto_rsp: AutoResponse,
+}
+
+impl Attribute {
+    /// Declarations are read only and answered by the stack.
+    fn declaration(uuid: u16, value: Vec<u8>) -> Self {
+        Self {
+            uuid: BtUuid::Uuid16(uuid),
+            permissions: PERM_READ,
+            max_len: value.len() as _,
+            value,
+            auto_rsp: AutoResponse::ByGatt,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ServiceBuilderError {
+    /// Two characteristics of the service have the same name
+    DuplicateName(String),
+    /// The initial value of an attribute is longer than its maximum length
+    ValueTooLong {
+        uuid: BtUuid,
+        len: usize,
+        max_len: usize,
+    },
+    /// The service needs more than `ATTRIBUTE_TABLE_MAX_LEN` attributes
+    TooManyAttributes(usize),
+}
+
+impl std::fmt::Display for ServiceBuilderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ServiceBuilderError::DuplicateName(name) => {
+                write!(f, "Two characteristics are named {}", name)
+            }
+            ServiceBuilderError::ValueTooLong { uuid, len, max_len } => write!(
+                f,
+                "Value of {:?} too long: {} bytes, at most {} are allowed",
+                uuid, len, max_len
+            ),
+            ServiceBuilderError::TooManyAttributes(len) => write!(
+                f,
+                "Service needs {} attributes, at most {} are allowed",
+                len, ATTRIBUTE_TABLE_MAX_LEN
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ServiceBuilderError {}
+
+/// A descriptor of a characteristic built by a `CharacteristicBuilder`.
+#[derive(Clone, Debug)]
+struct DescriptorDefinition {
+    uuid: BtUuid,
+    permissions: u16,
+    value: Vec<u8>,
+    max_len: Option<u16>,
+}
+
+/// Describe a characteristic of a `ServiceBuilder`, found back in the
+/// `ServiceHandles` by its name or its UUID.
+#[derive(Clone, Debug)]
+pub struct CharacteristicBuilder {
+    name: String,
+    uuid: BtUuid,
+    properties: u8,
+    permissions: u16,
+    value: Vec<u8>,
+    max_len: Option<u16>,
+    auto_rsp: AutoResponse,
+    descriptors: Vec<DescriptorDefinition>,
+}
+
+impl CharacteristicBuilder {
+    pub fn new(name: impl Into<String>, uuid: BtUuid) -> Self {
+        Self {
+            name: name.into(),
+            uuid,
+            properties: 0,
+            permissions: 0,
+            value: vec![],
+            max_len: None,
+            auto_rsp: AutoResponse::ByApp,
+            descriptors: vec![],
+        }
+    }
+
+    /// `ESP_GATT_CHAR_PROP_BIT_*` flags
+    pub fn properties(mut self, properties: u8) -> Self {
+        self.properties = properties;
+        self
+    }
+
+    /// `ESP_GATT_PERM_*` flags
+    pub fn permissions(mut self, permissions: u16) -> Self {
+        self.permissions = permissions;
+        self
+    }
+
+    pub fn value(mut self, value: &[u8]) -> Self {
+        self.value = value.to_vec();
+        self
+    }
+
+    /// Maximum length of the value, the length of the initial value by default.
+    pub fn max_len(mut self, max_len: u16) -> Self {
+        self.max_len = Some(max_len);
+        self
+    }
+
+    pub fn auto_response(mut self, auto_rsp: AutoResponse) -> Self {
+        self.auto_rsp = auto_rsp;
+        self
+    }
+
+    pub fn descriptor(mut self, uuid: BtUuid, permissions: u16, value: &[u8]) -> Self {
+        self.descriptors.push(DescriptorDefinition {
+            uuid,
+            permissions,
+            value: value.to_vec(),
+            max_len: None,
+        });
+        self
+    }
+
+    /// Add a Client Characteristic Configuration descriptor, for the clients to
+    /// subscribe to notifications or indications, see `EspBle::notify`.
+    pub fn cccd(mut self) -> Self {
+        self.descriptors.push(DescriptorDefinition {
+            uuid: BtUuid::Uuid16(CCCD_UUID),
+            permissions: PERM_READ | PERM_WRITE,
+            value: vec![0, 0],
+            max_len: Some(2),
+        });
+        self
+    }
+}
+
+/// A service included by a `ServiceBuilder`.
+#[derive(Clone, Debug)]
+struct IncludedService {
+    start_handle: u16,
+    end_handle: u16,
+    uuid: BtUuid,
+}
+
+/// Describe a whole service, to create it in one call with
+/// `EspBle::create_attribute_table`.
+#[derive(Clone, Debug)]
+pub struct ServiceBuilder {
+    uuid: BtUuid,
+    is_primary: bool,
+    instance_id: u8,
+    included: Vec<IncludedService>,
+    characteristics: Vec<CharacteristicBuilder>,
+}
+
+impl ServiceBuilder {
+    pub fn primary(uuid: BtUuid) -> Self {
+        Self {
+            uuid,
+            is_primary: true,
+            instance_id: 0,
+            included: vec![],
+            characteristics: vec![],
+        }
+    }
+
+    pub fn secondary(uuid: BtUuid) -> Self {
+        Self {
+            is_primary: false,
+            ..Self::primary(uuid)
+        }
+    }
+
+    pub fn instance_id(mut self, instance_id: u8) -> Self {
+        self.instance_id = instance_id;
+        self
+    }
+
+    /// Include a service created before.
+    pub fn include(mut self, service: &ServiceHandles) -> Self {
+        self.included.push(IncludedService {
+            start_handle: service.service_handle,
+            end_handle: service.end_handle,
+            uuid: service.uuid.clone(),
+        });
+        self
+    }
+
+    pub fn characteristic(mut self, characteristic: CharacteristicBuilder) -> Self {
+        self.characteristics.push(characteristic);
+        self
+    }
+
+    pub fn build(self) -> Result<AttributeTable, ServiceBuilderError> {
+        let mut attributes = vec![];
+        let mut layout = vec![];
+
+        let service_uuid = if self.is_primary {
+            PRIMARY_SERVICE_UUID
+        } else {
+            SECONDARY_SERVICE_UUID
+        };
+        attributes.push(Attribute::declaration(service_uuid, uuid_bytes(&self.uuid)));
+
+        for included in &self.included {
+            let mut value = vec![];
+            value.extend_from_slice(&included.start_handle.to_le_bytes());
+            value.extend_from_slice(&included.end_handle.to_le_bytes());
+            // Only the 16 bits UUIDs are part of the declaration
+            if let BtUuid::Uuid16(uuid) = included.uuid {
+                value.extend_from_slice(&uuid.to_le_bytes());
+            }
+            attributes.push(Attribute::declaration(INCLUDE_UUID, value));
+        }
+
+        for (i, characteristic) in self.characteristics.iter().enumerate() {
+            if self.characteristics[..i]
+                .iter()
+                .any(|other| other.name == characteristic.name)
+            {
+                return Err(ServiceBuilderError::DuplicateName(
+                    characteristic.name.clone(),
+                ));
+            }
+
+            attributes.push(Attribute::declaration(
+                CHARACTERISTIC_UUID,
+                vec![characteristic.properties],
+            ));
+            attributes.push(attribute(
+                &characteristic.uuid,
+                characteristic.permissions,
+                &characteristic.value,
+                characteristic.max_len,
+                characteristic.auto_rsp,
+            )?);
+            for descriptor in &characteristic.descriptors {
+                attributes.push(attribute(
+                    &descriptor.uuid,
+                    descriptor.permissions,
+                    &descriptor.value,
+                    descriptor.max_len,
+                    characteristic.auto_rsp,
+                )?);
+            }
+
+            layout.push(CharacteristicLayout {
+                name: characteristic.name.clone(),
+                uuid: characteristic.uuid.clone(),
+                descriptors: characteristic
+                    .descriptors
+                    .iter()
+                    .map(|descriptor| descriptor.uuid.clone())
+                    .collect(),
+            });
+        }
+
+        if attributes.len() > ATTRIBUTE_TABLE_MAX_LEN {
+            return Err(ServiceBuilderError::TooManyAttributes(attributes.len()));
+        }
+
+        Ok(AttributeTable {
+            uuid: self.uuid,
+            instance_id: self.instance_id,
+            included: self.included.len(),
+            attributes,
+            layout,
+        })
+    }
+}
+
+fn attribute(
+    uuid: &BtUuid,
+    permissions: u16,
+    value: &[u8],
+    max_len: Option<u16>,
+    auto_rsp: AutoResponse,
+) -> Result<Attribute, ServiceBuilderError> {
+    let max_len = max_len.unwrap_or(value.len() as _);
+    if value.len() > max_len as usize {
+        return Err(ServiceBuilderError::ValueTooLong {
+            uuid: uuid.clone(),
+            len: value.len(),
+            max_len: max_len as usize,
+        });
+    }
+
+    Ok(Attribute {
+        uuid: uuid.clone(),
+        permissions,
+        max_len,
+        value: value.to_vec(),
+        auto_rsp,
+    })
+}
+
+#[derive(Clone, Debug)]
+struct CharacteristicLayout {
+    name: String,
+    uuid: BtUuid,
+    descriptors: Vec<BtUuid>,
+}
+
+/// A service built by a `ServiceBuilder`, ready to be given to
+/// `EspBle::create_attribute_table`.
+#[derive(Clone, Debug)]
+pub struct AttributeTable {
+    uuid: BtUuid,
+    instance_id: u8,
+    included: usize,
+    attributes: Vec<Attribute>,
+    layout: Vec<CharacteristicLayout>,
+}
+
+impl AttributeTable {
+    pub fn uuid(&self) -> &BtUuid {
+        &self.uuid
+    }
+
+    pub fn instance_id(&self) -> u8 {
+        self.instance_id
+    }
+
+    pub fn attributes(&self) -> &[Attribute] {
+        &self.attributes
+    }
+
+    /// Map the handles reported once the table is created, in the order of
+    /// the attributes, to the characteristics. `None` if there is not one
+    /// handle per attribute.
+    pub(crate) fn handles(&self, handles: &[u16]) -> Option<ServiceHandles> {
+        if handles.len() != self.attributes.len() {
+            return None;
+        }
+
+        // Skip the service and included services declarations
+        let mut next = handles[1 + self.included..].iter().copied();
+        let characteristics = self
+            .layout
+            .iter()
+            .map(|characteristic| {
+                let declaration_handle = next.next()?;
+                let value_handle = next.next()?;
+                let descriptors = characteristic
+                    .descriptors
+                    .iter()
+                    .map(|uuid| Some((uuid.clone(), next.next()?)))
+                    .collect::<Option<Vec<_>>>()?;
+                Some(CharacteristicHandles {
+                    name: characteristic.name.clone(),
+                    uuid: characteristic.uuid.clone(),
+                    declaration_handle,
+                    value_handle,
+                    descriptors,
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(ServiceHandles {
+            uuid: self.uuid.clone(),
+            service_handle: handles[0],
+            end_handle: handles.iter().copied().max().unwrap_or(handles[0]),
+            characteristics,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CharacteristicHandles {
+    pub name: String,
+    pub uuid: BtUuid,
+    pub declaration_handle: u16,
+    /// Handle to read, write or notify the characteristic with
+    pub value_handle: u16,
+    pub descriptors: Vec<(BtUuid, u16)>,
+}
+
+impl CharacteristicHandles {
+    pub fn descriptor(&self, uuid: &BtUuid) -> Option<u16> {
+        self.descriptors
+            .iter()
+            .find(|(descriptor, _)| descriptor == uuid)
+            .map(|(_, handle)| *handle)
+    }
+
+    pub fn cccd(&self) -> Option<u16> {
+        self.descriptor(&BtUuid::Uuid16(CCCD_UUID))
+    }
+}
+
+/// Handles of a service created with `EspBle::create_attribute_table`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServiceHandles {
+    pub uuid: BtUuid,
+    pub service_handle: u16,
+    pub end_handle: u16,
+    pub characteristics: Vec<CharacteristicHandles>,
+}
+
+impl ServiceHandles {
+    pub fn characteristic(&self, name: &str) -> Option<&CharacteristicHandles> {
+        self.characteristics
+            .iter()
+            .find(|characteristic| characteristic.name == name)
+    }
+
+    /// The first characteristic with `uuid`.
+    pub fn characteristic_by_uuid(&self, uuid: &BtUuid) -> Option<&CharacteristicHandles> {
+        self.characteristics
+            .iter()
+            .find(|characteristic| characteristic.uuid == *uuid)
+    }
+
+    /// Value handle of the characteristic `name`.
+    pub fn handle(&self, name: &str) -> Option<u16> {
+        self.characteristic(name)
+            .map(|characteristic| characteristic.value_handle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const READ_NOTIFY: u8 = 0x02 | 0x10;
+
+    fn battery() -> ServiceBuilder {
+        ServiceBuilder::primary(BtUuid::Uuid16(0x180f)).characteristic(
+            CharacteristicBuilder::new("level", BtUuid::Uuid16(0x2a19))
+                .properties(READ_NOTIFY)
+                .permissions(PERM_READ)
+                .value(&[100])
+                .auto_response(AutoResponse::ByGatt)
+                .cccd(),
+        )
+    }
+
+    #[test]
+    fn attribute_layout() {
+        let table = battery().build().unwrap();
+        let uuids: Vec<BtUuid> = table
+            .attributes()
+            .iter()
+            .map(|attribute| attribute.uuid.clone())
+            .collect();
+        assert_eq!(
+            uuids,
+            [
+                BtUuid::Uuid16(PRIMARY_SERVICE_UUID),
+                BtUuid::Uuid16(CHARACTERISTIC_UUID),
+                BtUuid::Uuid16(0x2a19),
+                BtUuid::Uuid16(CCCD_UUID),
+            ]
+        );
+        assert_eq!(table.attributes()[0].value, [0x0f, 0x18]);
+        assert_eq!(table.attributes()[1].value, [READ_NOTIFY]);
+        assert_eq!(
+            table.attributes()[2],
+            Attribute {
+                uuid: BtUuid::Uuid16(0x2a19),
+                permissions: PERM_READ,
+                max_len: 1,
+                value: vec![100],
+                auto_rsp: AutoResponse::ByGatt,
+            }
+        );
+        assert_eq!(table.attributes()[3].value, [0, 0]);
+    }
+
+    #[test]
+    fn included_services() {
+        let included = ServiceHandles {
+            uuid: BtUuid::Uuid16(0x180a),
+            service_handle: 40,
+            end_handle: 45,
+            characteristics: vec![],
+        };
+        let table = ServiceBuilder::secondary(BtUuid::Uuid32(0x1234_5678))
+            .include(&included)
+            .build()
+            .unwrap();
+
+        let mut uuid = BASE_UUID;
+        uuid[12..].copy_from_slice(&[0x78, 0x56, 0x34, 0x12]);
+        assert_eq!(
+            table.attributes(),
+            [
+                Attribute::declaration(SECONDARY_SERVICE_UUID, uuid.to_vec()),
+                Attribute::declaration(INCLUDE_UUID, vec![40, 0, 45, 0, 0x0a, 0x18]),
+            ]
+        );
+    }
+
+    #[test]
+    fn handle_map() {
+        let table = battery()
+            .characteristic(
+                CharacteristicBuilder::new("alert", BtUuid::Uuid16(0x2a06))
+                    .permissions(PERM_WRITE)
+                    .max_len(1),
+            )
+            .build()
+            .unwrap();
+
+        assert_eq!(table.handles(&[40, 41, 42]), None);
+        let handles = table.handles(&[40, 41, 42, 43, 44, 45]).unwrap();
+        assert_eq!(handles.service_handle, 40);
+        assert_eq!(handles.end_handle, 45);
+        assert_eq!(handles.handle("level"), Some(42));
+        assert_eq!(handles.characteristic("level").unwrap().cccd(), Some(43));
+        assert_eq!(
+            handles.characteristic_by_uuid(&BtUuid::Uuid16(0x2a06)),
+            Some(&CharacteristicHandles {
+                name: "alert".into(),
+                uuid: BtUuid::Uuid16(0x2a06),
+                declaration_handle: 44,
+                value_handle: 45,
+                descriptors: vec![],
+            })
+        );
+        assert_eq!(handles.handle("missing"), None);
+    }
+
+    #[test]
+    fn invalid_services() {
+        assert_eq!(
+            battery()
+                .characteristic(CharacteristicBuilder::new("level", BtUuid::Uuid16(0x2a1a)))
+                .build()
+                .map(|_| ()),
+            Err(ServiceBuilderError::DuplicateName("level".into()))
+        );
+        assert_eq!(
+            ServiceBuilder::primary(BtUuid::Uuid16(0x180f))
+                .characteristic(
+                    CharacteristicBuilder::new("level", BtUuid::Uuid16(0x2a19))
+                        .value(&[1, 2])
+                        .max_len(1)
+                )
+                .build()
+                .map(|_| ()),
+            Err(ServiceBuilderError::ValueTooLong {
+                uuid: BtUuid::Uuid16(0x2a19),
+                len: 2,
+                max_len: 1,
+            })
+        );
+
+        let service = (0..127).fold(ServiceBuilder::primary(BtUuid::Uuid16(0x180f)), |s, i| {
+            s.characteristic(CharacteristicBuilder::new(
+                i.to_string(),
+                BtUuid::Uuid16(0x2a00 + i),
+            ))
+        });
+        assert_eq!(
+            service.clone().build().map(|t| t.attributes().len()),
+            Ok(255)
+        );
+        assert_eq!(
+            service
+                .characteristic(CharacteristicBuilder::new("last", BtUuid::Uuid16(0x2aff)))
+                .build()
+                .map(|_| ()),
+            Err(ServiceBuilderError::TooManyAttributes(257))
+        );
+    }
+}
@@ -0,0 +1,1349 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::backend::{BleBackend, ConnectionUpdate, GattResponse, SecurityParam};
+use crate::ble::{dispatch_gap_event, dispatch_gattc_event, dispatch_gatts_event};
+use crate::error::{esp_error, EspError, ESP_ERR_INVALID_ARG, ESP_ERR_INVALID_STATE};
+use crate::{
+    AddAttributeEvent, AddressType, AdvertiseData, AdvertisingParameters, Attribute,
+    AttributeTableEvent, AutoResponse, BdAddr, BleEncryption, BondEvent, BondedDevice, BtStatus,
+    BtUuid, ClientCloseEvent, ClientStatusEvent, ConfirmEvent, ConnectionParamsEvent,
+    CreateServiceEvent, DisconnectReason, ExtendedAdvertisingDuration,
+    ExtendedAdvertisingParameters, GapEvent, GattClientEvent, GattService, GattServiceEvent,
+    GattStatus, KeyType, NotifyRegistrationEvent, OobTemporaryKey, OpenEvent,
+    PeriodicAdvertisingParameters, ReadResultEvent, RegisterEvent, RemoteCharacteristic,
+    RemoteDescriptor, RemoteService, ResponseEvent, ScOobData, ScanParameters, SearchResultEvent,
+    ServiceEvent, WriteResultEvent, WriteType,
+};
+
+/// Call made by `EspBle` to its backend, as recorded by `SimulatedBackend`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BackendCall {
+    Init,
+    Deinit,
+    SetLocalMtu(u16),
+    SetDeviceName(String),
+    ConfigAdvData(AdvertiseData),
+    ConfigAdvDataRaw(Vec<u8>),
+    ConfigScanRspDataRaw(Vec<u8>),
+    StartAdvertising(AdvertisingParameters),
+    StopAdvertising,
+    SetScanParams(ScanParameters),
+    StartScanning(u32),
+    StopScanning,
+    UpdateConnParams(ConnectionUpdate),
+    SetSecurityParam(SecurityParam, Vec<u8>),
+    SecurityRsp {
+        bda: BdAddr,
+        accept: bool,
+    },
+    ConfirmReply {
+        bda: BdAddr,
+        accept: bool,
+    },
+    PasskeyReply {
+        bda: BdAddr,
+        accept: bool,
+        passkey: u32,
+    },
+    OobReqReply {
+        bda: BdAddr,
+        tk: OobTemporaryKey,
+    },
+    ScOobReqReply {
+        bda: BdAddr,
+        data: ScOobData,
+    },
+    CreateScOobData,
+    SetEncryption {
+        bda: BdAddr,
+        encryption: BleEncryption,
+    },
+    Disconnect(BdAddr),
+    RemoveBondDevice(BdAddr),
+    GattsAppRegister(u16),
+    GattsCreateService {
+        gatts_if: u8,
+        service: GattService,
+    },
+    GattsStartService(u16),
+    GattsCreateAttrTab {
+        gatts_if: u8,
+        attributes: Vec<Attribute>,
+        service_instance_id: u8,
+    },
+    GattsAddChar {
+        service_handle: u16,
+        uuid: BtUuid,
+        permissions: u16,
+        property: u8,
+        value: Vec<u8>,
+        auto_rsp: AutoResponse,
+    },
+    GattsAddCharDescr {
+        service_handle: u16,
+        uuid: BtUuid,
+        permissions: u16,
+    },
+    GattsSendIndicate {
+        gatts_if: u8,
+        conn_id: u16,
+        attr_handle: u16,
+        value: Vec<u8>,
+        need_confirm: bool,
+    },
+    GattsSendResponse {
+        gatts_if: u8,
+        conn_id: u16,
+        trans_id: u32,
+        status: GattStatus,
+        handle: u16,
+        offset: u16,
+        value: Vec<u8>,
+    },
+    GattcAppRegister(u16),
+    GattcOpen {
+        gattc_if: u8,
+        bda: BdAddr,
+        addr_type: AddressType,
+    },
+    GattcClose {
+        gattc_if: u8,
+        conn_id: u16,
+    },
+    GattcSearchService {
+        gattc_if: u8,
+        conn_id: u16,
+        filter: Option<BtUuid>,
+    },
+    GattcReadChar {
+        gattc_if: u8,
+        conn_id: u16,
+        handle: u16,
+    },
+    GattcWriteChar {
+        gattc_if: u8,
+        conn_id: u16,
+        handle: u16,
+        value: Vec<u8>,
+        write_type: WriteType,
+    },
+    GattcReadCharDescr {
+        gattc_if: u8,
+        conn_id: u16,
+        handle: u16,
+    },
+    GattcWriteCharDescr {
+        gattc_if: u8,
+        conn_id: u16,
+        handle: u16,
+        value: Vec<u8>,
+        write_type: WriteType,
+    },
+    GattcRegisterForNotify {
+        gattc_if: u8,
+        bda: BdAddr,
+        handle: u16,
+    },
+    GattcUnregisterForNotify {
+        gattc_if: u8,
+        bda: BdAddr,
+        handle: u16,
+    },
+    ExtAdvSetParams {
+        instance: u8,
+        params: ExtendedAdvertisingParameters,
+    },
+    ExtAdvSetRandAddr {
+        instance: u8,
+        addr: BdAddr,
+    },
+    ConfigExtAdvDataRaw {
+        instance: u8,
+        data: Vec<u8>,
+    },
+    ConfigExtScanRspDataRaw {
+        instance: u8,
+        data: Vec<u8>,
+    },
+    ExtAdvStart(Vec<(u8, ExtendedAdvertisingDuration)>),
+    ExtAdvStop(Vec<u8>),
+    ExtAdvSetRemove(u8),
+    ExtAdvSetClear,
+    PeriodicAdvSetParams {
+        instance: u8,
+        params: PeriodicAdvertisingParameters,
+    },
+    ConfigPeriodicAdvDataRaw {
+        instance: u8,
+        data: Vec<u8>,
+    },
+    PeriodicAdvStart(u8),
+    PeriodicAdvStop(u8),
+}
+
+/// Local Secure Connections OOB data created by `SimulatedBackend`.
+pub const SIMULATED_OOB_DATA: ScOobData = ScOobData {
+    confirm: [0xc0; 16],
+    random: [0x5a; 16],
+};
+
+/// Bluedroid hands out the interfaces from 3 to the servers and clients, the
+/// first handles are used by the built-in GAP and GATT services.
+const FIRST_GATT_IF: u8 = 3;
+const FIRST_HANDLE: u16 = 40;
+
+/// MTU of the connections opened by `SimulatedBackend` as a client.
+const CLIENT_MTU: u16 = 23;
+
+/// Longest device name Bluedroid accepts, `BTC_MAX_LOC_BD_NAME_LEN`.
+const DEVICE_NAME_MAX_LEN: usize = 248;
+
+struct SimulatedService {
+    gatts_if: u8,
+    next_handle: u16,
+    end_handle: u16,
+}
+
+/// Attributes of the server the simulated client connects to.
+#[derive(Default)]
+struct RemoteServer {
+    services: Vec<RemoteService>,
+    characteristics: Vec<RemoteCharacteristic>,
+    /// Descriptors with the handle of their characteristic
+    descriptors: Vec<(u16, RemoteDescriptor)>,
+    values: HashMap<u16, Vec<u8>>,
+}
+
+enum Event {
+    Gap(GapEvent),
+    Gatts(u8, GattServiceEvent),
+    Gattc(u8, GattClientEvent),
+}
+
+struct State {
+    running: bool,
+    calls: Vec<BackendCall>,
+    next_gatt_if: u8,
+    next_handle: u16,
+    next_conn_id: u16,
+    /// Peers of the connections opened as a client
+    client_connections: HashMap<u16, BdAddr>,
+    remote: RemoteServer,
+    services: HashMap<u16, SimulatedService>,
+    attributes: HashMap<u16, Vec<u8>>,
+    bonds: Vec<BdAddr>,
+    /// Completion events held back by `hold_events`
+    held: Option<Vec<Event>>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            running: false,
+            calls: vec![],
+            next_gatt_if: FIRST_GATT_IF,
+            next_handle: FIRST_HANDLE,
+            next_conn_id: 0,
+            client_connections: HashMap::new(),
+            remote: RemoteServer::default(),
+            services: HashMap::new(),
+            attributes: HashMap::new(),
+            bonds: vec![],
+            held: None,
+        }
+    }
+}
+
+/// In-memory `BleBackend` to run `EspBle` without the radio, e.g. on the host.
+///
+/// Every call is recorded, see `calls`, and completed right away by the event
+/// Bluedroid would report, like `GattServiceEvent::Register` after
+/// `register_gatt_service_application`. The events coming from the peers, like
+/// a connection or a write, are given with `inject_gap_event` and
+/// `inject_gatts_event`. The client talks to a server whose attributes are
+/// given with `add_remote_service`, `add_remote_characteristic` and
+/// `add_remote_descriptor`.
+///
+/// Clones share their state, so a clone can be kept to drive the backend given
+/// to `EspBle::with_backend`.
+#[derive(Clone, Default)]
+pub struct SimulatedBackend {
+    state: Arc<Mutex<State>>,
+}
+
+impl SimulatedBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The calls made since the creation or the last call to `take_calls`.
+    pub fn calls(&self) -> Vec<BackendCall> {
+        self.state
+            .lock()
+            .map(|state| state.calls.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn take_calls(&self) -> Vec<BackendCall> {
+        self.state
+            .lock()
+            .map(|mut state| std::mem::take(&mut state.calls))
+            .unwrap_or_default()
+    }
+
+    /// Bond with `bda`, as if it paired with this device.
+    pub fn add_bond(&self, bda: BdAddr) {
+        if let Ok(mut state) = self.state.lock() {
+            if !state.bonds.contains(&bda) {
+                state.bonds.push(bda);
+            }
+        }
+    }
+
+    /// Hold back the completion events of the next calls until
+    /// `release_events`, to have several operations pending at once.
+    pub fn hold_events(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            state.held.get_or_insert_with(Vec::new);
+        }
+    }
+
+    /// Report the events held back since `hold_events`, in order.
+    pub fn release_events(&self) {
+        let events = self
+            .state
+            .lock()
+            .ok()
+            .and_then(|mut state| state.held.take())
+            .unwrap_or_default();
+        Self::report(events);
+    }
+
+    /// Add a service to the server the client connects to.
+    pub fn add_remote_service(&self, service: RemoteService) {
+        if let Ok(mut state) = self.state.lock() {
+            state.remote.services.push(service);
+        }
+    }
+
+    /// Add a characteristic to the server the client connects to, the
+    /// characteristic belongs to the service containing its handle.
+    pub fn add_remote_characteristic(&self, characteristic: RemoteCharacteristic, value: &[u8]) {
+        if let Ok(mut state) = self.state.lock() {
+            state
+                .remote
+                .values
+                .insert(characteristic.handle, value.to_vec());
+            state.remote.characteristics.push(characteristic);
+        }
+    }
+
+    /// Add a descriptor to the characteristic at `char_handle` of the server
+    /// the client connects to.
+    pub fn add_remote_descriptor(
+        &self,
+        char_handle: u16,
+        descriptor: RemoteDescriptor,
+        value: &[u8],
+    ) {
+        if let Ok(mut state) = self.state.lock() {
+            state
+                .remote
+                .values
+                .insert(descriptor.handle, value.to_vec());
+            state.remote.descriptors.push((char_handle, descriptor));
+        }
+    }
+
+    /// Value of the attribute at `handle` of the server the client connects
+    /// to, as last written by the client.
+    pub fn remote_value(&self, handle: u16) -> Option<Vec<u8>> {
+        self.state
+            .lock()
+            .ok()
+            .and_then(|state| state.remote.values.get(&handle).cloned())
+    }
+
+    /// Report `event` as if it came from the stack.
+    pub fn inject_gap_event(&self, event: GapEvent) {
+        dispatch_gap_event(event);
+    }
+
+    /// Report `event` for the application registered as `gatts_if`, as if it
+    /// came from the stack.
+    pub fn inject_gatts_event(&self, gatts_if: u8, event: GattServiceEvent) {
+        dispatch_gatts_event(gatts_if, event);
+    }
+
+    /// Record `call`, then report the events it completes with. No lock is held
+    /// while the events are dispatched, so the callbacks can call the backend.
+    fn call(
+        &self,
+        call: BackendCall,
+        complete: impl FnOnce(&mut State) -> Result<Vec<Event>, EspError>,
+    ) -> Result<(), EspError> {
+        let events = {
+            let mut state = self
+                .state
+                .lock()
+                .map_err(|_| esp_error(ESP_ERR_INVALID_STATE))?;
+            state.calls.push(call);
+            if !state.running {
+                return Err(esp_error(ESP_ERR_INVALID_STATE));
+            }
+            let events = complete(&mut state)?;
+            match &mut state.held {
+                Some(held) => {
+                    held.extend(events);
+                    vec![]
+                }
+                None => events,
+            }
+        };
+
+        Self::report(events);
+        Ok(())
+    }
+
+    /// Report `event` for the application registered as `gattc_if`, as if it
+    /// came from the stack, e.g. a `GattClientEvent::Notify`.
+    pub fn inject_gattc_event(&self, gattc_if: u8, event: GattClientEvent) {
+        dispatch_gattc_event(gattc_if, event);
+    }
+
+    fn report(events: Vec<Event>) {
+        for event in events {
+            match event {
+                Event::Gap(event) => dispatch_gap_event(event),
+                Event::Gatts(gatts_if, event) => dispatch_gatts_event(gatts_if, event),
+                Event::Gattc(gattc_if, event) => dispatch_gattc_event(gattc_if, event),
+            }
+        }
+    }
+
+    /// Value read from the remote attribute at `handle`.
+    fn remote_read(state: &mut State, conn_id: u16, handle: u16) -> ReadResultEvent {
+        let value = state.remote.values.get(&handle).cloned();
+        ReadResultEvent {
+            status: match value {
+                Some(_) => GattStatus::Ok,
+                None => GattStatus::InvalidHandle,
+            },
+            conn_id,
+            handle,
+            value: value.unwrap_or_default(),
+        }
+    }
+
+    /// Write `value` to the remote attribute at `handle`.
+    fn remote_write(
+        state: &mut State,
+        conn_id: u16,
+        handle: u16,
+        value: &[u8],
+    ) -> WriteResultEvent {
+        let status = match state.remote.values.get_mut(&handle) {
+            Some(current) => {
+                *current = value.to_vec();
+                GattStatus::Ok
+            }
+            None => GattStatus::InvalidHandle,
+        };
+        WriteResultEvent {
+            status,
+            conn_id,
+            handle,
+            offset: 0,
+        }
+    }
+
+    fn gap(&self, call: BackendCall, event: GapEvent) -> Result<(), EspError> {
+        self.call(call, |_| Ok(vec![Event::Gap(event)]))
+    }
+
+    fn silent(&self, call: BackendCall) -> Result<(), EspError> {
+        self.call(call, |_| Ok(vec![]))
+    }
+}
+
+impl State {
+    /// Allocate `count` handles in the service `service_handle`.
+    fn allocate(&mut self, service_handle: u16, count: u16) -> Result<(u8, Option<u16>), EspError> {
+        let service = self
+            .services
+            .get_mut(&service_handle)
+            .ok_or_else(|| esp_error(ESP_ERR_INVALID_ARG))?;
+        if service.next_handle + count > service.end_handle {
+            return Ok((service.gatts_if, None));
+        }
+        let handle = service.next_handle;
+        service.next_handle += count;
+        Ok((service.gatts_if, Some(handle)))
+    }
+
+    fn add_attribute(
+        &mut self,
+        service_handle: u16,
+        count: u16,
+        uuid: &BtUuid,
+        complete: fn(AddAttributeEvent) -> GattServiceEvent,
+    ) -> Result<(Vec<Event>, Option<u16>), EspError> {
+        let (gatts_if, first) = self.allocate(service_handle, count)?;
+        // The handle of a characteristic is the one of its value, which
+        // follows its declaration.
+        let attr_handle = first.map(|first| first + count - 1);
+        let event = complete(AddAttributeEvent {
+            status: match attr_handle {
+                Some(_) => GattStatus::Ok,
+                None => GattStatus::InsufficientResources,
+            },
+            service_handle,
+            attr_handle: attr_handle.unwrap_or(0),
+            uuid: Some(uuid.clone()),
+        });
+        Ok((vec![Event::Gatts(gatts_if, event)], attr_handle))
+    }
+}
+
+impl BleBackend for SimulatedBackend {
+    fn init(&self) -> Result<(), EspError> {
+        let mut state = self
+            .state
+            .lock()
+            .map_err(|_| esp_error(ESP_ERR_INVALID_STATE))?;
+        state.calls.push(BackendCall::Init);
+        state.running = true;
+        Ok(())
+    }
+
+    fn deinit(&self) -> Result<(), EspError> {
+        let mut state = self
+            .state
+            .lock()
+            .map_err(|_| esp_error(ESP_ERR_INVALID_STATE))?;
+        state.calls.push(BackendCall::Deinit);
+        state.running = false;
+        Ok(())
+    }
+
+    fn set_local_mtu(&self, mtu: u16) -> Result<(), EspError> {
+        self.silent(BackendCall::SetLocalMtu(mtu))
+    }
+
+    fn set_device_name(&self, name: &str) -> Result<(), EspError> {
+        self.call(BackendCall::SetDeviceName(name.to_string()), |_| {
+            if name.len() > DEVICE_NAME_MAX_LEN {
+                return Err(esp_error(ESP_ERR_INVALID_ARG));
+            }
+            Ok(vec![])
+        })
+    }
+
+    fn config_adv_data(&self, data: &AdvertiseData) -> Result<(), EspError> {
+        let event = if data.set_scan_rsp {
+            GapEvent::ScanResponseDatasetComplete(BtStatus::Success)
+        } else {
+            GapEvent::AdvertisingDatasetComplete(BtStatus::Success)
+        };
+        self.gap(BackendCall::ConfigAdvData(data.clone()), event)
+    }
+
+    fn config_adv_data_raw(&self, data: &[u8]) -> Result<(), EspError> {
+        self.gap(
+            BackendCall::ConfigAdvDataRaw(data.to_vec()),
+            GapEvent::RawAdvertisingDatasetComplete(BtStatus::Success),
+        )
+    }
+
+    fn config_scan_rsp_data_raw(&self, data: &[u8]) -> Result<(), EspError> {
+        self.gap(
+            BackendCall::ConfigScanRspDataRaw(data.to_vec()),
+            GapEvent::RawScanResponseDatasetComplete(BtStatus::Success),
+        )
+    }
+
+    fn start_advertising(&self, params: &AdvertisingParameters) -> Result<(), EspError> {
+        self.gap(
+            BackendCall::StartAdvertising(*params),
+            GapEvent::AdvertisingStartComplete(BtStatus::Success),
+        )
+    }
+
+    fn stop_advertising(&self) -> Result<(), EspError> {
+        self.gap(
+            BackendCall::StopAdvertising,
+            GapEvent::AdvertisingStopComplete(BtStatus::Success),
+        )
+    }
+
+    fn set_scan_params(&self, params: &ScanParameters) -> Result<(), EspError> {
+        self.gap(
+            BackendCall::SetScanParams(*params),
+            GapEvent::ScanParameterDatasetComplete(BtStatus::Success),
+        )
+    }
+
+    fn start_scanning(&self, duration: u32) -> Result<(), EspError> {
+        self.gap(
+            BackendCall::StartScanning(duration),
+            GapEvent::ScanStartComplete(BtStatus::Success),
+        )
+    }
+
+    fn stop_scanning(&self) -> Result<(), EspError> {
+        self.gap(
+            BackendCall::StopScanning,
+            GapEvent::ScanStopComplete(BtStatus::Success),
+        )
+    }
+
+    fn update_conn_params(&self, params: &ConnectionUpdate) -> Result<(), EspError> {
+        self.gap(
+            BackendCall::UpdateConnParams(*params),
+            GapEvent::UpdateConnectionParamsComplete(ConnectionParamsEvent {
+                status: BtStatus::Success,
+                bda: params.bda,
+                min_int: params.min_int,
+                max_int: params.max_int,
+                latency: params.latency,
+                conn_int: params.max_int,
+                timeout: params.timeout,
+            }),
+        )
+    }
+
+    fn set_security_param(&self, param: SecurityParam, value: &[u8]) -> Result<(), EspError> {
+        self.silent(BackendCall::SetSecurityParam(param, value.to_vec()))
+    }
+
+    fn security_rsp(&self, bda: BdAddr, accept: bool) -> Result<(), EspError> {
+        self.silent(BackendCall::SecurityRsp { bda, accept })
+    }
+
+    fn confirm_reply(&self, bda: BdAddr, accept: bool) -> Result<(), EspError> {
+        self.silent(BackendCall::ConfirmReply { bda, accept })
+    }
+
+    fn passkey_reply(&self, bda: BdAddr, accept: bool, passkey: u32) -> Result<(), EspError> {
+        self.silent(BackendCall::PasskeyReply {
+            bda,
+            accept,
+            passkey,
+        })
+    }
+
+    fn oob_req_reply(&self, bda: BdAddr, tk: &OobTemporaryKey) -> Result<(), EspError> {
+        self.silent(BackendCall::OobReqReply { bda, tk: *tk })
+    }
+
+    fn sc_oob_req_reply(&self, bda: BdAddr, data: &ScOobData) -> Result<(), EspError> {
+        self.silent(BackendCall::ScOobReqReply { bda, data: *data })
+    }
+
+    fn create_sc_oob_data(&self) -> Result<(), EspError> {
+        self.gap(
+            BackendCall::CreateScOobData,
+            GapEvent::LocalOOBDataCreated(SIMULATED_OOB_DATA),
+        )
+    }
+
+    fn set_encryption(&self, bda: BdAddr, encryption: BleEncryption) -> Result<(), EspError> {
+        self.silent(BackendCall::SetEncryption { bda, encryption })
+    }
+
+    fn disconnect(&self, bda: BdAddr) -> Result<(), EspError> {
+        self.silent(BackendCall::Disconnect(bda))
+    }
+
+    fn bonded_devices(&self) -> Result<Vec<BondedDevice>, EspError> {
+        let bonds = self
+            .state
+            .lock()
+            .map(|state| state.bonds.clone())
+            .map_err(|_| esp_error(ESP_ERR_INVALID_STATE))?;
+        Ok(bonds
+            .into_iter()
+            .map(|bda| BondedDevice {
+                bda,
+                addr_type: AddressType::Public,
+                keys: vec![KeyType::PeerEncryption, KeyType::PeerIdentity],
+            })
+            .collect())
+    }
+
+    fn remove_bond_device(&self, bda: BdAddr) -> Result<(), EspError> {
+        self.call(BackendCall::RemoveBondDevice(bda), |state| {
+            let bonded = state.bonds.contains(&bda);
+            state.bonds.retain(|bond| *bond != bda);
+            Ok(vec![Event::Gap(GapEvent::RemoveDeviceBondComplete(
+                BondEvent {
+                    status: if bonded {
+                        BtStatus::Success
+                    } else {
+                        BtStatus::Fail
+                    },
+                    bda,
+                },
+            ))])
+        })
+    }
+
+    fn gatts_app_register(&self, app_id: u16) -> Result<(), EspError> {
+        self.call(BackendCall::GattsAppRegister(app_id), |state| {
+            let gatts_if = state.next_gatt_if;
+            state.next_gatt_if += 1;
+            Ok(vec![Event::Gatts(
+                gatts_if,
+                GattServiceEvent::Register(RegisterEvent {
+                    status: GattStatus::Ok,
+                    app_id,
+                }),
+            )])
+        })
+    }
+
+    fn gatts_create_service(&self, gatts_if: u8, service: &GattService) -> Result<(), EspError> {
+        let call = BackendCall::GattsCreateService {
+            gatts_if,
+            service: service.clone(),
+        };
+        self.call(call, |state| {
+            let service_handle = state.next_handle;
+            state.next_handle += service.handle;
+            state.services.insert(
+                service_handle,
+                SimulatedService {
+                    gatts_if,
+                    // The service declaration uses the first handle
+                    next_handle: service_handle + 1,
+                    end_handle: service_handle + service.handle,
+                },
+            );
+            Ok(vec![Event::Gatts(
+                gatts_if,
+                GattServiceEvent::Create(CreateServiceEvent {
+                    status: GattStatus::Ok,
+                    service_handle,
+                    service_uuid: service.id.clone(),
+                    is_primary: service.is_primary,
+                    instance_id: service.instance_id,
+                }),
+            )])
+        })
+    }
+
+    fn gatts_start_service(&self, service_handle: u16) -> Result<(), EspError> {
+        self.call(BackendCall::GattsStartService(service_handle), |state| {
+            let service = state
+                .services
+                .get(&service_handle)
+                .ok_or_else(|| esp_error(ESP_ERR_INVALID_ARG))?;
+            Ok(vec![Event::Gatts(
+                service.gatts_if,
+                GattServiceEvent::StartComplete(ServiceEvent {
+                    status: GattStatus::Ok,
+                    service_handle,
+                }),
+            )])
+        })
+    }
+
+    fn gatts_create_attr_tab(
+        &self,
+        gatts_if: u8,
+        attributes: &[Attribute],
+        service_instance_id: u8,
+    ) -> Result<(), EspError> {
+        let call = BackendCall::GattsCreateAttrTab {
+            gatts_if,
+            attributes: attributes.to_vec(),
+            service_instance_id,
+        };
+        self.call(call, |state| {
+            let declaration = attributes
+                .first()
+                .ok_or_else(|| esp_error(ESP_ERR_INVALID_ARG))?;
+            let service_uuid = match declaration.value[..] {
+                [low, high] => BtUuid::Uuid16(u16::from_le_bytes([low, high])),
+                ref uuid => BtUuid::Uuid128(
+                    uuid.try_into()
+                        .map_err(|_| esp_error(ESP_ERR_INVALID_ARG))?,
+                ),
+            };
+
+            // The table is full, nothing can be added to the service
+            let service_handle = state.next_handle;
+            let count = attributes.len() as u16;
+            state.next_handle += count;
+            state.services.insert(
+                service_handle,
+                SimulatedService {
+                    gatts_if,
+                    next_handle: service_handle + count,
+                    end_handle: service_handle + count,
+                },
+            );
+
+            let handles: Vec<u16> = (service_handle..service_handle + count).collect();
+            for (handle, attribute) in handles.iter().zip(attributes) {
+                state.attributes.insert(*handle, attribute.value.clone());
+            }
+            Ok(vec![Event::Gatts(
+                gatts_if,
+                GattServiceEvent::CreateAttributeTableComplete(AttributeTableEvent {
+                    status: GattStatus::Ok,
+                    service_uuid,
+                    service_instance_id,
+                    handles,
+                }),
+            )])
+        })
+    }
+
+    fn gatts_add_char(
+        &self,
+        service_handle: u16,
+        uuid: &BtUuid,
+        permissions: u16,
+        property: u8,
+        value: &[u8],
+        auto_rsp: AutoResponse,
+    ) -> Result<(), EspError> {
+        let call = BackendCall::GattsAddChar {
+            service_handle,
+            uuid: uuid.clone(),
+            permissions,
+            property,
+            value: value.to_vec(),
+            auto_rsp,
+        };
+        self.call(call, |state| {
+            let (events, attr_handle) = state.add_attribute(
+                service_handle,
+                2,
+                uuid,
+                GattServiceEvent::AddCharacteristicComplete,
+            )?;
+            if let Some(attr_handle) = attr_handle {
+                state.attributes.insert(attr_handle, value.to_vec());
+            }
+            Ok(events)
+        })
+    }
+
+    fn gatts_add_char_descr(
+        &self,
+        service_handle: u16,
+        uuid: &BtUuid,
+        permissions: u16,
+    ) -> Result<(), EspError> {
+        let call = BackendCall::GattsAddCharDescr {
+            service_handle,
+            uuid: uuid.clone(),
+            permissions,
+        };
+        self.call(call, |state| {
+            let (events, attr_handle) = state.add_attribute(
+                service_handle,
+                1,
+                uuid,
+                GattServiceEvent::AddDescriptorComplete,
+            )?;
+            if let Some(attr_handle) = attr_handle {
+                state.attributes.insert(attr_handle, vec![]);
+            }
+            Ok(events)
+        })
+    }
+
+    /// A notification is confirmed right away, an indication once the client
+    /// confirms it, see `inject_gatts_event`.
+    fn gatts_send_indicate(
+        &self,
+        gatts_if: u8,
+        conn_id: u16,
+        attr_handle: u16,
+        value: &[u8],
+        need_confirm: bool,
+    ) -> Result<(), EspError> {
+        let call = BackendCall::GattsSendIndicate {
+            gatts_if,
+            conn_id,
+            attr_handle,
+            value: value.to_vec(),
+            need_confirm,
+        };
+        self.call(call, |_| {
+            if need_confirm {
+                return Ok(vec![]);
+            }
+            Ok(vec![Event::Gatts(
+                gatts_if,
+                GattServiceEvent::Confirm(ConfirmEvent {
+                    status: GattStatus::Ok,
+                    conn_id,
+                    handle: attr_handle,
+                    value: value.to_vec(),
+                }),
+            )])
+        })
+    }
+
+    fn gatts_get_attr_value(&self, attr_handle: u16) -> Result<Vec<u8>, EspError> {
+        self.state
+            .lock()
+            .ok()
+            .and_then(|state| state.attributes.get(&attr_handle).cloned())
+            .ok_or_else(|| esp_error(ESP_ERR_INVALID_ARG))
+    }
+
+    fn gatts_send_response(
+        &self,
+        gatts_if: u8,
+        conn_id: u16,
+        trans_id: u32,
+        response: &GattResponse,
+    ) -> Result<(), EspError> {
+        let GattResponse {
+            status,
+            handle,
+            offset,
+            value,
+        } = *response;
+        let call = BackendCall::GattsSendResponse {
+            gatts_if,
+            conn_id,
+            trans_id,
+            status,
+            handle,
+            offset,
+            value: value.to_vec(),
+        };
+        self.call(call, |_| {
+            Ok(vec![Event::Gatts(
+                gatts_if,
+                GattServiceEvent::ResponseComplete(ResponseEvent { status, handle }),
+            )])
+        })
+    }
+
+    fn gattc_app_register(&self, app_id: u16) -> Result<(), EspError> {
+        self.call(BackendCall::GattcAppRegister(app_id), |state| {
+            let gattc_if = state.next_gatt_if;
+            state.next_gatt_if += 1;
+            Ok(vec![Event::Gattc(
+                gattc_if,
+                GattClientEvent::Register(RegisterEvent {
+                    status: GattStatus::Ok,
+                    app_id,
+                }),
+            )])
+        })
+    }
+
+    fn gattc_open(
+        &self,
+        gattc_if: u8,
+        bda: BdAddr,
+        addr_type: AddressType,
+    ) -> Result<(), EspError> {
+        let call = BackendCall::GattcOpen {
+            gattc_if,
+            bda,
+            addr_type,
+        };
+        self.call(call, |state| {
+            let conn_id = state.next_conn_id;
+            state.next_conn_id += 1;
+            state.client_connections.insert(conn_id, bda);
+            Ok(vec![Event::Gattc(
+                gattc_if,
+                GattClientEvent::Open(OpenEvent {
+                    status: GattStatus::Ok,
+                    conn_id,
+                    bda,
+                    mtu: CLIENT_MTU,
+                }),
+            )])
+        })
+    }
+
+    fn gattc_close(&self, gattc_if: u8, conn_id: u16) -> Result<(), EspError> {
+        self.call(BackendCall::GattcClose { gattc_if, conn_id }, |state| {
+            let bda = state
+                .client_connections
+                .remove(&conn_id)
+                .ok_or_else(|| esp_error(ESP_ERR_INVALID_ARG))?;
+            Ok(vec![Event::Gattc(
+                gattc_if,
+                GattClientEvent::Close(ClientCloseEvent {
+                    status: GattStatus::Ok,
+                    conn_id,
+                    bda,
+                    reason: DisconnectReason::LocalHostTerminated,
+                }),
+            )])
+        })
+    }
+
+    /// Every service of the server is found, then the search completes.
+    fn gattc_search_service(
+        &self,
+        gattc_if: u8,
+        conn_id: u16,
+        filter: Option<&BtUuid>,
+    ) -> Result<(), EspError> {
+        let call = BackendCall::GattcSearchService {
+            gattc_if,
+            conn_id,
+            filter: filter.cloned(),
+        };
+        self.call(call, |state| {
+            let mut events: Vec<Event> = state
+                .remote
+                .services
+                .iter()
+                .filter(|service| match filter {
+                    Some(uuid) => service.service.id == *uuid,
+                    None => true,
+                })
+                .map(|service| {
+                    Event::Gattc(
+                        gattc_if,
+                        GattClientEvent::SearchResult(SearchResultEvent {
+                            conn_id,
+                            start_handle: service.start_handle,
+                            end_handle: service.end_handle,
+                            uuid: service.service.id.clone(),
+                            instance_id: service.service.instance_id,
+                            is_primary: service.service.is_primary,
+                        }),
+                    )
+                })
+                .collect();
+            events.push(Event::Gattc(
+                gattc_if,
+                GattClientEvent::SearchComplete(ClientStatusEvent {
+                    status: GattStatus::Ok,
+                    conn_id,
+                }),
+            ));
+            Ok(events)
+        })
+    }
+
+    fn gattc_get_services(
+        &self,
+        _gattc_if: u8,
+        _conn_id: u16,
+    ) -> Result<Vec<RemoteService>, EspError> {
+        self.state
+            .lock()
+            .map(|state| state.remote.services.clone())
+            .map_err(|_| esp_error(ESP_ERR_INVALID_STATE))
+    }
+
+    fn gattc_get_characteristics(
+        &self,
+        _gattc_if: u8,
+        _conn_id: u16,
+        start_handle: u16,
+        end_handle: u16,
+    ) -> Result<Vec<RemoteCharacteristic>, EspError> {
+        self.state
+            .lock()
+            .map(|state| {
+                state
+                    .remote
+                    .characteristics
+                    .iter()
+                    .filter(|charac| (start_handle..=end_handle).contains(&charac.handle))
+                    .cloned()
+                    .collect()
+            })
+            .map_err(|_| esp_error(ESP_ERR_INVALID_STATE))
+    }
+
+    fn gattc_get_descriptors(
+        &self,
+        _gattc_if: u8,
+        _conn_id: u16,
+        char_handle: u16,
+    ) -> Result<Vec<RemoteDescriptor>, EspError> {
+        self.state
+            .lock()
+            .map(|state| {
+                state
+                    .remote
+                    .descriptors
+                    .iter()
+                    .filter(|(handle, _)| *handle == char_handle)
+                    .map(|(_, descr)| descr.clone())
+                    .collect()
+            })
+            .map_err(|_| esp_error(ESP_ERR_INVALID_STATE))
+    }
+
+    fn gattc_read_char(&self, gattc_if: u8, conn_id: u16, handle: u16) -> Result<(), EspError> {
+        let call = BackendCall::GattcReadChar {
+            gattc_if,
+            conn_id,
+            handle,
+        };
+        self.call(call, |state| {
+            let read = Self::remote_read(state, conn_id, handle);
+            Ok(vec![Event::Gattc(
+                gattc_if,
+                GattClientEvent::ReadCharacteristic(read),
+            )])
+        })
+    }
+
+    fn gattc_write_char(
+        &self,
+        gattc_if: u8,
+        conn_id: u16,
+        handle: u16,
+        value: &[u8],
+        write_type: WriteType,
+    ) -> Result<(), EspError> {
+        let call = BackendCall::GattcWriteChar {
+            gattc_if,
+            conn_id,
+            handle,
+            value: value.to_vec(),
+            write_type,
+        };
+        self.call(call, |state| {
+            let write = Self::remote_write(state, conn_id, handle, value);
+            Ok(vec![Event::Gattc(
+                gattc_if,
+                GattClientEvent::WriteCharacteristic(write),
+            )])
+        })
+    }
+
+    fn gattc_read_char_descr(
+        &self,
+        gattc_if: u8,
+        conn_id: u16,
+        handle: u16,
+    ) -> Result<(), EspError> {
+        let call = BackendCall::GattcReadCharDescr {
+            gattc_if,
+            conn_id,
+            handle,
+        };
+        self.call(call, |state| {
+            let read = Self::remote_read(state, conn_id, handle);
+            Ok(vec![Event::Gattc(
+                gattc_if,
+                GattClientEvent::ReadDescriptor(read),
+            )])
+        })
+    }
+
+    fn gattc_write_char_descr(
+        &self,
+        gattc_if: u8,
+        conn_id: u16,
+        handle: u16,
+        value: &[u8],
+        write_type: WriteType,
+    ) -> Result<(), EspError> {
+        let call = BackendCall::GattcWriteCharDescr {
+            gattc_if,
+            conn_id,
+            handle,
+            value: value.to_vec(),
+            write_type,
+        };
+        self.call(call, |state| {
+            let write = Self::remote_write(state, conn_id, handle, value);
+            Ok(vec![Event::Gattc(
+                gattc_if,
+                GattClientEvent::WriteDescriptor(write),
+            )])
+        })
+    }
+
+    fn gattc_register_for_notify(
+        &self,
+        gattc_if: u8,
+        bda: BdAddr,
+        handle: u16,
+    ) -> Result<(), EspError> {
+        let call = BackendCall::GattcRegisterForNotify {
+            gattc_if,
+            bda,
+            handle,
+        };
+        self.call(call, |_| {
+            Ok(vec![Event::Gattc(
+                gattc_if,
+                GattClientEvent::RegisterForNotify(NotifyRegistrationEvent {
+                    status: GattStatus::Ok,
+                    handle,
+                }),
+            )])
+        })
+    }
+
+    fn gattc_unregister_for_notify(
+        &self,
+        gattc_if: u8,
+        bda: BdAddr,
+        handle: u16,
+    ) -> Result<(), EspError> {
+        let call = BackendCall::GattcUnregisterForNotify {
+            gattc_if,
+            bda,
+            handle,
+        };
+        self.call(call, |_| {
+            Ok(vec![Event::Gattc(
+                gattc_if,
+                GattClientEvent::UnregisterForNotify(NotifyRegistrationEvent {
+                    status: GattStatus::Ok,
+                    handle,
+                }),
+            )])
+        })
+    }
+
+    fn ext_adv_set_params(
+        &self,
+        instance: u8,
+        params: &ExtendedAdvertisingParameters,
+    ) -> Result<(), EspError> {
+        self.gap(
+            BackendCall::ExtAdvSetParams {
+                instance,
+                params: *params,
+            },
+            GapEvent::ExtendedAdvertisingSetParamsComplete(BtStatus::Success),
+        )
+    }
+
+    fn ext_adv_set_rand_addr(&self, instance: u8, addr: BdAddr) -> Result<(), EspError> {
+        self.gap(
+            BackendCall::ExtAdvSetRandAddr { instance, addr },
+            GapEvent::ExtendedAdvertisingSetRandomAddressComplete(BtStatus::Success),
+        )
+    }
+
+    fn config_ext_adv_data_raw(&self, instance: u8, data: &[u8]) -> Result<(), EspError> {
+        self.gap(
+            BackendCall::ConfigExtAdvDataRaw {
+                instance,
+                data: data.to_vec(),
+            },
+            GapEvent::ExtendedAdvertisingDatasetComplete(BtStatus::Success),
+        )
+    }
+
+    fn config_ext_scan_rsp_data_raw(&self, instance: u8, data: &[u8]) -> Result<(), EspError> {
+        self.gap(
+            BackendCall::ConfigExtScanRspDataRaw {
+                instance,
+                data: data.to_vec(),
+            },
+            GapEvent::ExtendedScanResponseDatasetComplete(BtStatus::Success),
+        )
+    }
+
+    fn ext_adv_start(&self, sets: &[(u8, ExtendedAdvertisingDuration)]) -> Result<(), EspError> {
+        self.gap(
+            BackendCall::ExtAdvStart(sets.to_vec()),
+            GapEvent::ExtendedAdvertisingStartComplete(BtStatus::Success),
+        )
+    }
+
+    fn ext_adv_stop(&self, instances: &[u8]) -> Result<(), EspError> {
+        self.gap(
+            BackendCall::ExtAdvStop(instances.to_vec()),
+            GapEvent::ExtendedAdvertisingStopComplete(BtStatus::Success),
+        )
+    }
+
+    fn ext_adv_set_remove(&self, instance: u8) -> Result<(), EspError> {
+        self.gap(
+            BackendCall::ExtAdvSetRemove(instance),
+            GapEvent::ExtendedAdvertisingSetRemoveComplete(BtStatus::Success),
+        )
+    }
+
+    fn ext_adv_set_clear(&self) -> Result<(), EspError> {
+        self.gap(
+            BackendCall::ExtAdvSetClear,
+            GapEvent::ExtendedAdvertisingSetClearComplete(BtStatus::Success),
+        )
+    }
+
+    fn periodic_adv_set_params(
+        &self,
+        instance: u8,
+        params: &PeriodicAdvertisingParameters,
+    ) -> Result<(), EspError> {
+        self.gap(
+            BackendCall::PeriodicAdvSetParams {
+                instance,
+                params: *params,
+            },
+            GapEvent::PeriodicAdvertisingSetParamsComplete(BtStatus::Success),
+        )
+    }
+
+    fn config_periodic_adv_data_raw(&self, instance: u8, data: &[u8]) -> Result<(), EspError> {
+        self.gap(
+            BackendCall::ConfigPeriodicAdvDataRaw {
+                instance,
+                data: data.to_vec(),
+            },
+            GapEvent::PeriodicAdvertisingDatasetComplete(BtStatus::Success),
+        )
+    }
+
+    fn periodic_adv_start(&self, instance: u8) -> Result<(), EspError> {
+        self.gap(
+            BackendCall::PeriodicAdvStart(instance),
+            GapEvent::PeriodicAdvertisingStartComplete(BtStatus::Success),
+        )
+    }
+
+    fn periodic_adv_stop(&self, instance: u8) -> Result<(), EspError> {
+        self.gap(
+            BackendCall::PeriodicAdvStop(instance),
+            GapEvent::PeriodicAdvertisingStopComplete(BtStatus::Success),
+        )
+    }
+}
+
+/// `EspBle` running on a `SimulatedBackend`, for the tests of the crate.
+///
+/// `EspBle` is a singleton using global callback registries, so the tests
+/// using it are serialized.
+#[cfg(test)]
+pub(crate) struct SimulatedBle {
+    pub(crate) ble: crate::EspBle,
+    pub(crate) sim: SimulatedBackend,
+    _guard: std::sync::MutexGuard<'static, ()>,
+}
+
+#[cfg(test)]
+pub(crate) fn simulated_ble() -> SimulatedBle {
+    static LOCK: Mutex<()> = Mutex::new(());
+
+    let guard = LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let sim = SimulatedBackend::new();
+    let ble =
+        crate::EspBle::with_backend("simulated".into(), crate::ATT_MAX_MTU, sim.clone()).unwrap();
+    sim.take_calls();
+
+    SimulatedBle {
+        ble,
+        sim,
+        _guard: guard,
+    }
+}
@@ -0,0 +1,249 @@
+/// Status reported by the GAP events, mirrors `esp_bt_status_t`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BtStatus {
+    Success,
+    Fail,
+    NotReady,
+    NoMemory,
+    Busy,
+    Done,
+    Unsupported,
+    InvalidParameter,
+    Unhandled,
+    AuthenticationFailure,
+    RemoteDeviceDown,
+    AuthenticationRejected,
+    InvalidStaticRandomAddress,
+    Pending,
+    UnacceptableConnectionInterval,
+    ParameterOutOfRange,
+    Timeout,
+    PeerDataLengthUnsupported,
+    ControllerDataLengthUnsupported,
+    IllegalParameterFormat,
+    MemoryFull,
+    EirTooLarge,
+    Other(u32),
+}
+
+impl BtStatus {
+    pub fn is_success(&self) -> bool {
+        *self == BtStatus::Success
+    }
+}
+
+impl From<u32> for BtStatus {
+    fn from(status: u32) -> Self {
+        match status {
+            0 => BtStatus::Success,
+            1 => BtStatus::Fail,
+            2 => BtStatus::NotReady,
+            3 => BtStatus::NoMemory,
+            4 => BtStatus::Busy,
+            5 => BtStatus::Done,
+            6 => BtStatus::Unsupported,
+            7 => BtStatus::InvalidParameter,
+            8 => BtStatus::Unhandled,
+            9 => BtStatus::AuthenticationFailure,
+            10 => BtStatus::RemoteDeviceDown,
+            11 => BtStatus::AuthenticationRejected,
+            12 => BtStatus::InvalidStaticRandomAddress,
+            13 => BtStatus::Pending,
+            14 => BtStatus::UnacceptableConnectionInterval,
+            15 => BtStatus::ParameterOutOfRange,
+            16 => BtStatus::Timeout,
+            17 => BtStatus::PeerDataLengthUnsupported,
+            18 => BtStatus::ControllerDataLengthUnsupported,
+            19 => BtStatus::IllegalParameterFormat,
+            20 => BtStatus::MemoryFull,
+            21 => BtStatus::EirTooLarge,
+            other => BtStatus::Other(other),
+        }
+    }
+}
+
+impl From<BtStatus> for u32 {
+    fn from(status: BtStatus) -> Self {
+        match status {
+            BtStatus::Success => 0,
+            BtStatus::Fail => 1,
+            BtStatus::NotReady => 2,
+            BtStatus::NoMemory => 3,
+            BtStatus::Busy => 4,
+            BtStatus::Done => 5,
+            BtStatus::Unsupported => 6,
+            BtStatus::InvalidParameter => 7,
+            BtStatus::Unhandled => 8,
+            BtStatus::AuthenticationFailure => 9,
+            BtStatus::RemoteDeviceDown => 10,
+            BtStatus::AuthenticationRejected => 11,
+            BtStatus::InvalidStaticRandomAddress => 12,
+            BtStatus::Pending => 13,
+            BtStatus::UnacceptableConnectionInterval => 14,
+            BtStatus::ParameterOutOfRange => 15,
+            BtStatus::Timeout => 16,
+            BtStatus::PeerDataLengthUnsupported => 17,
+            BtStatus::ControllerDataLengthUnsupported => 18,
+            BtStatus::IllegalParameterFormat => 19,
+            BtStatus::MemoryFull => 20,
+            BtStatus::EirTooLarge => 21,
+            BtStatus::Other(other) => other,
+        }
+    }
+}
+
+/// Status reported by the GATT events and sent in responses, mirrors
+/// `esp_gatt_status_t`. The values below `0x80` are the ATT error codes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GattStatus {
+    Ok,
+    InvalidHandle,
+    ReadNotPermitted,
+    WriteNotPermitted,
+    InvalidPdu,
+    InsufficientAuthentication,
+    RequestNotSupported,
+    InvalidOffset,
+    InsufficientAuthorization,
+    PrepareQueueFull,
+    NotFound,
+    NotLong,
+    InsufficientKeySize,
+    InvalidAttributeLength,
+    Unlikely,
+    InsufficientEncryption,
+    UnsupportedGroupType,
+    InsufficientResources,
+    CccConfigError,
+    ProcedureInProgress,
+    OutOfRange,
+    Other(u32),
+}
+
+impl GattStatus {
+    pub fn is_ok(&self) -> bool {
+        *self == GattStatus::Ok
+    }
+}
+
+impl From<u32> for GattStatus {
+    fn from(status: u32) -> Self {
+        match status {
+            0x00 => GattStatus::Ok,
+            0x01 => GattStatus::InvalidHandle,
+            0x02 => GattStatus::ReadNotPermitted,
+            0x03 => GattStatus::WriteNotPermitted,
+            0x04 => GattStatus::InvalidPdu,
+            0x05 => GattStatus::InsufficientAuthentication,
+            0x06 => GattStatus::RequestNotSupported,
+            0x07 => GattStatus::InvalidOffset,
+            0x08 => GattStatus::InsufficientAuthorization,
+            0x09 => GattStatus::PrepareQueueFull,
+            0x0a => GattStatus::NotFound,
+            0x0b => GattStatus::NotLong,
+            0x0c => GattStatus::InsufficientKeySize,
+            0x0d => GattStatus::InvalidAttributeLength,
+            0x0e => GattStatus::Unlikely,
+            0x0f => GattStatus::InsufficientEncryption,
+            0x10 => GattStatus::UnsupportedGroupType,
+            0x11 => GattStatus::InsufficientResources,
+            0xfd => GattStatus::CccConfigError,
+            0xfe => GattStatus::ProcedureInProgress,
+            0xff => GattStatus::OutOfRange,
+            other => GattStatus::Other(other),
+        }
+    }
+}
+
+impl From<GattStatus> for u32 {
+    fn from(status: GattStatus) -> Self {
+        match status {
+            GattStatus::Ok => 0x00,
+            GattStatus::InvalidHandle => 0x01,
+            GattStatus::ReadNotPermitted => 0x02,
+            GattStatus::WriteNotPermitted => 0x03,
+            GattStatus::InvalidPdu => 0x04,
+            GattStatus::InsufficientAuthentication => 0x05,
+            GattStatus::RequestNotSupported => 0x06,
+            GattStatus::InvalidOffset => 0x07,
+            GattStatus::InsufficientAuthorization => 0x08,
+            GattStatus::PrepareQueueFull => 0x09,
+            GattStatus::NotFound => 0x0a,
+            GattStatus::NotLong => 0x0b,
+            GattStatus::InsufficientKeySize => 0x0c,
+            GattStatus::InvalidAttributeLength => 0x0d,
+            GattStatus::Unlikely => 0x0e,
+            GattStatus::InsufficientEncryption => 0x0f,
+            GattStatus::UnsupportedGroupType => 0x10,
+            GattStatus::InsufficientResources => 0x11,
+            GattStatus::CccConfigError => 0xfd,
+            GattStatus::ProcedureInProgress => 0xfe,
+            GattStatus::OutOfRange => 0xff,
+            GattStatus::Other(other) => other,
+        }
+    }
+}
+
+/// Reason of a disconnection, mirrors `esp_gatt_conn_reason_t`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisconnectReason {
+    Unknown,
+    L2capFailure,
+    Timeout,
+    RemoteUserTerminated,
+    LocalHostTerminated,
+    LmpTimeout,
+    FailedToEstablish,
+    Cancelled,
+    None,
+    Other(u32),
+}
+
+impl From<u32> for DisconnectReason {
+    fn from(reason: u32) -> Self {
+        match reason {
+            0x00 => DisconnectReason::Unknown,
+            0x01 => DisconnectReason::L2capFailure,
+            0x08 => DisconnectReason::Timeout,
+            0x13 => DisconnectReason::RemoteUserTerminated,
+            0x16 => DisconnectReason::LocalHostTerminated,
+            0x22 => DisconnectReason::LmpTimeout,
+            0x3e => DisconnectReason::FailedToEstablish,
+            0x100 => DisconnectReason::Cancelled,
+            0x101 => DisconnectReason::None,
+            other => DisconnectReason::Other(other),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bt_status_round_trip() {
+        for status in 0..=22 {
+            assert_eq!(u32::from(BtStatus::from(status)), status);
+        }
+        assert_eq!(BtStatus::from(0), BtStatus::Success);
+        assert_eq!(BtStatus::from(22), BtStatus::Other(22));
+    }
+
+    #[test]
+    fn gatt_status_round_trip() {
+        for status in (0..=0x12).chain(0xfc..=0x100) {
+            assert_eq!(u32::from(GattStatus::from(status)), status);
+        }
+        assert_eq!(GattStatus::from(0x07), GattStatus::InvalidOffset);
+        assert_eq!(GattStatus::from(0x85), GattStatus::Other(0x85));
+    }
+
+    #[test]
+    fn disconnect_reason() {
+        assert_eq!(
+            DisconnectReason::from(0x13),
+            DisconnectReason::RemoteUserTerminated
+        );
+        assert_eq!(DisconnectReason::from(0x42), DisconnectReason::Other(0x42));
+    }
+}